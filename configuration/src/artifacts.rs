@@ -0,0 +1,266 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Contract artifacts built by truffle (`build/contracts`) or hardhat
+//! (`artifacts`, with the `deployments` of hardhat-deploy), indexed by
+//! contract name so that concerns can name their contract instead of
+//! pointing to the artifact file.
+
+use error::*;
+use ethereum_types::Address;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// The contracts found in an artifacts directory, with the addresses
+/// where they were deployed on each network
+#[derive(Debug, Default)]
+pub struct Artifacts {
+    dir: PathBuf,
+    paths: HashMap<String, Vec<PathBuf>>,
+    // addresses by contract name and network id
+    addresses: HashMap<(String, String), Address>,
+}
+
+impl Artifacts {
+    /// Indexes every artifact found under the directory
+    pub fn open(dir: &Path) -> Result<Artifacts> {
+        let mut artifacts = Artifacts {
+            dir: dir.to_path_buf(),
+            ..Artifacts::default()
+        };
+        artifacts.index(dir)?;
+        Ok(artifacts)
+    }
+
+    fn index(&mut self, dir: &Path) -> Result<()> {
+        let entries = std::fs::read_dir(dir).chain_err(|| {
+            ErrorKind::ConfigError(format!(
+                "could not read artifacts directory {}",
+                dir.display()
+            ))
+        })?;
+        // hardhat-deploy keeps each network in a directory with its id
+        let network_id = std::fs::read_to_string(dir.join(".chainId"))
+            .ok()
+            .map(|id| id.trim().to_string());
+
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                self.index(&path)?;
+                continue;
+            }
+            let file_name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("");
+            // hardhat writes debug files next to each artifact
+            if !file_name.ends_with(".json") || file_name.ends_with(".dbg.json")
+            {
+                continue;
+            }
+            let content = std::fs::read_to_string(&path).chain_err(|| {
+                ErrorKind::AbiError(
+                    path.clone(),
+                    String::from("could not read file"),
+                )
+            })?;
+            let v: Value = match serde_json::from_str(&content) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            if !v["abi"].is_array() {
+                continue;
+            }
+            let name = match v["contractName"].as_str() {
+                Some(name) => name.to_string(),
+                None => file_name.trim_end_matches(".json").to_string(),
+            };
+            self.add(name, path, &v, &network_id)?;
+        }
+        Ok(())
+    }
+
+    fn add(
+        &mut self,
+        name: String,
+        path: PathBuf,
+        v: &Value,
+        network_id: &Option<String>,
+    ) -> Result<()> {
+        if let (Some(id), Some(address)) = (network_id, v["address"].as_str()) {
+            self.addresses
+                .insert((name, id.clone()), parse_address(&path, address)?);
+            return Ok(());
+        }
+        if let Some(networks) = v["networks"].as_object() {
+            for (id, network) in networks {
+                if let Some(address) = network["address"].as_str() {
+                    self.addresses.insert(
+                        (name.clone(), id.clone()),
+                        parse_address(&path, address)?,
+                    );
+                }
+            }
+        }
+        self.paths.entry(name).or_default().push(path);
+        Ok(())
+    }
+
+    /// Artifact file of the named contract, holding its abi
+    pub fn path_of(&self, name: &str) -> Result<PathBuf> {
+        match self.paths.get(name).map(|paths| &paths[..]) {
+            Some([path]) => Ok(path.clone()),
+            Some(paths) => Err(Error::from(ErrorKind::ConfigError(format!(
+                "contract {} is ambiguous, found {} artifacts in {}",
+                name,
+                paths.len(),
+                self.dir.display()
+            )))),
+            None => Err(Error::from(ErrorKind::ConfigError(format!(
+                "no artifact for contract {} in {}",
+                name,
+                self.dir.display()
+            )))),
+        }
+    }
+
+    /// Address of the named contract on the given network
+    pub fn address_of(&self, name: &str, network_id: &str) -> Result<Address> {
+        self.addresses
+            .get(&(name.to_string(), network_id.to_string()))
+            .cloned()
+            .ok_or(Error::from(ErrorKind::ConfigError(format!(
+                "contract {} is not deployed on network id {}",
+                name, network_id
+            ))))
+    }
+
+    /// Artifact file of the contract deployed at the given address, like
+    /// the implementation behind a proxy. hardhat-deploy records those
+    /// as `<Name>_Implementation`.
+    pub fn path_at(
+        &self,
+        address: Address,
+        network_id: &str,
+    ) -> Option<PathBuf> {
+        let name = self
+            .addresses
+            .iter()
+            .find(|((_, id), deployed)| {
+                id == network_id && **deployed == address
+            })
+            .map(|((name, _), _)| name)?;
+        self.path_of(name)
+            .or(self.path_of(name.trim_end_matches("_Implementation")))
+            .ok()
+    }
+
+    /// Artifact file and address of the named contract
+    pub fn locate(
+        &self,
+        name: &str,
+        network_id: &str,
+    ) -> Result<(PathBuf, Address)> {
+        Ok((self.path_of(name)?, self.address_of(name, network_id)?))
+    }
+}
+
+fn parse_address(path: &Path, address: &str) -> Result<Address> {
+    address.trim_start_matches("0x").parse().chain_err(|| {
+        ErrorKind::AbiError(
+            path.to_path_buf(),
+            format!("invalid address {}", address),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const ABI: &str = r#""abi": [{ "type": "function", "name": "foo" }]"#;
+
+    fn write(path: PathBuf, content: String) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn finds_truffle_and_hardhat_contracts() {
+        let dir = std::env::temp_dir()
+            .join(format!("artifacts-{}", std::process::id()));
+        write(
+            dir.join("build/contracts/PartitionInstantiator.json"),
+            format!(
+                r#"{{ "contractName": "PartitionInstantiator", {},
+                "networks": {{ "7777": {{ "address": "0x{}" }} }} }}"#,
+                ABI,
+                "11".repeat(20)
+            ),
+        );
+        write(
+            dir.join("artifacts/contracts/MM.sol/MMInstantiator.json"),
+            format!(r#"{{ "contractName": "MMInstantiator", {} }}"#, ABI),
+        );
+        write(
+            dir.join("artifacts/contracts/MM.sol/MMInstantiator.dbg.json"),
+            String::from(r#"{ "buildInfo": "" }"#),
+        );
+        write(dir.join("deployments/localhost/.chainId"), "31337\n".into());
+        write(
+            dir.join("deployments/localhost/MMInstantiator.json"),
+            format!(r#"{{ "address": "0x{}", {} }}"#, "22".repeat(20), ABI),
+        );
+        write(
+            dir.join(
+                "deployments/localhost/MMInstantiator_Implementation.json",
+            ),
+            format!(r#"{{ "address": "0x{}", {} }}"#, "33".repeat(20), ABI),
+        );
+
+        let artifacts = Artifacts::open(&dir).unwrap();
+        let (path, address) =
+            artifacts.locate("PartitionInstantiator", "7777").unwrap();
+        assert!(path.ends_with("build/contracts/PartitionInstantiator.json"));
+        assert_eq!(address, Address::repeat_byte(0x11));
+
+        let (path, address) =
+            artifacts.locate("MMInstantiator", "31337").unwrap();
+        assert!(path.ends_with("MM.sol/MMInstantiator.json"));
+        assert_eq!(address, Address::repeat_byte(0x22));
+
+        let path = artifacts.path_at(Address::repeat_byte(0x33), "31337");
+        assert!(path.unwrap().ends_with("MM.sol/MMInstantiator.json"));
+        assert!(artifacts
+            .path_at(Address::repeat_byte(0x33), "7777")
+            .is_none());
+
+        assert!(artifacts.locate("MMInstantiator", "7777").is_err());
+        assert!(artifacts.path_of("VGInstantiator").is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! EIP-55 checksums of addresses: the case of each letter of an address
+//! written in hex follows the hash of the address, so that typos are
+//! caught when it is read back.
+
+use error::*;
+use ethereum_types::Address;
+use parity_crypto::Keccak256;
+use serde::Serializer;
+
+/// An address in hex with its checksum, starting with 0x
+pub fn checksummed(address: &Address) -> String {
+    let lower = hex::encode(address.as_bytes());
+    let hash: [u8; 32] = lower.as_bytes().keccak256();
+    let mut result = String::from("0x");
+    for (i, c) in lower.chars().enumerate() {
+        let nibble = (hash[i / 2] >> (if i % 2 == 0 { 4 } else { 0 })) & 0xf;
+        if nibble >= 8 {
+            result.extend(c.to_uppercase());
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Parses an address in hex, with or without 0x. When it is written in
+/// mixed case its checksum is verified: a mismatch fails when strict, and
+/// is only warned about otherwise. All lower or upper case addresses carry
+/// no checksum and are taken as they are.
+pub fn parse(value: &str, strict: bool) -> Result<Address> {
+    let digits = value.trim_start_matches("0x");
+    let address: Address = digits
+        .parse()
+        .chain_err(|| format!("failed to parse address {}", value))?;
+    let mixed_case = digits.chars().any(|c| c.is_ascii_lowercase())
+        && digits.chars().any(|c| c.is_ascii_uppercase());
+    let expected = checksummed(&address);
+    if mixed_case && expected[2..] != *digits {
+        if strict {
+            return Err(Error::from(ErrorKind::ConfigError(format!(
+                "checksum of address {} does not match, expected {}",
+                value, expected
+            ))));
+        }
+        warn!(
+            "Checksum of address {} does not match, expected {}",
+            value, expected
+        );
+    }
+    Ok(address)
+}
+
+/// Serializes an address with its checksum, for serde's `serialize_with`
+pub fn serialize<S>(
+    address: &Address,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&checksummed(address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_follow_eip_55() {
+        for expected in &[
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+        ] {
+            let address = parse(expected, true).unwrap();
+            assert_eq!(checksummed(&address), *expected);
+        }
+        let lower = "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed";
+        assert!(parse(lower, true).is_ok());
+        let typo = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD";
+        assert!(parse(typo, true).is_err());
+        assert!(parse(typo, false).is_ok());
+    }
+}
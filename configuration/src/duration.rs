@@ -0,0 +1,156 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Durations given in the configuration, either as a bare number of
+//! seconds or with a unit, like `500ms`, `30s`, `2m`, `1h` or `1d`.
+
+use error::*;
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A duration read from the config file, the arguments or the environment
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConfigDuration(pub Duration);
+
+impl FromStr for ConfigDuration {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number: u64 = number.parse().chain_err(|| {
+            format!("invalid duration {:?}, expected e.g. 500ms or 2m", s)
+        })?;
+        let millis = match unit.trim() {
+            "ms" => 1,
+            "" | "s" => 1000,
+            "m" => 60 * 1000,
+            "h" => 60 * 60 * 1000,
+            "d" => 24 * 60 * 60 * 1000,
+            _ => {
+                return Err(Error::from(ErrorKind::ConfigError(format!(
+                    "invalid unit of duration {:?}, expected ms, s, m, h or d",
+                    s
+                ))));
+            }
+        };
+        number
+            .checked_mul(millis)
+            .map(|millis| ConfigDuration(Duration::from_millis(millis)))
+            .ok_or(Error::from(ErrorKind::ConfigError(format!(
+                "duration {:?} is too long",
+                s
+            ))))
+    }
+}
+
+struct DurationVisitor;
+
+impl<'de> Visitor<'de> for DurationVisitor {
+    type Value = ConfigDuration;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a number of seconds or a duration like 500ms or 2m")
+    }
+
+    fn visit_u64<E: de::Error>(
+        self,
+        seconds: u64,
+    ) -> ::std::result::Result<Self::Value, E> {
+        Ok(ConfigDuration(Duration::from_secs(seconds)))
+    }
+
+    fn visit_i64<E: de::Error>(
+        self,
+        seconds: i64,
+    ) -> ::std::result::Result<Self::Value, E> {
+        if seconds < 0 {
+            return Err(E::custom("durations cannot be negative"));
+        }
+        self.visit_u64(seconds as u64)
+    }
+
+    fn visit_str<E: de::Error>(
+        self,
+        s: &str,
+    ) -> ::std::result::Result<Self::Value, E> {
+        s.parse().map_err(|e: Error| E::custom(e.to_string()))
+    }
+}
+
+// numbers in the file are seconds, as before units were accepted, while
+// the environment gives every value as a string
+impl<'de> Deserialize<'de> for ConfigDuration {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> ::std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(DurationVisitor)
+    }
+}
+
+// written back with a unit, so that it reads the same once parsed again
+impl Serialize for ConfigDuration {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> ::std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{}ms", self.0.as_millis()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Duration {
+        s.parse::<ConfigDuration>().unwrap().0
+    }
+
+    #[test]
+    fn durations_take_units_or_seconds() {
+        assert_eq!(parse("500ms"), Duration::from_millis(500));
+        assert_eq!(parse("45"), Duration::from_secs(45));
+        assert_eq!(parse("45s"), Duration::from_secs(45));
+        assert_eq!(parse("2m"), Duration::from_secs(120));
+        assert_eq!(parse("1h"), Duration::from_secs(3600));
+        assert_eq!(parse("1d"), Duration::from_secs(86400));
+        assert!("2 weeks".parse::<ConfigDuration>().is_err());
+        assert!("m".parse::<ConfigDuration>().is_err());
+        assert!("-5s".parse::<ConfigDuration>().is_err());
+
+        let from_yaml: Vec<ConfigDuration> =
+            serde_yaml::from_str("[30, \"1m\"]").unwrap();
+        assert_eq!(from_yaml[0].0, Duration::from_secs(30));
+        assert_eq!(from_yaml[1].0, Duration::from_secs(60));
+
+        let to_yaml = serde_yaml::to_string(&from_yaml).unwrap();
+        let again: Vec<ConfigDuration> =
+            serde_yaml::from_str(&to_yaml).unwrap();
+        assert_eq!(again, from_yaml);
+    }
+}
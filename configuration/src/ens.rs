@@ -0,0 +1,141 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Resolution of ENS names given in place of addresses, through the
+//! registry and the resolver of each name on the configured node.
+
+use error::*;
+use ethereum_types::{Address, H256};
+use parity_crypto::Keccak256;
+use transport::GenericTransport;
+use web3::futures::Future;
+use web3::types::{Bytes, CallRequest};
+
+/// Address of the ENS registry, the same on mainnet and the test networks
+const REGISTRY: &str = "00000000000C2E074eC69A0dFb2997BA6C7d2e1e";
+/// Selector of `resolver(bytes32)` on the registry
+const RESOLVER_SELECTOR: [u8; 4] = [0x01, 0x78, 0xb8, 0xbf];
+/// Selector of `addr(bytes32)` on a resolver
+const ADDR_SELECTOR: [u8; 4] = [0x3b, 0x3b, 0x57, 0xde];
+
+/// Whether a configured value is an ENS name rather than an address
+pub fn is_name(value: &str) -> bool {
+    value.contains('.') && !value.starts_with("0x")
+}
+
+/// The node of a name in the registry, as defined by EIP-137
+pub fn namehash(name: &str) -> H256 {
+    let mut node = [0u8; 32];
+    if name.is_empty() {
+        return H256::from(node);
+    }
+    for label in name.rsplit('.') {
+        let label_hash: [u8; 32] = label.keccak256();
+        node = [&node[..], &label_hash[..]].concat().keccak256();
+    }
+    H256::from(node)
+}
+
+/// Resolves a name to the address its resolver gives, failing when the
+/// name has no resolver or no address
+pub fn resolve(
+    web3: &web3::Web3<GenericTransport>,
+    name: &str,
+) -> Result<Address> {
+    let node = namehash(&name.to_lowercase());
+    let registry: Address =
+        REGISTRY.parse().chain_err(|| "invalid registry")?;
+    let resolver = call_address(web3, registry, RESOLVER_SELECTOR, node)
+        .chain_err(|| format!("could not find the resolver of {}", name))?;
+    if resolver.is_zero() {
+        return Err(Error::from(ErrorKind::ConfigError(format!(
+            "ENS name {} has no resolver",
+            name
+        ))));
+    }
+    let address = call_address(web3, resolver, ADDR_SELECTOR, node)
+        .chain_err(|| format!("could not resolve {}", name))?;
+    if address.is_zero() {
+        return Err(Error::from(ErrorKind::ConfigError(format!(
+            "ENS name {} resolves to no address",
+            name
+        ))));
+    }
+    Ok(address)
+}
+
+// calls a function taking a node and returning an address
+fn call_address(
+    web3: &web3::Web3<GenericTransport>,
+    to: Address,
+    selector: [u8; 4],
+    node: H256,
+) -> Result<Address> {
+    let data = [&selector[..], node.as_bytes()].concat();
+    let result = web3
+        .eth()
+        .call(
+            CallRequest {
+                from: None,
+                to: to,
+                gas: None,
+                gas_price: None,
+                value: None,
+                data: Some(Bytes(data)),
+            },
+            None,
+        )
+        .wait()?;
+    if result.0.len() < 32 {
+        return Err(Error::from(format!(
+            "unexpected answer from {:#x}: {:?}",
+            to, result.0
+        )));
+    }
+    Ok(Address::from_slice(&result.0[12..32]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_hash_as_in_eip_137() {
+        assert_eq!(namehash(""), H256::zero());
+        assert_eq!(
+            namehash("eth"),
+            "93cdeb708b7545dc668eb9280176169d1c33cfd8ed6f04690a0bcc88a93fc4ae"
+                .parse()
+                .unwrap()
+        );
+        assert_eq!(
+            namehash("foo.eth"),
+            "de9b09fd7c5f901e23a3f19fecc54828e9c848539801e86591bd9801b019f84f"
+                .parse()
+                .unwrap()
+        );
+        assert!(is_name("dispatcher.cartesi.eth"));
+        assert!(!is_name("0xAF6Db79D717c176C64Cc1ff07930367a870f9968"));
+    }
+}
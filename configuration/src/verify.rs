@@ -0,0 +1,144 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Verification of the code deployed for a concern against the
+//! `deployedBytecode` of its artifact, so that transactions are not sent
+//! to a contract other than the one the abi describes. The metadata solc
+//! appends to the code, whose hash changes with source paths and comments,
+//! is left out of the comparison.
+//!
+//! Code with unlinked libraries cannot be verified, and contracts with
+//! immutable variables differ from their artifact where those are stored.
+
+use hex;
+use serde_json::Value;
+
+/// What the deployed code of a contract is found to be
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verification {
+    Matches,
+    Differs,
+    /// The artifact does not tell, for the given reason
+    Unknown(String),
+}
+
+/// The deployed code recorded in a truffle, hardhat or solc artifact
+pub fn expected_code(artifact: &Value) -> Option<&str> {
+    let code = &artifact["deployedBytecode"];
+    code.as_str()
+        .or(code["object"].as_str())
+        .or(artifact["evm"]["deployedBytecode"]["object"].as_str())
+}
+
+/// The code without the cbor encoded metadata solc appends to it, whose
+/// length is given by the last two bytes
+pub fn strip_metadata(code: &[u8]) -> &[u8] {
+    if code.len() < 2 {
+        return code;
+    }
+    let length =
+        ((code[code.len() - 2] as usize) << 8) | code[code.len() - 1] as usize;
+    match code.len().checked_sub(length + 2) {
+        // metadata is a cbor map
+        Some(start) if length > 0 && code[start] & 0xe0 == 0xa0 => {
+            &code[..start]
+        }
+        _ => code,
+    }
+}
+
+/// Compares the deployed code with the one of the artifact
+pub fn verify(artifact: &Value, deployed: &[u8]) -> Verification {
+    let expected = match expected_code(artifact) {
+        Some(expected) => expected.trim_start_matches("0x"),
+        None => {
+            return Verification::Unknown(String::from(
+                "artifact has no deployedBytecode",
+            ))
+        }
+    };
+    if expected.is_empty() {
+        return Verification::Unknown(String::from(
+            "artifact has an empty deployedBytecode",
+        ));
+    }
+    if expected.contains("__") {
+        return Verification::Unknown(String::from("artifact links libraries"));
+    }
+    let expected = match hex::decode(expected) {
+        Ok(expected) => expected,
+        Err(_) => {
+            return Verification::Unknown(String::from(
+                "artifact has an invalid deployedBytecode",
+            ))
+        }
+    };
+    if strip_metadata(&expected) == strip_metadata(deployed) {
+        Verification::Matches
+    } else {
+        Verification::Differs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // code followed by metadata holding the given byte, as solc appends it
+    fn with_metadata(code: &[u8], hash: u8) -> Vec<u8> {
+        let mut full = code.to_vec();
+        full.extend_from_slice(&[0xa1, 0x65, b'b', b'z', b'z', b'r', hash]);
+        full.extend_from_slice(&[0x00, 0x07]);
+        full
+    }
+
+    #[test]
+    fn metadata_is_left_out() {
+        let code = [0x60, 0x80, 0x60, 0x40];
+        let deployed = with_metadata(&code, 1);
+        assert_eq!(strip_metadata(&deployed), &code[..]);
+        assert_eq!(strip_metadata(&code), &code[..]);
+
+        let expected = hex::encode(with_metadata(&code, 2));
+        let artifact = json!({ "deployedBytecode": format!("0x{}", expected) });
+        assert_eq!(verify(&artifact, &deployed), Verification::Matches);
+        assert_eq!(
+            verify(&artifact, &with_metadata(&[0x60, 0x80], 1)),
+            Verification::Differs
+        );
+    }
+
+    #[test]
+    fn artifacts_without_code_do_not_tell() {
+        let unlinked = json!({ "deployedBytecode": "0x73__$abcd$__6080" });
+        match verify(&unlinked, &[0x60]) {
+            Verification::Unknown(_) => {}
+            other => panic!("expected unknown, got {:?}", other),
+        }
+        let solc =
+            json!({ "evm": { "deployedBytecode": { "object": "6080" } } });
+        assert_eq!(verify(&solc, &[0x60, 0x80]), Verification::Matches);
+        assert!(verify(&json!({}), &[0x60]) != Verification::Matches);
+    }
+}
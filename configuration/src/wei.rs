@@ -0,0 +1,156 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Amounts of wei given in the configuration, either as a bare number of
+//! wei or with a unit, like `500gwei` or `2ether`. Unlike plain integers
+//! they are not bounded by 64 bits.
+
+use error::*;
+use ethereum_types::U256;
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// An amount of wei read from the config file, the arguments or the
+/// environment
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConfigWei(pub U256);
+
+impl FromStr for ConfigWei {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number = U256::from_dec_str(number).map_err(|_| {
+            Error::from(ErrorKind::ConfigError(format!(
+                "invalid amount {:?}, expected e.g. 500gwei or 2ether",
+                s
+            )))
+        })?;
+        let decimals = match unit.trim() {
+            "" | "wei" => 0,
+            "gwei" => 9,
+            "ether" => 18,
+            _ => {
+                return Err(Error::from(ErrorKind::ConfigError(format!(
+                    "invalid unit of amount {:?}, expected wei, gwei or ether",
+                    s
+                ))));
+            }
+        };
+        number
+            .checked_mul(U256::exp10(decimals))
+            .map(ConfigWei)
+            .ok_or(Error::from(ErrorKind::ConfigError(format!(
+                "amount {:?} is too large",
+                s
+            ))))
+    }
+}
+
+struct WeiVisitor;
+
+impl<'de> Visitor<'de> for WeiVisitor {
+    type Value = ConfigWei;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a number of wei or an amount like 500gwei or 2ether")
+    }
+
+    fn visit_u64<E: de::Error>(
+        self,
+        wei: u64,
+    ) -> ::std::result::Result<Self::Value, E> {
+        Ok(ConfigWei(U256::from(wei)))
+    }
+
+    fn visit_i64<E: de::Error>(
+        self,
+        wei: i64,
+    ) -> ::std::result::Result<Self::Value, E> {
+        if wei < 0 {
+            return Err(E::custom("amounts cannot be negative"));
+        }
+        self.visit_u64(wei as u64)
+    }
+
+    fn visit_str<E: de::Error>(
+        self,
+        s: &str,
+    ) -> ::std::result::Result<Self::Value, E> {
+        s.parse().map_err(|e: Error| E::custom(e.to_string()))
+    }
+}
+
+// amounts above 64 bits can only be given as strings, as yaml numbers
+// are read as u64
+impl<'de> Deserialize<'de> for ConfigWei {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> ::std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(WeiVisitor)
+    }
+}
+
+// written back as a decimal string, which holds amounts of any size
+impl Serialize for ConfigWei {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> ::std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> U256 {
+        s.parse::<ConfigWei>().unwrap().0
+    }
+
+    #[test]
+    fn amounts_take_units_and_exceed_64_bits() {
+        assert_eq!(parse("1000"), U256::from(1000));
+        assert_eq!(parse("1000wei"), U256::from(1000));
+        assert_eq!(parse("5gwei"), U256::from(5_000_000_000u64));
+        assert_eq!(parse("100ether"), U256::exp10(20));
+        assert_eq!(
+            parse("100000000000000000000"),
+            U256::from_dec_str("100000000000000000000").unwrap()
+        );
+        assert!("2 eth".parse::<ConfigWei>().is_err());
+        assert!("ether".parse::<ConfigWei>().is_err());
+        assert!("-5".parse::<ConfigWei>().is_err());
+
+        let from_yaml: Vec<ConfigWei> =
+            serde_yaml::from_str("[30, \"20ether\"]").unwrap();
+        assert_eq!(from_yaml[0].0, U256::from(30));
+        assert_eq!(from_yaml[1].0, U256::exp10(19) * 2);
+    }
+}
@@ -0,0 +1,212 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Protocol of the grpc service exposing the status of a dispatcher, the
+//! same as its http status server, so that fleet-management tools poll
+//! many dispatchers with a typed client. The messages and the service are
+//! defined in `proto/status.proto`, which is the contract with clients in
+//! other languages; this crate holds the stubs generated from it by the
+//! build, together with the client and the server glue shared by both
+//! ends.
+//!
+//! Addresses travel as checksummed hex strings, and values that may be
+//! unknown sit alone in a oneof, unset while unknown. A missing or wrong
+//! key, or any failure to gather the status, is answered with the grpc
+//! status `INTERNAL` and the reason as its message.
+
+extern crate configuration;
+extern crate error;
+extern crate grpc;
+extern crate protobuf;
+extern crate web3;
+
+#[macro_use]
+extern crate log;
+
+pub mod status;
+pub mod status_grpc;
+
+pub use status::{
+    DispatcherStatus, InstanceStatus, StatusRequest, TransactionStatus,
+};
+
+use configuration::checksum::{self, checksummed};
+use configuration::TransPort;
+use error::*;
+use grpc::{Client, ClientStub, RequestOptions, SingleResponse};
+use status_grpc::{StatusService, StatusServiceClient, StatusServiceServer};
+use std::sync::Arc;
+use web3::futures::Future;
+
+/// The message of a concern
+pub fn concern_message(concern: &configuration::Concern) -> status::Concern {
+    let mut message = status::Concern::new();
+    message.set_contract_address(checksummed(&concern.contract_address));
+    message.set_user_address(checksummed(&concern.user_address));
+    message
+}
+
+/// The concern a message names, whose addresses must carry a valid
+/// checksum when written in mixed case
+pub fn concern_of(message: &status::Concern) -> Result<configuration::Concern> {
+    Ok(configuration::Concern {
+        contract_address: checksum::parse(
+            message.get_contract_address(),
+            true,
+        )?,
+        user_address: checksum::parse(message.get_user_address(), true)?,
+    })
+}
+
+fn to_grpc_error(e: Error) -> grpc::Error {
+    grpc::Error::GrpcMessage(grpc::GrpcMessageError {
+        grpc_status: grpc::GrpcStatus::Internal as i32,
+        grpc_message: format!("{}", e),
+    })
+}
+
+// answers status requests with the status given by the provider
+struct StatusHandler<F> {
+    provider: F,
+}
+
+impl<F> StatusService for StatusHandler<F>
+where
+    F: Fn(&StatusRequest) -> Result<DispatcherStatus> + Send + Sync,
+{
+    fn get_status(
+        &self,
+        _: RequestOptions,
+        request: StatusRequest,
+    ) -> SingleResponse<DispatcherStatus> {
+        match (self.provider)(&request) {
+            Ok(status) => SingleResponse::completed(status),
+            Err(e) => SingleResponse::err(to_grpc_error(e)),
+        }
+    }
+}
+
+/// Starts serving, on the given port, the status given by the provider
+pub fn serve<F>(provider: F, port: u16) -> Result<grpc::Server>
+where
+    F: Fn(&StatusRequest) -> Result<DispatcherStatus> + Send + Sync + 'static,
+{
+    let mut server = grpc::ServerBuilder::new_plain();
+    server.http.set_port(port);
+    server.add_service(StatusServiceServer::new_service_def(StatusHandler {
+        provider: provider,
+    }));
+    let server = server.build()?;
+    info!("Serving dispatcher status over grpc on port {}", port);
+    Ok(server)
+}
+
+/// Reads the status of a remote dispatcher
+pub struct StatusClient {
+    client: StatusServiceClient,
+    api_key: Option<String>,
+}
+
+impl StatusClient {
+    pub fn new(
+        dispatcher: &TransPort,
+        api_key: Option<String>,
+    ) -> Result<StatusClient> {
+        let client = Client::new_plain(
+            &dispatcher.address,
+            dispatcher.port,
+            Default::default(),
+        )
+        .chain_err(|| {
+            format!("could not connect to dispatcher at {}", dispatcher)
+        })?;
+        Ok(StatusClient {
+            client: StatusServiceClient::with_client(Arc::new(client)),
+            api_key: api_key,
+        })
+    }
+
+    pub fn status(
+        &self,
+    ) -> Box<dyn Future<Item = DispatcherStatus, Error = Error> + Send> {
+        let mut request = StatusRequest::new();
+        if let Some(api_key) = &self.api_key {
+            request.set_api_key(api_key.clone());
+        }
+        Box::new(
+            self.client
+                .get_status(RequestOptions::new(), request)
+                .drop_metadata()
+                .map_err(|e| {
+                    Error::from(e).chain_err(|| "status request failed")
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobuf::Message;
+    use web3::types::Address;
+
+    #[test]
+    fn statuses_survive_the_wire() {
+        let concern = configuration::Concern {
+            contract_address: Address::repeat_byte(0xaa),
+            user_address: Address::repeat_byte(0xbb),
+        };
+        let mut instance = InstanceStatus::new();
+        instance.set_concern(concern_message(&concern));
+        instance.set_index(3);
+        instance.set_moved_at(1_600_000_000);
+        let mut status = DispatcherStatus::new();
+        status.set_main_concern(concern_message(&concern));
+        status.mut_instances().push(instance);
+        status.set_last_block(1234);
+        status.set_reactions(42);
+
+        let bytes = status.write_to_bytes().unwrap();
+        let parsed: DispatcherStatus =
+            protobuf::parse_from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, status);
+        assert_eq!(concern_of(parsed.get_main_concern()).unwrap(), concern);
+        // unknown values are told apart from zero
+        assert!(parsed.get_instances()[0].has_moved_at());
+        assert!(parsed.has_last_block());
+        assert!(!parsed.has_node_delay());
+    }
+
+    #[test]
+    fn concerns_with_a_wrong_checksum_are_refused() {
+        let concern = configuration::Concern {
+            contract_address: Address::repeat_byte(0xaa),
+            user_address: Address::repeat_byte(0xbb),
+        };
+        let mut message = concern_message(&concern);
+        let wrong = message.get_contract_address().replacen("A", "a", 1);
+        message.set_contract_address(wrong);
+        assert!(concern_of(&message).is_err());
+    }
+}
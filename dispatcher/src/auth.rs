@@ -0,0 +1,183 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Authentication of the http APIs of the dispatcher. Clients present one
+//! of the configured keys as `Authorization: Bearer <key>`, and each key
+//! grants either read-only access or the right to have the dispatcher send
+//! transactions. Without any key configured the APIs stay open for reading
+//! only, so that no one can have transactions sent until a transact key
+//! is given. The grpc status service takes the key in its requests instead.
+
+use super::configuration::{AccessLevel, ApiKey};
+use hyper::header::AUTHORIZATION;
+use hyper::{Request, StatusCode};
+
+/// Checks the keys presented by clients of the http APIs
+pub struct Authenticator {
+    keys: Vec<ApiKey>,
+}
+
+impl Authenticator {
+    pub fn new(keys: Vec<ApiKey>) -> Self {
+        Authenticator { keys: keys }
+    }
+
+    /// Whether every client has read-only access, as no key was configured
+    pub fn is_open(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Access granted to the key presented with the request, if any
+    pub fn level_of<B>(&self, req: &Request<B>) -> Option<AccessLevel> {
+        let presented = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                let mut parts = value.splitn(2, ' ');
+                match (parts.next(), parts.next()) {
+                    (Some("Bearer"), Some(key)) => Some(key.trim()),
+                    _ => None,
+                }
+            });
+        self.level_of_key(presented)
+    }
+
+    /// Access granted to a key presented some other way, like in the
+    /// requests to the grpc status service
+    pub fn level_of_key(&self, presented: Option<&str>) -> Option<AccessLevel> {
+        if self.is_open() {
+            return Some(AccessLevel::ReadOnly);
+        }
+        let presented = presented?;
+        self.keys
+            .iter()
+            .filter(|key| same_key(&key.key, presented))
+            .map(|key| key.level)
+            .max()
+    }
+
+    /// Fails with the status and reason to answer unless the request
+    /// carries a key granting the required access
+    pub fn check<B>(
+        &self,
+        req: &Request<B>,
+        required: AccessLevel,
+    ) -> std::result::Result<(), (StatusCode, &'static str)> {
+        allow(self.level_of(req), required)
+    }
+}
+
+/// Fails with the status and reason to answer unless the access granted
+/// covers the required one
+pub fn allow(
+    granted: Option<AccessLevel>,
+    required: AccessLevel,
+) -> std::result::Result<(), (StatusCode, &'static str)> {
+    match granted {
+        Some(level) if level >= required => Ok(()),
+        Some(_) => Err((StatusCode::FORBIDDEN, "key does not allow this")),
+        None => Err((StatusCode::UNAUTHORIZED, "missing or unknown key")),
+    }
+}
+
+// compares keys in time independent of where they differ
+fn same_key(expected: &str, presented: &str) -> bool {
+    expected.len() == presented.len()
+        && expected
+            .bytes()
+            .zip(presented.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(key: Option<&str>) -> Request<()> {
+        let mut builder = Request::builder();
+        if let Some(key) = key {
+            builder.header(AUTHORIZATION, format!("Bearer {}", key));
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn keys_grant_their_level_only() {
+        let auth = Authenticator::new(vec![
+            ApiKey {
+                key: String::from("read-only-key-0123"),
+                level: AccessLevel::ReadOnly,
+            },
+            ApiKey {
+                key: String::from("transact-key-01234"),
+                level: AccessLevel::Transact,
+            },
+        ]);
+        let read = request(Some("read-only-key-0123"));
+        assert!(auth.check(&read, AccessLevel::ReadOnly).is_ok());
+        assert_eq!(
+            auth.check(&read, AccessLevel::Transact).unwrap_err().0,
+            StatusCode::FORBIDDEN
+        );
+        let transact = request(Some("transact-key-01234"));
+        assert!(auth.check(&transact, AccessLevel::Transact).is_ok());
+        for unknown in &[request(None), request(Some("read-only-key-012"))] {
+            assert_eq!(
+                auth.check(unknown, AccessLevel::ReadOnly).unwrap_err().0,
+                StatusCode::UNAUTHORIZED
+            );
+        }
+
+        assert_eq!(
+            auth.level_of_key(Some("read-only-key-0123")),
+            Some(AccessLevel::ReadOnly)
+        );
+        assert_eq!(auth.level_of_key(None), None);
+    }
+
+    #[test]
+    fn no_keys_grant_read_only() {
+        let open = Authenticator::new(vec![]);
+        assert!(open.is_open());
+        assert!(open.check(&request(None), AccessLevel::ReadOnly).is_ok());
+        assert_eq!(
+            open.check(&request(None), AccessLevel::Transact)
+                .unwrap_err()
+                .0,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            open.check(
+                &request(Some("any-key-0123456789")),
+                AccessLevel::Transact
+            )
+            .unwrap_err()
+            .0,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(open.level_of_key(None), Some(AccessLevel::ReadOnly));
+    }
+}
@@ -0,0 +1,171 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Budgets limiting what each concern spends on gas. Once a concern spent
+//! its budget, only calls to its essential functions are sent, like those
+//! claiming a timeout, until the operator overrides the budget with the
+//! `override-budget` command. Overrides are kept in a json file of the
+//! working path, like the paused contracts.
+
+use super::configuration::{Budget, Concern};
+use super::error::*;
+use super::ethereum_types::{Address, U256};
+use super::notify::{Alert, Notifier};
+use super::pause::AddressFile;
+use super::spend::SpendStore;
+use super::HashMap;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Decides whether the transactions of a concern fit its budget
+pub struct BudgetGuard {
+    budgets: HashMap<Concern, Budget>,
+    spending: Arc<SpendStore>,
+    overrides: AddressFile,
+    // concerns already reported over budget
+    exceeded: Mutex<HashSet<Concern>>,
+}
+
+impl BudgetGuard {
+    pub fn new(
+        budgets: HashMap<Concern, Budget>,
+        spending: Arc<SpendStore>,
+        working_path: &Path,
+    ) -> Self {
+        BudgetGuard {
+            budgets: budgets,
+            spending: spending,
+            overrides: AddressFile::new(working_path, "budget_overrides.json"),
+            exceeded: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Lets a contract spend over its budget, returning whether it was
+    /// not already allowed to
+    pub fn override_budget(&self, address: Address) -> Result<bool> {
+        self.overrides.insert(address)
+    }
+
+    /// Enforces the budget of a contract again, returning whether it was
+    /// overridden
+    pub fn enforce_budget(&self, address: Address) -> Result<bool> {
+        self.overrides.remove(address)
+    }
+
+    /// Whether a call to a function of the concern may be sent: always
+    /// for essential functions, and for the others while the concern has
+    /// budget left or its budget was overridden. The first call refused
+    /// raises an alert.
+    pub fn allows(
+        &self,
+        concern: &Concern,
+        function: &str,
+        notifier: &dyn Notifier,
+    ) -> Result<bool> {
+        let budget = match self.budgets.get(concern) {
+            Some(budget) => budget,
+            None => return Ok(true),
+        };
+        if budget.essential_functions.iter().any(|f| f == function) {
+            return Ok(true);
+        }
+        let spent = self.spending.get(*concern)?.wei_spent;
+        if spent < budget.max_wei {
+            return Ok(true);
+        }
+        if self.overrides.contains(&concern.contract_address)? {
+            return Ok(true);
+        }
+        if self.exceeded.lock().unwrap().insert(*concern) {
+            error!(
+                "CRITICAL: concern {} spent {} wei, over its budget of {}, \
+                 only essential functions are called until overridden",
+                concern, spent, budget.max_wei
+            );
+            notifier.notify(&Alert::BudgetExceeded {
+                concern: *concern,
+                spent: spent,
+                budget: budget.max_wei,
+            });
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use configuration::Storage;
+    use ethereum_types::H256;
+    use tempfile::tempdir;
+    use transaction::Spending;
+
+    struct Counter(Mutex<usize>);
+
+    impl Notifier for Counter {
+        fn notify(&self, _alert: &Alert) {
+            *self.0.lock().unwrap() += 1;
+        }
+    }
+
+    #[test]
+    fn only_essential_calls_are_sent_over_budget() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let concern = Concern {
+            contract_address: Address::repeat_byte(0xaa),
+            user_address: Address::zero(),
+        };
+        let spending =
+            Arc::new(SpendStore::open(Storage::LevelDb, &dir.join("spend_db")));
+        let mut budgets = HashMap::new();
+        budgets.insert(
+            concern,
+            Budget {
+                max_wei: U256::from(1_000),
+                essential_functions: vec![String::from("claimVictory")],
+            },
+        );
+        let guard = BudgetGuard::new(budgets, spending.clone(), dir);
+        let alerts = Counter(Mutex::new(0));
+
+        assert!(guard.allows(&concern, "reveal", &alerts).unwrap());
+        spending
+            .record(&Spending {
+                concern: concern,
+                hash: H256::zero(),
+                gas_used: U256::from(500),
+                gas_price: U256::from(2),
+            })
+            .unwrap();
+        assert!(!guard.allows(&concern, "reveal", &alerts).unwrap());
+        assert!(!guard.allows(&concern, "reveal", &alerts).unwrap());
+        assert!(guard.allows(&concern, "claimVictory", &alerts).unwrap());
+        assert_eq!(*alerts.0.lock().unwrap(), 1);
+
+        assert!(guard.override_budget(concern.contract_address).unwrap());
+        assert!(guard.allows(&concern, "reveal", &alerts).unwrap());
+    }
+}
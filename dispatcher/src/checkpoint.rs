@@ -0,0 +1,122 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Latest checkpoint of each machine manager session: the cycle of the
+//! last intermediate state the machine manager reported as persisted. A
+//! dapp resumes the run of a session from there after the dispatcher or
+//! the machine manager restarts, instead of running from cycle zero.
+
+use super::configuration::Storage;
+use super::error::*;
+use super::utils::kv::{KvStore, LazyStore};
+use std::path::Path;
+
+/// Checkpoints of the sessions still in use
+pub struct CheckpointStore {
+    database: LazyStore,
+}
+
+impl CheckpointStore {
+    /// The checkpoint database at the given path, created on the first run
+    pub fn open(storage: Storage, path: &Path) -> CheckpointStore {
+        CheckpointStore {
+            database: LazyStore::new(storage, path),
+        }
+    }
+
+    /// Cycle of the latest checkpoint of a session, if any
+    pub fn latest(&self, session_id: &str) -> Result<Option<u64>> {
+        let data = self
+            .database
+            .get(session_id.as_bytes())
+            .chain_err(|| format!("could not read from checkpoint database"))?;
+        match data {
+            Some(data) if data.len() == 8 => {
+                let mut cycle = [0u8; 8];
+                cycle.copy_from_slice(&data);
+                Ok(Some(u64::from_be_bytes(cycle)))
+            }
+            Some(data) => Err(Error::from(format!(
+                "checkpoint of {} should have 8 bytes, got {}",
+                session_id,
+                data.len()
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// Records a checkpoint of a session, unless a later one is recorded
+    /// already. Returns whether it was recorded.
+    pub fn record(&self, session_id: &str, cycle: u64) -> Result<bool> {
+        if self
+            .latest(session_id)?
+            .map_or(false, |latest| latest >= cycle)
+        {
+            return Ok(false);
+        }
+        self.database
+            .put(session_id.as_bytes(), &cycle.to_be_bytes())
+            .chain_err(|| format!("could not write to checkpoint database"))?;
+        Ok(true)
+    }
+
+    /// Forgets the checkpoints of a session that is no longer needed
+    pub fn remove(&self, session_id: &str) -> Result<()> {
+        self.database
+            .delete(session_id.as_bytes())
+            .chain_err(|| format!("could not delete from checkpoint database"))
+    }
+
+    /// Writes the checkpoints still buffered to disk
+    pub fn flush(&self) -> Result<()> {
+        self.database
+            .flush()
+            .chain_err(|| format!("could not flush checkpoint database"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn only_later_checkpoints_are_kept() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let checkpoints =
+            CheckpointStore::open(Storage::LevelDb, &dir.join("checkpoint_db"));
+
+        assert_eq!(checkpoints.latest("s1").unwrap(), None);
+        assert!(checkpoints.record("s1", 1_000_000).unwrap());
+        assert!(checkpoints.record("s1", 2_000_000).unwrap());
+        // a late report of an earlier checkpoint does not move it back
+        assert!(!checkpoints.record("s1", 1_000_000).unwrap());
+        assert_eq!(checkpoints.latest("s1").unwrap(), Some(2_000_000));
+        assert_eq!(checkpoints.latest("s2").unwrap(), None);
+
+        checkpoints.remove("s1").unwrap();
+        assert_eq!(checkpoints.latest("s1").unwrap(), None);
+    }
+}
@@ -0,0 +1,214 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! What a dapp can reach while reacting to an instance: the archive, the
+//! chain, the state of instances, the grpc clients of the services, like the machine manager, the
+//! clock and a view of the configuration. The dispatcher builds a context
+//! for each reaction from the services it shares with the dapp.
+
+use super::configuration::{
+    self, Concern, ConcernRole, Configuration, MachineBackend, MachineTemplate,
+};
+use super::dapp::Archive;
+use super::deadline::{Clock, Deadline};
+use super::error::*;
+use super::ethereum_types::U256;
+use super::state::StateReader;
+use super::utils::chain::ChainReader;
+use super::HashMap;
+use grpc::Client;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// The part of the configuration that dapps may read, leaving out keys
+/// and other secrets
+#[derive(Clone, Debug)]
+pub struct ConfigView {
+    pub main_concern: Concern,
+    pub contracts: HashMap<String, Concern>,
+    pub machines: HashMap<Concern, MachineTemplate>,
+    pub machine_backends: HashMap<Concern, MachineBackend>,
+    pub working_path: PathBuf,
+    pub testing: bool,
+    pub chain_id: u64,
+    pub roles: HashMap<Concern, ConcernRole>,
+    /// Whether the dispatcher never sends transactions
+    pub observer: bool,
+    /// Value at stake from which a dispute is of high value, in wei
+    pub high_stake_value: Option<U256>,
+    /// Name of the network of the concerns not on the one of url
+    pub concern_networks: HashMap<Concern, String>,
+}
+
+impl ConfigView {
+    pub fn of(config: &Configuration) -> Self {
+        ConfigView {
+            main_concern: config.main_concern,
+            contracts: config.contracts.clone(),
+            machines: config.machines.clone(),
+            machine_backends: config.machine_backends.clone(),
+            working_path: config.working_path.clone(),
+            testing: config.testing,
+            chain_id: config.chain_id,
+            roles: config.roles.clone(),
+            observer: config.observer,
+            high_stake_value: config.high_stake_value,
+            concern_networks: config.concern_networks.clone(),
+        }
+    }
+
+    /// Name of the network of a concern, none for the one of url
+    pub fn network_of(&self, concern: &Concern) -> Option<&String> {
+        self.concern_networks.get(concern)
+    }
+
+    /// Name of the service running the machines of a concern, to which
+    /// the dapp addresses its requests about them
+    pub fn machine_service_of(&self, concern: &Concern) -> String {
+        configuration::machine_service_of(&self.machine_backends, concern)
+    }
+
+    /// Whether requests to a service are machine runs, driven a bounded
+    /// number at a time
+    pub fn is_machine_service(&self, service: &str) -> bool {
+        service == configuration::DEFAULT_MACHINE_SERVICE
+            || self
+                .machine_backends
+                .keys()
+                .any(|concern| self.machine_service_of(concern) == service)
+    }
+
+    /// Whether a dispute with the given value at stake is of high value,
+    /// none being when no value makes one
+    pub fn is_high_stake(&self, stake: Option<U256>) -> bool {
+        match (stake, self.high_stake_value) {
+            (Some(stake), Some(threshold)) => stake >= threshold,
+            _ => false,
+        }
+    }
+
+    /// What the dispatcher does with a concern
+    pub fn role_of(&self, concern: &Concern) -> ConcernRole {
+        self.roles
+            .get(concern)
+            .cloned()
+            .unwrap_or(ConcernRole::default_for(*concern == self.main_concern))
+    }
+}
+
+/// The node of a network other than the one of url, with the clock
+/// following its blocks
+#[derive(Clone)]
+pub struct NetworkServices {
+    pub chain: Arc<Mutex<dyn ChainReader>>,
+    pub clock: Arc<dyn Clock>,
+}
+
+/// The services shared with dapps, from which the context of each
+/// reaction is made. The chain and the clock are those of the node of
+/// url, the ones of the other networks are kept by name.
+#[derive(Clone)]
+pub struct DAppServices {
+    pub chain: Arc<Mutex<dyn ChainReader>>,
+    pub state: Arc<Mutex<dyn StateReader>>,
+    pub clients: Arc<Mutex<HashMap<String, Arc<Mutex<Client>>>>>,
+    pub clock: Arc<dyn Clock>,
+    pub config: Arc<ConfigView>,
+    pub networks: Arc<HashMap<String, NetworkServices>>,
+}
+
+impl DAppServices {
+    /// The same services, reading the chain and the clock of the network
+    /// a concern is deployed on
+    pub fn for_concern(&self, concern: &Concern) -> DAppServices {
+        let mut services = self.clone();
+        let network = self
+            .config
+            .network_of(concern)
+            .and_then(|name| self.networks.get(name));
+        if let Some(network) = network {
+            services.chain = network.chain.clone();
+            services.clock = network.clock.clone();
+        }
+        services
+    }
+
+    /// Context of a reaction reading the given archive
+    pub fn context<'a>(&'a self, archive: &'a Archive) -> DAppContext<'a> {
+        DAppContext {
+            archive: archive,
+            services: self,
+        }
+    }
+}
+
+/// Everything handed to a dapp when it reacts to an instance
+pub struct DAppContext<'a> {
+    archive: &'a Archive,
+    services: &'a DAppServices,
+}
+
+impl<'a> DAppContext<'a> {
+    pub fn archive(&self) -> &Archive {
+        self.archive
+    }
+
+    pub fn chain(&self) -> Arc<Mutex<dyn ChainReader>> {
+        self.services.chain.clone()
+    }
+
+    /// Reader of the state of instances, also at past blocks
+    pub fn state(&self) -> Arc<Mutex<dyn StateReader>> {
+        self.services.state.clone()
+    }
+
+    /// The grpc client of a service, like the machine manager
+    pub fn client(&self, service: &str) -> Result<Arc<Mutex<Client>>> {
+        self.services
+            .clients
+            .lock()
+            .unwrap()
+            .get(service)
+            .cloned()
+            .ok_or(Error::from(format!(
+                "no grpc client for {} service",
+                service
+            )))
+    }
+
+    /// Time of the latest block seen by the dispatcher, to compare
+    /// deadlines with, and how long ago it was seen
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.services.clock.clone()
+    }
+
+    pub fn config(&self) -> &ConfigView {
+        &self.services.config
+    }
+
+    /// Deadline checker following the latest block seen by the dispatcher
+    pub fn deadline(&self) -> Deadline {
+        Deadline::new(self.services.clock.clone())
+    }
+}
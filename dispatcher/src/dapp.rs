@@ -22,29 +22,444 @@
 // be used independently under the Apache v2 license. After this component is
 // rewritten, the entire component will be released under the Apache v2 license.
 
+use super::checkpoint::CheckpointStore;
+use super::configuration::{Concern, ConcernLabels};
+use super::context::DAppContext;
+use super::deadline::{ChainClock, Clock, Deadline};
 use super::error::*;
 use super::ethereum_types::{Address, H256, U256};
+use super::hash_cache::HashCache;
+use super::notify::{Alert, Notifier};
 use super::serde::de::Error as SerdeError;
-use super::serde::{Deserialize, Deserializer};
+use super::serde::{Deserialize, Deserializer, Serialize, Serializer};
+use super::session::{SessionKey, SessionStore};
 use super::state::ServiceStatus;
-use super::transaction::TransactionRequest;
+use super::transaction::{CallParams, TransactionRequest};
+use super::utils::merkle::MerkleProof;
 use super::HashMap;
+use std::any::Any;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
 
-/// The total archive, for each machine session
+/// The responses and service statuses kept by an archive, as exported in
+/// snapshots
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ArchiveEntries {
+    pub responses: HashMap<String, std::result::Result<Vec<u8>, String>>,
+    pub services: HashMap<String, ServiceStatus>,
+}
+
+/// A request a dapp expects to make in a later reaction, like the hash of
+/// a machine at a time point of the next partition round, sent ahead of
+/// time so that its response is ready by then
+#[derive(Clone, Debug)]
+pub struct Prefetch {
+    pub service: String,
+    pub key: String,
+    pub method: String,
+    pub request: Vec<u8>,
+}
+
+// requests queued by dapps and those sent whose response is still missing
+#[derive(Default)]
+struct Prefetches {
+    queued: Vec<Prefetch>,
+    fetching: HashSet<String>,
+}
+
+// size and last use of a response, and the instance it was fetched for
+struct ResponseUsage {
+    size: usize,
+    last_used: u64,
+    owner: Option<(Concern, usize)>,
+}
+
+// what is needed to evict the least recently used responses, updated as
+// dapps read the archive
+#[derive(Default)]
+struct ArchiveUsage {
+    uses: u64,
+    total_size: usize,
+    responses: HashMap<String, ResponseUsage>,
+    active: HashSet<(Concern, usize)>,
+}
+
+impl ArchiveUsage {
+    fn touch(&mut self, key: &str) {
+        self.uses += 1;
+        if let Some(usage) = self.responses.get_mut(key) {
+            usage.last_used = self.uses;
+        }
+    }
+
+    fn inserted(
+        &mut self,
+        key: &str,
+        size: usize,
+        owner: Option<(Concern, usize)>,
+    ) {
+        self.removed(key);
+        self.uses += 1;
+        self.total_size += size;
+        self.responses.insert(
+            String::from(key),
+            ResponseUsage {
+                size: size,
+                last_used: self.uses,
+                owner: owner,
+            },
+        );
+    }
+
+    fn removed(&mut self, key: &str) {
+        if let Some(usage) = self.responses.remove(key) {
+            self.total_size -= usage.size;
+        }
+    }
+
+    // least recently used response that no active instance owns
+    fn eviction_candidate(&self) -> Option<String> {
+        self.responses
+            .iter()
+            .filter(|(_, usage)| match usage.owner {
+                Some(owner) => !self.active.contains(&owner),
+                None => true,
+            })
+            .min_by_key(|(_, usage)| usage.last_used)
+            .map(|(key, _)| key.clone())
+    }
+}
+
+fn response_size(
+    key: &str,
+    response: &std::result::Result<Vec<u8>, String>,
+) -> usize {
+    key.len()
+        + match response {
+            Ok(bytes) => bytes.len(),
+            Err(message) => message.len(),
+        }
+}
+
+/// The total archive, for each machine session. When given a cache size,
+/// the least recently used responses are evicted once they take more
+/// bytes than that, except those fetched for instances still active, and
+/// are fetched again from their service if needed.
 pub struct Archive {
     response_cache: HashMap<String, std::result::Result<Vec<u8>, String>>,
     service_status: HashMap<String, ServiceStatus>,
+    clock: Arc<dyn Clock>,
+    sessions: Option<Arc<SessionStore>>,
+    // instances whose session was recorded before the dispatcher restarted
+    restored_sessions: Mutex<HashSet<(Concern, U256)>>,
+    checkpoints: Option<Arc<CheckpointStore>>,
+    hashes: Option<Arc<HashCache>>,
+    // timestamps of the deadlines reported by dapps, for each instance
+    deadlines: Mutex<HashMap<(Concern, usize), u64>>,
+    // value at stake in each instance, in wei
+    stakes: Mutex<HashMap<(Concern, usize), U256>>,
+    prefetches: Mutex<Prefetches>,
+    notifier: Option<Arc<dyn Notifier>>,
+    cache_size: Option<usize>,
+    usage: Mutex<ArchiveUsage>,
 }
 
 impl Archive {
     /// Creates a NewArchive
     pub fn new() -> Result<Archive> {
+        Archive::with_clock(Arc::new(ChainClock::new()))
+    }
+
+    /// Creates a NewArchive whose deadlines follow the given clock
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Result<Archive> {
         Ok(Archive {
             response_cache: HashMap::new(),
             service_status: HashMap::new(),
+            clock: clock,
+            sessions: None,
+            restored_sessions: Mutex::new(HashSet::new()),
+            checkpoints: None,
+            hashes: None,
+            deadlines: Mutex::new(HashMap::new()),
+            stakes: Mutex::new(HashMap::new()),
+            prefetches: Mutex::new(Prefetches::default()),
+            notifier: None,
+            cache_size: None,
+            usage: Mutex::new(ArchiveUsage::default()),
         })
     }
 
+    /// Bounds the bytes taken by responses, unbounded if none
+    pub fn set_cache_size(&mut self, cache_size: Option<usize>) {
+        self.cache_size = cache_size;
+        self.evict();
+    }
+
+    /// Active instances of a concern, whose responses are not evicted
+    pub fn set_active(&mut self, concern: Concern, indices: &[usize]) {
+        {
+            let mut usage = self.usage.lock().unwrap();
+            usage.active.retain(|(c, _)| *c != concern);
+            usage
+                .active
+                .extend(indices.iter().map(|index| (concern, *index)));
+        }
+        self.deadlines
+            .lock()
+            .unwrap()
+            .retain(|(c, index), _| *c != concern || indices.contains(index));
+        self.stakes
+            .lock()
+            .unwrap()
+            .retain(|(c, index), _| *c != concern || indices.contains(index));
+        self.evict();
+    }
+
+    /// Bytes taken by the responses kept
+    pub fn size(&self) -> usize {
+        self.usage.lock().unwrap().total_size
+    }
+
+    fn evict(&mut self) {
+        let cache_size = match self.cache_size {
+            Some(cache_size) => cache_size,
+            None => return,
+        };
+        let mut usage = self.usage.lock().unwrap();
+        while usage.total_size > cache_size {
+            let key = match usage.eviction_candidate() {
+                Some(key) => key,
+                None => {
+                    warn!(
+                        "Archive takes {} bytes, over its cache size of {}, \
+                         but all responses belong to active instances",
+                        usage.total_size, cache_size
+                    );
+                    return;
+                }
+            };
+            trace!("Evicting response {} from the archive", key);
+            usage.removed(&key);
+            self.response_cache.remove(&key);
+        }
+    }
+
+    /// Attaches the persistent store of machine manager sessions
+    pub fn set_session_store(&mut self, sessions: Arc<SessionStore>) {
+        self.sessions = Some(sessions);
+    }
+
+    /// Attaches the persistent store of session checkpoints
+    pub fn set_checkpoint_store(&mut self, checkpoints: Arc<CheckpointStore>) {
+        self.checkpoints = Some(checkpoints);
+    }
+
+    /// Attaches the persistent cache of machine hashes
+    pub fn set_hash_cache(&mut self, hashes: Arc<HashCache>) {
+        self.hashes = Some(hashes);
+    }
+
+    /// Attaches the notifier that alerts raised by DApps are sent to
+    pub fn set_notifier(&mut self, notifier: Arc<dyn Notifier>) {
+        self.notifier = Some(notifier);
+    }
+
+    /// Tells the operator about something only the DApp understands, like
+    /// a divergence found or a timeout that can be claimed
+    pub fn alert(&self, alert: Alert) {
+        match &self.notifier {
+            Some(notifier) => notifier.notify(&alert),
+            None => warn!("Alert: {}", alert.summary(&ConcernLabels::new())),
+        }
+    }
+
+    /// The machine manager session of an instance, re-attaching to the
+    /// one recorded before a restart, or recording a new one
+    pub fn get_session(&self, concern: Concern, index: U256) -> Result<String> {
+        match &self.sessions {
+            Some(sessions) => sessions.get_or_create(concern, index),
+            None => Err(Error::from("no session store attached to archive")),
+        }
+    }
+
+    /// Every recorded session, by instance
+    pub fn sessions(&self) -> Result<Vec<(SessionKey, String)>> {
+        match &self.sessions {
+            Some(sessions) => sessions.list(),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Marks the session of an instance as recorded before a restart, to
+    /// be re-attached to
+    pub fn restore_session(&self, concern: Concern, index: U256) {
+        self.restored_sessions
+            .lock()
+            .unwrap()
+            .insert((concern, index));
+    }
+
+    /// Whether the session of an instance was recorded before the
+    /// dispatcher restarted. The machine manager may have lost it since,
+    /// so the dapp creates it again under the same id, resuming from its
+    /// `last_checkpoint`.
+    pub fn is_restored_session(&self, concern: Concern, index: U256) -> bool {
+        self.restored_sessions
+            .lock()
+            .unwrap()
+            .contains(&(concern, index))
+    }
+
+    /// Forgets the session of an instance that is no longer active, with
+    /// its checkpoints
+    pub fn remove_session(&self, concern: Concern, index: U256) -> Result<()> {
+        self.restored_sessions
+            .lock()
+            .unwrap()
+            .remove(&(concern, index));
+        let sessions = match &self.sessions {
+            Some(sessions) => sessions,
+            None => return Ok(()),
+        };
+        if let (Some(checkpoints), Some(session_id)) =
+            (&self.checkpoints, sessions.get(concern, index)?)
+        {
+            checkpoints.remove(&session_id)?;
+        }
+        sessions.remove(concern, index)
+    }
+
+    /// Forgets the sessions of the instances of a contract, whatever their
+    /// user, with their checkpoints, returning how many there were
+    pub fn remove_contract_sessions(
+        &self,
+        contract_address: Address,
+    ) -> Result<usize> {
+        self.restored_sessions
+            .lock()
+            .unwrap()
+            .retain(|(concern, _)| {
+                concern.contract_address != contract_address
+            });
+        let sessions = match &self.sessions {
+            Some(sessions) => sessions,
+            None => return Ok(0),
+        };
+        let removed = sessions.remove_contract(contract_address)?;
+        if let Some(checkpoints) = &self.checkpoints {
+            for session_id in removed.iter() {
+                checkpoints.remove(session_id)?;
+            }
+        }
+        Ok(removed.len())
+    }
+
+    /// Records the cycle of a checkpoint the machine manager persisted for
+    /// the session of an instance, keeping the latest one
+    pub fn record_checkpoint(
+        &self,
+        concern: Concern,
+        index: U256,
+        cycle: u64,
+    ) -> Result<()> {
+        let session_id = self.get_session(concern, index)?;
+        match &self.checkpoints {
+            Some(checkpoints) => {
+                if checkpoints.record(&session_id, cycle)? {
+                    trace!("Session {} checkpointed at {}", session_id, cycle);
+                }
+                Ok(())
+            }
+            None => Err(Error::from("no checkpoint store attached to archive")),
+        }
+    }
+
+    /// Cycle from which the run of the session of an instance resumes: its
+    /// latest checkpoint, if any
+    pub fn last_checkpoint(
+        &self,
+        concern: Concern,
+        index: U256,
+    ) -> Result<Option<u64>> {
+        let session_id = self.get_session(concern, index)?;
+        match &self.checkpoints {
+            Some(checkpoints) => checkpoints.latest(&session_id),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes the sessions and checkpoints still buffered to disk, before
+    /// the dispatcher exits
+    pub fn flush(&self) -> Result<()> {
+        if let Some(sessions) = &self.sessions {
+            sessions.flush()?;
+        }
+        if let Some(checkpoints) = &self.checkpoints {
+            checkpoints.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Hash of a machine, as identified by `MachineTemplate::id`, at the
+    /// given cycle, if it was computed before by any instance
+    pub fn cached_hash(
+        &self,
+        machine: H256,
+        cycle: u64,
+    ) -> Result<Option<H256>> {
+        match &self.hashes {
+            Some(hashes) => hashes.get(machine, cycle),
+            None => Ok(None),
+        }
+    }
+
+    /// Keeps the hash of a machine at the given cycle, for the rounds and
+    /// disputes asking for it later
+    pub fn cache_hash(
+        &self,
+        machine: H256,
+        cycle: u64,
+        hash: H256,
+    ) -> Result<()> {
+        match &self.hashes {
+            Some(hashes) => hashes.put(machine, cycle, hash),
+            None => Ok(()),
+        }
+    }
+
+    /// Deadline checker following the latest block seen by the dispatcher
+    pub fn deadline(&self) -> Deadline {
+        Deadline::new(self.clock.clone())
+    }
+
+    /// Records the timestamp by which an instance has to act on chain, so
+    /// that its machine runs are driven before those of other instances
+    pub fn report_deadline(&self, concern: Concern, index: usize, at: u64) {
+        self.deadlines.lock().unwrap().insert((concern, index), at);
+    }
+
+    /// Timestamp of the deadline last reported for an active instance
+    pub fn deadline_of(&self, concern: Concern, index: usize) -> Option<u64> {
+        self.deadlines
+            .lock()
+            .unwrap()
+            .get(&(concern, index))
+            .cloned()
+    }
+
+    /// Records the value at stake in an instance, in wei, so that high
+    /// value disputes react and run their machines first
+    pub fn report_stake(&self, concern: Concern, index: usize, value: U256) {
+        self.stakes.lock().unwrap().insert((concern, index), value);
+    }
+
+    /// Value at stake last reported for an active instance
+    pub fn stake_of(&self, concern: Concern, index: usize) -> Option<U256> {
+        self.stakes.lock().unwrap().get(&(concern, index)).cloned()
+    }
+
     pub fn get_response(
         &self,
         service: String,
@@ -52,6 +467,7 @@ impl Archive {
         method: String,
         request: Vec<u8>,
     ) -> Result<Vec<u8>> {
+        self.usage.lock().unwrap().touch(&key);
         match self.response_cache.get(&key) {
             Some(response) => match response {
                 Ok(s) => Ok(s.to_vec()),
@@ -65,6 +481,40 @@ impl Archive {
         }
     }
 
+    /// The Merkle proof in the response of a machine service, decoded by
+    /// `decode` and verified against the root hash the dapp expects, like
+    /// the one committed on-chain. A proof that does not lead to it is
+    /// reported as invalid, so that the dispatcher drops it and requests
+    /// it again, instead of it being submitted in a transaction that would
+    /// revert.
+    pub fn get_proof<F>(
+        &self,
+        service: String,
+        key: String,
+        method: String,
+        request: Vec<u8>,
+        expected_root: &H256,
+        decode: F,
+    ) -> Result<MerkleProof>
+    where
+        F: FnOnce(&[u8]) -> Result<MerkleProof>,
+    {
+        let response = self.get_response(
+            service.clone(),
+            key.clone(),
+            method.clone(),
+            request,
+        )?;
+        let verified = decode(&response).and_then(|proof| {
+            proof.verify_against(expected_root)?;
+            Ok(proof)
+        });
+        verified.map_err(|e| {
+            warn!("Rejecting proof from {} for key {}: {}", service, key, e);
+            Error::from(ErrorKind::ResponseInvalidError(service, key, method))
+        })
+    }
+
     pub fn get_service(&self, key: String) -> ServiceStatus {
         self.service_status
             .get(&key)
@@ -83,7 +533,26 @@ impl Archive {
         key: String,
         response: std::result::Result<Vec<u8>, String>,
     ) -> Option<std::result::Result<Vec<u8>, String>> {
-        self.response_cache.insert(key, response)
+        self.insert_response_for(key, response, None)
+    }
+
+    /// Inserts a response fetched for an instance, kept while the
+    /// instance is active
+    pub fn insert_response_for(
+        &mut self,
+        key: String,
+        response: std::result::Result<Vec<u8>, String>,
+        owner: Option<(Concern, usize)>,
+    ) -> Option<std::result::Result<Vec<u8>, String>> {
+        self.prefetches.lock().unwrap().fetching.remove(&key);
+        self.usage.lock().unwrap().inserted(
+            &key,
+            response_size(&key, &response),
+            owner,
+        );
+        let previous = self.response_cache.insert(key, response);
+        self.evict();
+        previous
     }
 
     pub fn insert_service(
@@ -95,8 +564,74 @@ impl Archive {
     }
 
     pub fn remove_response(&mut self, key: String) {
+        self.usage.lock().unwrap().removed(&key);
         self.response_cache.remove(&key);
     }
+
+    /// Asks for a response before the dapp needs it, which the dispatcher
+    /// fetches in the background when precomputing is enabled. Requests
+    /// already answered or on their way are left out.
+    pub fn prefetch(
+        &self,
+        service: String,
+        key: String,
+        method: String,
+        request: Vec<u8>,
+    ) {
+        if self.response_cache.contains_key(&key) {
+            return;
+        }
+        let mut prefetches = self.prefetches.lock().unwrap();
+        if prefetches.fetching.contains(&key)
+            || prefetches.queued.iter().any(|p| p.key == key)
+        {
+            return;
+        }
+        prefetches.queued.push(Prefetch {
+            service: service,
+            key: key,
+            method: method,
+            request: request,
+        });
+    }
+
+    /// Takes the requests queued by dapps, marking them on their way until
+    /// their response is inserted
+    pub fn take_prefetches(&self) -> Vec<Prefetch> {
+        let mut prefetches = self.prefetches.lock().unwrap();
+        let queued = std::mem::replace(&mut prefetches.queued, vec![]);
+        prefetches
+            .fetching
+            .extend(queued.iter().map(|p| p.key.clone()));
+        queued
+    }
+
+    /// Whether the response of a prefetched request is still on its way,
+    /// so that it is not asked for again
+    pub fn is_fetching(&self, key: &str) -> bool {
+        self.prefetches.lock().unwrap().fetching.contains(key)
+    }
+
+    /// Forgets a prefetched request that failed, to be asked for again
+    pub fn fetch_failed(&self, key: &str) {
+        self.prefetches.lock().unwrap().fetching.remove(key);
+    }
+
+    /// Copy of the responses and service statuses kept so far
+    pub fn entries(&self) -> ArchiveEntries {
+        ArchiveEntries {
+            responses: self.response_cache.clone(),
+            services: self.service_status.clone(),
+        }
+    }
+
+    /// Adds previously exported entries, replacing those with the same key
+    pub fn restore(&mut self, entries: ArchiveEntries) {
+        for (key, response) in entries.responses {
+            self.insert_response(key, response);
+        }
+        self.service_status.extend(entries.services);
+    }
 }
 
 // !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
@@ -109,30 +644,374 @@ impl Archive {
 /// . Request the machine to run and log the hashes to archive
 /// . Request the machine to give one logged step and save it to archive
 /// . Submit a transaction to the blockchain
+/// . Submit several transactions, in order: each is sent once the previous
+///   one was accepted by the node, so those signed by the same account are
+///   mined in the given order. If one of them is not sent, the following
+///   ones are dropped and the dapp is asked again on a later poll.
 /// . Idle and do nothing
+/// . Idle until the given timestamp, so the dispatcher can skip the
+///   instance until then
 #[derive(Debug)]
 pub enum Reaction {
     Transaction(TransactionRequest),
+    Transactions(Vec<TransactionRequest>),
     Terminate,
     Idle,
+    IdleUntil(U256),
+}
+
+/// A reaction as structured data, recorded in the history of instances
+/// and in the reaction streams of dry runs
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ReactionRecord {
+    /// The transactions requested, in the order they are sent
+    Transactions {
+        calls: Vec<CallRecord>,
+    },
+    Terminate,
+    Idle,
+    IdleUntil {
+        timestamp: U256,
+    },
+}
+
+/// A transaction requested by a dapp
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CallRecord {
+    pub concern: Concern,
+    pub contract_name: Option<String>,
+    pub function: String,
+    pub params: CallParams,
+    pub value: U256,
+    pub gas: Option<U256>,
+    pub strategy: String,
+    pub deadline_block: Option<u64>,
+    pub deadline_timestamp: Option<u64>,
+    pub stake_value: Option<U256>,
+}
+
+impl<'a> From<&'a TransactionRequest> for CallRecord {
+    fn from(request: &'a TransactionRequest) -> Self {
+        CallRecord {
+            concern: request.concern,
+            contract_name: request.contract_name.clone(),
+            function: request.function.clone(),
+            params: request.data.clone(),
+            value: request.value,
+            gas: request.gas,
+            strategy: format!("{:?}", request.strategy),
+            deadline_block: request.deadline_block,
+            deadline_timestamp: request.deadline_timestamp,
+            stake_value: request.stake_value,
+        }
+    }
 }
 
-pub trait DApp<T> {
+impl Reaction {
+    /// The reaction as structured data
+    pub fn record(&self) -> ReactionRecord {
+        match self {
+            Reaction::Transaction(request) => ReactionRecord::Transactions {
+                calls: vec![CallRecord::from(request)],
+            },
+            Reaction::Transactions(requests) => ReactionRecord::Transactions {
+                calls: requests.iter().map(CallRecord::from).collect(),
+            },
+            Reaction::Terminate => ReactionRecord::Terminate,
+            Reaction::Idle => ReactionRecord::Idle,
+            Reaction::IdleUntil(timestamp) => ReactionRecord::IdleUntil {
+                timestamp: *timestamp,
+            },
+        }
+    }
+}
+
+/// The logic of a dapp for the instances of a contract. The state of an
+/// instance is parsed once into the dapp's own context, which is then used
+/// to react and to prettify, and which parent dapps may parse to pick the
+/// parameters they give to the dapps of their sub-instances. The
+/// sub-instances are parsed for the sub-dapps the dapp declares, and
+/// handed to it along with its own context.
+pub trait DApp {
+    /// Given by the parent dapp, the dispatcher gives `()` to the dapp of
+    /// the main concern
+    type Params;
+    /// The state of an instance as the dapp understands it
+    type Ctx;
+    /// The dapps of the sub-instances, a tuple of `SubDApp` or `()` for a
+    /// dapp without sub-instances
+    type Subs: SubDApps;
+
+    /// Parses the state of an instance, usually with `parse_state`
+    fn parse(instance: &state::Instance) -> Result<Self::Ctx>;
+
     /// The function that makes a certain dapp react to the state of the
     /// instance
     fn react(
-        &state::Instance,
-        &Archive,
-        &Option<String>,
-        &T,
+        instance: &state::Instance,
+        ctx: &Self::Ctx,
+        subs: &<Self::Subs as SubDApps>::Ctx,
+        params: &Self::Params,
+        context: &DAppContext,
+        post_action: &Option<String>,
     ) -> Result<Reaction>;
+
     fn get_pretty_instance(
-        &state::Instance,
-        &Archive,
-        &T,
+        instance: &state::Instance,
+        ctx: &Self::Ctx,
+        subs: &<Self::Subs as SubDApps>::Ctx,
+        params: &Self::Params,
+        context: &DAppContext,
     ) -> Result<state::Instance>;
 }
 
+/// The sub-instances of the dapp `D`, as parsed for its sub-dapps
+pub type SubsOf<D> = <<D as DApp>::Subs as SubDApps>::Ctx;
+
+/// Declares the dapp handling the sub-instances created by a contract,
+/// named by the stem of its ABI file, like `PartitionInstantiator`
+pub trait SubDApp {
+    const CONTRACT: &'static str;
+    type DApp: DApp;
+}
+
+/// The sub-dapps of a dapp: `()` when it has none, or a tuple of up to
+/// four `SubDApp`, whose sub-instances are resolved in the same order
+pub trait SubDApps {
+    /// A tuple holding the sub-instance of each sub-dapp, if created yet
+    type Ctx;
+
+    /// Looks up the sub-instance of each sub-dapp and parses it, along
+    /// with its own sub-instances, for that sub-dapp
+    fn resolve(instance: &state::Instance) -> Result<Self::Ctx>;
+}
+
+impl SubDApps for () {
+    type Ctx = ();
+
+    fn resolve(_instance: &state::Instance) -> Result<()> {
+        Ok(())
+    }
+}
+
+macro_rules! sub_dapps {
+    ($($sub:ident),+) => {
+        impl<$($sub: SubDApp),+> SubDApps for ($($sub,)+) {
+            type Ctx = ($(Option<Sub<<$sub as SubDApp>::DApp>>,)+);
+
+            fn resolve(instance: &state::Instance) -> Result<Self::Ctx> {
+                Ok(($(Sub::< <$sub as SubDApp>::DApp>::resolve(
+                    instance,
+                    <$sub as SubDApp>::CONTRACT,
+                )?,)+))
+            }
+        }
+    };
+}
+
+sub_dapps!(A);
+sub_dapps!(A, B);
+sub_dapps!(A, B, C);
+sub_dapps!(A, B, C, D);
+
+/// A sub-instance parsed by the dapp `D` handling it, which the parent
+/// dapp routes its reactions to
+pub struct Sub<D: DApp> {
+    pub instance: state::Instance,
+    pub ctx: D::Ctx,
+    pub subs: SubsOf<D>,
+}
+
+impl<D: DApp> Sub<D> {
+    /// Parses the sub-instance created by the contract named `contract`,
+    /// none if the parent did not create it yet
+    pub fn resolve(
+        instance: &state::Instance,
+        contract: &str,
+    ) -> Result<Option<Sub<D>>> {
+        let sub_instance = match SubInstances::of(instance).find(contract) {
+            Some(sub_instance) => sub_instance,
+            None => return Ok(None),
+        };
+        let parsed = D::parse(sub_instance).and_then(|ctx| {
+            Ok(Sub {
+                instance: sub_instance.clone(),
+                ctx: ctx,
+                subs: <D::Subs as SubDApps>::resolve(sub_instance)?,
+            })
+        });
+        parsed.map(Some).chain_err(|| {
+            format!(
+                "could not parse {} sub-instance of instance {}",
+                contract, instance.index
+            )
+        })
+    }
+
+    /// Lets the dapp of the sub-instance react to it
+    pub fn react(
+        &self,
+        params: &D::Params,
+        context: &DAppContext,
+        post_action: &Option<String>,
+    ) -> Result<Reaction> {
+        D::react(
+            &self.instance,
+            &self.ctx,
+            &self.subs,
+            params,
+            context,
+            post_action,
+        )
+        .chain_err(|| {
+            format!("could not react to {} sub-instance", self.instance.name)
+        })
+    }
+
+    /// Prettifies the sub-instance with its dapp
+    pub fn get_pretty_instance(
+        &self,
+        params: &D::Params,
+        context: &DAppContext,
+    ) -> Result<state::Instance> {
+        D::get_pretty_instance(
+            &self.instance,
+            &self.ctx,
+            &self.subs,
+            params,
+            context,
+        )
+        .chain_err(|| {
+            format!("could not prettify {} sub-instance", self.instance.name)
+        })
+    }
+}
+
+/// Parses the instance and its sub-instances for the dapp `D` and lets it
+/// react
+pub fn react<D: DApp>(
+    instance: &state::Instance,
+    context: &DAppContext,
+    post_action: &Option<String>,
+    params: &D::Params,
+) -> Result<Reaction> {
+    let ctx = D::parse(instance)?;
+    let subs = <D::Subs as SubDApps>::resolve(instance)?;
+    D::react(instance, &ctx, &subs, params, context, post_action)
+}
+
+/// Like `react`, but a panic of the dapp, like an unwrap of malformed
+/// state, is turned into an error attributed to the concern of the
+/// instance, so that the other concerns keep running
+pub fn react_guarded<D: DApp>(
+    instance: &state::Instance,
+    context: &DAppContext,
+    post_action: &Option<String>,
+    params: &D::Params,
+) -> Result<Reaction> {
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        react::<D>(instance, context, post_action, params)
+    }))
+    .unwrap_or_else(|payload| {
+        Err(Error::from(ErrorKind::DAppPanicked(
+            format!("{}", instance.concern),
+            format!(
+                "instance {}: {}",
+                instance.index,
+                panic_message(payload.as_ref())
+            ),
+        )))
+    })
+}
+
+// what was given to `panic!`, which is a string unless panicking with
+// `std::panic::panic_any`
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => String::from(*message),
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => String::from("(no message)"),
+        },
+    }
+}
+
+/// Parses the instance and its sub-instances for the dapp `D` and
+/// prettifies it
+pub fn get_pretty_instance<D: DApp>(
+    instance: &state::Instance,
+    context: &DAppContext,
+    params: &D::Params,
+) -> Result<state::Instance> {
+    let ctx = D::parse(instance)?;
+    let subs = <D::Subs as SubDApps>::resolve(instance)?;
+    D::get_pretty_instance(instance, &ctx, &subs, params, context)
+}
+
+/// The sub-instances of an instance, looked up by the name of the
+/// contract that created them (the stem of its ABI file). Sub-dapps are
+/// resolved through it, and dapps may use it for sub-instances they do not
+/// declare.
+pub struct SubInstances<'a> {
+    instance: &'a state::Instance,
+}
+
+impl<'a> SubInstances<'a> {
+    pub fn of(instance: &'a state::Instance) -> Self {
+        SubInstances { instance: instance }
+    }
+
+    /// The sub-instance created by the contract named `name`, if any
+    pub fn find(&self, name: &str) -> Option<&'a state::Instance> {
+        self.instance
+            .sub_instances
+            .iter()
+            .map(|sub_instance| &**sub_instance)
+            .find(|sub_instance| sub_instance.name == name)
+    }
+
+    /// Finds the sub-instance created by the contract named `name`
+    pub fn get(&self, name: &str) -> Result<&'a state::Instance> {
+        self.find(name)
+            .ok_or(Error::from(ErrorKind::ContractStateError(
+                format!("{}", self.instance.concern),
+                format!(
+                    "instance {} of {} has no {} sub-instance",
+                    self.instance.index, self.instance.name, name
+                ),
+            )))
+    }
+
+    /// Whether a sub-instance created by `name` exists
+    pub fn contains(&self, name: &str) -> bool {
+        self.find(name).is_some()
+    }
+
+    /// Lets the dapp `D` react to the sub-instance created by `name`
+    pub fn react<D: DApp>(
+        &self,
+        name: &str,
+        context: &DAppContext,
+        post_action: &Option<String>,
+        params: &D::Params,
+    ) -> Result<Reaction> {
+        react::<D>(self.get(name)?, context, post_action, params)
+            .chain_err(|| format!("could not react to {} sub-instance", name))
+    }
+
+    /// Prettifies the sub-instance created by `name` with the dapp `D`
+    pub fn get_pretty_instance<D: DApp>(
+        &self,
+        name: &str,
+        context: &DAppContext,
+        params: &D::Params,
+    ) -> Result<state::Instance> {
+        get_pretty_instance::<D>(self.get(name)?, context, params)
+            .chain_err(|| format!("could not prettify {} sub-instance", name))
+    }
+}
+
 // !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
 // Do proper serialization/deserialization of these types and
 // share the implementation with that of get_instance in state manager.
@@ -231,6 +1110,109 @@ pub struct BoolArray {
     pub value: Vec<bool>,
 }
 
+/// The types that fixed-size arrays of the state hold, by solidity name
+pub trait ElementType {
+    const NAME: &'static str;
+}
+
+impl ElementType for Address {
+    const NAME: &'static str = "address";
+}
+
+impl ElementType for U256 {
+    const NAME: &'static str = "uint256";
+}
+
+impl ElementType for H256 {
+    const NAME: &'static str = "bytes32";
+}
+
+impl ElementType for bool {
+    const NAME: &'static str = "bool";
+}
+
+/// A fixed-size array of the state, like `uint256[6]`, whose length is
+/// checked when parsed
+pub struct FixedArray<T, const N: usize> {
+    pub name: String,
+    pub ty: String,
+    pub value: [T; N],
+}
+
+pub type AddressFixedArray<const N: usize> = FixedArray<Address, N>;
+pub type U256FixedArray<const N: usize> = FixedArray<U256, N>;
+pub type Bytes32FixedArray<const N: usize> = FixedArray<H256, N>;
+pub type BoolFixedArray<const N: usize> = FixedArray<bool, N>;
+
+impl<T: ElementType, const N: usize> FixedArray<T, N> {
+    /// The solidity type of the array
+    pub fn solidity_type() -> String {
+        format!("{}[{}]", T::NAME, N)
+    }
+}
+
+impl<T: Serialize, const N: usize> Serialize for FixedArray<T, N> {
+    fn serialize<S>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        #[derive(Serialize)]
+        struct Field<'a, T: 'a> {
+            name: &'a str,
+            #[serde(rename = "type")]
+            ty: &'a str,
+            value: &'a [T],
+        }
+        Field {
+            name: &self.name,
+            ty: &self.ty,
+            value: &self.value[..],
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, T, const N: usize> Deserialize<'de> for FixedArray<T, N>
+where
+    T: Deserialize<'de> + ElementType,
+{
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Field<T> {
+            name: String,
+            #[serde(rename = "type")]
+            ty: String,
+            value: Vec<T>,
+        }
+        let field: Field<T> = Field::deserialize(deserializer)?;
+        let expected = Self::solidity_type();
+        if field.ty != expected {
+            return Err(D::Error::custom(format!(
+                "field {} expected {}, got {}",
+                field.name, expected, field.ty
+            )));
+        }
+        let len = field.value.len();
+        let value = <[T; N]>::try_from(field.value).map_err(|_| {
+            D::Error::custom(format!(
+                "field {} expected {} values, got {}",
+                field.name, N, len
+            ))
+        })?;
+        Ok(FixedArray {
+            name: field.name,
+            ty: field.ty,
+            value: value,
+        })
+    }
+}
+
 fn string_from_hex<'de, D>(
     deserializer: D,
 ) -> std::result::Result<String, D::Error>
@@ -276,3 +1258,228 @@ pub struct BytesField {
     #[serde(deserialize_with = "bytes_from_hex")]
     pub value: Vec<u8>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn concern() -> Concern {
+        Concern {
+            contract_address: Address::zero(),
+            user_address: Address::zero(),
+        }
+    }
+
+    fn has(archive: &Archive, key: &str) -> bool {
+        archive
+            .get_response(
+                String::from("emulator"),
+                String::from(key),
+                String::from("Run"),
+                vec![],
+            )
+            .is_ok()
+    }
+
+    // knows the instances of its contract by their state alone
+    struct Leaf();
+
+    impl DApp for Leaf {
+        type Params = ();
+        type Ctx = String;
+        type Subs = ();
+
+        fn parse(instance: &state::Instance) -> Result<String> {
+            Ok(instance.json_data.clone())
+        }
+
+        fn react(
+            _instance: &state::Instance,
+            _ctx: &String,
+            _subs: &(),
+            _params: &(),
+            _context: &DAppContext,
+            _post_action: &Option<String>,
+        ) -> Result<Reaction> {
+            Ok(Reaction::Idle)
+        }
+
+        fn get_pretty_instance(
+            instance: &state::Instance,
+            _ctx: &String,
+            _subs: &(),
+            _params: &(),
+            _context: &DAppContext,
+        ) -> Result<state::Instance> {
+            Ok(instance.clone())
+        }
+    }
+
+    struct LeafOf();
+
+    impl SubDApp for LeafOf {
+        const CONTRACT: &'static str = "Leaf";
+        type DApp = Leaf;
+    }
+
+    struct OtherOf();
+
+    impl SubDApp for OtherOf {
+        const CONTRACT: &'static str = "Other";
+        type DApp = Leaf;
+    }
+
+    fn instance(name: &str, json_data: &str) -> state::Instance {
+        state::Instance {
+            name: String::from(name),
+            concern: concern(),
+            index: U256::zero(),
+            service_status: ServiceStatus {
+                service_name: String::new(),
+                service_method: String::new(),
+                status: 0,
+                progress: 0,
+                description: String::new(),
+            },
+            json_data: String::from(json_data),
+            sub_instances: vec![],
+        }
+    }
+
+    #[test]
+    fn sub_instances_are_parsed_by_the_dapp_of_their_contract() {
+        let mut parent = instance("Parent", "parent");
+        parent
+            .sub_instances
+            .push(Box::new(instance("Other", "other")));
+        parent
+            .sub_instances
+            .push(Box::new(instance("Leaf", "leaf")));
+
+        let (leaf, other) =
+            <(LeafOf, OtherOf) as SubDApps>::resolve(&parent).unwrap();
+        assert_eq!(leaf.unwrap().ctx, "leaf");
+        assert_eq!(other.unwrap().ctx, "other");
+
+        // not created yet
+        parent.sub_instances.clear();
+        let (leaf,) = <(LeafOf,) as SubDApps>::resolve(&parent).unwrap();
+        assert!(leaf.is_none());
+    }
+
+    #[test]
+    fn fixed_arrays_check_their_length() {
+        let json = r#"{ "name": "_values", "type": "uint256[3]",
+                        "value": ["0x1", "0x2", "0x3"] }"#;
+        let array: U256FixedArray<3> = serde_json::from_str(json).unwrap();
+        assert_eq!(array.value[2], U256::from(3));
+        assert!(serde_json::from_str::<U256FixedArray<4>>(json).is_err());
+        assert!(serde_json::from_str::<Bytes32FixedArray<3>>(json).is_err());
+
+        let back = serde_json::to_string(&array).unwrap();
+        assert!(serde_json::from_str::<U256FixedArray<3>>(&back).is_ok());
+    }
+
+    #[test]
+    fn least_recently_used_responses_of_inactive_instances_are_evicted() {
+        let mut archive = Archive::new().unwrap();
+        archive.set_cache_size(Some(30));
+        archive.set_active(concern(), &[0]);
+        archive.insert_response_for(
+            String::from("a"),
+            Ok(vec![0; 9]),
+            Some((concern(), 0)),
+        );
+        archive.insert_response_for(
+            String::from("b"),
+            Ok(vec![0; 9]),
+            Some((concern(), 1)),
+        );
+        archive.insert_response(String::from("c"), Ok(vec![0; 9]));
+        assert_eq!(archive.size(), 30);
+
+        // b is older than c, but c was used since
+        assert!(has(&archive, "c"));
+        archive.insert_response(String::from("d"), Ok(vec![0; 9]));
+        assert!(!has(&archive, "b"));
+        assert!(has(&archive, "c"));
+
+        // the responses of active instances are kept, even if the oldest
+        archive.insert_response(String::from("e"), Ok(vec![0; 19]));
+        assert!(has(&archive, "a"));
+        assert!(!has(&archive, "c"));
+        assert!(!has(&archive, "d"));
+        assert_eq!(archive.size(), 30);
+
+        // once the instance is no longer active, its responses may go
+        archive.set_active(concern(), &[]);
+        archive.insert_response(String::from("f"), Ok(vec![0; 29]));
+        assert!(!has(&archive, "a"));
+        assert_eq!(archive.size(), 30);
+    }
+    #[test]
+    fn proofs_not_leading_to_the_expected_root_are_invalid() {
+        let mut archive = Archive::new().unwrap();
+        archive.insert_response(String::from("proof"), Ok(vec![]));
+        let get_proof = |archive: &Archive, root: H256| {
+            archive.get_proof(
+                String::from("emulator"),
+                String::from("proof"),
+                String::from("GetProof"),
+                vec![],
+                &root,
+                |_| {
+                    Ok(MerkleProof {
+                        address: 0,
+                        log2_size: 3,
+                        log2_root_size: 3,
+                        target_hash: H256::repeat_byte(1),
+                        sibling_hashes: vec![],
+                        root_hash: H256::repeat_byte(1),
+                    })
+                },
+            )
+        };
+
+        assert!(get_proof(&archive, H256::repeat_byte(1)).is_ok());
+        match get_proof(&archive, H256::repeat_byte(2))
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::ResponseInvalidError(_, key, _) => {
+                assert_eq!(key, "proof")
+            }
+            other => panic!("expected an invalid response, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn prefetched_requests_are_sent_once() {
+        let mut archive = Archive::new().unwrap();
+        let prefetch = |archive: &Archive, key: &str| {
+            archive.prefetch(
+                String::from("emulator"),
+                String::from(key),
+                String::from("Run"),
+                vec![],
+            )
+        };
+        archive.insert_response(String::from("a"), Ok(vec![]));
+        prefetch(&archive, "a");
+        prefetch(&archive, "b");
+        prefetch(&archive, "b");
+        let keys: Vec<String> = archive
+            .take_prefetches()
+            .into_iter()
+            .map(|p| p.key)
+            .collect();
+        assert_eq!(keys, vec![String::from("b")]);
+
+        // on its way until answered
+        prefetch(&archive, "b");
+        assert!(archive.take_prefetches().is_empty());
+        assert!(archive.is_fetching("b"));
+        archive.insert_response(String::from("b"), Ok(vec![]));
+        assert!(!archive.is_fetching("b"));
+    }
+}
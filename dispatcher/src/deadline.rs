@@ -0,0 +1,195 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Helpers for the "has the deadline passed?" logic shared by DApps.
+
+use super::ethereum_types::U256;
+use super::utils::convert::Timestamp;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A source for the current time, in seconds since the epoch. Dapps
+/// compare deadlines against the time of the chain, as contracts do, and
+/// not against the wall clock of the host.
+pub trait Clock: Send + Sync {
+    fn timestamp(&self) -> u64;
+
+    /// How long ago the time was read from the chain, `None` if it never
+    /// was. A stale time lags behind the contracts, whose deadlines may
+    /// have passed already.
+    fn staleness(&self) -> Option<Duration> {
+        Some(Duration::from_secs(0))
+    }
+}
+
+/// Follows the timestamp of the latest block seen by the dispatcher,
+/// which refreshes it on every tick of the main loop
+#[derive(Clone, Debug, Default)]
+pub struct ChainClock {
+    timestamp: Arc<AtomicU64>,
+    updated_at: Arc<Mutex<Option<Instant>>>,
+}
+
+impl ChainClock {
+    pub fn new() -> Self {
+        ChainClock::default()
+    }
+
+    /// Records the timestamp of a newly seen block
+    pub fn update(&self, timestamp: u64) {
+        self.timestamp.store(timestamp, Ordering::SeqCst);
+        *self.updated_at.lock().unwrap() = Some(Instant::now());
+    }
+}
+
+impl Clock for ChainClock {
+    fn timestamp(&self) -> u64 {
+        self.timestamp.load(Ordering::SeqCst)
+    }
+
+    fn staleness(&self) -> Option<Duration> {
+        self.updated_at.lock().unwrap().map(|at| at.elapsed())
+    }
+}
+
+/// A clock that only moves when told to, for testing DApps
+#[derive(Clone, Debug, Default)]
+pub struct MockClock {
+    timestamp: Arc<AtomicU64>,
+    staleness: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    pub fn new(timestamp: u64) -> Self {
+        MockClock {
+            timestamp: Arc::new(AtomicU64::new(timestamp)),
+            staleness: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn set(&self, timestamp: u64) {
+        self.timestamp.store(timestamp, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.timestamp.fetch_add(seconds, Ordering::SeqCst);
+    }
+
+    /// Pretends the time was read from the chain this many seconds ago
+    pub fn set_staleness(&self, seconds: u64) {
+        self.staleness.store(seconds, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn timestamp(&self) -> u64 {
+        self.timestamp.load(Ordering::SeqCst)
+    }
+
+    fn staleness(&self) -> Option<Duration> {
+        Some(Duration::from_secs(self.staleness.load(Ordering::SeqCst)))
+    }
+}
+
+/// Checks round deadlines against a clock
+#[derive(Clone)]
+pub struct Deadline {
+    clock: Arc<dyn Clock>,
+}
+
+impl Deadline {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Deadline { clock: clock }
+    }
+
+    /// The current time according to the underlying clock
+    pub fn now(&self) -> U256 {
+        Timestamp(self.clock.timestamp()).into()
+    }
+
+    /// How long ago the current time was read from the chain, `None` if
+    /// no block was seen yet
+    pub fn staleness(&self) -> Option<Duration> {
+        self.clock.staleness()
+    }
+
+    /// Whether the round started at `time_of_last_move` has ran out
+    pub fn expired(
+        &self,
+        time_of_last_move: U256,
+        round_duration: U256,
+    ) -> bool {
+        self.now() > time_of_last_move.saturating_add(round_duration)
+    }
+
+    /// Seconds left before the round expires, zero if it already did
+    pub fn remaining(
+        &self,
+        time_of_last_move: U256,
+        round_duration: U256,
+    ) -> U256 {
+        time_of_last_move
+            .saturating_add(round_duration)
+            .saturating_sub(self.now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadline_expires_after_round_duration() {
+        let clock = MockClock::new(100);
+        let deadline = Deadline::new(Arc::new(clock.clone()));
+
+        assert!(!deadline.expired(U256::from(50), U256::from(50)));
+        assert_eq!(
+            deadline.remaining(U256::from(50), U256::from(60)),
+            U256::from(10)
+        );
+
+        clock.advance(1);
+        assert!(deadline.expired(U256::from(50), U256::from(50)));
+        assert_eq!(
+            deadline.remaining(U256::from(50), U256::from(50)),
+            U256::zero()
+        );
+    }
+
+    #[test]
+    fn chain_clock_is_stale_until_a_block_is_seen() {
+        let clock = ChainClock::new();
+        assert_eq!(clock.staleness(), None);
+        clock.update(100);
+        assert_eq!(clock.timestamp(), 100);
+        assert!(clock.staleness().unwrap() < Duration::from_secs(1));
+
+        let mock = MockClock::new(100);
+        mock.set_staleness(30);
+        let deadline = Deadline::new(Arc::new(mock));
+        assert_eq!(deadline.staleness(), Some(Duration::from_secs(30)));
+    }
+}
@@ -0,0 +1,223 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Dry runs of the dapp over the instances of a snapshot, without node or
+//! services: the archive of the snapshot answers the requests to services
+//! and the clock is stopped at the time it was taken. How the dapp reacts
+//! to each instance is recorded as a reaction stream, and the streams of
+//! two versions of the dapp over the same snapshot are compared by `diff`,
+//! so that a refactor of the dapp logic can be checked to react as before.
+
+use super::configuration::Concern;
+use super::context::{ConfigView, DAppServices};
+use super::dapp::{react_guarded, Archive, DApp, ReactionRecord};
+use super::deadline::MockClock;
+use super::error::*;
+use super::serde_json;
+use super::snapshot::{Snapshot, SnapshotReader};
+use super::utils::chain::MockChain;
+use super::HashMap;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// How the dapp reacted to an instance, or the error it failed with
+pub type Outcome = std::result::Result<ReactionRecord, String>;
+
+/// The reaction of the dapp to an instance of the snapshot
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedReaction {
+    pub index: usize,
+    pub outcome: Outcome,
+}
+
+/// The reactions of a version of the dapp to the instances of a snapshot
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReactionStream {
+    pub main_concern: Concern,
+    /// When the snapshot was taken
+    pub taken_at: u64,
+    pub reactions: Vec<RecordedReaction>,
+}
+
+/// Feeds each instance of the snapshot to the dapp, with an archive of its
+/// own holding the entries of the snapshot
+pub fn run<T: DApp<Params = ()>>(
+    snapshot: &Snapshot,
+    config: &ConfigView,
+) -> Result<ReactionStream> {
+    let clock = Arc::new(MockClock::new(snapshot.taken_at));
+    let services = DAppServices {
+        chain: Arc::new(Mutex::new(MockChain::new())),
+        state: Arc::new(Mutex::new(SnapshotReader::new(snapshot))),
+        clients: Arc::new(Mutex::new(HashMap::new())),
+        clock: clock.clone(),
+        config: Arc::new(config.clone()),
+        networks: Arc::new(HashMap::new()),
+    };
+    let mut reactions = vec![];
+    for instance in snapshot.instances.iter() {
+        let mut archive = Archive::with_clock(clock.clone())?;
+        archive.restore(snapshot.archive.clone());
+        let outcome = react_guarded::<T>(
+            &instance.instance,
+            &services.context(&archive),
+            &None,
+            &(),
+        )
+        .map(|reaction| reaction.record())
+        .map_err(|e| e.to_string());
+        reactions.push(RecordedReaction {
+            index: instance.index,
+            outcome: outcome,
+        });
+    }
+    Ok(ReactionStream {
+        main_concern: snapshot.main_concern,
+        taken_at: snapshot.taken_at,
+        reactions: reactions,
+    })
+}
+
+/// An instance the two streams react differently to, `None` when it is
+/// missing from a stream
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReactionDiff {
+    pub index: usize,
+    pub before: Option<Outcome>,
+    pub after: Option<Outcome>,
+}
+
+fn describe(outcome: &Option<Outcome>) -> String {
+    match outcome {
+        Some(Ok(reaction)) => serde_json::to_string(reaction)
+            .unwrap_or_else(|_| format!("{:?}", reaction)),
+        Some(Err(e)) => format!("error: {}", e),
+        None => String::from("missing"),
+    }
+}
+
+impl fmt::Display for ReactionDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "instance {}: {} became {}",
+            self.index,
+            describe(&self.before),
+            describe(&self.after)
+        )
+    }
+}
+
+/// The instances the two streams react differently to, by index. Streams
+/// of different snapshots are refused.
+pub fn diff(
+    before: &ReactionStream,
+    after: &ReactionStream,
+) -> Result<Vec<ReactionDiff>> {
+    if before.main_concern != after.main_concern
+        || before.taken_at != after.taken_at
+    {
+        return Err(Error::from(format!(
+            "reaction streams are not of the same snapshot: {} at {} and {} \
+             at {}",
+            before.main_concern,
+            before.taken_at,
+            after.main_concern,
+            after.taken_at
+        )));
+    }
+    let mut outcomes: BTreeMap<usize, (Option<Outcome>, Option<Outcome>)> =
+        BTreeMap::new();
+    for reaction in before.reactions.iter() {
+        outcomes.entry(reaction.index).or_default().0 =
+            Some(reaction.outcome.clone());
+    }
+    for reaction in after.reactions.iter() {
+        outcomes.entry(reaction.index).or_default().1 =
+            Some(reaction.outcome.clone());
+    }
+    Ok(outcomes
+        .into_iter()
+        .filter(|(_, (before, after))| before != after)
+        .map(|(index, (before, after))| ReactionDiff {
+            index: index,
+            before: before,
+            after: after,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethereum_types::{Address, U256};
+
+    fn stream(reactions: Vec<(usize, Outcome)>) -> ReactionStream {
+        ReactionStream {
+            main_concern: Concern {
+                contract_address: Address::repeat_byte(0xaa),
+                user_address: Address::zero(),
+            },
+            taken_at: 1000,
+            reactions: reactions
+                .into_iter()
+                .map(|(index, outcome)| RecordedReaction {
+                    index: index,
+                    outcome: outcome,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn only_changed_reactions_are_reported() {
+        let until = |timestamp: u64| ReactionRecord::IdleUntil {
+            timestamp: U256::from(timestamp),
+        };
+        let before = stream(vec![
+            (0, Ok(ReactionRecord::Idle)),
+            (1, Ok(until(1200))),
+            (2, Err(String::from("could not parse state"))),
+        ]);
+        let after = stream(vec![
+            (1, Ok(until(1300))),
+            (0, Ok(ReactionRecord::Idle)),
+            (3, Ok(ReactionRecord::Terminate)),
+        ]);
+
+        let diffs = diff(&before, &after).unwrap();
+        assert_eq!(
+            diffs.iter().map(|d| d.index).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(diffs[1].after, None);
+        assert_eq!(diffs[2].before, None);
+        assert!(diff(&before, &before).unwrap().is_empty());
+
+        let mut later = after.clone();
+        later.taken_at += 1;
+        assert!(diff(&before, &later).is_err());
+    }
+}
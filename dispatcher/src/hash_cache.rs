@@ -0,0 +1,106 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Machine hashes already computed, by machine and cycle. Partition rounds
+//! ask for hashes at time points that repeat across rounds and across the
+//! disputes over the same machine, which are then answered without running
+//! the machine again. Machines are told apart by `MachineTemplate::id`.
+
+use super::configuration::Storage;
+use super::error::*;
+use super::ethereum_types::H256;
+use super::utils::kv::{KvStore, LazyStore};
+use std::path::Path;
+
+/// Hashes of the states of machines, kept in the working path
+pub struct HashCache {
+    database: LazyStore,
+}
+
+// the machine id followed by the cycle
+fn key(machine: H256, cycle: u64) -> Vec<u8> {
+    [machine.as_bytes(), &cycle.to_be_bytes()[..]].concat()
+}
+
+impl HashCache {
+    /// The hash database at the given path, created on the first run
+    pub fn open(storage: Storage, path: &Path) -> HashCache {
+        HashCache {
+            database: LazyStore::new(storage, path),
+        }
+    }
+
+    /// Hash of the machine at the given cycle, if it was computed before
+    pub fn get(&self, machine: H256, cycle: u64) -> Result<Option<H256>> {
+        let data = self
+            .database
+            .get(&key(machine, cycle))
+            .chain_err(|| format!("could not read from hash database"))?;
+        match data {
+            Some(data) if data.len() == 32 => Ok(Some(H256::from_slice(&data))),
+            Some(data) => Err(Error::from(format!(
+                "hash of {:#x} at {} should have 32 bytes, got {}",
+                machine,
+                cycle,
+                data.len()
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// Records the hash of the machine at the given cycle
+    pub fn put(&self, machine: H256, cycle: u64, hash: H256) -> Result<()> {
+        self.database
+            .put(&key(machine, cycle), hash.as_bytes())
+            .chain_err(|| format!("could not write to hash database"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn hashes_are_kept_by_machine_and_cycle() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let cache = HashCache::open(Storage::LevelDb, &dir.join("hash_db"));
+        let machine = H256::repeat_byte(1);
+        let other = H256::repeat_byte(2);
+
+        assert_eq!(cache.get(machine, 100).unwrap(), None);
+        cache.put(machine, 100, H256::repeat_byte(0xaa)).unwrap();
+        cache.put(other, 100, H256::repeat_byte(0xbb)).unwrap();
+        assert_eq!(
+            cache.get(machine, 100).unwrap(),
+            Some(H256::repeat_byte(0xaa))
+        );
+        assert_eq!(cache.get(machine, 200).unwrap(), None);
+        assert_eq!(
+            cache.get(other, 100).unwrap(),
+            Some(H256::repeat_byte(0xbb))
+        );
+    }
+}
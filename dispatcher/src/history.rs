@@ -0,0 +1,265 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! History of what the dispatcher saw of each instance of the main concern
+//! and how the dapp reacted, so that a lost dispute can be reconstructed
+//! afterwards. An entry is recorded whenever the state of an instance or
+//! the reaction to it changes, rather than on every poll. Each concern
+//! has its own database, in its directory of the working path.
+
+use super::configuration::{Concern, Storage};
+use super::error::*;
+use super::layout::ConcernDirs;
+use super::serde_json;
+use super::utils::kv::{self, KvStore, LazyStore};
+use super::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The state of an instance at a block and the reaction of the dapp to it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub block: Option<u64>,
+    pub timestamp: u64,
+    pub state: String,
+    /// The `ReactionRecord` as json, or its debug form in older entries
+    pub reaction: String,
+}
+
+/// Name of the history database, in the directory of each concern
+pub const HISTORY_DB: &str = "history_db";
+
+/// History of each instance, kept in the directory of its concern
+pub struct HistoryStore {
+    storage: Storage,
+    dirs: ConcernDirs,
+    databases: Mutex<HashMap<Concern, Arc<LazyStore>>>,
+    // hash of the last state and reaction recorded for each instance
+    last: Mutex<HashMap<(Concern, usize), u64>>,
+}
+
+// key prefix of the entries of an instance in the database of its concern,
+// which are then ordered by the time they were recorded
+fn prefix(index: usize) -> Vec<u8> {
+    (index as u64).to_be_bytes().to_vec()
+}
+
+impl HistoryStore {
+    /// The history databases of the concerns under the given working path,
+    /// created as they are first written to
+    pub fn open(storage: Storage, working_path: &Path) -> HistoryStore {
+        HistoryStore {
+            storage: storage,
+            dirs: ConcernDirs::new(working_path),
+            databases: Mutex::new(HashMap::new()),
+            last: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn database(&self, concern: Concern) -> Result<Arc<LazyStore>> {
+        let mut databases = self.databases.lock().unwrap();
+        if let Some(database) = databases.get(&concern) {
+            return Ok(database.clone());
+        }
+        let path = self.dirs.dir_of(&concern)?.join(HISTORY_DB);
+        let database = Arc::new(LazyStore::new(self.storage, &path));
+        databases.insert(concern, database.clone());
+        Ok(database)
+    }
+
+    /// Records the entry, unless the state and reaction are those recorded
+    /// last for the instance. Returns whether it was recorded.
+    pub fn record(
+        &self,
+        concern: Concern,
+        index: usize,
+        entry: &HistoryEntry,
+    ) -> Result<bool> {
+        let mut hasher = DefaultHasher::new();
+        entry.state.hash(&mut hasher);
+        entry.reaction.hash(&mut hasher);
+        let hash = hasher.finish();
+        let mut last = self.last.lock().unwrap();
+        if last.get(&(concern, index)) == Some(&hash) {
+            return Ok(false);
+        }
+
+        let recorded_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos() as u64)
+            .unwrap_or(0);
+        let mut key = prefix(index);
+        key.extend_from_slice(&recorded_at.to_be_bytes());
+        self.database(concern)?
+            .put(&key, &serde_json::to_vec(entry)?)
+            .chain_err(|| format!("could not write to history database"))?;
+        last.insert((concern, index), hash);
+        Ok(true)
+    }
+
+    /// Everything recorded for the instance, oldest first
+    pub fn of(
+        &self,
+        concern: Concern,
+        index: usize,
+    ) -> Result<Vec<HistoryEntry>> {
+        self.database(concern)?
+            .scan_prefix(&prefix(index))
+            .chain_err(|| format!("could not read from history database"))?
+            .into_iter()
+            .map(|(_, data)| -> Result<HistoryEntry> {
+                Ok(serde_json::from_slice(&data)?)
+            })
+            .collect()
+    }
+}
+
+/// Moves the entries of the history database shared by the concerns,
+/// before schema version 2, to the databases of their concerns
+pub fn split_by_concern(working_path: &Path, storage: Storage) -> Result<()> {
+    let shared_path = working_path.join(HISTORY_DB);
+    if storage != Storage::Sqlite && !shared_path.exists() {
+        return Ok(());
+    }
+    let dirs = ConcernDirs::new(working_path);
+    let entries = {
+        let shared = kv::open(storage, &shared_path)?;
+        let entries = shared.scan_prefix(&[])?;
+        let mut databases: HashMap<Concern, Arc<dyn KvStore>> = HashMap::new();
+        for (key, data) in entries.iter() {
+            if key.len() < 40 {
+                return Err(Error::from(format!(
+                    "history key should have at least 40 bytes, got {}",
+                    key.len()
+                )));
+            }
+            let concern = Concern::from_bytes(&key[0..40])?;
+            if !databases.contains_key(&concern) {
+                let path = dirs.dir_of(&concern)?.join(HISTORY_DB);
+                databases.insert(concern, kv::open(storage, &path)?);
+            }
+            databases[&concern].put(&key[40..], data)?;
+        }
+        for (key, _) in entries.iter() {
+            shared.delete(key)?;
+        }
+        entries.len()
+    };
+    info!("Moved {} history entries to their concerns", entries);
+    if shared_path.is_dir() {
+        std::fs::remove_dir_all(&shared_path).chain_err(|| {
+            format!("could not delete {}", shared_path.display())
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethereum_types::Address;
+    use layout::CONCERNS_DIR;
+    use tempfile::tempdir;
+
+    fn entry(block: u64, state: &str, reaction: &str) -> HistoryEntry {
+        HistoryEntry {
+            block: Some(block),
+            timestamp: block * 15,
+            state: String::from(state),
+            reaction: String::from(reaction),
+        }
+    }
+
+    #[test]
+    fn changes_are_recorded_in_order() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let history = HistoryStore::open(Storage::LevelDb, dir);
+        let concern = Concern {
+            contract_address: Address::repeat_byte(0xaa),
+            user_address: Address::zero(),
+        };
+
+        assert!(history.record(concern, 1, &entry(1, "a", "Idle")).unwrap());
+        assert!(!history.record(concern, 1, &entry(2, "a", "Idle")).unwrap());
+        assert!(history.record(concern, 1, &entry(3, "b", "Idle")).unwrap());
+        assert!(history.record(concern, 2, &entry(3, "c", "Idle")).unwrap());
+
+        let recorded = history.of(concern, 1).unwrap();
+        assert_eq!(
+            recorded,
+            vec![entry(1, "a", "Idle"), entry(3, "b", "Idle")]
+        );
+        assert_eq!(history.of(concern, 2).unwrap().len(), 1);
+        assert!(dir
+            .join(CONCERNS_DIR)
+            .join(ConcernDirs::key_of(&concern))
+            .join(HISTORY_DB)
+            .exists());
+    }
+
+    #[test]
+    fn shared_history_is_split_by_concern() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let concerns = [
+            Concern {
+                contract_address: Address::repeat_byte(0xaa),
+                user_address: Address::zero(),
+            },
+            Concern {
+                contract_address: Address::repeat_byte(0xbb),
+                user_address: Address::zero(),
+            },
+        ];
+        {
+            let shared =
+                kv::open(Storage::LevelDb, &dir.join(HISTORY_DB)).unwrap();
+            for (time, concern) in concerns.iter().enumerate() {
+                let mut key = concern.to_bytes();
+                key.extend_from_slice(&prefix(1));
+                key.extend_from_slice(&(time as u64).to_be_bytes());
+                shared
+                    .put(
+                        &key,
+                        &serde_json::to_vec(&entry(1, "a", "Idle")).unwrap(),
+                    )
+                    .unwrap();
+            }
+        }
+
+        split_by_concern(dir, Storage::LevelDb).unwrap();
+        assert!(!dir.join(HISTORY_DB).exists());
+        let history = HistoryStore::open(Storage::LevelDb, dir);
+        for concern in concerns.iter() {
+            assert_eq!(
+                history.of(*concern, 1).unwrap(),
+                vec![entry(1, "a", "Idle")]
+            );
+        }
+    }
+}
@@ -0,0 +1,207 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Bounded number of machine runs driven at once. Requests to the machine
+//! services beyond `max_machine_jobs` wait for a running one to finish,
+//! and the instances whose on-chain deadline is closest go first. Waiting
+//! requests of instances that reported no deadline go last. Among those
+//! of the same deadline, high value disputes go first, then the others in
+//! the order they came.
+
+use super::error::*;
+use std::sync::{Arc, Mutex};
+use web3::futures::future;
+use web3::futures::sync::oneshot;
+use web3::futures::Future;
+
+struct Waiter {
+    // deadline of the instance, whether it is of low value, then order of
+    // arrival
+    turn: (u64, bool, u64),
+    sender: oneshot::Sender<()>,
+}
+
+struct JobsState {
+    max_jobs: usize,
+    running: usize,
+    arrivals: u64,
+    waiters: Vec<Waiter>,
+}
+
+impl JobsState {
+    // hands the slot of a finished job to the most urgent waiter, returning
+    // false when nobody is waiting for it
+    fn hand_over(&mut self) -> bool {
+        while !self.waiters.is_empty() {
+            let position = self
+                .waiters
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, waiter)| waiter.turn)
+                .map(|(position, _)| position)
+                .unwrap();
+            let waiter = self.waiters.swap_remove(position);
+            // a waiter whose request was dropped does not take a slot
+            if waiter.sender.send(()).is_ok() {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// The right to drive a machine run, given back when dropped
+pub struct JobPermit {
+    state: Arc<Mutex<JobsState>>,
+}
+
+impl Drop for JobPermit {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        if !state.hand_over() {
+            state.running -= 1;
+        }
+    }
+}
+
+/// Limits the machine runs driven at once
+#[derive(Clone)]
+pub struct MachineJobs {
+    state: Arc<Mutex<JobsState>>,
+}
+
+impl MachineJobs {
+    pub fn new(max_jobs: usize) -> Self {
+        MachineJobs {
+            state: Arc::new(Mutex::new(JobsState {
+                max_jobs: max_jobs.max(1),
+                running: 0,
+                arrivals: 0,
+                waiters: vec![],
+            })),
+        }
+    }
+
+    /// Waits for a free slot, given the timestamp of the deadline of the
+    /// instance the run is for, if it reported one, and whether it is a
+    /// high value dispute
+    pub fn acquire(
+        &self,
+        deadline: Option<u64>,
+        high_stake: bool,
+    ) -> Box<dyn Future<Item = JobPermit, Error = Error> + Send> {
+        let permit_state = self.state.clone();
+        let mut state = self.state.lock().unwrap();
+
+        if state.running < state.max_jobs && state.waiters.is_empty() {
+            state.running += 1;
+            return Box::new(future::ok(JobPermit {
+                state: permit_state,
+            }));
+        }
+
+        let (tx, rx) = oneshot::channel();
+        state.arrivals += 1;
+        let turn = (
+            deadline.unwrap_or(u64::max_value()),
+            !high_stake,
+            state.arrivals,
+        );
+        state.waiters.push(Waiter {
+            turn: turn,
+            sender: tx,
+        });
+        trace!(
+            "Machine run waiting, {} running and {} waiting",
+            state.running,
+            state.waiters.len()
+        );
+
+        Box::new(
+            rx.map(move |_| JobPermit {
+                state: permit_state,
+            })
+            .map_err(|_| Error::from("machine jobs dropped")),
+        )
+    }
+
+    /// Machine runs being driven and waiting for a slot
+    pub fn load(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.running, state.waiters.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn waiting(jobs: &MachineJobs) -> Vec<u64> {
+        let state = jobs.state.lock().unwrap();
+        let mut deadlines: Vec<u64> =
+            state.waiters.iter().map(|waiter| waiter.turn.0).collect();
+        deadlines.sort();
+        deadlines
+    }
+
+    #[test]
+    fn closest_deadlines_are_run_first() {
+        let jobs = MachineJobs::new(1);
+        let never = u64::max_value();
+
+        let first = jobs.acquire(Some(500), false).wait().unwrap();
+        let none = jobs.acquire(None, false);
+        let late = jobs.acquire(Some(300), false);
+        let early = jobs.acquire(Some(200), false);
+        assert_eq!(waiting(&jobs), vec![200, 300, never]);
+
+        drop(first);
+        assert_eq!(waiting(&jobs), vec![300, never]);
+        drop(early.wait().unwrap());
+        assert_eq!(waiting(&jobs), vec![never]);
+        drop(late.wait().unwrap());
+        assert_eq!(jobs.load(), (1, 0));
+        drop(none.wait().unwrap());
+        assert_eq!(jobs.load(), (0, 0));
+    }
+
+    #[test]
+    fn high_value_disputes_go_first_for_the_same_deadline() {
+        let jobs = MachineJobs::new(1);
+
+        let first = jobs.acquire(None, false).wait().unwrap();
+        let low = jobs.acquire(None, false);
+        let high = jobs.acquire(None, true);
+        let urgent = jobs.acquire(Some(200), false);
+
+        drop(first);
+        let urgent = urgent.wait().unwrap();
+        drop(urgent);
+        let high = high.wait().unwrap();
+        assert_eq!(jobs.load(), (1, 1));
+        drop(high);
+        drop(low.wait().unwrap());
+        assert_eq!(jobs.load(), (0, 0));
+    }
+}
@@ -0,0 +1,214 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Directories of the working path holding the data of a single concern,
+//! so that the data of a finished dispute can be archived or deleted
+//! without touching the others. Each concern has a directory under
+//! `concerns`, named after the hex of its bytes, and `concerns/index.json`
+//! tells which concern each directory belongs to.
+
+use super::configuration::Concern;
+use super::error::*;
+use super::ethereum_types::Address;
+use super::hex;
+use super::serde::Serialize;
+use super::serde_json;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Directory of the working path holding those of the concerns
+pub const CONCERNS_DIR: &str = "concerns";
+
+const INDEX_FILE: &str = "index.json";
+
+/// Writes the value as json to the file of the working path, replacing it
+/// in one go so that a running dispatcher never reads it half written
+pub fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let temporary = path.with_extension("json.tmp");
+    std::fs::write(&temporary, serde_json::to_string_pretty(value)?)
+        .chain_err(|| format!("could not write {}", temporary.display()))?;
+    std::fs::rename(&temporary, path)
+        .chain_err(|| format!("could not replace {}", path.display()))
+}
+
+/// What the index tells of the directory of a concern
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConcernDir {
+    pub concern: Concern,
+    /// Seconds since the epoch
+    pub created_at: u64,
+}
+
+/// The directories of the concerns, under a working path
+#[derive(Clone, Debug)]
+pub struct ConcernDirs {
+    root: PathBuf,
+}
+
+impl ConcernDirs {
+    pub fn new(working_path: &Path) -> Self {
+        ConcernDirs {
+            root: working_path.join(CONCERNS_DIR),
+        }
+    }
+
+    /// Name of the directory of a concern
+    pub fn key_of(concern: &Concern) -> String {
+        hex::encode(concern.to_bytes())
+    }
+
+    /// Directory of a concern, created and indexed on first use. One left
+    /// out of the index, like by a crash between the two, is indexed then,
+    /// so that it can still be removed.
+    pub fn dir_of(&self, concern: &Concern) -> Result<PathBuf> {
+        let key = ConcernDirs::key_of(concern);
+        let dir = self.root.join(&key);
+        if dir.is_dir() && self.list()?.contains_key(&key) {
+            return Ok(dir);
+        }
+        std::fs::create_dir_all(&dir)
+            .chain_err(|| format!("could not create {}", dir.display()))?;
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        self.update(|index| {
+            index.entry(key).or_insert(ConcernDir {
+                concern: *concern,
+                created_at: created_at,
+            });
+        })?;
+        Ok(dir)
+    }
+
+    /// Directories of the concerns, by name
+    pub fn list(&self) -> Result<BTreeMap<String, ConcernDir>> {
+        let path = self.root.join(INDEX_FILE);
+        if !path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let content = std::fs::read_to_string(&path)
+            .chain_err(|| format!("could not read {}", path.display()))?;
+        Ok(serde_json::from_str(&content).chain_err(|| {
+            format!("invalid index of concerns in {}", path.display())
+        })?)
+    }
+
+    /// Deletes the directories of the concerns of a contract, whatever
+    /// their user, returning the concerns they belonged to
+    pub fn remove(&self, contract_address: Address) -> Result<Vec<Concern>> {
+        let removed: Vec<(String, Concern)> = self
+            .list()?
+            .into_iter()
+            .filter(|(_, dir)| dir.concern.contract_address == contract_address)
+            .map(|(key, dir)| (key, dir.concern))
+            .collect();
+        for (key, _) in removed.iter() {
+            let dir = self.root.join(key);
+            if dir.exists() {
+                std::fs::remove_dir_all(&dir).chain_err(|| {
+                    format!("could not delete {}", dir.display())
+                })?;
+            }
+        }
+        self.update(|index| {
+            for (key, _) in removed.iter() {
+                index.remove(key);
+            }
+        })?;
+        Ok(removed.into_iter().map(|(_, concern)| concern).collect())
+    }
+
+    // changes the index, rewriting the file
+    fn update<F>(&self, change: F) -> Result<()>
+    where
+        F: FnOnce(&mut BTreeMap<String, ConcernDir>),
+    {
+        let mut index = self.list()?;
+        change(&mut index);
+        std::fs::create_dir_all(&self.root).chain_err(|| {
+            format!("could not create {}", self.root.display())
+        })?;
+        write_json(&self.root.join(INDEX_FILE), &index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn directories_of_a_contract_are_removed_together() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let concern = |contract: u8, user: u8| Concern {
+            contract_address: Address::repeat_byte(contract),
+            user_address: Address::repeat_byte(user),
+        };
+
+        let dirs = ConcernDirs::new(dir);
+        let first = dirs.dir_of(&concern(0xaa, 1)).unwrap();
+        assert_eq!(dirs.dir_of(&concern(0xaa, 1)).unwrap(), first);
+        dirs.dir_of(&concern(0xaa, 2)).unwrap();
+        let other = dirs.dir_of(&concern(0xbb, 1)).unwrap();
+        assert_eq!(dirs.list().unwrap().len(), 3);
+
+        let removed = dirs.remove(Address::repeat_byte(0xaa)).unwrap();
+        assert_eq!(removed, vec![concern(0xaa, 1), concern(0xaa, 2)]);
+        assert!(!first.exists());
+        assert!(other.is_dir());
+        let index = ConcernDirs::new(dir).list().unwrap();
+        assert_eq!(index.len(), 1);
+        assert_eq!(
+            index[&ConcernDirs::key_of(&concern(0xbb, 1))].concern,
+            concern(0xbb, 1)
+        );
+    }
+
+    #[test]
+    fn directories_missing_from_the_index_are_indexed() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let concern = Concern {
+            contract_address: Address::repeat_byte(0xcc),
+            user_address: Address::repeat_byte(1),
+        };
+        let dirs = ConcernDirs::new(dir);
+        // created by a dispatcher that stopped before indexing it
+        let unindexed =
+            dir.join(CONCERNS_DIR).join(ConcernDirs::key_of(&concern));
+        std::fs::create_dir_all(&unindexed).unwrap();
+        assert!(dirs.list().unwrap().is_empty());
+
+        assert_eq!(dirs.dir_of(&concern).unwrap(), unindexed);
+        assert_eq!(dirs.list().unwrap().len(), 1);
+        assert_eq!(
+            dirs.remove(Address::repeat_byte(0xcc)).unwrap(),
+            vec![concern]
+        );
+        assert!(!unindexed.exists());
+    }
+}
@@ -0,0 +1,431 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Leader election between redundant dispatchers of the same concerns.
+//! They take turns holding a lease: the holder sends transactions and
+//! renews the lease, the others keep tracking the concerns and take the
+//! lease over once it expires. Where the lease is kept is up to a
+//! `LeaseBackend`; `FileLease` keeps it in a file on shared storage.
+
+use super::error::*;
+use super::serde_json;
+use std::fs::OpenOptions;
+use std::io::ErrorKind as IoErrorKind;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Where redundant dispatchers keep their lease
+pub trait LeaseBackend: Send + Sync {
+    /// Takes the lease for the holder, or renews it if already held,
+    /// until `ttl` from now. Returns false when another holder has it.
+    fn acquire(&self, holder: &str, ttl: Duration) -> Result<bool>;
+
+    /// Gives the lease up, if the holder has it
+    fn release(&self, holder: &str) -> Result<()>;
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LeaseRecord {
+    holder: String,
+    // milliseconds since the epoch
+    expires_at: u64,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Attempts at taking the lock of a lease held by another dispatcher
+const LOCK_ATTEMPTS: u32 = 50;
+/// Wait between attempts at taking the lock of a lease
+const LOCK_RETRY: Duration = Duration::from_millis(10);
+/// Age from which the lock of a lease is taken to be left over by a
+/// dispatcher that died holding it
+const LOCK_STALE: Duration = Duration::from_secs(10);
+
+static LOCK_TOKENS: AtomicUsize = AtomicUsize::new(0);
+
+// a token telling apart the locks taken by each dispatcher, and by each
+// attempt of the same one
+fn lock_token() -> String {
+    format!(
+        "{}-{}-{}",
+        std::process::id(),
+        now_millis(),
+        LOCK_TOKENS.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+// whether a lock was last written long enough ago to be left over
+fn is_stale(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .map_or(false, |age| age > LOCK_STALE)
+}
+
+/// A lease kept in a file, written whole and renamed in place. Reading and
+/// writing it happen under a lock file created exclusively next to it, so
+/// that two dispatchers cannot both take an expired lease. The clocks of
+/// the hosts sharing it should agree to well within the ttl.
+pub struct FileLease {
+    path: PathBuf,
+    lock_path: PathBuf,
+}
+
+// the lock of a lease holding the token of its taker, removed when
+// dropped unless another dispatcher broke it meanwhile
+struct LeaseLock<'a> {
+    path: &'a Path,
+    token: String,
+}
+
+impl<'a> Drop for LeaseLock<'a> {
+    fn drop(&mut self) {
+        match std::fs::read_to_string(self.path) {
+            Ok(ref token) if *token == self.token => {
+                if let Err(e) = std::fs::remove_file(self.path) {
+                    warn!(
+                        "Could not remove lock {}: {}",
+                        self.path.display(),
+                        e
+                    );
+                }
+            }
+            _ => warn!("Lock {} was broken while held", self.path.display()),
+        }
+    }
+}
+
+impl FileLease {
+    pub fn new(path: &Path) -> Self {
+        FileLease {
+            path: path.to_path_buf(),
+            lock_path: path.with_extension("lock"),
+        }
+    }
+
+    // takes the lock of the lease, waiting a little for another dispatcher
+    // to give it back; a lock left over by one that died is broken
+    fn lock(&self) -> Result<LeaseLock> {
+        for _ in 0..LOCK_ATTEMPTS {
+            let token = lock_token();
+            let created = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&self.lock_path)
+                .and_then(|mut file| file.write_all(token.as_bytes()));
+            match created {
+                Ok(_) => {
+                    return Ok(LeaseLock {
+                        path: &self.lock_path,
+                        token: token,
+                    })
+                }
+                Err(ref e) if e.kind() == IoErrorKind::AlreadyExists => {
+                    if !self.break_if_stale(&token) {
+                        std::thread::sleep(LOCK_RETRY);
+                    }
+                }
+                Err(e) => {
+                    return Err(Error::from(e)).chain_err(|| {
+                        format!(
+                            "could not lock lease {}",
+                            self.lock_path.display()
+                        )
+                    })
+                }
+            }
+        }
+        Err(Error::from(format!(
+            "lease {} stayed locked by another dispatcher",
+            self.path.display()
+        )))
+    }
+
+    // breaks a lock left over by a dispatcher that died, telling whether
+    // the lock may be taken right away. The lock is first renamed to a
+    // name of our own, which only one contender can do, and put back if
+    // another broke it and took a fresh one since it was judged stale.
+    fn break_if_stale(&self, token: &str) -> bool {
+        let stale = match std::fs::read_to_string(&self.lock_path) {
+            Ok(ref stale) if is_stale(&self.lock_path) => stale.clone(),
+            _ => return false,
+        };
+        let claimed = self.lock_path.with_extension(format!("{}.lock", token));
+        if std::fs::rename(&self.lock_path, &claimed).is_err() {
+            // broken by another contender
+            return true;
+        }
+        let broken = match std::fs::read_to_string(&claimed) {
+            Ok(ref taken) => *taken == stale && is_stale(&claimed),
+            Err(_) => false,
+        };
+        if broken {
+            warn!("Broke stale lock {}", self.lock_path.display());
+        } else if let Err(e) = std::fs::hard_link(&claimed, &self.lock_path) {
+            warn!(
+                "Could not put lock {} back: {}",
+                self.lock_path.display(),
+                e
+            );
+        }
+        let _ = std::fs::remove_file(&claimed);
+        broken
+    }
+
+    // the current record, none if missing or unreadable
+    fn read(&self) -> Option<LeaseRecord> {
+        std::fs::read(&self.path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+    }
+}
+
+impl LeaseBackend for FileLease {
+    fn acquire(&self, holder: &str, ttl: Duration) -> Result<bool> {
+        let _lock = self.lock()?;
+        let now = now_millis();
+        if let Some(record) = self.read() {
+            if record.holder != holder && record.expires_at > now {
+                return Ok(false);
+            }
+        }
+        let record = LeaseRecord {
+            holder: String::from(holder),
+            expires_at: now + ttl.as_millis() as u64,
+        };
+        // the lock keeps other writers of the temporary file out
+        let temporary = self.path.with_extension("tmp");
+        std::fs::write(&temporary, serde_json::to_vec(&record)?)
+            .and_then(|_| std::fs::rename(&temporary, &self.path))
+            .chain_err(|| {
+                format!("could not write lease {}", self.path.display())
+            })?;
+        Ok(true)
+    }
+
+    fn release(&self, holder: &str) -> Result<()> {
+        let _lock = self.lock()?;
+        match self.read() {
+            Some(ref record) if record.holder == holder => {
+                std::fs::remove_file(&self.path).chain_err(|| {
+                    format!("could not remove lease {}", self.path.display())
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether this dispatcher sends transactions. Without a backend it is
+/// always the leader; with one, only while it holds the lease.
+#[derive(Clone)]
+pub struct Leadership {
+    backend: Option<Arc<dyn LeaseBackend>>,
+    holder: String,
+    ttl: Duration,
+    leader: Arc<AtomicBool>,
+}
+
+impl Leadership {
+    /// A dispatcher running alone
+    pub fn alone() -> Self {
+        Leadership {
+            backend: None,
+            holder: String::new(),
+            ttl: Duration::from_secs(0),
+            leader: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// A dispatcher taking turns with others, a follower until it first
+    /// takes the lease
+    pub fn new(
+        backend: Arc<dyn LeaseBackend>,
+        holder: &str,
+        ttl: Duration,
+    ) -> Self {
+        Leadership {
+            backend: Some(backend),
+            holder: String::from(holder),
+            ttl: ttl,
+            leader: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.leader.load(Ordering::SeqCst)
+    }
+
+    /// Whether the lease has to be renewed
+    pub fn is_shared(&self) -> bool {
+        self.backend.is_some()
+    }
+
+    /// How often the lease is renewed, well before it expires
+    pub fn period(&self) -> Duration {
+        self.ttl / 3
+    }
+
+    /// Takes or renews the lease, stepping down when it cannot be told
+    /// whether this dispatcher still holds it
+    pub fn renew(&self) -> bool {
+        let backend = match &self.backend {
+            Some(backend) => backend,
+            None => return true,
+        };
+        let leader = match backend.acquire(&self.holder, self.ttl) {
+            Ok(leader) => leader,
+            Err(e) => {
+                warn!("Could not renew the leader lease: {}", e);
+                false
+            }
+        };
+        let was_leader = self.leader.swap(leader, Ordering::SeqCst);
+        if leader && !was_leader {
+            info!("{} is now the leader, sending transactions", self.holder);
+        } else if !leader && was_leader {
+            warn!("{} is no longer the leader, following", self.holder);
+        }
+        leader
+    }
+
+    /// Gives the lease up for another dispatcher to take over right away
+    pub fn resign(&self) -> Result<()> {
+        self.leader.store(false, Ordering::SeqCst);
+        match &self.backend {
+            Some(backend) => backend.release(&self.holder),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn one_holder_at_a_time_until_the_lease_expires() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let backend: Arc<dyn LeaseBackend> =
+            Arc::new(FileLease::new(&dir.join("dispatcher.lease")));
+        let ttl = Duration::from_millis(300);
+        let a = Leadership::new(backend.clone(), "a", ttl);
+        let b = Leadership::new(backend.clone(), "b", ttl);
+
+        assert!(a.renew());
+        assert!(!b.renew());
+        assert!(a.renew());
+        assert!(a.is_leader() && !b.is_leader());
+
+        // a stops renewing
+        std::thread::sleep(ttl + Duration::from_millis(50));
+        assert!(b.renew());
+        assert!(!a.renew());
+
+        // resigning hands over right away
+        b.resign().unwrap();
+        assert!(!b.is_leader());
+        assert!(a.renew());
+    }
+
+    #[test]
+    fn contending_dispatchers_do_not_both_take_the_lease() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("dispatcher.lease");
+        let start = Arc::new(std::sync::Barrier::new(8));
+        let contenders: Vec<_> = (0..8)
+            .map(|i| {
+                let lease = FileLease::new(&path);
+                let start = start.clone();
+                std::thread::spawn(move || {
+                    start.wait();
+                    lease
+                        .acquire(&format!("{}", i), Duration::from_secs(60))
+                        .unwrap_or(false)
+                })
+            })
+            .collect();
+        let taken = contenders
+            .into_iter()
+            .map(|contender| contender.join().unwrap())
+            .filter(|taken| *taken)
+            .count();
+        assert_eq!(taken, 1);
+        assert!(!dir.join("dispatcher.lock").exists());
+    }
+
+    #[test]
+    fn contenders_break_a_stale_lock_once() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("dispatcher.lease");
+        // left over by a dispatcher that died holding it
+        let lock = dir.join("dispatcher.lock");
+        std::fs::write(&lock, "dead").unwrap();
+        OpenOptions::new()
+            .write(true)
+            .open(&lock)
+            .unwrap()
+            .set_modified(SystemTime::now() - LOCK_STALE * 2)
+            .unwrap();
+
+        let start = Arc::new(std::sync::Barrier::new(2));
+        let contenders: Vec<_> = (0..2)
+            .map(|i| {
+                let lease = FileLease::new(&path);
+                let start = start.clone();
+                std::thread::spawn(move || {
+                    start.wait();
+                    lease
+                        .acquire(&format!("{}", i), Duration::from_secs(60))
+                        .unwrap_or(false)
+                })
+            })
+            .collect();
+        let taken = contenders
+            .into_iter()
+            .map(|contender| contender.join().unwrap())
+            .filter(|taken| *taken)
+            .count();
+        assert_eq!(taken, 1);
+        // neither the lock nor a claimed one is left behind
+        let left: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(left, vec![std::ffi::OsString::from("dispatcher.lease")]);
+    }
+}
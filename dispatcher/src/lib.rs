@@ -22,9 +22,35 @@
 // be used independently under the Apache v2 license. After this component is
 // rewritten, the entire component will be released under the Apache v2 license.
 
+pub mod auth;
+pub mod budget;
+pub mod checkpoint;
+pub mod context;
 pub mod dapp;
+pub mod deadline;
+pub mod dryrun;
+pub mod hash_cache;
+pub mod history;
+pub mod jobs;
+pub mod layout;
+pub mod leader;
+pub mod machine;
+pub mod migrate;
+pub mod notify;
+pub mod pause;
+pub mod role;
+pub mod schema;
+pub mod session;
+pub mod shutdown;
+pub mod snapshot;
+pub mod spend;
+pub mod status;
+pub mod stress;
+pub mod tracker;
+pub mod watchdog;
 
 extern crate configuration;
+extern crate dispatcher_proto;
 extern crate error;
 extern crate ethereum_types;
 extern crate grpc;
@@ -40,38 +66,90 @@ extern crate env_logger;
 extern crate ethabi;
 extern crate hex;
 extern crate hyper;
+extern crate hyper_tls;
+extern crate lettre;
+extern crate lettre_email;
+extern crate native_tls;
 extern crate serde;
 extern crate serde_json;
+extern crate signal_hook;
 extern crate state;
+extern crate state_server;
+#[cfg(test)]
+extern crate tempfile;
 extern crate transaction;
 extern crate transport;
 
 use std::str;
 
-use configuration::{Concern, Configuration};
+use configuration::checksum::checksummed;
+use configuration::{
+    AccessLevel, Command, Concern, ConcernLabels, Configuration, MachineBackend,
+};
 pub use error::*;
+use ethereum_types::{Address, U256};
 use grpc::{Client, RequestOptions};
 use hyper::service::service_fn;
 use hyper::{Body, Request, Response, Server, StatusCode};
-use state::StateManager;
+use state::{StateManager, StateReader};
+use state_server::StateClient;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::prelude::Sink;
 use tokio::timer::Interval;
-use transaction::{TransactionManager, TransactionRequest};
-use transport::GenericTransport;
+use transaction::{
+    audit, Strategy, SubmitStrategy, TransactionManager, TransactionRequest,
+    TransactionSender,
+};
+use transport::{Connection, GenericTransport, RequestBudgets};
+use utils::chain::ChainReader;
+use utils::compress;
+use utils::convert::Timestamp;
+use utils::retry::Retry;
 use utils::{print_error, EthWeb3};
-use web3::futures::future::lazy;
+use web3::futures::future::{lazy, Either};
 use web3::futures::sync::{mpsc, oneshot};
 use web3::futures::{future, stream, Future, Stream};
 
+pub use auth::Authenticator;
+pub use budget::BudgetGuard;
+pub use checkpoint::CheckpointStore;
+pub use context::{ConfigView, DAppContext, DAppServices, NetworkServices};
 pub use dapp::{
-    AddressArray, AddressField, Archive, BoolArray, BoolField, Bytes32Array,
-    Bytes32Field, BytesField, DApp, FieldType, Reaction, String32Field,
-    U256Array, U256Field,
+    AddressArray, AddressField, AddressFixedArray, Archive, ArchiveEntries,
+    BoolArray, BoolField, BoolFixedArray, Bytes32Array, Bytes32Field,
+    Bytes32FixedArray, BytesField, CallRecord, DApp, ElementType, FieldType,
+    FixedArray, Prefetch, Reaction, ReactionRecord, String32Field, Sub,
+    SubDApp, SubDApps, SubInstances, SubsOf, U256Array, U256Field,
+    U256FixedArray,
 };
+pub use deadline::{ChainClock, Clock, Deadline, MockClock};
+pub use dryrun::{ReactionDiff, ReactionStream, RecordedReaction};
+pub use hash_cache::HashCache;
+pub use history::{HistoryEntry, HistoryStore};
+pub use jobs::{JobPermit, MachineJobs};
+pub use layout::{ConcernDir, ConcernDirs};
+pub use leader::{FileLease, Leadership, LeaseBackend};
+pub use machine::LocalHasher;
+pub use notify::{Alert, Alerts, Notifier, SmtpNotifier, WebhookNotifier};
+pub use pause::PauseStore;
+pub use role::{party_index, role_for, Parties, Party, Role};
+pub use schema::{parse_state, stake_value, InstanceState, StateField};
+pub use session::SessionStore;
+pub use shutdown::Shutdown;
+pub use snapshot::{Snapshot, SnapshotReader};
+pub use spend::{ConcernSpending, SpendStore, SpendingReport};
+pub use status::{StatusBoard, StatusContext};
+pub use stress::{Sample, StressReport};
+pub use tracker::{InstanceTracker, TrackedInstance};
+pub use watchdog::Watchdog;
+
+/// How often ENS names of the configuration are resolved again
+const ENS_CHECK_PERIOD: Duration = Duration::from_secs(3600);
+/// How often the hashers spawned by the dispatcher are checked
+const HASHER_CHECK_PERIOD: Duration = Duration::from_secs(5);
 
 /// Responsible for querying the state of each concern, get a reaction
 /// from the dapp and submit reactions for either the Transaction Manager or
@@ -80,6 +158,8 @@ pub struct Dispatcher {
     config: Configuration,
     _web3: web3::api::Web3<GenericTransport>, // to stay in scope
     _eloop: web3::transports::EventLoopHandle, // kept to stay in scope
+    _connections: Vec<Connection>, // to the other networks, to stay in scope
+    hashers: Vec<Arc<LocalHasher>>,
     assets: Assets,
 }
 
@@ -87,23 +167,76 @@ pub struct Dispatcher {
 // should we put the Arc<Mutex<>> in the Assets instead of in each of them?
 // !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
 
-/// All the assets in the dispatcher that have to be shared by tokio tasks
+/// The node of a network and the clock following its blocks
+#[derive(Clone)]
+struct NetworkAssets {
+    chain: Arc<Mutex<dyn ChainReader>>,
+    clock: ChainClock,
+}
+
+/// All the assets in the dispatcher that have to be shared by tokio tasks.
+/// Reactions only see the chain and the transaction manager through the
+/// `chain` and `sender` traits, so that they can run against mocks.
 struct Assets {
     transaction_manager: Arc<Mutex<TransactionManager>>,
-    state_manager: Arc<Mutex<StateManager>>,
+    sender: Arc<Mutex<dyn TransactionSender>>,
+    state_manager: Arc<Mutex<dyn StateReader>>,
     archive: Arc<Mutex<Archive>>,
     clients: Arc<Mutex<HashMap<String, Arc<Mutex<Client>>>>>,
+    // by network name, none for the one of url
+    networks: Arc<HashMap<Option<String>, NetworkAssets>>,
+    wake_ups: Arc<Mutex<HashMap<(Concern, usize), U256>>>,
+    poll_intervals: Arc<HashMap<Concern, Duration>>,
+    labels: Arc<ConcernLabels>,
+    status: Arc<Mutex<StatusBoard>>,
+    watchdog: Arc<Mutex<Watchdog>>,
+    paused: PauseStore,
+    alerts: Arc<Alerts>,
+    services: DAppServices,
+    spending: Arc<SpendStore>,
+    budgets: Arc<BudgetGuard>,
+    tracker: Arc<Mutex<InstanceTracker>>,
+    history: Arc<HistoryStore>,
+    machine_jobs: MachineJobs,
+    leadership: Leadership,
+    shutdown: Shutdown,
+    simulate_transactions: bool,
+    precompute: bool,
 }
 
 impl Assets {
     fn clone(&self) -> Self {
         Assets {
             transaction_manager: self.transaction_manager.clone(),
+            sender: self.sender.clone(),
             state_manager: self.state_manager.clone(),
             archive: self.archive.clone(),
             clients: self.clients.clone(),
+            networks: self.networks.clone(),
+            wake_ups: self.wake_ups.clone(),
+            poll_intervals: self.poll_intervals.clone(),
+            labels: self.labels.clone(),
+            status: self.status.clone(),
+            watchdog: self.watchdog.clone(),
+            paused: self.paused.clone(),
+            alerts: self.alerts.clone(),
+            services: self.services.clone(),
+            spending: self.spending.clone(),
+            budgets: self.budgets.clone(),
+            tracker: self.tracker.clone(),
+            history: self.history.clone(),
+            machine_jobs: self.machine_jobs.clone(),
+            leadership: self.leadership.clone(),
+            shutdown: self.shutdown.clone(),
+            simulate_transactions: self.simulate_transactions,
+            precompute: self.precompute,
         }
     }
+
+    // the node and the clock of the network a concern is deployed on
+    fn network_of(&self, concern: &Concern) -> &NetworkAssets {
+        &self.networks[&self.services.config.network_of(concern).cloned()]
+    }
 }
 
 impl Dispatcher {
@@ -123,10 +256,51 @@ impl Dispatcher {
                         &config.url
                     )
                 })?;
+        // hosted nodes enforce quotas
+        let transport = transport
+            .with_budgets(RequestBudgets::new(
+                config.read_rate_limit,
+                config.send_rate_limit,
+            ))
+            .with_cache(config.rpc_cache_size);
 
         info!("Testing Ethereum node's functionality");
         let web3 = web3::Web3::new(transport);
-        web3.test_connection(&config).wait()?;
+        Retry::new().run(|| web3.test_connection(&config).wait())?;
+
+        // the concerns of the other networks read their chain and follow
+        // the time of their own node
+        let mut connections = vec![];
+        let mut networks = HashMap::new();
+        let mut network_services = HashMap::new();
+        let chain: Arc<Mutex<dyn ChainReader>> =
+            Arc::new(Mutex::new(web3.clone()));
+        networks.insert(
+            None,
+            NetworkAssets {
+                chain: chain.clone(),
+                clock: ChainClock::new(),
+            },
+        );
+        for (name, network) in config.networks.iter() {
+            info!("Testing the node of network {}", name);
+            let connection = config.connect_to(network)?;
+            Retry::new()
+                .run(|| connection.web3.test_node(&network.url).wait())?;
+            let assets = NetworkAssets {
+                chain: Arc::new(Mutex::new(connection.web3.clone())),
+                clock: ChainClock::new(),
+            };
+            network_services.insert(
+                name.clone(),
+                NetworkServices {
+                    chain: assets.chain.clone(),
+                    clock: Arc::new(assets.clock.clone()),
+                },
+            );
+            networks.insert(Some(name.clone()), assets);
+            connections.push(connection);
+        }
 
         info!("Creating transaction manager");
         let transaction_manager =
@@ -134,12 +308,73 @@ impl Dispatcher {
                 || format!("could not create transaction manager"),
             )?;
 
-        info!("Creating state manager");
-        let state_manager = StateManager::new(config.clone(), web3.clone())
-            .chain_err(|| format!("could not create state manager"))?;
+        let state_manager: Arc<Mutex<dyn StateReader>> = match &config
+            .state_server
+        {
+            Some(server) => {
+                info!("Connecting to state server at {}", server);
+                Arc::new(Mutex::new(StateClient::new(server)?))
+            }
+            None => {
+                info!("Creating state manager");
+                Arc::new(Mutex::new(
+                    StateManager::new(config.clone(), web3.clone()).chain_err(
+                        || format!("could not create state manager"),
+                    )?,
+                ))
+            }
+        };
 
         info!("Creating archive");
-        let archive = Archive::new()?;
+        let clock = networks[&None].clock.clone();
+        let mut archive = Archive::with_clock(Arc::new(clock.clone()))?;
+
+        // the databases are only opened once used, so that commands run
+        // next to a dispatcher do not find them locked
+        let sessions = SessionStore::open(
+            config.storage,
+            &config.working_path.join("session_db"),
+        );
+        archive.set_session_store(Arc::new(sessions));
+        let checkpoints = CheckpointStore::open(
+            config.storage,
+            &config.working_path.join("checkpoint_db"),
+        );
+        archive.set_checkpoint_store(Arc::new(checkpoints));
+        let hashes = HashCache::open(
+            config.storage,
+            &config.working_path.join("hash_db"),
+        );
+        archive.set_hash_cache(Arc::new(hashes));
+        let spending = SpendStore::open(
+            config.storage,
+            &config.working_path.join("spend_db"),
+        );
+        let history = HistoryStore::open(config.storage, &config.working_path);
+
+        info!("Creating notifiers");
+        let mut notifiers: Vec<Box<dyn Notifier>> = vec![];
+        for webhook in config.webhooks.iter() {
+            notifiers.push(Box::new(WebhookNotifier::new(
+                webhook,
+                config.labels.clone(),
+            )?));
+        }
+        if let Some(smtp) = &config.smtp {
+            notifiers.push(Box::new(SmtpNotifier::new(
+                smtp,
+                config.labels.clone(),
+            )?));
+        }
+        let alerts = Arc::new(Alerts::new(
+            notifiers,
+            config.max_delay.num_seconds(),
+            config.labels.clone(),
+        ));
+        archive.set_notifier(alerts.clone());
+        archive.set_cache_size(
+            config.archive_cache_size.map(|size| size as usize),
+        );
 
         info!("Creating grpc client");
         let mut clients = HashMap::new();
@@ -152,27 +387,864 @@ impl Dispatcher {
             clients.insert(service.name.clone(), Arc::new(Mutex::new(client)));
         }
 
+        // concerns with their own machine backend reach it as a service
+        // of their own
+        let mut hashers = vec![];
+        for (concern, backend) in config.machine_backends.iter() {
+            let client = match backend {
+                MachineBackend::Remote(transport) => Client::new_plain(
+                    &transport.address,
+                    transport.port,
+                    Default::default(),
+                )?,
+                MachineBackend::Local { hasher, port, args } => {
+                    hashers.push(Arc::new(LocalHasher::spawn(
+                        hasher, *port, args,
+                    )?));
+                    Client::new_plain("127.0.0.1", *port, Default::default())?
+                }
+            };
+            clients.insert(
+                config.machine_service_of(concern),
+                Arc::new(Mutex::new(client)),
+            );
+        }
+
+        let clients = Arc::new(Mutex::new(clients));
+        let services = DAppServices {
+            chain: chain,
+            state: state_manager.clone(),
+            clients: clients.clone(),
+            clock: Arc::new(clock),
+            config: Arc::new(ConfigView::of(&config)),
+            networks: Arc::new(network_services),
+        };
+
+        let transaction_manager = Arc::new(Mutex::new(transaction_manager));
+        let watchdog = Watchdog::new(config.stall_timeout);
+        let poll_intervals = Arc::new(config.poll_intervals.clone());
+        let labels = Arc::new(config.labels.clone());
+        let paused = PauseStore::new(&config.working_path);
+        let spending = Arc::new(spending);
+        let budgets = BudgetGuard::new(
+            config.budgets.clone(),
+            spending.clone(),
+            &config.working_path,
+        );
+        let simulate_transactions = config.simulate_transactions;
+        let machine_jobs = MachineJobs::new(config.max_machine_jobs);
+        let precompute = config.precompute;
+        let leadership = match &config.leader_lease {
+            Some(lease) => Leadership::new(
+                Arc::new(FileLease::new(&lease.path)),
+                &lease.id,
+                lease.ttl,
+            ),
+            None => Leadership::alone(),
+        };
         let dispatcher = Dispatcher {
             config: config,
-            _web3: web3,
+            _web3: web3.clone(),
             _eloop: _eloop,
+            _connections: connections,
+            hashers: hashers,
             assets: Assets {
-                transaction_manager: Arc::new(Mutex::new(transaction_manager)),
-                state_manager: Arc::new(Mutex::new(state_manager)),
+                transaction_manager: transaction_manager.clone(),
+                sender: transaction_manager,
+                state_manager: state_manager,
                 archive: Arc::new(Mutex::new(archive)),
-                clients: Arc::new(Mutex::new(clients)),
+                clients: clients,
+                networks: Arc::new(networks),
+                wake_ups: Arc::new(Mutex::new(HashMap::new())),
+                poll_intervals: poll_intervals,
+                labels: labels,
+                status: Arc::new(Mutex::new(StatusBoard::new())),
+                watchdog: Arc::new(Mutex::new(watchdog)),
+                paused: paused,
+                alerts: alerts,
+                services: services,
+                spending: spending,
+                budgets: Arc::new(budgets),
+                tracker: Arc::new(Mutex::new(InstanceTracker::new())),
+                history: Arc::new(history),
+                machine_jobs: machine_jobs,
+                leadership: leadership,
+                shutdown: Shutdown::new(),
+                simulate_transactions: simulate_transactions,
+                precompute: precompute,
             },
         };
 
         return Ok(dispatcher);
     }
 
-    pub fn run<T: DApp<()>>(&self) {
+    /// Makes a submission strategy of the dapp available to its requests,
+    /// which select it with `Strategy::Named`
+    pub fn register_strategy(
+        &self,
+        name: &str,
+        strategy: Arc<dyn SubmitStrategy>,
+    ) {
+        self.assets
+            .transaction_manager
+            .lock()
+            .unwrap()
+            .register_strategy(name, strategy);
+    }
+
+    /// Keeps the leader lease in the given backend instead of the file
+    /// of the configuration, which still gives its id and ttl
+    pub fn set_lease_backend(
+        &mut self,
+        backend: Arc<dyn LeaseBackend>,
+    ) -> Result<()> {
+        let lease = self.config.leader_lease.as_ref().ok_or(Error::from(
+            ErrorKind::ConfigError(String::from(
+                "Need a leader_lease to keep it in another backend",
+            )),
+        ))?;
+        self.assets.leadership = Leadership::new(backend, &lease.id, lease.ttl);
+        Ok(())
+    }
+
+    /// Executes the command given in the command line: either runs the
+    /// dispatcher or performs a single operational task and returns
+    pub fn execute<T: DApp<Params = ()>>(&self) -> Result<()> {
+        let main_concern = self.config.main_concern.clone();
+        match self.config.command {
+            Command::MigrateDb
+            | Command::ValidateConfig
+            | Command::VerifyAuditLog { .. }
+            | Command::DryRun { .. }
+            | Command::DiffReactions { .. } => {}
+            _ => migrate::check(&self.config.working_path)?,
+        }
+        match self.config.command {
+            Command::Send { .. }
+            | Command::Cancel { .. }
+            | Command::Stress { .. }
+                if self.config.observer =>
+            {
+                return Err(Error::from(ErrorKind::ConfigError(String::from(
+                    "An observer never sends transactions",
+                ))));
+            }
+            _ => {}
+        }
+        match self.config.command.clone() {
+            Command::Run => {
+                self.run::<T>();
+                Ok(())
+            }
+            Command::ValidateConfig => self.validate_config(),
+            Command::VerifyAuditLog { input } => {
+                let key_path = self.config.audit_key_path.as_ref().ok_or(
+                    Error::from(ErrorKind::ConfigError(String::from(
+                        "Need an audit_key_path to verify the audit log",
+                    ))),
+                )?;
+                let path = input
+                    .unwrap_or(self.config.working_path.join(audit::AUDIT_LOG));
+                let count = audit::verify(&path, &audit::read_key(key_path)?)?;
+                info!("All {} entries of {} verified", count, path.display());
+                Ok(())
+            }
+            Command::MigrateDb => {
+                let initial = migrate::migrate(
+                    &self.config.working_path,
+                    self.config.storage,
+                )?;
+                if initial == migrate::SCHEMA_VERSION {
+                    info!(
+                        "Data already at schema version {}",
+                        migrate::SCHEMA_VERSION
+                    );
+                } else {
+                    info!(
+                        "Data upgraded from schema version {} to {}",
+                        initial,
+                        migrate::SCHEMA_VERSION
+                    );
+                }
+                Ok(())
+            }
+            Command::ListInstances { active } => {
+                let indices = Retry::new()
+                    .run(|| {
+                        self.assets
+                            .state_manager
+                            .lock()
+                            .unwrap()
+                            .get_indices(main_concern, active)
+                            .wait()
+                    })
+                    .chain_err(|| format!("could not get instance indices"))?;
+                println!("{}", serde_json::to_string_pretty(&indices)?);
+                Ok(())
+            }
+            Command::ShowInstance {
+                index,
+                history: true,
+            } => {
+                let history =
+                    self.assets.history.of(main_concern, index).chain_err(
+                        || format!("could not get history of {}", index),
+                    )?;
+                println!("{}", serde_json::to_string_pretty(&history)?);
+                Ok(())
+            }
+            Command::ShowInstance {
+                index,
+                history: false,
+            } => {
+                let instance = Retry::new()
+                    .run(|| {
+                        self.assets
+                            .state_manager
+                            .lock()
+                            .unwrap()
+                            .get_instance(main_concern, index)
+                            .wait()
+                    })
+                    .chain_err(|| {
+                        format!("could not get instance {}", index)
+                    })?;
+                let archive = self.assets.archive.lock().unwrap();
+                let pretty_instance = dapp::get_pretty_instance::<T>(
+                    &instance,
+                    &self
+                        .assets
+                        .services
+                        .for_concern(&main_concern)
+                        .context(&archive),
+                    &(),
+                )?;
+                println!("{}", serde_json::to_string_pretty(&pretty_instance)?);
+                Ok(())
+            }
+            Command::Send {
+                function,
+                params,
+                contract,
+                value,
+            } => {
+                let concern = self.contract_concern(contract)?;
+                let transaction_manager =
+                    self.assets.transaction_manager.lock().unwrap();
+                let data = transaction_manager
+                    .parse_params(&concern, &function, &params)?;
+                transaction_manager
+                    .send(TransactionRequest {
+                        concern: concern,
+                        value: value.map(|value| value.0).unwrap_or_default(),
+                        function: function,
+                        data: data,
+                        gas: None,
+                        strategy: Strategy::Simplest,
+                        contract_name: None,
+                        deadline_block: None,
+                        deadline_timestamp: None,
+                        stake_value: None,
+                    })
+                    .wait()
+            }
+            Command::ExportState { output } => {
+                let context = Arc::new(self.status_context(&self.assets));
+                let snapshot = snapshot::take::<T>(&context)
+                    .wait()
+                    .chain_err(|| format!("could not take snapshot"))?;
+                // archive entries hold large proofs, stored compressed
+                compress::write_file(
+                    &output,
+                    &serde_json::to_vec_pretty(&snapshot)?,
+                )?;
+                info!(
+                    "Exported {} instances to {}",
+                    snapshot.instances.len(),
+                    output.display()
+                );
+                Ok(())
+            }
+            Command::DryRun { input, output } => {
+                let snapshot: Snapshot =
+                    serde_json::from_slice(&compress::read_file(&input)?)
+                        .chain_err(|| {
+                            format!("invalid snapshot in {}", input.display())
+                        })?;
+                let stream =
+                    dryrun::run::<T>(&snapshot, &self.assets.services.config)?;
+                std::fs::write(&output, serde_json::to_vec_pretty(&stream)?)
+                    .chain_err(|| {
+                        format!("could not write {}", output.display())
+                    })?;
+                info!(
+                    "Wrote the reactions to {} instances to {}",
+                    stream.reactions.len(),
+                    output.display()
+                );
+                Ok(())
+            }
+            Command::DiffReactions { before, after } => {
+                let read =
+                    |path: &std::path::PathBuf| -> Result<ReactionStream> {
+                        let content = std::fs::read(path).chain_err(|| {
+                            format!("could not read {}", path.display())
+                        })?;
+                        Ok(serde_json::from_slice(&content).chain_err(
+                            || {
+                                format!(
+                                    "invalid reactions in {}",
+                                    path.display()
+                                )
+                            },
+                        )?)
+                    };
+                let diffs = dryrun::diff(&read(&before)?, &read(&after)?)?;
+                for diff in diffs.iter() {
+                    warn!("{}", diff);
+                }
+                if !diffs.is_empty() {
+                    return Err(Error::from(format!(
+                        "reactions to {} instances differ",
+                        diffs.len()
+                    )));
+                }
+                info!("Reactions are the same");
+                Ok(())
+            }
+            Command::ImportState { input } => {
+                let snapshot: Snapshot =
+                    serde_json::from_slice(&compress::read_file(&input)?)
+                        .chain_err(|| {
+                            format!("invalid snapshot in {}", input.display())
+                        })?;
+                if snapshot.main_concern != main_concern {
+                    return Err(Error::from(ErrorKind::ConfigError(format!(
+                        "snapshot is of concern {}, not of the main concern",
+                        snapshot.main_concern
+                    ))));
+                }
+                info!(
+                    "Running on {} instances of snapshot taken at {}",
+                    snapshot.instances.len(),
+                    snapshot.taken_at
+                );
+                let mut assets = self.assets.clone();
+                assets.state_manager =
+                    Arc::new(Mutex::new(SnapshotReader::new(&snapshot)));
+                assets.services.state = assets.state_manager.clone();
+                assets.archive.lock().unwrap().restore(snapshot.archive);
+                self.run_with::<T>(assets);
+                Ok(())
+            }
+            Command::Cancel { nonce, contract } => {
+                let concern = self.contract_concern(contract)?;
+                self.assets
+                    .transaction_manager
+                    .lock()
+                    .unwrap()
+                    .cancel(concern, U256::from(nonce))
+                    .wait()
+            }
+            Command::ShowSpending => {
+                let spending = self.assets.spending.list()?;
+                println!("{}", serde_json::to_string_pretty(&spending)?);
+                Ok(())
+            }
+            Command::OverrideBudget { address } => {
+                let address = parse_address(&address)?;
+                if self.assets.budgets.override_budget(address)? {
+                    info!("Budget of {} overridden", checksummed(&address));
+                } else {
+                    info!(
+                        "Budget of {} was already overridden",
+                        checksummed(&address)
+                    );
+                }
+                Ok(())
+            }
+            Command::EnforceBudget { address } => {
+                let address = parse_address(&address)?;
+                if self.assets.budgets.enforce_budget(address)? {
+                    info!("Budget of {} enforced again", checksummed(&address));
+                } else {
+                    info!(
+                        "Budget of {} was not overridden",
+                        checksummed(&address)
+                    );
+                }
+                Ok(())
+            }
+            Command::PauseConcern { address } => {
+                let address = parse_address(&address)?;
+                if self.assets.paused.pause(address)? {
+                    info!("Paused transactions of {}", checksummed(&address));
+                } else {
+                    info!(
+                        "Transactions of {} were already paused",
+                        checksummed(&address)
+                    );
+                }
+                Ok(())
+            }
+            Command::Stress {
+                disputes,
+                function,
+                params,
+                duration,
+            } => {
+                if !self.config.testing {
+                    return Err(Error::from(ErrorKind::ConfigError(
+                        String::from(
+                            "stress tests only run against a dev chain, in \
+                             testing mode",
+                        ),
+                    )));
+                }
+                self.instantiate(disputes, function, params)?;
+
+                // report once the dispatcher ran for the given duration
+                let status = self.assets.status.clone();
+                let transport = self._web3.transport().clone();
+                let start = Sample::take(&status.lock().unwrap(), &transport);
+                let started_at = Instant::now();
+                std::thread::spawn(move || {
+                    std::thread::sleep(duration.0);
+                    let end = Sample::take(&status.lock().unwrap(), &transport);
+                    let report = StressReport::new(
+                        disputes,
+                        started_at.elapsed(),
+                        start,
+                        end,
+                    );
+                    match serde_json::to_string_pretty(&report) {
+                        Ok(report) => println!("{}", report),
+                        Err(e) => error!("Could not print report: {}", e),
+                    }
+                    std::process::exit(0);
+                });
+                self.run::<T>();
+                Ok(())
+            }
+            Command::Gc { concern } => {
+                let address = parse_address(&concern)?;
+                if self
+                    .config
+                    .concerns
+                    .iter()
+                    .any(|concern| concern.contract_address == address)
+                {
+                    return Err(Error::from(ErrorKind::ConfigError(format!(
+                        "{} is a concern of the configuration, remove it \
+                         before deleting its data",
+                        checksummed(&address)
+                    ))));
+                }
+                let mut removed = ConcernDirs::new(&self.config.working_path)
+                    .remove(address)?;
+                // the stores shared by the concerns keep entries of it too,
+                // except the machine hashes, which other disputes reuse
+                for concern in self.assets.spending.remove_contract(address)? {
+                    if !removed.contains(&concern) {
+                        removed.push(concern);
+                    }
+                }
+                let sessions = self
+                    .assets
+                    .archive
+                    .lock()
+                    .unwrap()
+                    .remove_contract_sessions(address)?;
+                let paused = self.assets.paused.resume(address)?;
+                let overridden = self.assets.budgets.enforce_budget(address)?;
+                if removed.is_empty() && sessions == 0 && !paused && !overridden
+                {
+                    info!("No data kept for {}", checksummed(&address));
+                }
+                for concern in removed.iter() {
+                    info!("Deleted the data of concern ({})", concern);
+                }
+                if sessions > 0 {
+                    info!(
+                        "Deleted {} machine sessions of {}",
+                        sessions,
+                        checksummed(&address)
+                    );
+                }
+                Ok(())
+            }
+            Command::ResumeConcern { address } => {
+                let address = parse_address(&address)?;
+                if self.assets.paused.resume(address)? {
+                    info!("Resumed transactions of {}", checksummed(&address));
+                } else {
+                    info!(
+                        "Transactions of {} were not paused",
+                        checksummed(&address)
+                    );
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // sends the given number of calls to a function of the main concern,
+    // waiting for all of them to be sent
+    fn instantiate(
+        &self,
+        disputes: usize,
+        function: String,
+        params: Vec<String>,
+    ) -> Result<()> {
+        let main_concern = self.config.main_concern;
+        let transaction_manager =
+            self.assets.transaction_manager.lock().unwrap();
+        let data = transaction_manager.parse_params(
+            &main_concern,
+            &function,
+            &params,
+        )?;
+        info!("Instantiating {} disputes with {}", disputes, function);
+        let sent = (0..disputes).map(|_| {
+            transaction_manager.send(TransactionRequest {
+                concern: main_concern,
+                value: U256::zero(),
+                function: function.clone(),
+                data: data.clone(),
+                gas: None,
+                strategy: Strategy::Simplest,
+                contract_name: None,
+                deadline_block: None,
+                deadline_timestamp: None,
+                stake_value: None,
+            })
+        });
+        future::join_all(sent.collect::<Vec<_>>())
+            .wait()
+            .chain_err(|| format!("could not instantiate disputes"))?;
+        Ok(())
+    }
+
+    // concern of the named contract, or the main concern if none is given
+    fn contract_concern(&self, contract: Option<String>) -> Result<Concern> {
+        match contract {
+            Some(name) => self.config.contracts.get(&name).cloned().ok_or(
+                Error::from(ErrorKind::ConfigError(format!(
+                    "unknown contract: {}",
+                    name
+                ))),
+            ),
+            None => Ok(self.config.main_concern.clone()),
+        }
+    }
+
+    // loading the configuration already checked it can be parsed, here we
+    // look for the problems that only show up when running, reporting
+    // all of them at once
+    fn validate_config(&self) -> Result<()> {
+        println!("Configuration: {}", self.config);
+        let mut problems: Vec<String> = vec![];
+
+        for concern in self.config.concerns.iter() {
+            match self.config.abis.get(concern) {
+                Some(concern_abi) => {
+                    let loaded = std::fs::read_to_string(&concern_abi.abi)
+                        .map_err(Error::from)
+                        .and_then(|s| -> Result<()> {
+                            let v: serde_json::Value =
+                                serde_json::from_str(&s[..])?;
+                            ethabi::Contract::load(
+                                serde_json::to_string(&v["abi"])?.as_bytes(),
+                            )?;
+                            Ok(())
+                        });
+                    if let Err(e) = loaded {
+                        problems.push(format!(
+                            "abi {} of concern ({}) is invalid: {}",
+                            concern_abi.abi.display(),
+                            concern,
+                            e
+                        ));
+                    }
+                }
+                None => {
+                    problems.push(format!("concern ({}) has no abi", concern))
+                }
+            }
+
+            let code = self
+                .assets
+                .network_of(concern)
+                .chain
+                .lock()
+                .unwrap()
+                .code(concern.contract_address)
+                .wait();
+            match code {
+                Ok(ref code) if code.0.is_empty() => problems.push(format!(
+                    "no contract code deployed at {:#x}",
+                    concern.contract_address
+                )),
+                Ok(_) => {}
+                Err(e) => problems.push(format!(
+                    "could not get code at {:#x}: {}",
+                    concern.contract_address, e
+                )),
+            }
+        }
+
+        if !self.config.working_path.is_dir() {
+            problems.push(format!(
+                "working path {} is not a directory",
+                self.config.working_path.display()
+            ));
+        }
+
+        let mut ports = HashSet::new();
+        ports.insert(self.config.query_port);
+        if let Some(status_port) = self.config.status_port {
+            if !ports.insert(status_port) {
+                problems.push(format!(
+                    "status port {} is the same as the query port",
+                    status_port
+                ));
+            }
+        }
+
+        if let Some(status_grpc_port) = self.config.status_grpc_port {
+            if !ports.insert(status_grpc_port) {
+                problems.push(format!(
+                    "status grpc port {} is already used",
+                    status_grpc_port
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            println!("Configuration is valid");
+            return Ok(());
+        }
+        for problem in problems.iter() {
+            println!("Problem: {}", problem);
+        }
+        Err(Error::from(ErrorKind::ConfigError(format!(
+            "{} problem(s) found",
+            problems.len()
+        ))))
+    }
+
+    // what the status server and snapshots read from the given assets
+    fn status_context(&self, assets: &Assets) -> StatusContext {
+        StatusContext {
+            main_concern: self.config.main_concern.clone(),
+            concerns: self.config.concerns.clone(),
+            contracts: self.config.contracts.clone(),
+            labels: self.config.labels.clone(),
+            authenticator: Arc::new(Authenticator::new(
+                self.config.api_keys.clone(),
+            )),
+            transaction_manager: assets.transaction_manager.clone(),
+            state_manager: assets.state_manager.clone(),
+            archive: assets.archive.clone(),
+            board: assets.status.clone(),
+            watchdog: assets.watchdog.clone(),
+            paused: assets.paused.clone(),
+            services: assets.services.clone(),
+            spending: assets.spending.clone(),
+            budgets: assets.budgets.clone(),
+            tracker: assets.tracker.clone(),
+            history: assets.history.clone(),
+            leadership: assets.leadership.clone(),
+            transport: self._web3.transport().clone(),
+        }
+    }
+
+    pub fn run<T: DApp<Params = ()>>(&self) {
+        self.run_with::<T>(self.assets.clone())
+    }
+
+    fn run_with<T: DApp<Params = ()>>(&self, assets_run: Assets) {
         // get owned copies of main_concern and assets to move into task
         let main_concern_run = (&self).config.main_concern.clone();
-        let assets_run = (&self).assets.clone();
+        let polled_concerns = (&self).config.polled_concerns();
         let port = (&self).config.query_port;
         let polling_interval = (&self).config.polling_interval;
+        let status_port = (&self).config.status_port;
+        let status_context = self.status_context(&assets_run);
+        let transaction_manager = assets_run.transaction_manager.clone();
+        let spending = assets_run.spending.clone();
+        let labels = assets_run.labels.clone();
+
+        // sessions left unrestored are still re-attached to as their
+        // instances react
+        if let Err(e) = restore_sessions(&assets_run) {
+            print_error(&e.chain_err(|| "could not restore machine sessions"));
+        }
+        // adopt the instances created before the dispatcher first ran,
+        // even by a concern that cannot instantiate, before polling; a
+        // failed backfill is resumed by the scans of the polling cycles
+        if self.config.backfill {
+            for concern in polled_concerns.iter() {
+                let backfilled = assets_run
+                    .state_manager
+                    .lock()
+                    .unwrap()
+                    .backfill(*concern)
+                    .wait();
+                match backfilled {
+                    Ok(indices) => {
+                        info!(
+                            "Adopting {} instances of {}",
+                            indices.len(),
+                            labels.describe(concern)
+                        );
+                        assets_run
+                            .tracker
+                            .lock()
+                            .unwrap()
+                            .discovered(*concern, &indices, true);
+                    }
+                    Err(e) => print_error(&e.chain_err(|| {
+                        format!(
+                            "could not backfill instances of {}",
+                            labels.describe(concern)
+                        )
+                    })),
+                }
+            }
+        }
+        // spawn a thread to renew the leader lease, taking it first so that
+        // a dispatcher running alone sends transactions from the start
+        let leadership = assets_run.leadership.clone();
+        if leadership.is_shared() {
+            leadership.renew();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(leadership.period());
+                leadership.renew();
+            });
+        }
+
+        // spawn a thread to stop on SIGTERM or SIGINT once the reactions
+        // still running got their transactions sent and recorded
+        let shutdown = assets_run.shutdown.clone();
+        let shutdown_timeout = self.config.shutdown_timeout;
+        let archive = assets_run.archive.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = shutdown.wait_for_signal() {
+                print_error(&e);
+                return;
+            }
+            info!(
+                "Waiting for {} reactions to finish, for at most {:?}",
+                shutdown.running(),
+                shutdown_timeout
+            );
+            if !shutdown.drain(shutdown_timeout) {
+                warn!(
+                    "Exiting with {} reactions still running, their \
+                     transactions may be sent again on restart",
+                    shutdown.running()
+                );
+            }
+            if let Err(e) = archive.lock().unwrap().flush() {
+                print_error(&e.chain_err(|| "could not flush archive"));
+                std::process::exit(1);
+            }
+            info!("Dispatcher stopped");
+            std::process::exit(0);
+        });
+
+        let authenticator = status_context.authenticator.clone();
+        if authenticator.is_open() {
+            warn!(
+                "No api keys configured, the query and status ports are \
+                 read-only; configure a transact key to send transactions \
+                 through them"
+            );
+        }
+
+        // serve the status over grpc too, for as long as the server is kept
+        let _status_grpc = match self.config.status_grpc_port {
+            Some(port) => match status::serve_grpc(
+                port,
+                Arc::new(self.status_context(&assets_run)),
+            ) {
+                Ok(server) => Some(server),
+                Err(e) => {
+                    error!("could not serve status over grpc: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        // spawn a thread to report concerns whose react loop stalled
+        for concern in polled_concerns.iter() {
+            assets_run
+                .watchdog
+                .lock()
+                .unwrap()
+                .watch(*concern, Instant::now());
+        }
+        let watchdog = assets_run.watchdog.clone();
+        let restart_on_stall = self.config.restart_on_stall;
+        let labels = self.config.labels.clone();
+        std::thread::spawn(move || loop {
+            let period = watchdog.lock().unwrap().stall_timeout() / 2;
+            std::thread::sleep(period.max(Duration::from_secs(1)));
+            for stalled in watchdog.lock().unwrap().check(Instant::now()) {
+                error!(
+                    "CRITICAL: concern {} made no progress for {}s ({} stalls \
+                     so far)",
+                    labels.describe(&stalled.concern),
+                    stalled.idle_for,
+                    stalled.stalls
+                );
+                if restart_on_stall {
+                    error!("Shutting down dispatcher to be restarted");
+                    std::process::exit(1);
+                }
+            }
+        });
+
+        // spawn a thread to warn when a configured ENS name moves, as the
+        // address resolved at startup keeps being used until a restart
+        let ens_names = self.config.ens_names.clone();
+        if !ens_names.is_empty() {
+            let web3 = self._web3.clone();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(ENS_CHECK_PERIOD);
+                for (name, address) in ens_names.iter() {
+                    match configuration::ens::resolve(&web3, name) {
+                        Ok(current) if current != *address => warn!(
+                            "ENS name {} now resolves to {:#x} instead of \
+                             {:#x}, restart the dispatcher to follow it",
+                            name, current, address
+                        ),
+                        Ok(_) => {}
+                        Err(e) => warn!("Could not check {}: {}", name, e),
+                    }
+                }
+            });
+        }
+
+        // spawn a thread to restart the hashers spawned by the dispatcher
+        // when they exit
+        let hashers = self.hashers.clone();
+        if !hashers.is_empty() {
+            std::thread::spawn(move || loop {
+                std::thread::sleep(HASHER_CHECK_PERIOD);
+                for hasher in hashers.iter() {
+                    if let Err(e) = hasher.restart_if_exited() {
+                        error!(
+                            "Could not restart hasher on port {}: {}",
+                            hasher.port(),
+                            e
+                        );
+                    }
+                }
+            });
+        }
 
         // spawn a thread to monitor worker state
         let worker_opt = self.config.worker.clone();
@@ -206,6 +1278,7 @@ impl Dispatcher {
             tokio::spawn(
                 background_process::<T>(
                     main_concern_run,
+                    polled_concerns,
                     assets_run,
                     query_rx,
                     polling_interval,
@@ -218,18 +1291,24 @@ impl Dispatcher {
                 }),
             );
 
+            // account for what the mined transactions spent
+            tokio::spawn(account_spending(
+                transaction_manager,
+                spending,
+                labels,
+                polling_interval,
+            ));
+
+            // serve the status of the dispatcher, when asked to
+            if let Some(status_port) = status_port {
+                tokio::spawn(status::serve::<T>(
+                    bind_address(status_port),
+                    status_context,
+                ));
+            }
+
             // start listening to port for state queries
-            let addr = match std::env::var_os("DOCKER") {
-                Some(val) => {
-                    if val == "TRUE" {
-                        trace!("Binding to 0.0.0.0 as dispatcher running inside a docker");
-                        ([0, 0, 0, 0], port).into()
-                    } else {
-                        ([127, 0, 0, 1], port).into()
-                    }
-                }
-                None => ([127, 0, 0, 1], port).into(),
-            };
+            let addr = bind_address(port);
             let listener = tokio::net::TcpListener::bind(&addr)
                 .expect("could not bind to port");
 
@@ -239,7 +1318,10 @@ impl Dispatcher {
             Server::builder(listener.incoming())
                 .serve(move || {
                     let tx = query_tx.clone();
-                    service_fn(move |req| replier(tx.clone(), req))
+                    let authenticator = authenticator.clone();
+                    service_fn(move |req| {
+                        replier(tx.clone(), authenticator.clone(), req)
+                    })
                 })
                 // .with_graceful_shutdown(shutdown_rx)
                 .map_err(|e| error!("error in socket {}", e))
@@ -247,6 +1329,126 @@ impl Dispatcher {
     }
 }
 
+// re-attaches the instances still active to the machine manager sessions
+// recorded before a restart, and forgets those of the instances that ended
+// while the dispatcher was down
+fn restore_sessions(assets: &Assets) -> Result<()> {
+    let archive = assets.archive.lock().unwrap();
+    let mut active: HashMap<Concern, Vec<usize>> = HashMap::new();
+    for (key, session_id) in archive.sessions()? {
+        let concern = key.concern;
+        if !active.contains_key(&concern) {
+            let indices = Retry::new().run(|| {
+                assets
+                    .state_manager
+                    .lock()
+                    .unwrap()
+                    .get_indices(concern, true)
+                    .wait()
+            })?;
+            active.insert(concern, indices);
+        }
+        let is_active = active[&concern]
+            .iter()
+            .any(|index| U256::from(*index) == key.index);
+        if is_active {
+            info!(
+                "Re-attaching session {} to instance {} of {}, from cycle {}",
+                session_id,
+                key.index,
+                assets.labels.describe(&concern),
+                archive.last_checkpoint(concern, key.index)?.unwrap_or(0)
+            );
+            archive.restore_session(concern, key.index);
+        } else {
+            info!(
+                "Forgetting session {} of ended instance {} of {}",
+                session_id,
+                key.index,
+                assets.labels.describe(&concern)
+            );
+            archive.remove_session(concern, key.index)?;
+        }
+    }
+    Ok(())
+}
+
+// records the spending of mined transactions on every polling interval,
+// failures are retried on the next one
+fn account_spending(
+    transaction_manager: Arc<Mutex<TransactionManager>>,
+    spending: Arc<SpendStore>,
+    labels: Arc<ConcernLabels>,
+    polling_interval: Duration,
+) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+    Box::new(
+        Interval::new(
+            Instant::now(),
+            polling_interval.max(Duration::from_secs(1)),
+        )
+        .map_err(|e| error!("spending timer failed: {}", e))
+        .for_each(move |_| {
+            let spending = spending.clone();
+            let labels = labels.clone();
+            transaction_manager.lock().unwrap().mined_spending().then(
+                move |mined| {
+                    match mined {
+                        Ok(mined) => {
+                            for spent in mined.iter() {
+                                match spending.record(spent) {
+                                    Ok(total) => info!(
+                                        "Transaction {:?} of {} used {} gas, \
+                                         {} wei spent so far",
+                                        spent.hash,
+                                        labels.describe(&spent.concern),
+                                        spent.gas_used,
+                                        total.wei_spent
+                                    ),
+                                    Err(e) => warn!(
+                                        "Could not record spending of {:?}: {}",
+                                        spent.hash, e
+                                    ),
+                                }
+                            }
+                        }
+                        Err(e) => warn!("Could not get spending: {}", e),
+                    }
+                    Ok(())
+                },
+            )
+        }),
+    )
+}
+
+// contract address given by the operator, with or without 0x, warning
+// about a checksum that does not match
+fn parse_address(address: &str) -> Result<Address> {
+    configuration::checksum::parse(address, false).map_err(|_| {
+        Error::from(ErrorKind::ConfigError(format!(
+            "invalid address: {}",
+            address
+        )))
+    })
+}
+
+// address to bind servers to, exposed to other containers when running
+// inside a docker
+fn bind_address(port: u16) -> std::net::SocketAddr {
+    match std::env::var_os("DOCKER") {
+        Some(val) => {
+            if val == "TRUE" {
+                trace!(
+                    "Binding to 0.0.0.0 as dispatcher running inside a docker"
+                );
+                ([0, 0, 0, 0], port).into()
+            } else {
+                ([127, 0, 0, 1], port).into()
+            }
+        }
+        None => ([127, 0, 0, 1], port).into(),
+    }
+}
+
 /// The query handle comes with a query and a oneshot communication
 /// channel for sending the result
 #[derive(Debug)]
@@ -278,11 +1480,12 @@ enum Query {
 
 // creates a future representing the background process that organizes
 // all instances and delegates tasks
-fn background_process<T: DApp<()>>(
+fn background_process<T: DApp<Params = ()>>(
     main_concern: Concern,
+    polled_concerns: Vec<Concern>,
     assets: Assets,
     query_rx: mpsc::Receiver<QueryHandle>,
-    polling_interval: u64,
+    polling_interval: Duration,
 ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
     // during the course of execution, there are periodic (Tick) events,
     // or external queries concerning the current state. we need to react
@@ -292,16 +1495,8 @@ fn background_process<T: DApp<()>>(
         Asked(QueryHandle),
     }
 
-    // !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-    // use this state to avoid treating an instance twice
-    // !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-    struct State {
-        _handled: HashSet<usize>,
-    }
-
     // Interval at which we poll and dispatch instances
-    let tick_duration = Duration::from_secs(polling_interval);
-    let interval = Interval::new_interval(tick_duration)
+    let interval = Interval::new_interval(polling_interval)
         .map(|_| Message::Tick)
         .map_err(|_| ());
 
@@ -310,11 +1505,6 @@ fn background_process<T: DApp<()>>(
         // Merge queries received from channel to the stream of Ticks
         .select(interval);
 
-    // Initialize state as empty
-    let initial_state = State {
-        _handled: HashSet::new(),
-    };
-
     // clone assets to move them inside the closure
     let main_concern_fold = main_concern.clone();
     let assets_fold = assets.clone();
@@ -322,10 +1512,10 @@ fn background_process<T: DApp<()>>(
 
     let message_fold = messages
         .fold(
-            initial_state,
-            move |_state,
+            (),
+            move |_,
                 message|
-                -> Box<dyn Future<Item = State, Error = ()> + Send> {
+                -> Box<dyn Future<Item = (), Error = ()> + Send> {
                 match message {
                     // message is a query, answer it appropriately
                     Message::Asked(q) => {
@@ -392,7 +1582,7 @@ fn background_process<T: DApp<()>>(
                                             {
                                                 Ok(instance) => {
                                                     let archive = assets_fold.archive.lock().unwrap();
-                                                    let pretty_instance = T::get_pretty_instance(&instance, &archive, &()).unwrap();
+                                                    let pretty_instance = dapp::get_pretty_instance::<T>(&instance, &assets_fold.services.for_concern(&main_concern_fold).context(&archive), &()).unwrap();
                                                     let answer = Answer {
                                                         status_code: StatusCode::OK.as_u16(),
                                                         body: serde_json::to_string(&pretty_instance).unwrap(),
@@ -425,23 +1615,74 @@ fn background_process<T: DApp<()>>(
                                     }
                                 }
                             },
+                            Query::Post(ref body)
+                                if assets_fold.services.config.observer =>
+                            {
+                                info!(
+                                    "Refusing post to instance {} as an \
+                                     observer",
+                                    body.index
+                                );
+                                let answer = Answer {
+                                    status_code: StatusCode::FORBIDDEN.as_u16(),
+                                    body: "dispatcher is an observer".into(),
+                                };
+                                q.oneshot.send(
+                                    serde_json::to_string(&answer).unwrap()
+                                ).unwrap();
+                            }
+                            Query::Post(ref body) if assets_fold
+                                .services
+                                .config
+                                .role_of(&main_concern_fold)
+                                .reactive_only =>
+                            {
+                                info!(
+                                    "Refusing post to instance {} of a \
+                                     reactive only concern",
+                                    body.index
+                                );
+                                let answer = Answer {
+                                    status_code: StatusCode::FORBIDDEN.as_u16(),
+                                    body: "concern is reactive only".into(),
+                                };
+                                q.oneshot.send(
+                                    serde_json::to_string(&answer).unwrap()
+                                ).unwrap();
+                            }
                             Query::Post(body) => {
                                 // clone assets to move inside
                                 let main_concern_index = main_concern_fold.clone();
                                 let assets_index = assets_fold.clone();
 
-                                tokio::spawn(
-                                    execute_reaction::<T>(
-                                        main_concern_index,
-                                        body.index,
-                                        Some(body.payload),
-                                        assets_index.clone(),
-                                    )
-                                    .map_err(|e| print_error(&e)),
-                                );
-                                let answer = Answer {
-                                    status_code: StatusCode::OK.as_u16(),
-                                    body: "".into(),
+                                let answer = match assets_fold
+                                    .shutdown
+                                    .start_reaction()
+                                {
+                                    Some(running) => {
+                                        tokio::spawn(
+                                            execute_reaction::<T>(
+                                                main_concern_index,
+                                                body.index,
+                                                Some(body.payload),
+                                                assets_index.clone(),
+                                            )
+                                            .then(move |res| {
+                                                drop(running);
+                                                res
+                                            })
+                                            .map_err(|e| print_error(&e)),
+                                        );
+                                        Answer {
+                                            status_code: StatusCode::OK.as_u16(),
+                                            body: "".into(),
+                                        }
+                                    }
+                                    None => Answer {
+                                        status_code: StatusCode::SERVICE_UNAVAILABLE
+                                            .as_u16(),
+                                        body: "dispatcher is shutting down".into(),
+                                    },
                                 };
                                 // send result back from oneshot channel
                                 q.oneshot.send(
@@ -450,66 +1691,46 @@ fn background_process<T: DApp<()>>(
                             }
                         };
 
-                        // for now we don't keep track of the state
-                        Box::new(future::ok::<State, ()>(
-                            State {
-                                _handled: HashSet::new(),
-                            },
-                        ))
+                        // live instances are kept by the tracker
+                        Box::new(future::ok::<(), ()>(()))
                     },
                     // received a periodic Tick. We need to check
                     // for new instances and launch tasks for each.
                     Message::Tick => {
-                        // clone assets to have static lifetime
-                        let state_manager_indices =
-                            assets_fold.state_manager.clone();
-
-                        trace!(
-                            "Getting indices for {:?}",
-                            main_concern_fold
-                        );
-                        let stream_of_indices = state_manager_indices
-                            .lock()
-                            .unwrap()
-                            .get_indices(main_concern_fold.clone(), true)
-                            .map_err(|e| {
-                                print_error(&e.chain_err(|| {
-                                    format!("could not get issue indices")
-                                }));
-                            })
-                            .map(|vector_of_indices| {
-                                stream::iter_ok(vector_of_indices)
-                            })
-                            .flatten_stream();
-
-                        // clone assets to move inside each index
-                        let main_concern_index = main_concern_fold.clone();
-                        let assets_index = assets_fold.clone();
-
-                        let tx_fold = tx.clone();
-                        let returned_state = stream_of_indices
-                            .inspect(|index| {
-                                trace!("Processing index {}", index)
-                            })
-                            .for_each(move |index| {
-                                let tx_fold_clone = tx_fold.clone();
-                                tokio::spawn(
-                                    execute_reaction::<T>(
-                                        main_concern_index,
-                                        index,
-                                        None,
-                                        assets_index.clone(),
-                                    )
-                                    .map_err(|e| {
-                                        print_error(&e);
-                                        tx_fold_clone.send(()).wait();
-                                    })
-                                );
-                                Ok(())
+                        // no new react cycle starts once stopping
+                        if assets_fold.shutdown.is_requested() {
+                            return Box::new(future::ok::<(), ()>(()));
+                        }
+                        let polled_concerns_tick = polled_concerns.clone();
+                        let assets_tick = assets_fold.clone();
+                        let tx_tick = tx.clone();
+
+                        // refresh the clocks used by the dapp deadlines
+                        // before looking at the instances, skipping the
+                        // concerns of a node that did not answer
+                        let returned_state = refresh_networks(&assets_fold)
+                            .and_then(move |refreshed| {
+                                let config = assets_tick.services.config.clone();
+                                future::join_all(
+                                    polled_concerns_tick
+                                        .into_iter()
+                                        .filter(move |concern| {
+                                            refreshed.contains(
+                                                &config
+                                                    .network_of(concern)
+                                                    .cloned(),
+                                            )
+                                        })
+                                        .map(move |concern| {
+                                            poll_concern::<T>(
+                                                concern,
+                                                assets_tick.clone(),
+                                                tx_tick.clone(),
+                                            )
+                                        }),
+                                )
                             })
-                            .map(|_| State {
-                                _handled: HashSet::new(),
-                            });
+                            .map(|_| ());
                         Box::new(returned_state)
                     }
                 }
@@ -536,7 +1757,175 @@ fn background_process<T: DApp<()>>(
     ));
 }
 
-fn execute_reaction<T: DApp<()>>(
+// reads the latest block of the node of each network, refreshing the clock
+// its concerns follow, and gives the networks whose node answered
+fn refresh_networks(
+    assets: &Assets,
+) -> Box<dyn Future<Item = HashSet<Option<String>>, Error = ()> + Send> {
+    let refreshed: Vec<_> = assets
+        .networks
+        .iter()
+        .map(|(network, network_assets)| {
+            let network = network.clone();
+            let clock = network_assets.clock.clone();
+            let status = assets.status.clone();
+            let latest_block =
+                network_assets.chain.lock().unwrap().latest_block();
+            latest_block.then(move |res| {
+                let refreshed = match res {
+                    Ok(block) => {
+                        status.lock().unwrap().network_block_seen(
+                            network.as_ref(),
+                            block.number,
+                            block.timestamp,
+                        );
+                        clock.update(block.timestamp);
+                        Some(network)
+                    }
+                    Err(e) => {
+                        print_error(&e.chain_err(|| match &network {
+                            Some(name) => format!(
+                                "could not get latest block of network {}",
+                                name
+                            ),
+                            None => format!("could not get latest block"),
+                        }));
+                        None
+                    }
+                };
+                future::ok::<Option<Option<String>>, ()>(refreshed)
+            })
+        })
+        .collect();
+
+    let status = assets.status.clone();
+    let alerts = assets.alerts.clone();
+    Box::new(future::join_all(refreshed).map(move |refreshed| {
+        if let Some(delay) = status.lock().unwrap().worst_node_delay() {
+            alerts.node_delay(delay);
+        }
+        refreshed
+            .into_iter()
+            .filter_map(|network| network)
+            .collect::<HashSet<_>>()
+    }))
+}
+
+// looks for the instances of a concern and spawns a reaction to each,
+// those of high value disputes first, skipping the ones asleep or still
+// reacting; a failed reaction is sent to `failed`, stopping the dispatcher
+fn poll_concern<T: DApp<Params = ()>>(
+    concern: Concern,
+    assets: Assets,
+    failed: mpsc::Sender<()>,
+) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+    // clone assets to have static lifetime
+    let state_manager_indices = assets.state_manager.clone();
+    let alerts_indices = assets.alerts.clone();
+    let tracker_indices = assets.tracker.clone();
+    let can_instantiate =
+        assets.services.config.role_of(&concern).can_instantiate;
+    let archive_indices = assets.archive.clone();
+    let config_indices = assets.services.config.clone();
+
+    trace!("Getting indices for {:?}", concern);
+    let stream_of_indices = Retry::new()
+        .run_future(move || {
+            state_manager_indices
+                .lock()
+                .unwrap()
+                .get_indices(concern, true)
+        })
+        .map_err(move |e| {
+            print_error(&e.chain_err(|| {
+                format!("could not get issue indices of {:?}", concern)
+            }));
+        })
+        .map(move |mut vector_of_indices| {
+            alerts_indices.instances_seen(concern, &vector_of_indices);
+            tracker_indices.lock().unwrap().discovered(
+                concern,
+                &vector_of_indices,
+                can_instantiate,
+            );
+            let mut archive = archive_indices.lock().unwrap();
+            archive.set_active(concern, &vector_of_indices);
+            // high value disputes react first
+            vector_of_indices.sort_by_key(|index| {
+                !config_indices.is_high_stake(archive.stake_of(concern, *index))
+            });
+            stream::iter_ok(vector_of_indices)
+        })
+        .flatten_stream();
+
+    // clone assets to move inside each index
+    let watchdog = assets.watchdog.clone();
+    let assets_index = assets.clone();
+
+    let returned_state = stream_of_indices
+        .inspect(|index| trace!("Processing index {}", index))
+        .for_each(move |index| {
+            // skip instances the dapp asked to sleep on
+            if let Some(wake_up) =
+                assets_index.wake_ups.lock().unwrap().get(&(concern, index))
+            {
+                let now: U256 = Timestamp(
+                    assets_index.network_of(&concern).clock.timestamp(),
+                )
+                .into();
+                if now < *wake_up {
+                    trace!("Skipping index {} until {}", index, wake_up);
+                    return Ok(());
+                }
+            }
+            // one reaction at a time for each instance
+            let tracker = assets_index.tracker.clone();
+            if !tracker.lock().unwrap().start_reaction(concern, index) {
+                trace!("Reaction to index {} still pending", index);
+                return Ok(());
+            }
+            // counted until its transactions are sent, for a shutdown to
+            // wait for
+            let running = match assets_index.shutdown.start_reaction() {
+                Some(running) => running,
+                None => {
+                    tracker.lock().unwrap().finish_reaction(concern, index);
+                    return Ok(());
+                }
+            };
+            let failed = failed.clone();
+            tokio::spawn(
+                execute_reaction::<T>(
+                    concern,
+                    index,
+                    None,
+                    assets_index.clone(),
+                )
+                .then(move |res| {
+                    tracker.lock().unwrap().finish_reaction(concern, index);
+                    drop(running);
+                    res
+                })
+                .map_err(move |e| {
+                    print_error(&e);
+                    // a panicking dapp does not take the other concerns
+                    // down
+                    if let ErrorKind::DAppPanicked(..) = e.kind() {
+                        return;
+                    }
+                    failed.send(()).wait();
+                }),
+            );
+            Ok(())
+        })
+        .map(move |_| {
+            // the cycle got through, tell the watchdog
+            watchdog.lock().unwrap().progressed(concern, Instant::now());
+        });
+    Box::new(returned_state)
+}
+
+fn execute_reaction<T: DApp<Params = ()>>(
     main_concern: Concern,
     index: usize,
     post_action: Option<String>,
@@ -550,12 +1939,21 @@ fn execute_reaction<T: DApp<()>>(
             .get_instance(main_concern, index)
             .and_then(
             move |instance| -> Box<dyn Future<Item = (), Error = Error> + Send> {
-                let transaction_manager =
-                    assets.transaction_manager.lock().unwrap();
+                // the time of the network the concern is deployed on
+                let clock = assets.network_of(&main_concern).clock.clone();
+                assets.tracker.lock().unwrap().observed(
+                    index,
+                    &instance,
+                    clock.timestamp(),
+                );
                 let mut archive = assets.archive.lock().unwrap();
+                // the dapp may report another value at stake while reacting
+                if let Some(stake) = schema::stake_value(&instance) {
+                    archive.report_stake(main_concern, index, stake);
+                }
 
                 // get reaction from dapp to this instance
-                let reaction = match T::react(&instance, &archive, &post_action, &())
+                let reaction = match dapp::react_guarded::<T>(&instance, &assets.services.for_concern(&main_concern).context(&archive), &post_action, &())
                 // TODO: may need to uncomment below line
                 //    .chain_err(|| format!("could not get dapp reaction"))
                 {
@@ -566,7 +1964,12 @@ fn execute_reaction<T: DApp<()>>(
                             // try to get it from the service through grpc request
                             ErrorKind::ResponseMissError(service, key, method, request) => {
                                 trace!("handling ResponseMissError for service: {}, and key: {}", service, key);
-                                return send_grpc_request(&mut archive, assets.clients.clone(), request.to_vec(), method.into(), service.into(), key.into());
+                                if archive.is_fetching(key) {
+                                    trace!("response for key {} is being prefetched", key);
+                                    return Box::new(future::ok::<(), _>(()));
+                                }
+                                let permit = machine_job(&assets, &archive, service, (main_concern, index));
+                                return send_grpc_request(assets.archive.clone(), assets.clients.clone(), permit, request.to_vec(), method.into(), service.into(), key.into(), Some((main_concern, index)));
                             },
                             // the archive consists invalid data for `key`,
                             // remove the entry and let `ResponseMissError` handle the rest
@@ -590,34 +1993,109 @@ fn execute_reaction<T: DApp<()>>(
                                     progress: *progress,
                                     description: description.clone(),
                                 };
-                                archive.insert_service(contract.clone(), service_status);
-                                return send_grpc_request(&mut archive, assets.clients.clone(), request.to_vec(), method.into(), service.into(), key.into());
+                                let previous_status = archive.insert_service(contract.clone(), service_status.clone());
+                                report_service_progress(contract, &previous_status, &service_status);
+                                let permit = machine_job(&assets, &archive, service, (main_concern, index));
+                                return send_grpc_request(assets.archive.clone(), assets.clients.clone(), permit, request.to_vec(), method.into(), service.into(), key.into(), Some((main_concern, index)));
 
                             },
+                            // only this reaction fails, named by its label
+                            ErrorKind::DAppPanicked(_, details) => {
+                                return Box::new(future::err(Error::from(ErrorKind::DAppPanicked(assets.labels.describe(&main_concern), details.clone()))));
+                            },
                             _ => {
                                 return Box::new(future::err(e));
                             }
                         }
                     }
                 };
-                trace!(
-                    "Reaction to instance {} of {} is: {:?}",
+                // recorded as json, which dry runs and tools can compare
+                let record_json = serde_json::to_string(&reaction.record())
+                    .unwrap_or_else(|_| format!("{:?}", reaction));
+                debug!(
+                    target: "reactions",
+                    "Reaction to instance {} of {} is: {}",
                     index,
-                    main_concern.contract_address,
-                    reaction,
+                    assets.labels.describe(&main_concern),
+                    record_json,
                 );
+                let prefetches = archive.take_prefetches();
+                if assets.precompute {
+                    for prefetch in prefetches {
+                        send_prefetch(&assets, prefetch, (main_concern, index));
+                    }
+                } else {
+                    for prefetch in prefetches {
+                        archive.fetch_failed(&prefetch.key);
+                    }
+                }
+                let block = {
+                    let mut status = assets.status.lock().unwrap();
+                    status.reaction_computed();
+                    status.last_block_of(
+                        assets.services.config.network_of(&main_concern),
+                    )
+                };
+                let entry = HistoryEntry {
+                    block: block.as_ref().and_then(|block| block.number),
+                    timestamp: clock.timestamp(),
+                    state: instance.json_data.clone(),
+                    reaction: record_json,
+                };
+                if let Err(e) =
+                    assets.history.record(main_concern, index, &entry)
+                {
+                    warn!(
+                        "Could not record history of instance {}: {}",
+                        index, e
+                    );
+                }
+
+                // any reaction other than IdleUntil wakes the instance up,
+                // unless idle instances of the concern are polled rarely
+                {
+                    let mut wake_ups = assets.wake_ups.lock().unwrap();
+                    let poll_interval =
+                        assets.poll_intervals.get(&main_concern).cloned();
+                    match (&reaction, poll_interval) {
+                        (Reaction::IdleUntil(timestamp), _) => {
+                            wake_ups.insert((main_concern, index), *timestamp);
+                        }
+                        (Reaction::Idle, Some(interval)) => {
+                            let now = Timestamp(clock.timestamp());
+                            wake_ups.insert(
+                                (main_concern, index),
+                                U256::from(now + interval),
+                            );
+                        }
+                        _ => {
+                            wake_ups.remove(&(main_concern, index));
+                        }
+                    }
+                }
+
+                // transactions of high value disputes are escalated earlier
+                let stake = archive.stake_of(main_concern, index);
 
                 // act according to dapp reaction
                 match reaction {
                     Reaction::Transaction(transaction_request) => {
-                        process_transaction_request(
+                        send_transactions(
                             main_concern,
                             index,
-                            transaction_request,
-                            &transaction_manager,
+                            with_stake(vec![transaction_request], stake),
+                            &assets,
                         )
                     }
-                    Reaction::Idle => {
+                    Reaction::Transactions(transaction_requests) => {
+                        send_transactions(
+                            main_concern,
+                            index,
+                            with_stake(transaction_requests, stake),
+                            &assets,
+                        )
+                    }
+                    Reaction::Idle | Reaction::IdleUntil(_) => {
                         Box::new(future::ok::<(), _>(()))
                     }
                     Reaction::Terminate => {
@@ -629,12 +2107,270 @@ fn execute_reaction<T: DApp<()>>(
     );
 }
 
+// the requests of a reaction carry the value at stake in the instance,
+// unless the dapp gave them another
+fn with_stake(
+    transaction_requests: Vec<TransactionRequest>,
+    stake: Option<U256>,
+) -> Vec<TransactionRequest> {
+    transaction_requests
+        .into_iter()
+        .map(|mut request| {
+            request.stake_value = request.stake_value.or(stake);
+            request
+        })
+        .collect()
+}
+
+// log the progress reported by a long running service, warning when it
+// did not move since the last poll, so that a slow machine run can be told
+// apart from a hung emulator
+fn report_service_progress(
+    contract: &str,
+    previous: &Option<state::ServiceStatus>,
+    current: &state::ServiceStatus,
+) {
+    match previous {
+        Some(previous)
+            if previous.service_method == current.service_method
+                && previous.progress == current.progress =>
+        {
+            warn!(
+                "Service {} ({}) for {} made no progress since last poll: \
+                 {}%, {}",
+                current.service_name,
+                current.service_method,
+                contract,
+                current.progress,
+                current.description
+            );
+        }
+        _ => {
+            info!(
+                "Service {} ({}) for {} progress: {}%, {}",
+                current.service_name,
+                current.service_method,
+                contract,
+                current.progress,
+                current.description
+            );
+        }
+    }
+}
+
+// the concern a request is sent to, which is the one of another contract
+// of the dapp when the request names it, if that contract is configured
+fn target_of(
+    request: &TransactionRequest,
+    contracts: &HashMap<String, Concern>,
+) -> Option<Concern> {
+    match &request.contract_name {
+        Some(name) => contracts.get(name).cloned(),
+        None => Some(request.concern),
+    }
+}
+
+// sends the transactions of a reaction, unless a contract they involve is
+// paused
+fn send_transactions(
+    main_concern: Concern,
+    index: usize,
+    transaction_requests: Vec<TransactionRequest>,
+    assets: &Assets,
+) -> Box<dyn Future<Item = (), Error = Error> + Send> {
+    // an observer only records what it would have sent
+    if assets.services.config.observer {
+        let functions: Vec<&str> = transaction_requests
+            .iter()
+            .map(|request| request.function.as_str())
+            .collect();
+        info!(
+            "Observing, not sending {} for instance {} of {}",
+            functions.join(", "),
+            index,
+            assets.labels.describe(&main_concern)
+        );
+        return Box::new(future::ok::<(), _>(()));
+    }
+
+    // followers leave the transactions to the leader
+    if !assets.leadership.is_leader() {
+        info!(
+            "Following, not sending transactions for instance {} of {}",
+            index,
+            assets.labels.describe(&main_concern)
+        );
+        return Box::new(future::ok::<(), _>(()));
+    }
+
+    // paused contracts are tracked but not acted upon, looking at the
+    // contract each request is sent to rather than the one of its concern
+    let contracts = &assets.services.config.contracts;
+    let mut addresses = vec![main_concern.contract_address];
+    addresses.extend(
+        transaction_requests
+            .iter()
+            .filter_map(|request| target_of(request, contracts))
+            .map(|concern| concern.contract_address),
+    );
+    let paused = addresses
+        .iter()
+        .map(|address| assets.paused.is_paused(address))
+        .collect::<Result<Vec<bool>>>();
+    match paused {
+        Ok(ref paused) if paused.contains(&true) => {
+            let functions: Vec<&str> = transaction_requests
+                .iter()
+                .map(|request| request.function.as_str())
+                .collect();
+            info!(
+                "Paused, not sending {} for instance {} of {}",
+                functions.join(", "),
+                index,
+                assets.labels.describe(&main_concern)
+            );
+            return Box::new(future::ok::<(), _>(()));
+        }
+        Ok(_) => {}
+        Err(e) => return Box::new(future::err(e)),
+    }
+
+    // concerns over budget only get their essential calls sent
+    for request in transaction_requests.iter() {
+        let allowed = match target_of(request, contracts) {
+            Some(concern) => assets.budgets.allows(
+                &concern,
+                &request.function,
+                &*assets.alerts,
+            ),
+            None => Ok(true),
+        };
+        match allowed {
+            Ok(true) => {}
+            Ok(false) => {
+                info!(
+                    "Over budget, not sending {} for instance {} of {}",
+                    request.function,
+                    index,
+                    assets.labels.describe(&main_concern)
+                );
+                return Box::new(future::ok::<(), _>(()));
+            }
+            Err(e) => return Box::new(future::err(e)),
+        }
+    }
+
+    if assets.simulate_transactions {
+        simulate_transactions(
+            main_concern,
+            index,
+            &transaction_requests,
+            &*assets.sender.lock().unwrap(),
+        );
+    }
+
+    send_in_order(
+        main_concern,
+        index,
+        transaction_requests,
+        assets.sender.clone(),
+        assets.status.clone(),
+        assets.alerts.clone(),
+    )
+}
+
+// runs each transaction of a reaction as a call against the latest block,
+// warning about those that would revert, to catch bugs of the dapp before
+// they cost gas. Transactions are still sent, and each one is run without
+// the earlier ones of the reaction, on which it may depend.
+fn simulate_transactions(
+    main_concern: Concern,
+    index: usize,
+    transaction_requests: &[TransactionRequest],
+    sender: &dyn TransactionSender,
+) {
+    for request in transaction_requests {
+        let function = request.function.clone();
+        tokio::spawn(sender.simulate(request.clone()).then(move |res| {
+            match res {
+                Ok(()) => trace!(
+                    "Simulated {} for instance {} of {}",
+                    function,
+                    index,
+                    main_concern
+                ),
+                Err(e) => warn!(
+                    "Simulation of {} for instance {} of {} failed: {}",
+                    function, index, main_concern, e
+                ),
+            }
+            Ok(())
+        }));
+    }
+}
+
+// sends transactions one after the other, each once the previous one was
+// accepted by the node, so that those of the same account take increasing
+// nonces. Once one is not sent the following ones are dropped, as they may
+// depend on it.
+fn send_in_order(
+    main_concern: Concern,
+    index: usize,
+    transaction_requests: Vec<TransactionRequest>,
+    sender: Arc<Mutex<dyn TransactionSender>>,
+    status: Arc<Mutex<StatusBoard>>,
+    alerts: Arc<Alerts>,
+) -> Box<dyn Future<Item = (), Error = Error> + Send> {
+    Box::new(
+        stream::iter_ok::<_, Error>(transaction_requests)
+            .fold(true, move |sending, transaction_request| {
+                if !sending {
+                    info!(
+                        "Dropping {} for instance {} of {}, as an earlier \
+                         transaction was not sent",
+                        transaction_request.function, index, main_concern
+                    );
+                    return Either::A(future::ok(false));
+                }
+                let status = status.clone();
+                let alerts = alerts.clone();
+                let function = transaction_request.function.clone();
+                let id = status.lock().unwrap().transaction_started(
+                    main_concern,
+                    index,
+                    function.clone(),
+                );
+                let sent = process_transaction_request(
+                    main_concern,
+                    index,
+                    transaction_request,
+                    &*sender.lock().unwrap(),
+                );
+                Either::B(sent.then(move |res| {
+                    status.lock().unwrap().transaction_finished(id);
+                    if let Err(e) = &res {
+                        alerts.notify(&Alert::TransactionFailed {
+                            concern: main_concern,
+                            index: index,
+                            function: function,
+                            reason: e.to_string(),
+                        });
+                    }
+                    res
+                }))
+            })
+            .map(|_| ()),
+    )
+}
+
+// sends a transaction, resolving to whether it was sent or left for a
+// later tick
 fn process_transaction_request(
     main_concern: Concern,
     index: usize,
     transaction_request: TransactionRequest,
-    transaction_manager: &TransactionManager,
-) -> Box<dyn Future<Item = (), Error = Error> + Send> {
+    sender: &dyn TransactionSender,
+) -> Box<dyn Future<Item = bool, Error = Error> + Send> {
     info!(
         "Send transaction (concern {:?}, index {}): {:?}",
         main_concern, index, transaction_request
@@ -643,8 +2379,21 @@ fn process_transaction_request(
     let index_clone = index.clone();
     let transaction_request_clone = transaction_request.clone();
     Box::new(
-        transaction_manager
+        sender
             .send(transaction_request)
+            .map(|_| true)
+            // a full queue is not fatal, the instance is reconsidered on
+            // the next tick
+            .or_else(|e| match e.kind() {
+                ErrorKind::SubmissionQueueFull(account) => {
+                    warn!(
+                        "Transaction queue of {} is full, will try again",
+                        account
+                    );
+                    Ok(false)
+                }
+                _ => Err(e),
+            })
             .map_err(move |e| {
                 e.chain_err(move || {
                     format!(
@@ -663,9 +2412,11 @@ fn process_transaction_request(
 // connnection.
 fn replier(
     tx: mpsc::Sender<QueryHandle>,
+    authenticator: Arc<Authenticator>,
     req: Request<Body>,
 ) -> Box<dyn Future<Item = Response<Body>, Error = std::io::Error> + Send> {
     let (resp_tx, resp_rx) = oneshot::channel();
+    let granted = authenticator.level_of(&req);
     let (_, body) = req.into_parts();
 
     let body_future = body
@@ -677,7 +2428,7 @@ fn replier(
         })
         .concat2();
     let query_future = body_future
-        .and_then(|body| {
+        .and_then(move |body| {
             let query: Query = match serde_json::from_slice(&body) {
                 Ok(q) => q,
                 Err(e) => {
@@ -685,40 +2436,55 @@ fn replier(
                     Query::Indices
                 }
             };
+            // posts may lead to transactions, other queries only read
+            let required = match query {
+                Query::Post(_) => AccessLevel::Transact,
+                _ => AccessLevel::ReadOnly,
+            };
+            if let Err((status, reason)) = auth::allow(granted, required) {
+                return Either::A(future::ok(
+                    Response::builder()
+                        .status(status)
+                        .body(Body::from(reason))
+                        .unwrap(),
+                ));
+            }
             // send to background task: the query and the tx for oneshot answer
-            tx.send(QueryHandle {
-                query: query,
-                oneshot: resp_tx,
-            })
-            .map_err(|e| {
-                std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("request error {}", e),
-                )
-            })
-            .and_then(|_| {
-                // received response from background task
-                resp_rx
-                    .and_then(|answer_string| {
-                        let answer: Answer =
-                            serde_json::from_str(&answer_string).unwrap();
-                        let response = Response::builder()
-                            .header("Content-Type", " application/json")
-                            .status(
-                                StatusCode::from_u16(answer.status_code)
-                                    .unwrap(),
+            Either::B(
+                tx.send(QueryHandle {
+                    query: query,
+                    oneshot: resp_tx,
+                })
+                .map_err(|e| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::Other,
+                        format!("request error {}", e),
+                    )
+                })
+                .and_then(|_| {
+                    // received response from background task
+                    resp_rx
+                        .and_then(|answer_string| {
+                            let answer: Answer =
+                                serde_json::from_str(&answer_string).unwrap();
+                            let response = Response::builder()
+                                .header("Content-Type", " application/json")
+                                .status(
+                                    StatusCode::from_u16(answer.status_code)
+                                        .unwrap(),
+                                )
+                                .body(Body::from(answer.body))
+                                .unwrap();
+                            Ok(response)
+                        })
+                        .map_err(|e| {
+                            std::io::Error::new(
+                                std::io::ErrorKind::Other,
+                                format!("request error {}", e),
                             )
-                            .body(Body::from(answer.body))
-                            .unwrap();
-                        Ok(response)
-                    })
-                    .map_err(|e| {
-                        std::io::Error::new(
-                            std::io::ErrorKind::Other,
-                            format!("request error {}", e),
-                        )
-                    })
-            })
+                        })
+                }),
+            )
         })
         .map_err(|e| {
             std::io::Error::new(
@@ -729,43 +2495,100 @@ fn replier(
     Box::new(query_future)
 }
 
+// waits for a slot to drive a machine run, given right away for the
+// requests to other services
+fn machine_job(
+    assets: &Assets,
+    archive: &Archive,
+    service: &str,
+    owner: (Concern, usize),
+) -> Box<dyn Future<Item = Option<JobPermit>, Error = Error> + Send> {
+    if !assets.services.config.is_machine_service(service) {
+        return Box::new(future::ok(None));
+    }
+    let deadline = archive.deadline_of(owner.0, owner.1);
+    let high_stake = assets
+        .services
+        .config
+        .is_high_stake(archive.stake_of(owner.0, owner.1));
+    Box::new(assets.machine_jobs.acquire(deadline, high_stake).map(Some))
+}
+
+// sends a request a dapp expects to make later, after the machine runs of
+// the instances that reported a deadline
+fn send_prefetch(assets: &Assets, prefetch: Prefetch, owner: (Concern, usize)) {
+    let permit: Box<
+        dyn Future<Item = Option<JobPermit>, Error = Error> + Send,
+    > = if assets.services.config.is_machine_service(&prefetch.service) {
+        Box::new(assets.machine_jobs.acquire(None, false).map(Some))
+    } else {
+        Box::new(future::ok(None))
+    };
+    let archive = assets.archive.clone();
+    let key = prefetch.key.clone();
+    tokio::spawn(
+        send_grpc_request(
+            assets.archive.clone(),
+            assets.clients.clone(),
+            permit,
+            prefetch.request,
+            prefetch.method,
+            prefetch.service,
+            prefetch.key,
+            Some(owner),
+        )
+        .map_err(move |e| {
+            archive.lock().unwrap().fetch_failed(&key);
+            warn!("Could not prefetch response for key {}: {}", key, e);
+        }),
+    );
+}
+
+// the archive is only locked to store the response, so that other
+// instances react while the service works
 fn send_grpc_request(
-    archive: &mut Archive,
+    archive_arc: Arc<Mutex<Archive>>,
     clients_arc: Arc<Mutex<HashMap<String, Arc<Mutex<Client>>>>>,
+    permit: Box<dyn Future<Item = Option<JobPermit>, Error = Error> + Send>,
     request: Vec<u8>,
     method: String,
     service: String,
     key: String,
+    owner: Option<(Concern, usize)>,
 ) -> Box<dyn Future<Item = (), Error = Error> + Send> {
-    let clients = clients_arc.lock().unwrap();
-    if let Some(client) = clients.get(&service.clone()) {
-        let response =
-            grpc_call_unary(client.clone(), request.clone(), method.clone())
-                .wait_drop_metadata();
-
-        match response {
-            Ok(resp) => {
-                archive.insert_response(key.clone(), Ok(resp));
-                return Box::new(future::ok::<(), _>(()));
-            }
-            Err(e) => match e {
-                grpc::Error::GrpcMessage(msg) => {
-                    archive.insert_response(
-                        key.clone(),
-                        Err(msg.grpc_message.clone()),
-                    );
-                    return Box::new(future::ok::<(), _>(()));
-                }
-                _ => {
-                    return Box::new(future::err(e.into()));
-                }
-            },
+    let client = match clients_arc.lock().unwrap().get(&service) {
+        Some(client) => client.clone(),
+        None => {
+            return Box::new(future::err(Error::from(format!(
+                "Fail to get grpc client of {} service",
+                service
+            ))));
         }
-    }
-    return Box::new(future::err(Error::from(format!(
-        "Fail to get grpc client of {} service",
-        service
-    ))));
+    };
+    Box::new(permit.and_then(move |permit| {
+        // errors reported by the service itself are stored in the archive,
+        // only failures to reach it are retried, on a timer so that the
+        // worker thread keeps serving the other instances meanwhile
+        Retry::new()
+            .run_future(move || {
+                grpc_call_unary(client.clone(), request.clone(), method.clone())
+                    .drop_metadata()
+                    .then(|res| match res {
+                        Ok(resp) => Ok(Ok(resp)),
+                        Err(grpc::Error::GrpcMessage(msg)) => {
+                            Ok(Err(msg.grpc_message))
+                        }
+                        Err(e) => Err(Error::from(e)),
+                    })
+            })
+            .map(move |response| {
+                drop(permit);
+                archive_arc
+                    .lock()
+                    .unwrap()
+                    .insert_response_for(key, response, owner);
+            })
+    }))
 }
 
 // send grpc request with binary data
@@ -785,3 +2608,78 @@ fn grpc_call_unary(
 
     client.call_unary(RequestOptions::new(), req, method)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transaction::{CallParams, MockSender};
+
+    fn request(concern: Concern) -> TransactionRequest {
+        TransactionRequest {
+            concern: concern,
+            value: U256::zero(),
+            function: String::from("claimVictory"),
+            data: CallParams::new(),
+            gas: None,
+            strategy: Strategy::Simplest,
+            contract_name: None,
+            deadline_block: None,
+            deadline_timestamp: None,
+            stake_value: None,
+        }
+    }
+
+    #[test]
+    fn full_submission_queue_is_not_fatal() {
+        let concern = Concern {
+            contract_address: Address::zero(),
+            user_address: Address::zero(),
+        };
+        let sender = MockSender::new()
+            .answer(Err(Error::from(ErrorKind::SubmissionQueueFull(
+                String::from("0x0"),
+            ))))
+            .answer(Err(Error::from("reverted")));
+
+        let sent =
+            process_transaction_request(concern, 0, request(concern), &sender);
+        assert_eq!(sent.wait().ok(), Some(false));
+        let sent =
+            process_transaction_request(concern, 0, request(concern), &sender);
+        assert!(sent.wait().is_err());
+        assert_eq!(sender.sent().len(), 2);
+    }
+
+    #[test]
+    fn transactions_after_one_not_sent_are_dropped() {
+        let concern = Concern {
+            contract_address: Address::zero(),
+            user_address: Address::zero(),
+        };
+        let send = |sender: &Arc<Mutex<MockSender>>| {
+            send_in_order(
+                concern,
+                0,
+                vec![request(concern), request(concern), request(concern)],
+                sender.clone(),
+                Arc::new(Mutex::new(StatusBoard::new())),
+                Arc::new(Alerts::new(vec![], 0, ConcernLabels::new())),
+            )
+            .wait()
+        };
+
+        let sender = Arc::new(Mutex::new(
+            MockSender::new().answer(Ok(())).answer(Err(Error::from(
+                ErrorKind::SubmissionQueueFull(String::from("0x0")),
+            ))),
+        ));
+        assert!(send(&sender).is_ok());
+        assert_eq!(sender.lock().unwrap().sent().len(), 2);
+
+        let sender = Arc::new(Mutex::new(
+            MockSender::new().answer(Err(Error::from("reverted"))),
+        ));
+        assert!(send(&sender).is_err());
+        assert_eq!(sender.lock().unwrap().sent().len(), 1);
+    }
+}
@@ -0,0 +1,108 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Hasher emulators spawned by the dispatcher for the concerns whose
+//! machine backend is local, so that a test deployment runs as a single
+//! process. Each one is restarted if it exits and killed with the
+//! dispatcher.
+
+use super::error::*;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::Mutex;
+
+/// A hasher emulator serving grpc on a port of localhost
+pub struct LocalHasher {
+    hasher: PathBuf,
+    port: u16,
+    args: Vec<String>,
+    child: Mutex<Child>,
+}
+
+// starts the hasher on the given port
+fn spawn(hasher: &Path, port: u16, args: &[String]) -> Result<Child> {
+    Command::new(hasher)
+        .arg("--port")
+        .arg(port.to_string())
+        .args(args)
+        .spawn()
+        .chain_err(|| format!("could not start {}", hasher.display()))
+}
+
+impl LocalHasher {
+    pub fn spawn(hasher: &Path, port: u16, args: &[String]) -> Result<Self> {
+        info!("Starting hasher {} on port {}", hasher.display(), port);
+        Ok(LocalHasher {
+            hasher: hasher.to_path_buf(),
+            port: port,
+            args: args.to_vec(),
+            child: Mutex::new(spawn(hasher, port, args)?),
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Starts the hasher again if it exited, returning whether it did
+    pub fn restart_if_exited(&self) -> Result<bool> {
+        let mut child = self.child.lock().unwrap();
+        match child.try_wait()? {
+            None => Ok(false),
+            Some(status) => {
+                warn!(
+                    "Hasher on port {} exited with {}, restarting it",
+                    self.port, status
+                );
+                *child = spawn(&self.hasher, self.port, &self.args)?;
+                Ok(true)
+            }
+        }
+    }
+}
+
+impl Drop for LocalHasher {
+    fn drop(&mut self) {
+        let child = self.child.get_mut().unwrap();
+        if let Err(e) = child.kill() {
+            trace!("Hasher on port {} already stopped: {}", self.port, e);
+        }
+        let _ = child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exited_hashers_are_restarted() {
+        assert!(LocalHasher::spawn(Path::new("/no/hasher"), 1, &[]).is_err());
+
+        // true ignores its arguments and exits right away
+        let hasher = LocalHasher::spawn(Path::new("true"), 50051, &[]).unwrap();
+        hasher.child.lock().unwrap().wait().unwrap();
+        assert!(hasher.restart_if_exited().unwrap());
+    }
+}
@@ -0,0 +1,217 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Versions of the data kept in the working path. The version is written
+//! to a `schema_version` file, and the dispatcher refuses to run on data
+//! of another version: older data is upgraded in place by the
+//! `migrate-db` command, newer data is left alone. Data written before
+//! versioning is of the first version, while a working path with no
+//! database yet gets the current one.
+//!
+//! A change to the format of a database, like the layout of its keys or
+//! of the entries it holds, bumps `SCHEMA_VERSION` and adds the migration
+//! from the previous version to `MIGRATIONS`.
+
+use super::configuration::Storage;
+use super::error::*;
+use super::history;
+use std::fs;
+use std::path::Path;
+
+/// Version of the data written by this dispatcher
+pub const SCHEMA_VERSION: u32 = 2;
+
+const VERSION_FILE: &str = "schema_version";
+
+/// What the dispatcher keeps in the working path, telling apart data
+/// written before versioning from none at all
+const DATA: &[&str] = &[
+    "session_db",
+    "checkpoint_db",
+    "hash_db",
+    "spend_db",
+    "history_db",
+    "dispatcher.sqlite",
+];
+
+/// Upgrade of the data of a version to the next one
+pub struct Migration {
+    pub from: u32,
+    pub description: &'static str,
+    pub run: fn(&Path, Storage) -> Result<()>,
+}
+
+/// Migrations from each version to the next, in order
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    from: 1,
+    description: "history kept in the directory of each concern",
+    run: history::split_by_concern,
+}];
+
+/// Version of the data in the working path, the first one if it was
+/// written before versioning and the current one if there is no data yet
+pub fn stored_version(working_path: &Path) -> Result<u32> {
+    let path = working_path.join(VERSION_FILE);
+    if !path.exists() {
+        if DATA.iter().any(|data| working_path.join(data).exists()) {
+            return Ok(1);
+        }
+        return Ok(SCHEMA_VERSION);
+    }
+    let version = fs::read_to_string(&path)
+        .chain_err(|| format!("could not read {}", path.display()))?;
+    version
+        .trim()
+        .parse()
+        .chain_err(|| format!("invalid schema version in {}", path.display()))
+}
+
+fn store_version(working_path: &Path, version: u32) -> Result<()> {
+    let path = working_path.join(VERSION_FILE);
+    fs::write(&path, format!("{}\n", version))
+        .chain_err(|| format!("could not write {}", path.display()))
+}
+
+/// Fails unless the data in the working path is of the current version,
+/// recording the version of data not versioned yet
+pub fn check(working_path: &Path) -> Result<()> {
+    let version = stored_version(working_path)?;
+    if version > SCHEMA_VERSION {
+        return Err(Error::from(ErrorKind::ConfigError(format!(
+            "data in {} is of schema version {}, unknown to this dispatcher \
+             (at version {})",
+            working_path.display(),
+            version,
+            SCHEMA_VERSION
+        ))));
+    }
+    if version < SCHEMA_VERSION {
+        return Err(Error::from(ErrorKind::ConfigError(format!(
+            "data in {} is of schema version {}, run migrate-db to upgrade \
+             it to version {}",
+            working_path.display(),
+            version,
+            SCHEMA_VERSION
+        ))));
+    }
+    if working_path.is_dir() && !working_path.join(VERSION_FILE).exists() {
+        store_version(working_path, version)?;
+    }
+    Ok(())
+}
+
+/// Upgrades the data in the working path to the current version, returning
+/// the version it was at
+pub fn migrate(working_path: &Path, storage: Storage) -> Result<u32> {
+    migrate_with(working_path, storage, SCHEMA_VERSION, MIGRATIONS)
+}
+
+fn migrate_with(
+    working_path: &Path,
+    storage: Storage,
+    target: u32,
+    migrations: &[Migration],
+) -> Result<u32> {
+    let initial = stored_version(working_path)?;
+    if initial > target {
+        return Err(Error::from(ErrorKind::ConfigError(format!(
+            "data in {} is of schema version {}, newer than version {}",
+            working_path.display(),
+            initial,
+            target
+        ))));
+    }
+    let mut version = initial;
+    while version < target {
+        let migration = migrations
+            .iter()
+            .find(|migration| migration.from == version)
+            .ok_or(Error::from(format!(
+                "no migration from schema version {}",
+                version
+            )))?;
+        info!(
+            "Migrating from schema version {}: {}",
+            version, migration.description
+        );
+        (migration.run)(working_path, storage).chain_err(|| {
+            format!("could not migrate from schema version {}", version)
+        })?;
+        version += 1;
+        // an interrupted migration resumes from the last completed step
+        store_version(working_path, version)?;
+    }
+    Ok(initial)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn touch(working_path: &Path, _storage: Storage) -> Result<()> {
+        let steps = working_path.join("steps");
+        let done = fs::read_to_string(&steps).unwrap_or_default();
+        Ok(fs::write(&steps, format!("{}x", done))?)
+    }
+
+    #[test]
+    fn migrations_run_in_order_up_to_the_target() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        // data written before versioning
+        fs::create_dir_all(dir.join("session_db")).unwrap();
+        let migrations = [
+            Migration {
+                from: 2,
+                description: "second",
+                run: touch,
+            },
+            Migration {
+                from: 1,
+                description: "first",
+                run: touch,
+            },
+        ];
+
+        let initial =
+            migrate_with(dir, Storage::LevelDb, 3, &migrations).unwrap();
+        assert_eq!(initial, 1);
+        assert_eq!(stored_version(dir).unwrap(), 3);
+        assert_eq!(fs::read_to_string(dir.join("steps")).unwrap(), "xx");
+
+        // newer data than known is refused
+        assert!(check(dir).is_err());
+        assert!(migrate_with(dir, Storage::LevelDb, 2, &migrations).is_err());
+    }
+
+    #[test]
+    fn working_path_without_data_is_of_the_current_version() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        assert_eq!(stored_version(dir).unwrap(), SCHEMA_VERSION);
+        check(dir).unwrap();
+        assert!(dir.join(VERSION_FILE).exists());
+    }
+}
@@ -0,0 +1,451 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Alerts about the disputes and the dispatcher itself, sent to the
+//! operator through notifiers like webhooks or mail. The dispatcher raises the
+//! alerts it can tell from the outside, like new instances or failed
+//! transactions, while DApps raise those only they understand, like a
+//! divergence found, through `Archive::alert`.
+
+use super::configuration::{Concern, ConcernLabels, SmtpConfig};
+use super::error::*;
+use super::ethereum_types::U256;
+use super::serde_json;
+use hyper::client::HttpConnector;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Client, Request, Uri};
+use hyper_tls::HttpsConnector;
+use lettre::smtp::authentication::Credentials;
+use lettre::smtp::client::net::ClientTlsParameters;
+use lettre::{ClientSecurity, SmtpClient, SmtpTransport, Transport};
+use lettre_email::EmailBuilder;
+use native_tls::TlsConnector;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::executor::{DefaultExecutor, Executor};
+use web3::futures::Future;
+
+/// Something the operator should hear about
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Alert {
+    DisputeInstantiated {
+        concern: Concern,
+        index: usize,
+    },
+    DivergenceFound {
+        concern: Concern,
+        index: usize,
+        details: String,
+    },
+    TimeoutClaimAvailable {
+        concern: Concern,
+        index: usize,
+    },
+    TransactionFailed {
+        concern: Concern,
+        index: usize,
+        function: String,
+        reason: String,
+    },
+    NodeDelayed {
+        delay: i64,
+        max_delay: i64,
+    },
+    BudgetExceeded {
+        concern: Concern,
+        spent: U256,
+        budget: U256,
+    },
+}
+
+impl Alert {
+    /// One line describing the alert, for chat messages and mail subjects,
+    /// naming concerns by their labels
+    pub fn summary(&self, labels: &ConcernLabels) -> String {
+        match self {
+            Alert::DisputeInstantiated { concern, index } => format!(
+                "New instance {} of {}",
+                index,
+                labels.describe(concern)
+            ),
+            Alert::DivergenceFound {
+                concern,
+                index,
+                details,
+            } => format!(
+                "Divergence found in instance {} of {}: {}",
+                index,
+                labels.describe(concern),
+                details
+            ),
+            Alert::TimeoutClaimAvailable { concern, index } => format!(
+                "Timeout can be claimed in instance {} of {}",
+                index,
+                labels.describe(concern)
+            ),
+            Alert::TransactionFailed {
+                concern,
+                index,
+                function,
+                reason,
+            } => format!(
+                "Transaction {} for instance {} of {} failed: {}",
+                function,
+                index,
+                labels.describe(concern),
+                reason
+            ),
+            Alert::NodeDelayed { delay, max_delay } => format!(
+                "Ethereum node is {}s behind, more than the {}s allowed",
+                delay, max_delay
+            ),
+            Alert::BudgetExceeded {
+                concern,
+                spent,
+                budget,
+            } => format!(
+                "{} spent {} wei, over its budget of {}",
+                labels.describe(concern),
+                spent,
+                budget
+            ),
+        }
+    }
+}
+
+/// Delivers alerts to the operator, without waiting for the delivery
+pub trait Notifier: Send + Sync {
+    fn notify(&self, alert: &Alert);
+}
+
+/// Posts alerts as json to a webhook. The summary is sent as `text` and
+/// `content`, as read by Slack and Discord, together with the alert.
+pub struct WebhookNotifier {
+    url: Uri,
+    client: Client<HttpsConnector<HttpConnector>, Body>,
+    labels: ConcernLabels,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: &str, labels: ConcernLabels) -> Result<WebhookNotifier> {
+        let uri = url.parse::<Uri>().map_err(|e| {
+            Error::from(ErrorKind::ConfigError(format!(
+                "invalid webhook url: {}",
+                e
+            )))
+        })?;
+        let https = HttpsConnector::new(1)
+            .chain_err(|| "could not create tls connector for webhooks")?;
+        Ok(WebhookNotifier {
+            url: uri,
+            client: Client::builder().build(https),
+            labels: labels,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct WebhookBody<'a> {
+    text: String,
+    content: String,
+    alert: &'a Alert,
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify(&self, alert: &Alert) {
+        let summary = alert.summary(&self.labels);
+        let request = Request::post(self.url.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::to_string(&WebhookBody {
+                    text: summary.clone(),
+                    content: summary,
+                    alert: alert,
+                })
+                .unwrap(),
+            ))
+            .expect("webhook request is well formed");
+        let url = self.url.clone();
+        let delivery = self.client.request(request).then(move |res| {
+            match res {
+                Ok(response) if response.status().is_success() => {}
+                Ok(response) => warn!(
+                    "Webhook {} refused alert: {}",
+                    url,
+                    response.status()
+                ),
+                Err(e) => warn!("Could not post alert to {}: {}", url, e),
+            }
+            Ok(())
+        });
+        if let Err(e) = DefaultExecutor::current().spawn(Box::new(delivery)) {
+            warn!("Could not post alert to {}: {:?}", self.url, e);
+        }
+    }
+}
+
+// port of smtp over tls, the others start plain and upgrade with STARTTLS
+const SMTPS_PORT: u16 = 465;
+
+/// Mails alerts to the operator. Alerts are gathered and sent together
+/// every batch interval, so that many instances changing state at once
+/// make a single mail instead of a flood.
+pub struct SmtpNotifier {
+    pending: Arc<Mutex<Vec<Alert>>>,
+}
+
+impl SmtpNotifier {
+    pub fn new(
+        config: &SmtpConfig,
+        labels: ConcernLabels,
+    ) -> Result<SmtpNotifier> {
+        let tls = TlsConnector::new()
+            .chain_err(|| "could not create tls connector for mail")?;
+        let parameters = ClientTlsParameters::new(config.server.clone(), tls);
+        let security = if config.port == SMTPS_PORT {
+            ClientSecurity::Wrapper(parameters)
+        } else {
+            ClientSecurity::Required(parameters)
+        };
+        let mut client =
+            SmtpClient::new((config.server.as_str(), config.port), security)
+                .map_err(|e| {
+                    Error::from(ErrorKind::ConfigError(format!(
+                        "invalid smtp server {}: {}",
+                        config.server, e
+                    )))
+                })?;
+        if let Some((username, password)) = &config.credentials {
+            client = client.credentials(Credentials::new(
+                username.clone(),
+                password.clone(),
+            ));
+        }
+
+        let pending = Arc::new(Mutex::new(vec![]));
+        let batch = pending.clone();
+        let config = config.clone();
+        let mut transport = client.transport();
+        std::thread::Builder::new()
+            .name(String::from("smtp-notifier"))
+            .spawn(move || loop {
+                std::thread::sleep(config.batch_interval);
+                let alerts: Vec<Alert> =
+                    batch.lock().unwrap().drain(..).collect();
+                if alerts.is_empty() {
+                    continue;
+                }
+                if let Err(e) =
+                    send_mail(&mut transport, &config, &labels, &alerts)
+                {
+                    warn!("Could not mail {} alerts: {}", alerts.len(), e);
+                }
+            })
+            .chain_err(|| "could not start smtp notifier")?;
+
+        Ok(SmtpNotifier { pending: pending })
+    }
+}
+
+impl Notifier for SmtpNotifier {
+    fn notify(&self, alert: &Alert) {
+        self.pending.lock().unwrap().push(alert.clone());
+    }
+}
+
+/// Subject and text of the mail reporting a batch of alerts
+fn compose_mail(labels: &ConcernLabels, alerts: &[Alert]) -> (String, String) {
+    let subject = match alerts {
+        [alert] => format!("Dispatcher alert: {}", alert.summary(labels)),
+        _ => format!("Dispatcher alerts: {} events", alerts.len()),
+    };
+    let text = alerts
+        .iter()
+        .map(|alert| format!("- {}\n", alert.summary(labels)))
+        .collect();
+    (subject, text)
+}
+
+fn send_mail(
+    transport: &mut SmtpTransport,
+    config: &SmtpConfig,
+    labels: &ConcernLabels,
+    alerts: &[Alert],
+) -> Result<()> {
+    let (subject, text) = compose_mail(labels, alerts);
+    let mut builder = EmailBuilder::new()
+        .from(config.from.clone())
+        .subject(subject)
+        .text(text);
+    for to in config.to.iter() {
+        builder = builder.to(to.clone());
+    }
+    let email = builder
+        .build()
+        .map_err(|e| Error::from(format!("could not build mail: {}", e)))?;
+    transport
+        .send(email.into())
+        .map_err(|e| Error::from(format!("could not send mail: {}", e)))?;
+    Ok(())
+}
+
+/// Raises alerts to every notifier, remembering what was already told so
+/// that the operator is not flooded on each polling cycle
+pub struct Alerts {
+    notifiers: Vec<Box<dyn Notifier>>,
+    max_delay: i64,
+    labels: ConcernLabels,
+    // instances known by concern, alerting on the ones seen afterwards
+    known: Mutex<HashMap<Concern, HashSet<usize>>>,
+    node_delayed: AtomicBool,
+}
+
+impl Alerts {
+    pub fn new(
+        notifiers: Vec<Box<dyn Notifier>>,
+        max_delay: i64,
+        labels: ConcernLabels,
+    ) -> Self {
+        Alerts {
+            notifiers: notifiers,
+            max_delay: max_delay,
+            labels: labels,
+            known: Mutex::new(HashMap::new()),
+            node_delayed: AtomicBool::new(false),
+        }
+    }
+
+    /// Instances of a concern seen in a polling cycle. Those already there
+    /// when the concern is first polled are not reported as new.
+    pub fn instances_seen(&self, concern: Concern, indices: &[usize]) {
+        let new = {
+            let mut known = self.known.lock().unwrap();
+            let first_seen = !known.contains_key(&concern);
+            let known = known.entry(concern).or_default();
+            let new: Vec<usize> = indices
+                .iter()
+                .filter(|index| known.insert(**index))
+                .cloned()
+                .collect();
+            if first_seen {
+                vec![]
+            } else {
+                new
+            }
+        };
+        for index in new {
+            self.notify(&Alert::DisputeInstantiated {
+                concern: concern,
+                index: index,
+            });
+        }
+    }
+
+    /// Delay of the node, alerting once when it exceeds the maximum
+    /// delay, and again only after it caught up
+    pub fn node_delay(&self, delay: i64) {
+        let delayed = delay > self.max_delay;
+        let was_delayed = self.node_delayed.swap(delayed, Ordering::SeqCst);
+        if delayed && !was_delayed {
+            self.notify(&Alert::NodeDelayed {
+                delay: delay,
+                max_delay: self.max_delay,
+            });
+        }
+    }
+}
+
+impl Notifier for Alerts {
+    fn notify(&self, alert: &Alert) {
+        warn!("Alert: {}", alert.summary(&self.labels));
+        for notifier in self.notifiers.iter() {
+            notifier.notify(alert);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethereum_types::Address;
+
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Notifier for Recorder {
+        fn notify(&self, alert: &Alert) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(alert.summary(&ConcernLabels::new()));
+        }
+    }
+
+    #[test]
+    fn alerts_are_raised_once() {
+        let told = Arc::new(Mutex::new(vec![]));
+        let alerts = Alerts::new(
+            vec![Box::new(Recorder(told.clone()))],
+            60,
+            ConcernLabels::new(),
+        );
+        let concern = Concern {
+            contract_address: Address::zero(),
+            user_address: Address::zero(),
+        };
+
+        alerts.instances_seen(concern, &[0, 1]);
+        alerts.instances_seen(concern, &[1, 2]);
+        alerts.instances_seen(concern, &[2]);
+        alerts.node_delay(30);
+        alerts.node_delay(90);
+        alerts.node_delay(120);
+        alerts.node_delay(10);
+        alerts.node_delay(70);
+
+        let told = told.lock().unwrap();
+        assert_eq!(told.len(), 3);
+        assert!(told[0].starts_with("New instance 2 of"));
+        assert!(told[1].starts_with("Ethereum node is 90s behind"));
+        assert!(told[2].starts_with("Ethereum node is 70s behind"));
+    }
+
+    #[test]
+    fn alerts_are_mailed_in_batches() {
+        let delayed = Alert::NodeDelayed {
+            delay: 90,
+            max_delay: 60,
+        };
+        let labels = ConcernLabels::new();
+        let (subject, text) = compose_mail(&labels, &[delayed.clone()]);
+        assert!(subject.starts_with("Dispatcher alert: Ethereum node"));
+        assert_eq!(text.lines().count(), 1);
+
+        let (subject, text) =
+            compose_mail(&labels, &[delayed.clone(), delayed]);
+        assert_eq!(subject, "Dispatcher alerts: 2 events");
+        assert_eq!(text.lines().count(), 2);
+    }
+}
@@ -0,0 +1,144 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Contracts paused by the operator, for which no transaction is sent while
+//! their instances keep being tracked. They are kept in a json file of the
+//! working path, read again before each transaction, so that a running
+//! dispatcher follows the `pause-concern` and `resume-concern` commands.
+
+use super::error::*;
+use super::ethereum_types::Address;
+use super::layout::write_json;
+use super::serde_json;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// A set of contract addresses kept in a json file of the working path
+#[derive(Clone, Debug)]
+pub struct AddressFile {
+    path: PathBuf,
+}
+
+impl AddressFile {
+    pub fn new(working_path: &Path, name: &str) -> Self {
+        AddressFile {
+            path: working_path.join(name),
+        }
+    }
+
+    /// The addresses in the file, none if it was never written
+    pub fn addresses(&self) -> Result<BTreeSet<Address>> {
+        if !self.path.exists() {
+            return Ok(BTreeSet::new());
+        }
+        let content = std::fs::read_to_string(&self.path)
+            .chain_err(|| format!("could not read {}", self.path.display()))?;
+        Ok(serde_json::from_str(&content).chain_err(|| {
+            format!("invalid addresses in {}", self.path.display())
+        })?)
+    }
+
+    pub fn contains(&self, address: &Address) -> Result<bool> {
+        Ok(self.addresses()?.contains(address))
+    }
+
+    /// Adds an address, returning whether it was missing
+    pub fn insert(&self, address: Address) -> Result<bool> {
+        self.update(|addresses| addresses.insert(address))
+    }
+
+    /// Removes an address, returning whether it was there
+    pub fn remove(&self, address: Address) -> Result<bool> {
+        self.update(|addresses| addresses.remove(&address))
+    }
+
+    // changes the set, rewriting the file
+    fn update<F>(&self, change: F) -> Result<bool>
+    where
+        F: FnOnce(&mut BTreeSet<Address>) -> bool,
+    {
+        let mut addresses = self.addresses()?;
+        let changed = change(&mut addresses);
+        write_json(&self.path, &addresses)?;
+        Ok(changed)
+    }
+}
+
+/// The file holding the paused contract addresses
+#[derive(Clone, Debug)]
+pub struct PauseStore {
+    file: AddressFile,
+}
+
+impl PauseStore {
+    pub fn new(working_path: &Path) -> Self {
+        PauseStore {
+            file: AddressFile::new(working_path, "paused_concerns.json"),
+        }
+    }
+
+    /// The paused contracts
+    pub fn paused(&self) -> Result<BTreeSet<Address>> {
+        self.file.addresses()
+    }
+
+    pub fn is_paused(&self, address: &Address) -> Result<bool> {
+        self.file.contains(address)
+    }
+
+    /// Pauses a contract, returning whether it was running
+    pub fn pause(&self, address: Address) -> Result<bool> {
+        self.file.insert(address)
+    }
+
+    /// Resumes a contract, returning whether it was paused
+    pub fn resume(&self, address: Address) -> Result<bool> {
+        self.file.remove(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn paused_contracts_survive_reopening() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let a = Address::repeat_byte(0xaa);
+        let b = Address::repeat_byte(0xbb);
+
+        let store = PauseStore::new(dir);
+        assert!(!store.is_paused(&a).unwrap());
+        assert!(store.pause(a).unwrap());
+        assert!(!store.pause(a).unwrap());
+        assert!(store.pause(b).unwrap());
+
+        let store = PauseStore::new(dir);
+        assert!(store.resume(a).unwrap());
+        assert!(!store.is_paused(&a).unwrap());
+        assert!(store.is_paused(&b).unwrap());
+    }
+}
@@ -0,0 +1,220 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! The part the user of a concern plays in a dispute, found among the
+//! parties of an instance. DApps used to compare the user with the claimer
+//! and the challenger of their instances each on their own; `role_for`
+//! does it once, for disputes with any number of parties.
+//!
+//! Games between more than two parties, like tournaments, tell the parties
+//! apart by their `Party`, a position among the `Parties` of an instance
+//! and maybe a name, rather than by the two roles.
+
+use super::configuration::Concern;
+use super::dapp::AddressArray;
+use super::error::*;
+use super::ethereum_types::Address;
+
+/// Whether the user makes the claim or disputes it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Claimer,
+    Challenger,
+}
+
+/// A seat in a dispute: its position among the parties of the instance
+/// and its name, if the game names its parties
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Party {
+    pub index: usize,
+    pub name: Option<String>,
+}
+
+impl Party {
+    /// The role of a two-party game taken by this seat, the first party
+    /// being the claimer
+    pub fn role(&self) -> Role {
+        match self.index {
+            0 => Role::Claimer,
+            _ => Role::Challenger,
+        }
+    }
+}
+
+/// The parties of an instance, in the order its contract lists them
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Parties {
+    addresses: Vec<Address>,
+    names: Vec<String>,
+}
+
+impl Parties {
+    pub fn new(addresses: Vec<Address>) -> Self {
+        Parties {
+            addresses: addresses,
+            names: vec![],
+        }
+    }
+
+    /// Parties with a name each, like "claimer" and "challenger"
+    pub fn named(parties: Vec<(&str, Address)>) -> Self {
+        let (names, addresses) = parties
+            .into_iter()
+            .map(|(name, address)| (String::from(name), address))
+            .unzip();
+        Parties {
+            addresses: addresses,
+            names: names,
+        }
+    }
+
+    pub fn addresses(&self) -> &[Address] {
+        &self.addresses
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    /// Address of the party at the given position
+    pub fn address_of(&self, index: usize) -> Option<Address> {
+        self.addresses.get(index).cloned()
+    }
+
+    /// Address of the party of the given name
+    pub fn address_named(&self, name: &str) -> Option<Address> {
+        self.names
+            .iter()
+            .position(|n| n == name)
+            .and_then(|index| self.address_of(index))
+    }
+
+    /// The seat of the user of the concern
+    pub fn party_of(&self, concern: &Concern) -> Result<Party> {
+        let index = party_index(concern, &self.addresses)?;
+        Ok(Party {
+            index: index,
+            name: self.names.get(index).cloned(),
+        })
+    }
+
+    /// Addresses of the parties other than the given one, the opponents of
+    /// the user in a tournament
+    pub fn others(&self, party: &Party) -> Vec<Address> {
+        self.addresses
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index != party.index)
+            .map(|(_, address)| *address)
+            .collect()
+    }
+}
+
+impl From<AddressArray> for Parties {
+    fn from(array: AddressArray) -> Self {
+        Parties::new(array.value)
+    }
+}
+
+/// Position of the user of the concern among the parties of an instance,
+/// the first one if the user plays more than one part, as when testing
+/// against oneself
+pub fn party_index(concern: &Concern, parties: &[Address]) -> Result<usize> {
+    parties
+        .iter()
+        .position(|party| *party == concern.user_address)
+        .ok_or(Error::from(ErrorKind::ContractStateError(
+            format!("{:#x}", concern.contract_address),
+            format!(
+                "user {:#x} is not one of the parties {}",
+                concern.user_address,
+                parties
+                    .iter()
+                    .map(|party| format!("{:#x}", party))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        )))
+}
+
+/// Role of the user of the concern, given the parties of an instance with
+/// the claimer first and the challengers after it
+pub fn role_for(concern: &Concern, parties: &[Address]) -> Result<Role> {
+    Parties::new(parties.to_vec())
+        .party_of(concern)
+        .map(|party| party.role())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn concern(user: u64) -> Concern {
+        Concern {
+            contract_address: Address::zero(),
+            user_address: Address::from_low_u64_be(user),
+        }
+    }
+
+    #[test]
+    fn roles_follow_the_position_among_parties() {
+        let parties: Vec<Address> =
+            (1..4).map(Address::from_low_u64_be).collect();
+        assert_eq!(role_for(&concern(1), &parties).unwrap(), Role::Claimer);
+        assert_eq!(role_for(&concern(3), &parties).unwrap(), Role::Challenger);
+        assert_eq!(party_index(&concern(3), &parties).unwrap(), 2);
+
+        let e = role_for(&concern(4), &parties).unwrap_err().to_string();
+        assert!(e.contains("is not one of the parties"));
+
+        // playing both parts, the user claims
+        let both = vec![Address::from_low_u64_be(1); 2];
+        assert_eq!(role_for(&concern(1), &both).unwrap(), Role::Claimer);
+    }
+
+    #[test]
+    fn named_parties_are_found_by_position_and_name() {
+        let parties = Parties::named(vec![
+            ("claimer", Address::from_low_u64_be(1)),
+            ("first", Address::from_low_u64_be(2)),
+            ("second", Address::from_low_u64_be(3)),
+        ]);
+        let party = parties.party_of(&concern(2)).unwrap();
+        assert_eq!(party.index, 1);
+        assert_eq!(party.name, Some(String::from("first")));
+        assert_eq!(party.role(), Role::Challenger);
+        assert_eq!(
+            parties.others(&party),
+            vec![Address::from_low_u64_be(1), Address::from_low_u64_be(3)]
+        );
+        assert_eq!(
+            parties.address_named("second"),
+            Some(Address::from_low_u64_be(3))
+        );
+    }
+}
@@ -0,0 +1,297 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Validation of the state of an instance before a dapp parses it. The
+//! state manager writes each output of the contract's `getState` as a
+//! field with its name and solidity type, which make the schema of the
+//! state. Each field type of the dapp declares the solidity types it can
+//! parse, so that a tuple of them is checked field by field against that
+//! schema, failing with the field that does not match instead of an
+//! opaque serde error.
+
+use super::dapp::{
+    AddressArray, AddressField, BoolArray, BoolField, Bytes32Array,
+    Bytes32Field, BytesField, ElementType, FixedArray, String32Field,
+    U256Array, U256Field,
+};
+use super::error::*;
+use super::ethereum_types::U256;
+use super::serde::de::DeserializeOwned;
+use super::serde_json;
+use super::state;
+
+/// A field type a dapp parses the state of an instance with
+pub trait StateField {
+    /// The solidity types it parses, as written in the ABI
+    const TYPES: &'static [&'static str];
+
+    /// Whether it parses the given solidity type
+    fn accepts(ty: &str) -> bool {
+        Self::TYPES.contains(&ty)
+    }
+
+    /// The solidity types it parses, as shown in errors
+    fn expected() -> String {
+        Self::TYPES.join(" or ")
+    }
+}
+
+impl<T: ElementType, const N: usize> StateField for FixedArray<T, N> {
+    const TYPES: &'static [&'static str] = &[];
+
+    fn accepts(ty: &str) -> bool {
+        ty == Self::solidity_type()
+    }
+
+    fn expected() -> String {
+        Self::solidity_type()
+    }
+}
+
+impl StateField for AddressField {
+    const TYPES: &'static [&'static str] = &["address"];
+}
+
+impl StateField for AddressArray {
+    const TYPES: &'static [&'static str] = &["address[]"];
+}
+
+impl StateField for U256Field {
+    const TYPES: &'static [&'static str] = &["uint256", "uint8"];
+}
+
+impl StateField for U256Array {
+    const TYPES: &'static [&'static str] = &["uint256[]"];
+}
+
+impl StateField for Bytes32Field {
+    const TYPES: &'static [&'static str] = &["bytes32"];
+}
+
+impl StateField for Bytes32Array {
+    const TYPES: &'static [&'static str] = &["bytes32[]"];
+}
+
+impl StateField for BoolField {
+    const TYPES: &'static [&'static str] = &["bool"];
+}
+
+impl StateField for BoolArray {
+    const TYPES: &'static [&'static str] = &["bool[]"];
+}
+
+impl StateField for String32Field {
+    const TYPES: &'static [&'static str] = &["bytes32"];
+}
+
+impl StateField for BytesField {
+    const TYPES: &'static [&'static str] = &["bytes"];
+}
+
+/// How the solidity type of a field is checked
+pub struct FieldCheck {
+    pub accepts: fn(&str) -> bool,
+    pub expected: fn() -> String,
+}
+
+/// The state of an instance as parsed by a dapp, a tuple of fields
+pub trait InstanceState: DeserializeOwned {
+    /// Checks of the solidity type of each field, in order
+    fn field_types() -> Vec<FieldCheck>;
+}
+
+macro_rules! instance_state {
+    ($($field:ident),+) => {
+        impl<$($field: StateField + DeserializeOwned),+> InstanceState
+            for ($($field,)+)
+        {
+            fn field_types() -> Vec<FieldCheck> {
+                vec![$(FieldCheck {
+                    accepts: $field::accepts,
+                    expected: $field::expected,
+                }),+]
+            }
+        }
+    };
+}
+
+instance_state!(A);
+instance_state!(A, B);
+instance_state!(A, B, C);
+instance_state!(A, B, C, D);
+instance_state!(A, B, C, D, E);
+instance_state!(A, B, C, D, E, F);
+instance_state!(A, B, C, D, E, F, G);
+instance_state!(A, B, C, D, E, F, G, H);
+instance_state!(A, B, C, D, E, F, G, H, I);
+instance_state!(A, B, C, D, E, F, G, H, I, J);
+instance_state!(A, B, C, D, E, F, G, H, I, J, K);
+instance_state!(A, B, C, D, E, F, G, H, I, J, K, L);
+instance_state!(A, B, C, D, E, F, G, H, I, J, K, L, M);
+instance_state!(A, B, C, D, E, F, G, H, I, J, K, L, M, N);
+instance_state!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O);
+instance_state!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P);
+
+/// A field of the state of an instance, as written by the state manager
+#[derive(Debug, Deserialize)]
+pub struct FieldSchema {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+}
+
+/// The names and solidity types of the fields of an instance's state
+pub fn schema_of(instance: &state::Instance) -> Result<Vec<FieldSchema>> {
+    serde_json::from_str(&instance.json_data).chain_err(|| {
+        state_error(instance, String::from("state is not a list of fields"))
+    })
+}
+
+/// Checks the state of an instance against the fields of `T`, then parses
+/// it
+pub fn parse_state<T: InstanceState>(instance: &state::Instance) -> Result<T> {
+    let schema = schema_of(instance)?;
+    let expected = T::field_types();
+    if schema.len() != expected.len() {
+        return Err(state_error(
+            instance,
+            format!("expected {} fields, got {}", expected.len(), schema.len()),
+        ));
+    }
+    for (i, (field, ty)) in schema.iter().zip(expected.iter()).enumerate() {
+        if !(ty.accepts)(&field.ty) {
+            return Err(state_error(
+                instance,
+                format!(
+                    "field {} ({}) expected {}, got {}",
+                    i + 1,
+                    field.name,
+                    (ty.expected)(),
+                    field.ty
+                ),
+            ));
+        }
+    }
+    serde_json::from_str(&instance.json_data).chain_err(|| {
+        state_error(instance, String::from("could not parse state"))
+    })
+}
+
+/// The value of a `uint256` field of the state named after the value at
+/// stake in the instance, like `_stakeValue` or `stake_value`, if any
+pub fn stake_value(instance: &state::Instance) -> Option<U256> {
+    let fields: Vec<serde_json::Value> =
+        serde_json::from_str(&instance.json_data).ok()?;
+    fields
+        .iter()
+        .find(|field| {
+            let name = field["name"].as_str().unwrap_or_default();
+            field["type"] == "uint256"
+                && name.replace('_', "").eq_ignore_ascii_case("stakevalue")
+        })
+        .and_then(|field| serde_json::from_value(field["value"].clone()).ok())
+}
+
+fn state_error(instance: &state::Instance, message: String) -> Error {
+    Error::from(ErrorKind::ContractStateError(
+        format!("{}", instance.concern),
+        format!(
+            "instance {} of {}: {}",
+            instance.index, instance.name, message
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use configuration::Concern;
+    use ethereum_types::Address;
+
+    fn instance(json_data: &str) -> state::Instance {
+        state::Instance {
+            name: String::from("Example"),
+            concern: Concern {
+                contract_address: Address::zero(),
+                user_address: Address::zero(),
+            },
+            index: U256::from(0),
+            service_status: state::ServiceStatus {
+                service_name: String::new(),
+                service_method: String::new(),
+                status: 0,
+                description: String::new(),
+                progress: 0,
+            },
+            json_data: String::from(json_data),
+            sub_instances: vec![],
+        }
+    }
+
+    #[test]
+    fn fields_are_checked_against_the_schema() {
+        let state = instance(
+            r#"[{ "name": "_challenger", "type": "address",
+                  "value": "0x0000000000000000000000000000000000000001" },
+                { "name": "_deadline", "type": "uint256", "value": "0x10" }]"#,
+        );
+        let (challenger, deadline): (AddressField, U256Field) =
+            parse_state(&state).unwrap();
+        assert_eq!(challenger.value, Address::from_low_u64_be(1));
+        assert_eq!(deadline.value, U256::from(16));
+
+        let e = parse_state::<(AddressField, Bytes32Field)>(&state)
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("field 2 (_deadline) expected bytes32, got uint256"));
+
+        let e = parse_state::<(AddressField,)>(&state)
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("expected 1 fields, got 2"));
+
+        let state = instance(
+            r#"[{ "name": "_values", "type": "uint256[2]",
+                  "value": ["0x1", "0x2"] }]"#,
+        );
+        assert!(parse_state::<(FixedArray<U256, 2>,)>(&state).is_ok());
+        let e = parse_state::<(FixedArray<U256, 3>,)>(&state)
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("expected uint256[3], got uint256[2]"));
+    }
+
+    #[test]
+    fn stake_value_is_read_from_its_field() {
+        let state = instance(
+            r#"[{ "name": "_deadline", "type": "uint256", "value": "0x10" },
+                { "name": "_stakeValue", "type": "uint256", "value": "0x64" }]"#,
+        );
+        assert_eq!(stake_value(&state), Some(U256::from(100)));
+        let state = instance(
+            r#"[{ "name": "_deadline", "type": "uint256", "value": "0x10" }]"#,
+        );
+        assert_eq!(stake_value(&state), None);
+    }
+}
@@ -0,0 +1,234 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Persistent mapping between instances and the machine manager sessions
+//! computing for them, so that a restarted dispatcher re-attaches to the
+//! sessions it had already created.
+
+use super::configuration::{Concern, Storage};
+use super::error::*;
+use super::ethereum_types::{Address, U256};
+use super::utils::kv::{KvStore, LazyStore};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Database key of a session: the concern followed by the instance index
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionKey {
+    pub concern: Concern,
+    pub index: U256,
+}
+
+impl SessionKey {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut index = [0u8; 32];
+        self.index.to_big_endian(&mut index);
+        [&self.concern.to_bytes()[..], &index[..]].concat()
+    }
+
+    fn from_bytes(key: &[u8]) -> Result<SessionKey> {
+        if key.len() != 72 {
+            return Err(Error::from(format!(
+                "session key should have 72 bytes, got {}",
+                key.len()
+            )));
+        }
+        Ok(SessionKey {
+            concern: Concern::from_bytes(&key[0..40])?,
+            index: U256::from_big_endian(&key[40..72]),
+        })
+    }
+}
+
+/// Sessions created on the machine manager, by instance
+pub struct SessionStore {
+    database: LazyStore,
+}
+
+impl SessionStore {
+    /// The session database at the given path, created on the first run
+    pub fn open(storage: Storage, path: &Path) -> SessionStore {
+        SessionStore {
+            database: LazyStore::new(storage, path),
+        }
+    }
+
+    /// The session previously recorded for an instance, if any
+    pub fn get(&self, concern: Concern, index: U256) -> Result<Option<String>> {
+        let key = SessionKey {
+            concern: concern,
+            index: index,
+        };
+        self.database
+            .get(&key.to_bytes())
+            .chain_err(|| format!("could not read from session database"))?
+            .map(|data| -> Result<String> {
+                Ok(String::from_utf8(data).map_err(|e| e.utf8_error())?)
+            })
+            .transpose()
+    }
+
+    /// The session recorded for an instance, or a newly recorded one.
+    /// New session ids carry their creation time, so that a recreated
+    /// session never collides with a stale one on the machine manager.
+    pub fn get_or_create(
+        &self,
+        concern: Concern,
+        index: U256,
+    ) -> Result<String> {
+        if let Some(session_id) = self.get(concern, index)? {
+            return Ok(session_id);
+        }
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Error::from("Time went backwords"))?
+            .as_nanos();
+        let session_id =
+            format!("{:x}_{}_{}", concern.contract_address, index, created);
+        self.insert(concern, index, &session_id)?;
+        Ok(session_id)
+    }
+
+    /// Records the session computing for an instance
+    pub fn insert(
+        &self,
+        concern: Concern,
+        index: U256,
+        session_id: &str,
+    ) -> Result<()> {
+        let key = SessionKey {
+            concern: concern,
+            index: index,
+        };
+        self.database
+            .put(&key.to_bytes(), session_id.as_bytes())
+            .chain_err(|| format!("could not write to session database"))
+    }
+
+    /// Forgets the session of an instance, once it is no longer needed
+    pub fn remove(&self, concern: Concern, index: U256) -> Result<()> {
+        let key = SessionKey {
+            concern: concern,
+            index: index,
+        };
+        self.database
+            .delete(&key.to_bytes())
+            .chain_err(|| format!("could not delete from session database"))
+    }
+
+    /// Writes the sessions still buffered to disk
+    pub fn flush(&self) -> Result<()> {
+        self.database
+            .flush()
+            .chain_err(|| format!("could not flush session database"))
+    }
+
+    /// Forgets the sessions of the instances of a contract, whatever their
+    /// user, returning their ids
+    pub fn remove_contract(
+        &self,
+        contract_address: Address,
+    ) -> Result<Vec<String>> {
+        let mut removed = vec![];
+        for (key, session_id) in self.list()? {
+            if key.concern.contract_address == contract_address {
+                self.remove(key.concern, key.index)?;
+                removed.push(session_id);
+            }
+        }
+        Ok(removed)
+    }
+
+    /// All recorded sessions, leaving out the malformed entries of a
+    /// corrupt database, which are only reported
+    pub fn list(&self) -> Result<Vec<(SessionKey, String)>> {
+        let entries = self
+            .database
+            .scan_prefix(&[])
+            .chain_err(|| format!("could not read from session database"))?;
+        let mut sessions = vec![];
+        for (key, data) in entries {
+            let session = SessionKey::from_bytes(&key).and_then(|key| {
+                Ok((key, String::from_utf8(data).map_err(|e| e.utf8_error())?))
+            });
+            match session {
+                Ok(session) => sessions.push(session),
+                Err(e) => warn!("Skipping malformed session entry: {}", e),
+            }
+        }
+        Ok(sessions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethereum_types::Address;
+    use tempfile::tempdir;
+
+    #[test]
+    fn malformed_keys_are_errors() {
+        let key = SessionKey {
+            concern: Concern {
+                contract_address: Address::repeat_byte(0xaa),
+                user_address: Address::repeat_byte(0xbb),
+            },
+            index: U256::from(7),
+        };
+        let bytes = key.to_bytes();
+        assert_eq!(SessionKey::from_bytes(&bytes).unwrap(), key);
+        assert!(SessionKey::from_bytes(&bytes[1..]).is_err());
+        assert!(SessionKey::from_bytes(&[]).is_err());
+    }
+
+    #[test]
+    fn sessions_of_a_contract_are_removed_together() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let sessions =
+            SessionStore::open(Storage::LevelDb, &dir.join("session_db"));
+        let concern = |contract: u8, user: u8| Concern {
+            contract_address: Address::repeat_byte(contract),
+            user_address: Address::repeat_byte(user),
+        };
+        sessions
+            .insert(concern(0xaa, 1), U256::from(0), "a1")
+            .unwrap();
+        sessions
+            .insert(concern(0xaa, 2), U256::from(3), "a2")
+            .unwrap();
+        sessions
+            .insert(concern(0xbb, 1), U256::from(0), "b1")
+            .unwrap();
+
+        let mut removed = sessions
+            .remove_contract(Address::repeat_byte(0xaa))
+            .unwrap();
+        removed.sort();
+        assert_eq!(removed, vec!["a1", "a2"]);
+        let left = sessions.list().unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].1, "b1");
+    }
+}
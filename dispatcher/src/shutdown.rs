@@ -0,0 +1,135 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Shutdown on SIGTERM or SIGINT. Once asked to stop, the dispatcher
+//! starts no new reaction, waits a bounded time for the reactions still
+//! running to get their transactions sent and recorded, writes to disk
+//! what its stores still buffer and exits. Killing it in the middle of a
+//! reaction could leave a transaction sent but not recorded.
+
+use super::error::*;
+use super::signal_hook;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often the reactions still running are counted while draining
+const DRAIN_CHECK_PERIOD: Duration = Duration::from_millis(100);
+
+/// Whether the dispatcher was asked to stop, and the reactions running
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+    running: Arc<AtomicUsize>,
+}
+
+/// A running reaction, counted until dropped
+pub struct RunningReaction {
+    running: Arc<AtomicUsize>,
+}
+
+impl Drop for RunningReaction {
+    fn drop(&mut self) {
+        self.running.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Shutdown::default()
+    }
+
+    /// Blocks until SIGTERM or SIGINT is received, then asks to stop
+    pub fn wait_for_signal(&self) -> Result<()> {
+        let signals = signal_hook::iterator::Signals::new(&[
+            signal_hook::SIGTERM,
+            signal_hook::SIGINT,
+        ])
+        .chain_err(|| "could not listen to signals")?;
+        if let Some(signal) = signals.forever().next() {
+            info!("Received signal {}, shutting down", signal);
+        }
+        self.request();
+        Ok(())
+    }
+
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Counts a reaction as running until the returned value is dropped,
+    /// unless the dispatcher is stopping, in which case it should not
+    /// start at all
+    pub fn start_reaction(&self) -> Option<RunningReaction> {
+        if self.is_requested() {
+            return None;
+        }
+        self.running.fetch_add(1, Ordering::SeqCst);
+        Some(RunningReaction {
+            running: self.running.clone(),
+        })
+    }
+
+    /// Reactions still running
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Waits for the running reactions to finish, for at most the given
+    /// time. Returns whether they all did.
+    pub fn drain(&self, timeout: Duration) -> bool {
+        let started = Instant::now();
+        while self.running() > 0 {
+            if started.elapsed() >= timeout {
+                return false;
+            }
+            std::thread::sleep(DRAIN_CHECK_PERIOD);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_reaction_starts_once_stopping() {
+        let shutdown = Shutdown::new();
+        let running = shutdown.start_reaction().unwrap();
+        assert_eq!(shutdown.running(), 1);
+
+        shutdown.request();
+        assert!(shutdown.start_reaction().is_none());
+        assert!(!shutdown.drain(Duration::from_millis(10)));
+
+        drop(running);
+        assert_eq!(shutdown.running(), 0);
+        assert!(shutdown.drain(Duration::from_millis(10)));
+    }
+}
@@ -0,0 +1,218 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Snapshots of what the dispatcher tracks, exported as json to reproduce
+//! production disputes locally. An imported snapshot replaces the chain as
+//! the source of instances, and fills the archive with the responses the
+//! services gave in production.
+
+use super::configuration::Concern;
+use super::dapp::{get_pretty_instance, ArchiveEntries, DApp};
+use super::error::*;
+use super::state::{Instance, StateReader};
+use super::status::{PendingTransaction, StatusContext};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use web3::futures::{future, Future};
+
+/// An instance of the main concern, as read from the chain and as
+/// prettified by the dapp
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InstanceSnapshot {
+    pub index: usize,
+    pub active: bool,
+    pub instance: Instance,
+    pub pretty_instance: Option<Instance>,
+}
+
+/// Everything the dispatcher tracked at some moment
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Time of the latest block when taken, which dapps replaying the
+    /// snapshot compare their deadlines with
+    pub taken_at: u64,
+    pub main_concern: Concern,
+    pub concerns: Vec<Concern>,
+    pub instances: Vec<InstanceSnapshot>,
+    pub archive: ArchiveEntries,
+    pub pending_transactions: Vec<PendingTransaction>,
+}
+
+/// Takes a snapshot of the instances of the main concern, the archive and
+/// the transactions not completed yet
+pub fn take<T: DApp<Params = ()>>(
+    context: &Arc<StatusContext>,
+) -> Box<dyn Future<Item = Snapshot, Error = Error> + Send> {
+    let main_concern = context.main_concern;
+    let context_instances = context.clone();
+    let context_snapshot = context.clone();
+    let (indices, active) = {
+        let state_manager = context.state_manager.lock().unwrap();
+        (
+            state_manager.get_indices(main_concern, false),
+            state_manager.get_indices(main_concern, true),
+        )
+    };
+
+    Box::new(
+        indices
+            .join(active)
+            .and_then(move |(indices, active)| {
+                let instances = indices
+                    .into_iter()
+                    .map(|index| {
+                        let context = context_instances.clone();
+                        let active = active.contains(&index);
+                        context_instances
+                            .state_manager
+                            .lock()
+                            .unwrap()
+                            .get_instance(main_concern, index)
+                            .map(move |instance| {
+                                let archive = context.archive.lock().unwrap();
+                                let pretty_instance = get_pretty_instance::<T>(
+                                    &instance,
+                                    &context
+                                        .services
+                                        .for_concern(&main_concern)
+                                        .context(&archive),
+                                    &(),
+                                )
+                                .map_err(|e| {
+                                    warn!(
+                                        "could not prettify instance {}: {}",
+                                        index, e
+                                    )
+                                })
+                                .ok();
+                                InstanceSnapshot {
+                                    index: index,
+                                    active: active,
+                                    instance: instance,
+                                    pretty_instance: pretty_instance,
+                                }
+                            })
+                    })
+                    .collect::<Vec<_>>();
+                future::join_all(instances)
+            })
+            .map(move |instances| Snapshot {
+                taken_at: chain_time(&context_snapshot),
+                main_concern: main_concern,
+                concerns: context_snapshot.concerns.clone(),
+                instances: instances,
+                archive: context_snapshot.archive.lock().unwrap().entries(),
+                pending_transactions: context_snapshot
+                    .board
+                    .lock()
+                    .unwrap()
+                    .pending_transactions(),
+            }),
+    )
+}
+
+// time of the chain, or of the host if no block was seen yet
+fn chain_time(context: &StatusContext) -> u64 {
+    let clock = context.services.for_concern(&context.main_concern).clock;
+    match clock.staleness() {
+        Some(_) => clock.timestamp(),
+        None => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0),
+    }
+}
+
+/// Serves the instances of a snapshot as if they were read from the chain
+pub struct SnapshotReader {
+    main_concern: Concern,
+    instances: Vec<InstanceSnapshot>,
+}
+
+impl SnapshotReader {
+    pub fn new(snapshot: &Snapshot) -> Self {
+        SnapshotReader {
+            main_concern: snapshot.main_concern,
+            instances: snapshot.instances.clone(),
+        }
+    }
+
+    fn check_concern(&self, concern: Concern) -> Result<()> {
+        if concern == self.main_concern {
+            Ok(())
+        } else {
+            Err(Error::from(ErrorKind::InvalidStateRequest(format!(
+                "concern {} is not in the snapshot",
+                concern
+            ))))
+        }
+    }
+}
+
+impl StateReader for SnapshotReader {
+    fn get_indices(
+        &self,
+        concern: Concern,
+        active: bool,
+    ) -> Box<dyn Future<Item = Vec<usize>, Error = Error> + Send> {
+        Box::new(future::result(self.check_concern(concern).map(|_| {
+            self.instances
+                .iter()
+                .filter(|snapshot| snapshot.active || !active)
+                .map(|snapshot| snapshot.index)
+                .collect()
+        })))
+    }
+
+    fn get_instance(
+        &self,
+        concern: Concern,
+        index: usize,
+    ) -> Box<dyn Future<Item = Instance, Error = Error> + Send> {
+        Box::new(future::result(self.check_concern(concern).and_then(|_| {
+            self.instances
+                .iter()
+                .find(|snapshot| snapshot.index == index)
+                .map(|snapshot| snapshot.instance.clone())
+                .ok_or(Error::from(ErrorKind::InvalidStateRequest(format!(
+                    "instance {} is not in the snapshot",
+                    index
+                ))))
+        })))
+    }
+
+    fn get_instance_at(
+        &self,
+        _concern: Concern,
+        _index: usize,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = Instance, Error = Error> + Send> {
+        Box::new(future::err(Error::from(ErrorKind::InvalidStateRequest(
+            format!(
+                "a snapshot has no state of past blocks, like {}",
+                block_number
+            ),
+        ))))
+    }
+}
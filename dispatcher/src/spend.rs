@@ -0,0 +1,157 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Gas used and ether spent by each concern, accumulated from the receipts
+//! of its mined transactions, so that operators can tell what each dispute
+//! cost.
+
+use super::configuration::{Concern, Storage};
+use super::error::*;
+use super::ethereum_types::{Address, U256};
+use super::serde_json;
+use super::transaction::Spending;
+use super::utils::kv::{KvStore, LazyStore};
+use std::path::Path;
+
+/// What a concern spent so far
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConcernSpending {
+    pub transactions: u64,
+    pub gas_used: U256,
+    pub wei_spent: U256,
+}
+
+impl ConcernSpending {
+    fn add(&mut self, spending: &Spending) {
+        self.transactions += 1;
+        self.gas_used = self.gas_used.saturating_add(spending.gas_used);
+        self.wei_spent = self.wei_spent.saturating_add(spending.wei());
+    }
+}
+
+/// What a concern spent, as reported to the operator
+#[derive(Clone, Debug, Serialize)]
+pub struct SpendingReport {
+    pub concern: Concern,
+    #[serde(flatten)]
+    pub spending: ConcernSpending,
+}
+
+/// Spending of each concern, kept in the working path
+pub struct SpendStore {
+    database: LazyStore,
+}
+
+impl SpendStore {
+    /// The spending database at the given path, created on the first run
+    pub fn open(storage: Storage, path: &Path) -> SpendStore {
+        SpendStore {
+            database: LazyStore::new(storage, path),
+        }
+    }
+
+    /// What the concern spent so far, nothing if it never sent anything
+    pub fn get(&self, concern: Concern) -> Result<ConcernSpending> {
+        self.database
+            .get(&concern.to_bytes())
+            .chain_err(|| format!("could not read from spending database"))?
+            .map(|data| -> Result<ConcernSpending> {
+                Ok(serde_json::from_slice(&data)?)
+            })
+            .unwrap_or(Ok(ConcernSpending::default()))
+    }
+
+    /// Adds a mined transaction to the spending of its concern
+    pub fn record(&self, spending: &Spending) -> Result<ConcernSpending> {
+        let mut total = self.get(spending.concern)?;
+        total.add(spending);
+        self.database
+            .put(&spending.concern.to_bytes(), &serde_json::to_vec(&total)?)
+            .chain_err(|| format!("could not write to spending database"))?;
+        Ok(total)
+    }
+
+    /// Forgets what the concerns of a contract spent, whatever their user,
+    /// returning those concerns
+    pub fn remove_contract(
+        &self,
+        contract_address: Address,
+    ) -> Result<Vec<Concern>> {
+        let mut removed = vec![];
+        for report in self.list()? {
+            if report.concern.contract_address == contract_address {
+                self.database.delete(&report.concern.to_bytes()).chain_err(
+                    || format!("could not delete from spending database"),
+                )?;
+                removed.push(report.concern);
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Spending of every concern that sent transactions
+    pub fn list(&self) -> Result<Vec<SpendingReport>> {
+        self.database
+            .scan_prefix(&[])
+            .chain_err(|| format!("could not read from spending database"))?
+            .into_iter()
+            .map(|(concern, data)| -> Result<SpendingReport> {
+                Ok(SpendingReport {
+                    concern: Concern::from_bytes(&concern)?,
+                    spending: serde_json::from_slice(&data)?,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethereum_types::{Address, H256};
+
+    #[test]
+    fn spending_accumulates() {
+        let spending = Spending {
+            concern: Concern {
+                contract_address: Address::zero(),
+                user_address: Address::zero(),
+            },
+            hash: H256::zero(),
+            gas_used: U256::from(21_000),
+            gas_price: U256::from(2),
+        };
+        let mut total = ConcernSpending::default();
+        total.add(&spending);
+        total.add(&spending);
+        assert_eq!(
+            total,
+            ConcernSpending {
+                transactions: 2,
+                gas_used: U256::from(42_000),
+                wei_spent: U256::from(84_000),
+            }
+        );
+    }
+}
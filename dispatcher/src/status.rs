@@ -0,0 +1,848 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Optional http server exposing what the dispatcher is doing as json, so
+//! that disputes can be followed in production without grepping logs.
+//!
+//! The following endpoints are served:
+//! - `GET /concerns`: the main concern, every configured concern, their
+//!   labels and the paused contracts
+//! - `GET /instances`: the active instances of the main concern, prettified
+//! - `GET /instances/<index>`: a single instance, prettified
+//! - `GET /instances/<index>/history`: each state of the instance seen
+//!   and the reaction of the dapp to it
+//! - `GET /tracked`: the live instances of each concern with their
+//!   sub-instances and whether a reaction to them is pending
+//! - `GET /transactions`: transactions that were not completed yet
+//! - `GET /chain`: the last block seen, the delay of the node and the
+//!   requests sent to it, with those held back by the rate limits and
+//!   those answered from the cache
+//! - `GET /spending`: gas used and ether spent by each concern
+//! - `GET /watchdog`: for each concern, the seconds since its last polling
+//!   cycle got through and how many times it stalled
+//! - `GET /snapshot`: everything above together with the archive, in the
+//!   format read by `import-state`
+//!
+//! Stuck transactions can also be dealt with, posting a json body:
+//! - `POST /transactions/cancel`: `{"nonce": 7, "contract": "name"}`
+//!   replaces the transaction with that nonce by an empty transfer
+//! - `POST /transactions/replace`: `{"function": "claimVictory",
+//!   "params": ["0"], "value": 0, "bump": 20, "contract": "name"}` sends
+//!   the pending call again, paying `bump` percent more for gas; the value
+//!   is in wei, or a string with a unit like `"1ether"`
+//!
+//! And contracts paused, or resumed, with `{"address": "0x..."}`:
+//! - `POST /concerns/pause`: no transaction is sent for the contract while
+//!   its instances keep being tracked
+//! - `POST /concerns/resume`: transactions are sent again
+//! - `POST /concerns/override-budget`: every transaction is sent even if
+//!   the contract spent its budget
+//! - `POST /concerns/enforce-budget`: the budget is enforced again
+//!
+//! After a contract is upgraded, the abi of its concern is swapped in
+//! without a restart, keeping the disputes in progress:
+//! - `POST /concerns/abi`: `{"abi": "/path/to/Contract.json", "contract":
+//!   "name"}` reads and calls the contract with the abi of the artifact,
+//!   refused if a pending call of the concern could not be replaced with it
+//!
+//! The contract is optional and defaults to the main concern. When api
+//! keys are configured, GET requests need a read-only key and POST requests
+//! one that may transact, given as `Authorization: Bearer <key>`.
+//!
+//! The concerns, live instances and pending transactions are also served
+//! over grpc, as defined by the `dispatcher-proto` crate, when a
+//! `status_grpc_port` is configured.
+
+use super::auth::{self, Authenticator};
+use super::budget::BudgetGuard;
+use super::configuration::checksum::checksummed;
+use super::configuration::{AccessLevel, Concern, ConcernLabels, ConfigWei};
+use super::context::DAppServices;
+use super::dapp::{self, Archive, DApp};
+use super::dispatcher_proto::{
+    self, concern_message, DispatcherStatus, InstanceStatus, StatusRequest,
+    TransactionStatus,
+};
+use super::error::*;
+use super::ethereum_types::U256;
+use super::history::HistoryStore;
+use super::leader::Leadership;
+use super::pause::PauseStore;
+use super::serde::Serialize;
+use super::serde_json;
+use super::snapshot;
+use super::spend::SpendStore;
+use super::state::StateReader;
+use super::tracker::InstanceTracker;
+use super::transaction::{
+    load_abi, Strategy, TransactionManager, TransactionRequest,
+};
+use super::transport::GenericTransport;
+use super::watchdog::Watchdog;
+use grpc;
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio;
+use web3::futures::{future, Future, Stream};
+
+/// A transaction handed to the transaction manager that did not complete
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingTransaction {
+    pub concern: Concern,
+    pub index: usize,
+    pub function: String,
+    pub since: u64,
+}
+
+/// The last block the dispatcher saw while polling
+#[derive(Clone, Debug, Serialize)]
+pub struct BlockSeen {
+    pub number: Option<u64>,
+    pub timestamp: u64,
+}
+
+/// What the dispatcher observed and did so far, filled by the main loop
+/// and read by the status server
+#[derive(Default)]
+pub struct StatusBoard {
+    last_block: Option<BlockSeen>,
+    // by network name, for the networks other than the one of url
+    network_blocks: HashMap<String, BlockSeen>,
+    pending: HashMap<u64, PendingTransaction>,
+    next_id: u64,
+    reactions: u64,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+fn delay_of(block: &BlockSeen) -> i64 {
+    unix_now() as i64 - block.timestamp as i64
+}
+
+impl StatusBoard {
+    pub fn new() -> Self {
+        StatusBoard::default()
+    }
+
+    /// Records the latest block returned by the node
+    pub fn block_seen(&mut self, number: Option<u64>, timestamp: u64) {
+        self.last_block = Some(BlockSeen {
+            number: number,
+            timestamp: timestamp,
+        });
+    }
+
+    /// Records the latest block returned by the node of a network, none
+    /// being the one of url
+    pub fn network_block_seen(
+        &mut self,
+        network: Option<&String>,
+        number: Option<u64>,
+        timestamp: u64,
+    ) {
+        match network {
+            Some(name) => {
+                self.network_blocks.insert(
+                    name.clone(),
+                    BlockSeen {
+                        number: number,
+                        timestamp: timestamp,
+                    },
+                );
+            }
+            None => self.block_seen(number, timestamp),
+        }
+    }
+
+    pub fn last_block(&self) -> Option<BlockSeen> {
+        self.last_block.clone()
+    }
+
+    /// The last block seen on a network, none being the one of url
+    pub fn last_block_of(&self, network: Option<&String>) -> Option<BlockSeen> {
+        match network {
+            Some(name) => self.network_blocks.get(name).cloned(),
+            None => self.last_block(),
+        }
+    }
+
+    /// Seconds between now and the last block seen
+    pub fn node_delay(&self) -> Option<i64> {
+        self.last_block.as_ref().map(delay_of)
+    }
+
+    /// Seconds between now and the last block seen on each network other
+    /// than the one of url
+    pub fn network_delays(&self) -> BTreeMap<String, i64> {
+        self.network_blocks
+            .iter()
+            .map(|(name, block)| (name.clone(), delay_of(block)))
+            .collect()
+    }
+
+    /// The largest delay among the nodes of all networks
+    pub fn worst_node_delay(&self) -> Option<i64> {
+        self.network_blocks
+            .values()
+            .map(delay_of)
+            .chain(self.node_delay())
+            .max()
+    }
+
+    /// Records a transaction about to be sent, returning the id to be
+    /// given back once it completes
+    pub fn transaction_started(
+        &mut self,
+        concern: Concern,
+        index: usize,
+        function: String,
+    ) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.insert(
+            id,
+            PendingTransaction {
+                concern: concern,
+                index: index,
+                function: function,
+                since: unix_now(),
+            },
+        );
+        id
+    }
+
+    pub fn transaction_finished(&mut self, id: u64) {
+        self.pending.remove(&id);
+    }
+
+    /// Records that the dapp reacted to an instance
+    pub fn reaction_computed(&mut self) {
+        self.reactions += 1;
+    }
+
+    /// Reactions of the dapp so far
+    pub fn reactions(&self) -> u64 {
+        self.reactions
+    }
+
+    /// Transactions started so far, sent or not
+    pub fn transactions_started(&self) -> u64 {
+        self.next_id
+    }
+
+    /// Pending transactions, oldest first
+    pub fn pending_transactions(&self) -> Vec<PendingTransaction> {
+        let mut ids: Vec<&u64> = self.pending.keys().collect();
+        ids.sort();
+        ids.into_iter().map(|id| self.pending[id].clone()).collect()
+    }
+}
+
+#[derive(Serialize)]
+struct ConcernLabel {
+    concern: Concern,
+    label: String,
+}
+
+#[derive(Serialize)]
+struct ConcernsAnswer {
+    main_concern: Concern,
+    concerns: Vec<Concern>,
+    labels: Vec<ConcernLabel>,
+    paused: Vec<String>,
+    observer: bool,
+    leader: bool,
+}
+
+#[derive(Serialize)]
+struct ChainAnswer {
+    last_block: Option<BlockSeen>,
+    node_delay: Option<i64>,
+    // of the nodes of the other networks, by name
+    network_delays: BTreeMap<String, i64>,
+    rpc_calls: usize,
+    // requests that waited for their turn under the rate limits
+    throttled_reads: usize,
+    throttled_sends: usize,
+    // requests answered from the cache of immutable responses
+    cached_calls: usize,
+}
+
+#[derive(Deserialize)]
+struct CancelRequest {
+    contract: Option<String>,
+    nonce: u64,
+}
+
+#[derive(Deserialize)]
+struct AddressRequest {
+    address: String,
+}
+
+#[derive(Deserialize)]
+struct AbiRequest {
+    abi: PathBuf,
+    contract: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ReplaceRequest {
+    contract: Option<String>,
+    function: String,
+    #[serde(default)]
+    params: Vec<String>,
+    value: Option<ConfigWei>,
+    bump: Option<u64>,
+}
+
+/// Everything the status server needs to answer its requests
+pub struct StatusContext {
+    pub main_concern: Concern,
+    pub concerns: Vec<Concern>,
+    pub contracts: HashMap<String, Concern>,
+    pub labels: ConcernLabels,
+    pub authenticator: Arc<Authenticator>,
+    pub transaction_manager: Arc<Mutex<TransactionManager>>,
+    pub state_manager: Arc<Mutex<dyn StateReader>>,
+    pub archive: Arc<Mutex<Archive>>,
+    pub board: Arc<Mutex<StatusBoard>>,
+    pub watchdog: Arc<Mutex<Watchdog>>,
+    pub paused: PauseStore,
+    pub services: DAppServices,
+    pub spending: Arc<SpendStore>,
+    pub budgets: Arc<BudgetGuard>,
+    pub tracker: Arc<Mutex<InstanceTracker>>,
+    pub history: Arc<HistoryStore>,
+    pub leadership: Leadership,
+    pub transport: GenericTransport,
+}
+
+type ReplyFuture =
+    Box<dyn Future<Item = Response<Body>, Error = std::io::Error> + Send>;
+
+type OperationFuture =
+    Box<dyn Future<Item = &'static str, Error = Error> + Send>;
+
+// an operation posted to the server, given the body of the request
+type Operation = fn(&StatusContext, &[u8]) -> OperationFuture;
+
+fn json_response<S: Serialize>(status: StatusCode, body: &S) -> Response<Body> {
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .status(status)
+        .body(Body::from(serde_json::to_string(body).unwrap()))
+        .unwrap()
+}
+
+fn reply_now<S: Serialize>(status: StatusCode, body: &S) -> ReplyFuture {
+    Box::new(future::ok(json_response(status, body)))
+}
+
+fn reply_future<T, F>(answer: F) -> ReplyFuture
+where
+    T: Serialize,
+    F: Future<Item = T, Error = Error> + Send + 'static,
+{
+    Box::new(answer.then(
+        |res| -> std::result::Result<Response<Body>, std::io::Error> {
+            Ok(match res {
+                Ok(body) => json_response(StatusCode::OK, &body),
+                Err(e) => json_response(
+                    StatusCode::GATEWAY_TIMEOUT,
+                    &format!("{}", e),
+                ),
+            })
+        },
+    ))
+}
+
+// get the instance from the state manager and prettify it with the dapp
+fn pretty_instance<T: DApp<Params = ()>>(
+    context: &Arc<StatusContext>,
+    index: usize,
+) -> Box<dyn Future<Item = super::state::Instance, Error = Error> + Send> {
+    let context_pretty = context.clone();
+    Box::new(
+        context
+            .state_manager
+            .lock()
+            .unwrap()
+            .get_instance(context.main_concern, index)
+            .and_then(move |instance| {
+                let archive = context_pretty.archive.lock().unwrap();
+                dapp::get_pretty_instance::<T>(
+                    &instance,
+                    &context_pretty
+                        .services
+                        .for_concern(&context_pretty.main_concern)
+                        .context(&archive),
+                    &(),
+                )
+            }),
+    )
+}
+
+fn concern_of(
+    context: &StatusContext,
+    contract: &Option<String>,
+) -> Result<Concern> {
+    match contract {
+        Some(name) => context.contracts.get(name).cloned().ok_or(Error::from(
+            ErrorKind::InvalidTransactionRequest(format!(
+                "unknown contract: {}",
+                name
+            )),
+        )),
+        None => Ok(context.main_concern),
+    }
+}
+
+// an observer refuses the operations sending transactions
+fn refuse_if_observer(context: &StatusContext) -> Result<()> {
+    if context.services.config.observer {
+        return Err(Error::from(ErrorKind::InvalidTransactionRequest(
+            String::from("dispatcher is an observer"),
+        )));
+    }
+    Ok(())
+}
+
+fn cancel(context: &StatusContext, body: &[u8]) -> OperationFuture {
+    let cancel = serde_json::from_slice::<CancelRequest>(body)
+        .chain_err(|| "could not parse cancel request")
+        .and_then(|cancel| {
+            refuse_if_observer(context)?;
+            Ok((concern_of(context, &cancel.contract)?, cancel.nonce))
+        });
+    match cancel {
+        Ok((concern, nonce)) => Box::new(
+            context
+                .transaction_manager
+                .lock()
+                .unwrap()
+                .cancel(concern, U256::from(nonce))
+                .map(|_| "cancellation sent"),
+        ),
+        Err(e) => Box::new(future::err(e)),
+    }
+}
+
+fn replace(context: &StatusContext, body: &[u8]) -> OperationFuture {
+    let transaction_manager = context.transaction_manager.lock().unwrap();
+    let replace = serde_json::from_slice::<ReplaceRequest>(body)
+        .chain_err(|| "could not parse replace request")
+        .and_then(|replace| {
+            refuse_if_observer(context)?;
+            let concern = concern_of(context, &replace.contract)?;
+            if context.services.config.role_of(&concern).reactive_only {
+                return Err(Error::from(ErrorKind::InvalidTransactionRequest(
+                    String::from("concern is reactive only"),
+                )));
+            }
+            let data = transaction_manager.parse_params(
+                &concern,
+                &replace.function,
+                &replace.params,
+            )?;
+            let request = TransactionRequest {
+                concern: concern,
+                value: replace.value.map(|value| value.0).unwrap_or_default(),
+                function: replace.function,
+                data: data,
+                gas: None,
+                strategy: Strategy::Simplest,
+                contract_name: None,
+                deadline_block: None,
+                deadline_timestamp: None,
+                stake_value: None,
+            };
+            Ok((request, Strategy::Bump(replace.bump.unwrap_or(0))))
+        });
+    match replace {
+        Ok((request, strategy)) => Box::new(
+            transaction_manager
+                .replace(request, strategy)
+                .map(|_| "replacement sent"),
+        ),
+        Err(e) => Box::new(future::err(e)),
+    }
+}
+
+fn replace_abi(context: &StatusContext, body: &[u8]) -> OperationFuture {
+    Box::new(future::result(
+        serde_json::from_slice::<AbiRequest>(body)
+            .chain_err(|| "could not parse abi request")
+            .and_then(|request| {
+                let concern = concern_of(context, &request.contract)?;
+                let abi = load_abi(&request.abi)?;
+                // checked against the pending transactions before the state
+                // manager reads with it
+                let mut transaction_manager =
+                    context.transaction_manager.lock().unwrap();
+                transaction_manager.check_abi(&concern, &abi)?;
+                context
+                    .state_manager
+                    .lock()
+                    .unwrap()
+                    .replace_abi(concern, &request.abi)?;
+                transaction_manager.replace_abi(&concern, abi)?;
+                info!(
+                    "Replaced the abi of {} with {}",
+                    context.labels.describe(&concern),
+                    request.abi.display()
+                );
+                Ok("abi replaced")
+            }),
+    ))
+}
+
+fn pause(context: &StatusContext, body: &[u8]) -> OperationFuture {
+    Box::new(future::result(
+        serde_json::from_slice::<AddressRequest>(body)
+            .chain_err(|| "could not parse pause request")
+            .and_then(|pause| super::parse_address(&pause.address))
+            .and_then(|address| context.paused.pause(address))
+            .map(|changed| {
+                if changed {
+                    "concern paused"
+                } else {
+                    "concern was already paused"
+                }
+            }),
+    ))
+}
+
+fn resume(context: &StatusContext, body: &[u8]) -> OperationFuture {
+    Box::new(future::result(
+        serde_json::from_slice::<AddressRequest>(body)
+            .chain_err(|| "could not parse resume request")
+            .and_then(|resume| super::parse_address(&resume.address))
+            .and_then(|address| context.paused.resume(address))
+            .map(|changed| {
+                if changed {
+                    "concern resumed"
+                } else {
+                    "concern was not paused"
+                }
+            }),
+    ))
+}
+
+fn override_budget(context: &StatusContext, body: &[u8]) -> OperationFuture {
+    Box::new(future::result(
+        serde_json::from_slice::<AddressRequest>(body)
+            .chain_err(|| "could not parse override request")
+            .and_then(|request| super::parse_address(&request.address))
+            .and_then(|address| context.budgets.override_budget(address))
+            .map(|changed| {
+                if changed {
+                    "budget overridden"
+                } else {
+                    "budget was already overridden"
+                }
+            }),
+    ))
+}
+
+fn enforce_budget(context: &StatusContext, body: &[u8]) -> OperationFuture {
+    Box::new(future::result(
+        serde_json::from_slice::<AddressRequest>(body)
+            .chain_err(|| "could not parse enforce request")
+            .and_then(|request| super::parse_address(&request.address))
+            .and_then(|address| context.budgets.enforce_budget(address))
+            .map(|changed| {
+                if changed {
+                    "budget enforced again"
+                } else {
+                    "budget was not overridden"
+                }
+            }),
+    ))
+}
+
+// answers the posted operations once their body arrives
+fn reply_post(
+    context: Arc<StatusContext>,
+    req: Request<Body>,
+    operation: Operation,
+) -> ReplyFuture {
+    Box::new(req.into_body().concat2().then(move |body| match body {
+        Ok(body) => Box::new(operation(&context, &body).then(
+            |res| -> std::result::Result<Response<Body>, std::io::Error> {
+                Ok(match res {
+                    Ok(answer) => json_response(StatusCode::OK, &answer),
+                    Err(e) => json_response(
+                        StatusCode::BAD_REQUEST,
+                        &format!("{}", e),
+                    ),
+                })
+            },
+        )) as ReplyFuture,
+        Err(e) => reply_now(StatusCode::BAD_REQUEST, &format!("{}", e)),
+    }))
+}
+
+fn reply<T: DApp<Params = ()>>(
+    context: Arc<StatusContext>,
+    req: Request<Body>,
+) -> ReplyFuture {
+    let path: Vec<String> = req
+        .uri()
+        .path()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(String::from)
+        .collect();
+    let path: Vec<&str> = path.iter().map(String::as_str).collect();
+
+    // operations are posted, everything else only reads
+    let required = if req.method() == Method::POST {
+        AccessLevel::Transact
+    } else {
+        AccessLevel::ReadOnly
+    };
+    if let Err((status, reason)) = context.authenticator.check(&req, required) {
+        return reply_now(status, &reason);
+    }
+
+    if req.method() == Method::POST {
+        return match &path[..] {
+            ["transactions", "cancel"] => reply_post(context, req, cancel),
+            ["transactions", "replace"] => reply_post(context, req, replace),
+            ["concerns", "abi"] => reply_post(context, req, replace_abi),
+            ["concerns", "pause"] => reply_post(context, req, pause),
+            ["concerns", "resume"] => reply_post(context, req, resume),
+            ["concerns", "override-budget"] => {
+                reply_post(context, req, override_budget)
+            }
+            ["concerns", "enforce-budget"] => {
+                reply_post(context, req, enforce_budget)
+            }
+            _ => reply_now(StatusCode::NOT_FOUND, &"unknown endpoint"),
+        };
+    }
+    if req.method() != Method::GET {
+        return reply_now(
+            StatusCode::METHOD_NOT_ALLOWED,
+            &"only GET and POST allowed",
+        );
+    }
+
+    match &path[..] {
+        ["concerns"] => match context.paused.paused() {
+            Ok(paused) => reply_now(
+                StatusCode::OK,
+                &ConcernsAnswer {
+                    main_concern: context.main_concern,
+                    concerns: context.concerns.clone(),
+                    labels: context
+                        .labels
+                        .iter()
+                        .map(|(concern, label)| ConcernLabel {
+                            concern: *concern,
+                            label: label.clone(),
+                        })
+                        .collect(),
+                    paused: paused.iter().map(checksummed).collect(),
+                    observer: context.services.config.observer,
+                    leader: context.leadership.is_leader(),
+                },
+            ),
+            Err(e) => {
+                reply_now(StatusCode::INTERNAL_SERVER_ERROR, &format!("{}", e))
+            }
+        },
+        ["instances"] => {
+            let context_instances = context.clone();
+            let indices = context
+                .state_manager
+                .lock()
+                .unwrap()
+                .get_indices(context.main_concern, true);
+            reply_future(indices.and_then(move |indices| {
+                future::join_all(
+                    indices
+                        .into_iter()
+                        .map(|index| {
+                            pretty_instance::<T>(&context_instances, index)
+                        })
+                        .collect::<Vec<_>>(),
+                )
+            }))
+        }
+        ["instances", index] => match index.parse::<usize>() {
+            Ok(index) => reply_future(pretty_instance::<T>(&context, index)),
+            Err(_) => {
+                reply_now(StatusCode::BAD_REQUEST, &"index is not a number")
+            }
+        },
+        ["instances", index, "history"] => match index.parse::<usize>() {
+            Ok(index) => {
+                match context.history.of(context.main_concern, index) {
+                    Ok(history) => reply_now(StatusCode::OK, &history),
+                    Err(e) => reply_now(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        &format!("{}", e),
+                    ),
+                }
+            }
+            Err(_) => {
+                reply_now(StatusCode::BAD_REQUEST, &"index is not a number")
+            }
+        },
+        ["tracked"] => reply_now(
+            StatusCode::OK,
+            &context.tracker.lock().unwrap().instances(),
+        ),
+        ["transactions"] => reply_now(
+            StatusCode::OK,
+            &context.board.lock().unwrap().pending_transactions(),
+        ),
+        ["snapshot"] => reply_future(snapshot::take::<T>(&context)),
+        ["spending"] => match context.spending.list() {
+            Ok(spending) => reply_now(StatusCode::OK, &spending),
+            Err(e) => {
+                reply_now(StatusCode::INTERNAL_SERVER_ERROR, &format!("{}", e))
+            }
+        },
+        ["watchdog"] => reply_now(
+            StatusCode::OK,
+            &context.watchdog.lock().unwrap().progress(Instant::now()),
+        ),
+        ["chain"] => {
+            let board = context.board.lock().unwrap();
+            let throttled = context.transport.throttled();
+            reply_now(
+                StatusCode::OK,
+                &ChainAnswer {
+                    last_block: board.last_block(),
+                    node_delay: board.node_delay(),
+                    network_delays: board.network_delays(),
+                    rpc_calls: context.transport.calls(),
+                    throttled_reads: throttled.reads,
+                    throttled_sends: throttled.sends,
+                    cached_calls: context.transport.cache_hits(),
+                },
+            )
+        }
+        _ => reply_now(StatusCode::NOT_FOUND, &"unknown endpoint"),
+    }
+}
+
+// the status served over grpc, to read-only keys and above
+fn grpc_status(
+    context: &StatusContext,
+    request: &StatusRequest,
+) -> Result<DispatcherStatus> {
+    // an empty key stands for none
+    let api_key = Some(request.get_api_key()).filter(|key| !key.is_empty());
+    let granted = context.authenticator.level_of_key(api_key);
+    if let Err((_, reason)) = auth::allow(granted, AccessLevel::ReadOnly) {
+        return Err(Error::from(reason));
+    }
+    let mut status = DispatcherStatus::new();
+    status.set_main_concern(concern_message(&context.main_concern));
+    for concern in context.concerns.iter() {
+        status.mut_concerns().push(concern_message(concern));
+    }
+    for address in context.paused.paused()?.iter() {
+        status.mut_paused().push(checksummed(address));
+    }
+    status.set_observer(context.services.config.observer);
+    status.set_leader(context.leadership.is_leader());
+    for tracked in context.tracker.lock().unwrap().instances() {
+        let mut instance = InstanceStatus::new();
+        instance.set_concern(concern_message(&tracked.concern));
+        instance.set_index(tracked.index as u64);
+        if let Some(moved_at) = tracked.moved_at {
+            instance.set_moved_at(moved_at);
+        }
+        instance.set_sub_instances(tracked.sub_instances.len() as u64);
+        instance.set_reacting(tracked.reacting);
+        status.mut_instances().push(instance);
+    }
+
+    let board = context.board.lock().unwrap();
+    for pending in board.pending_transactions() {
+        let mut transaction = TransactionStatus::new();
+        transaction.set_concern(concern_message(&pending.concern));
+        transaction.set_index(pending.index as u64);
+        transaction.set_function(pending.function);
+        transaction.set_since(pending.since);
+        status.mut_pending_transactions().push(transaction);
+    }
+    if let Some(number) = board.last_block().and_then(|block| block.number) {
+        status.set_last_block(number);
+    }
+    if let Some(delay) = board.node_delay() {
+        status.set_node_delay(delay);
+    }
+    status.set_reactions(board.reactions());
+    status.set_transactions_started(board.transactions_started());
+    Ok(status)
+}
+
+/// Serves the status of the dispatcher over grpc on the given port, for
+/// as long as the returned server is kept
+pub fn serve_grpc(
+    port: u16,
+    context: Arc<StatusContext>,
+) -> Result<grpc::Server> {
+    dispatcher_proto::serve(move |request| grpc_status(&context, request), port)
+}
+
+/// Serves the status of the dispatcher on the given address
+pub fn serve<T: DApp<Params = ()>>(
+    addr: SocketAddr,
+    context: StatusContext,
+) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+    let listener = match tokio::net::TcpListener::bind(&addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("could not bind status server to {}: {}", addr, e);
+            return Box::new(future::err(()));
+        }
+    };
+    info!("Serving dispatcher status on {}", addr);
+
+    let context = Arc::new(context);
+    Box::new(
+        Server::builder(listener.incoming())
+            .serve(move || {
+                let context = context.clone();
+                service_fn(move |req| reply::<T>(context.clone(), req))
+            })
+            .map_err(|e| error!("error in status socket {}", e)),
+    )
+}
@@ -0,0 +1,138 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Load test of the dispatcher: disputes instantiated all at once on a
+//! dev chain, whose dapp (like the fake hasher) answers right away, with
+//! what the dispatcher did and used to handle them reported at the end.
+//! Its numbers are a baseline to compare changes to the main loop against.
+
+use super::status::StatusBoard;
+use super::transport::GenericTransport;
+use std::time::Duration;
+
+/// Counters of the dispatcher at some point of the test
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sample {
+    pub reactions: u64,
+    pub transactions: u64,
+    pub rpc_calls: usize,
+}
+
+impl Sample {
+    pub fn take(status: &StatusBoard, transport: &GenericTransport) -> Self {
+        Sample {
+            reactions: status.reactions(),
+            transactions: status.transactions_started(),
+            rpc_calls: transport.calls(),
+        }
+    }
+}
+
+/// What the dispatcher did and used while handling the disputes
+#[derive(Debug, Serialize, PartialEq)]
+pub struct StressReport {
+    pub disputes: usize,
+    pub seconds: f64,
+    pub reactions: u64,
+    pub reactions_per_second: f64,
+    pub transactions: u64,
+    pub rpc_calls: usize,
+    pub rpc_calls_per_second: f64,
+    /// Resident memory at the end and at its peak, in kB, where known
+    pub memory_kb: Option<u64>,
+    pub peak_memory_kb: Option<u64>,
+}
+
+impl StressReport {
+    pub fn new(
+        disputes: usize,
+        elapsed: Duration,
+        start: Sample,
+        end: Sample,
+    ) -> Self {
+        let seconds = elapsed.as_secs_f64();
+        let rate = |count: f64| {
+            if seconds > 0.0 {
+                count / seconds
+            } else {
+                0.0
+            }
+        };
+        let reactions = end.reactions.saturating_sub(start.reactions);
+        let rpc_calls = end.rpc_calls.saturating_sub(start.rpc_calls);
+        let status = std::fs::read_to_string("/proc/self/status").ok();
+        let memory = |field| status.as_ref().and_then(|s| kb_of(s, field));
+        StressReport {
+            disputes: disputes,
+            seconds: seconds,
+            reactions: reactions,
+            reactions_per_second: rate(reactions as f64),
+            transactions: end.transactions.saturating_sub(start.transactions),
+            rpc_calls: rpc_calls,
+            rpc_calls_per_second: rate(rpc_calls as f64),
+            memory_kb: memory("VmRSS:"),
+            peak_memory_kb: memory("VmHWM:"),
+        }
+    }
+}
+
+// reads a field of /proc/self/status, given in kB
+fn kb_of(status: &str, field: &str) -> Option<u64> {
+    status
+        .lines()
+        .find(|line| line.starts_with(field))
+        .and_then(|line| line[field.len()..].split_whitespace().next())
+        .and_then(|kb| kb.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_only_what_happened_during_the_test() {
+        let start = Sample {
+            reactions: 5,
+            transactions: 1,
+            rpc_calls: 40,
+        };
+        let end = Sample {
+            reactions: 25,
+            transactions: 11,
+            rpc_calls: 240,
+        };
+        let report = StressReport::new(10, Duration::from_secs(4), start, end);
+        assert_eq!(report.reactions, 20);
+        assert_eq!(report.reactions_per_second, 5.0);
+        assert_eq!(report.transactions, 10);
+        assert_eq!(report.rpc_calls_per_second, 50.0);
+
+        let status = "Name:\tdispatcher\n\
+                      VmHWM:\t  20480 kB\n\
+                      VmRSS:\t  10240 kB\n";
+        assert_eq!(kb_of(status, "VmRSS:"), Some(10240));
+        assert_eq!(kb_of(status, "VmHWM:"), Some(20480));
+        assert_eq!(kb_of(status, "VmSwap:"), None);
+    }
+}
@@ -0,0 +1,239 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Live instances of each concern, as discovered on every polling cycle.
+//! Each instance remembers where its state machine was when last read,
+//! its sub-instances and whether a reaction to it is still pending, so
+//! that a slow reaction is not started again on the next tick.
+
+use super::configuration::Concern;
+use super::ethereum_types::U256;
+use super::state::Instance;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+/// Sub-instance of a tracked instance, as created by its contract
+#[derive(Clone, Debug, Serialize)]
+pub struct SubInstanceRef {
+    pub name: String,
+    pub concern: Concern,
+    pub index: U256,
+}
+
+/// What the dispatcher knows about a live instance
+#[derive(Clone, Debug, Serialize)]
+pub struct TrackedInstance {
+    pub concern: Concern,
+    pub index: usize,
+    /// Fingerprint of the instance state when last read, changing when
+    /// its state machine moves
+    pub position: Option<u64>,
+    /// Timestamp of the clock when the position last changed
+    pub moved_at: Option<u64>,
+    pub sub_instances: Vec<SubInstanceRef>,
+    /// Whether a reaction to the instance is still running
+    pub reacting: bool,
+}
+
+impl TrackedInstance {
+    fn new(concern: Concern, index: usize) -> Self {
+        TrackedInstance {
+            concern: concern,
+            index: index,
+            position: None,
+            moved_at: None,
+            sub_instances: vec![],
+            reacting: false,
+        }
+    }
+}
+
+fn position_of(instance: &Instance) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    instance.json_data.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Live instances of every concern, by concern and index
+#[derive(Default)]
+pub struct InstanceTracker {
+    instances: HashMap<(Concern, usize), TrackedInstance>,
+    // concerns polled at least once
+    polled: HashSet<Concern>,
+}
+
+impl InstanceTracker {
+    pub fn new() -> Self {
+        InstanceTracker::default()
+    }
+
+    /// Active indices of a concern found in a polling cycle. New ones
+    /// start being tracked, unless the concern cannot instantiate and was
+    /// already polled, and those no longer active are forgotten.
+    pub fn discovered(
+        &mut self,
+        concern: Concern,
+        indices: &[usize],
+        can_instantiate: bool,
+    ) {
+        self.instances
+            .retain(|(c, index), _| *c != concern || indices.contains(index));
+        if !self.polled.insert(concern) && !can_instantiate {
+            return;
+        }
+        for index in indices {
+            self.instances
+                .entry((concern, *index))
+                .or_insert_with(|| TrackedInstance::new(concern, *index));
+        }
+    }
+
+    /// Claims the instance for a reaction, returning false if it is not
+    /// tracked or a previous reaction to it is still pending
+    pub fn start_reaction(&mut self, concern: Concern, index: usize) -> bool {
+        match self.instances.get_mut(&(concern, index)) {
+            Some(tracked) if !tracked.reacting => {
+                tracked.reacting = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn finish_reaction(&mut self, concern: Concern, index: usize) {
+        if let Some(tracked) = self.instances.get_mut(&(concern, index)) {
+            tracked.reacting = false;
+        }
+    }
+
+    /// Records the state of an instance just read, at the given timestamp
+    pub fn observed(&mut self, index: usize, instance: &Instance, now: u64) {
+        let tracked = match self.instances.get_mut(&(instance.concern, index)) {
+            Some(tracked) => tracked,
+            None => return,
+        };
+        let position = position_of(instance);
+        if tracked.position != Some(position) {
+            tracked.position = Some(position);
+            tracked.moved_at = Some(now);
+        }
+        tracked.sub_instances = instance
+            .sub_instances
+            .iter()
+            .map(|sub| SubInstanceRef {
+                name: sub.name.clone(),
+                concern: sub.concern,
+                index: sub.index,
+            })
+            .collect();
+    }
+
+    /// Tracked instances, by contract and index
+    pub fn instances(&self) -> Vec<TrackedInstance> {
+        let mut instances: Vec<TrackedInstance> =
+            self.instances.values().cloned().collect();
+        instances.sort_by(|a, b| {
+            a.concern
+                .contract_address
+                .cmp(&b.concern.contract_address)
+                .then(a.index.cmp(&b.index))
+        });
+        instances
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethereum_types::Address;
+
+    fn concern(n: u64) -> Concern {
+        Concern {
+            contract_address: Address::from_low_u64_be(n),
+            user_address: Address::zero(),
+        }
+    }
+
+    fn instance(concern: Concern, json_data: &str) -> Instance {
+        Instance {
+            name: String::from("Example"),
+            concern: concern,
+            index: U256::from(0),
+            service_status: state::ServiceStatus {
+                service_name: String::new(),
+                service_method: String::new(),
+                status: 0,
+                description: String::new(),
+                progress: 0,
+            },
+            json_data: String::from(json_data),
+            sub_instances: vec![],
+        }
+    }
+
+    #[test]
+    fn pending_reactions_are_not_started_twice() {
+        let mut tracker = InstanceTracker::new();
+        tracker.discovered(concern(1), &[0, 1], true);
+        tracker.discovered(concern(2), &[0], true);
+        assert!(tracker.start_reaction(concern(1), 0));
+        assert!(!tracker.start_reaction(concern(1), 0));
+        assert!(tracker.start_reaction(concern(2), 0));
+        assert!(!tracker.start_reaction(concern(1), 7));
+
+        tracker.finish_reaction(concern(1), 0);
+        assert!(tracker.start_reaction(concern(1), 0));
+
+        // instances no longer active are forgotten, other concerns kept
+        tracker.discovered(concern(1), &[1], true);
+        let indices: Vec<(Concern, usize)> = tracker
+            .instances()
+            .iter()
+            .map(|t| (t.concern, t.index))
+            .collect();
+        assert_eq!(indices, vec![(concern(1), 1), (concern(2), 0)]);
+    }
+
+    #[test]
+    fn concerns_that_cannot_instantiate_keep_their_first_instances() {
+        let mut tracker = InstanceTracker::new();
+        tracker.discovered(concern(1), &[0, 1], false);
+        tracker.discovered(concern(1), &[1, 2], false);
+        assert!(tracker.start_reaction(concern(1), 1));
+        assert!(!tracker.start_reaction(concern(1), 2));
+        assert_eq!(tracker.instances().len(), 1);
+    }
+
+    #[test]
+    fn position_moves_with_the_instance_state() {
+        let mut tracker = InstanceTracker::new();
+        tracker.discovered(concern(1), &[0], true);
+        tracker.observed(0, &instance(concern(1), "[1]"), 10);
+        tracker.observed(0, &instance(concern(1), "[1]"), 20);
+        assert_eq!(tracker.instances()[0].moved_at, Some(10));
+        tracker.observed(0, &instance(concern(1), "[2]"), 30);
+        assert_eq!(tracker.instances()[0].moved_at, Some(30));
+    }
+}
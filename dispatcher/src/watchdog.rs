@@ -0,0 +1,147 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Watchdog over the react loop. Each polling cycle that gets through the
+//! instances of a concern is recorded, and a concern that did not get
+//! through one for too long, like when a call to the node hangs, is
+//! reported as stalled.
+
+use super::configuration::Concern;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How a concern watched over is doing
+#[derive(Clone, Debug, Serialize)]
+pub struct ConcernProgress {
+    pub concern: Concern,
+    /// Seconds since the last cycle that got through
+    pub idle_for: u64,
+    /// Times the concern was found stalled
+    pub stalls: u64,
+}
+
+struct Progress {
+    last: Instant,
+    stalls: u64,
+    // whether the current stall was already reported
+    reported: bool,
+}
+
+/// Last cycle of each concern that got through its instances
+pub struct Watchdog {
+    stall_timeout: Duration,
+    concerns: HashMap<Concern, Progress>,
+}
+
+impl Watchdog {
+    pub fn new(stall_timeout: Duration) -> Self {
+        Watchdog {
+            stall_timeout: stall_timeout,
+            concerns: HashMap::new(),
+        }
+    }
+
+    pub fn stall_timeout(&self) -> Duration {
+        self.stall_timeout
+    }
+
+    /// Starts watching over a concern, as if it had just progressed
+    pub fn watch(&mut self, concern: Concern, now: Instant) {
+        self.concerns.entry(concern).or_insert(Progress {
+            last: now,
+            stalls: 0,
+            reported: false,
+        });
+    }
+
+    /// Records a cycle of the concern that got through
+    pub fn progressed(&mut self, concern: Concern, now: Instant) {
+        self.watch(concern, now);
+        if let Some(progress) = self.concerns.get_mut(&concern) {
+            progress.last = now;
+            progress.reported = false;
+        }
+    }
+
+    /// Concerns that stalled since the last check, each reported once
+    /// until it progresses again
+    pub fn check(&mut self, now: Instant) -> Vec<ConcernProgress> {
+        let stall_timeout = self.stall_timeout;
+        let mut stalled = vec![];
+        for (concern, progress) in self.concerns.iter_mut() {
+            let idle_for = now.duration_since(progress.last);
+            if idle_for > stall_timeout && !progress.reported {
+                progress.stalls += 1;
+                progress.reported = true;
+                stalled.push(ConcernProgress {
+                    concern: *concern,
+                    idle_for: idle_for.as_secs(),
+                    stalls: progress.stalls,
+                });
+            }
+        }
+        stalled
+    }
+
+    /// How every concern watched over is doing
+    pub fn progress(&self, now: Instant) -> Vec<ConcernProgress> {
+        self.concerns
+            .iter()
+            .map(|(concern, progress)| ConcernProgress {
+                concern: *concern,
+                idle_for: now.duration_since(progress.last).as_secs(),
+                stalls: progress.stalls,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethereum_types::Address;
+
+    #[test]
+    fn stalls_are_reported_once_until_progress() {
+        let concern = Concern {
+            contract_address: Address::zero(),
+            user_address: Address::zero(),
+        };
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut watchdog = Watchdog::new(Duration::from_secs(60));
+        watchdog.watch(concern, start);
+
+        watchdog.progressed(concern, at(30));
+        assert!(watchdog.check(at(80)).is_empty());
+        assert_eq!(watchdog.check(at(100))[0].idle_for, 70);
+        assert!(watchdog.check(at(200)).is_empty());
+
+        watchdog.progressed(concern, at(210));
+        assert!(watchdog.check(at(260)).is_empty());
+        let stalled = watchdog.check(at(300));
+        assert_eq!(stalled[0].stalls, 2);
+        assert_eq!(watchdog.progress(at(300))[0].idle_for, 90);
+    }
+}
@@ -37,8 +37,12 @@ extern crate serde_yaml;
 extern crate time;
 extern crate web3;
 
+use std::path::PathBuf;
 use time::Duration;
+use web3::types::H256;
 
+// Errors that callers are expected to match on are typed variants of
+// ErrorKind, anything else is reported as a message through `chain_err`.
 error_chain! {
     foreign_links {
         Io(::std::io::Error) #[cfg(unix)];
@@ -65,7 +69,7 @@ error_chain! {
             description("mspc send error")
                 display("mspc send error: {}", details)
         }
-        InvalidConfig(details: String) {
+        ConfigError(details: String) {
             description("invalid configuration")
                 display("invalid configuration: {}", details)
         }
@@ -74,6 +78,16 @@ error_chain! {
                 display("blockchain presented error: {}",
                         details)
         }
+        RpcError(method: String, url: String) {
+            description("rpc call to Ethereum node failed")
+                display("rpc call {} to Ethereum node at {} failed",
+                        method, url)
+        }
+        AbiError(path: PathBuf, details: String) {
+            description("invalid contract abi")
+                display("invalid contract abi {}: {}",
+                        path.display(), details)
+        }
         ChainNotInSync(delay: Duration, max_delay: Duration) {
             description("chain too delayed")
                 display("ETH node not up to date: delay {}, max_delay {}",
@@ -84,17 +98,30 @@ error_chain! {
             description("request of transaction invalid")
                 display("request of transaction invalid: {}", details)
         }
-        TransactionError(details: String) {
-            description("request of transaction invalid")
-                display("request of transaction invalid: {}", details)
+        TransactionError(hash: Option<H256>, reason: String) {
+            description("transaction failed")
+                display("transaction {} failed: {}",
+                        hash.map(|h| format!("{:?}", h))
+                            .unwrap_or(String::from("(not sent)")),
+                        reason)
+        }
+        SubmissionQueueFull(account: String) {
+            description("transaction submission queue full")
+                display("too many transactions waiting to be sent from {}",
+                        account)
         }
         InvalidStateRequest(details: String) {
             description("request of state invalid")
                 display("request of state invalid: {}", details)
         }
-        InvalidContractState(details: String) {
+        ContractStateError(concern: String, state: String) {
             description("contract state invalid")
-                display("contract state invalid: {}", details)
+                display("contract state of {} invalid: {}", concern, state)
+        }
+        DAppPanicked(concern: String, details: String) {
+            description("dapp panicked while reacting")
+                display("dapp panicked while reacting to {}: {}",
+                        concern, details)
         }
         GrpcError(details: String) {
             description("error received from grpc")
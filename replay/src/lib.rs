@@ -0,0 +1,337 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Deterministic replay of DApp logic. Instances recorded as json fixtures,
+//! together with their sub-instances, the archive entries and the time the
+//! DApp saw, are fed to the DApp's `react` without any node, so that tests
+//! can assert on the reaction.
+//!
+//! Fixtures are recorded from a live chain with `record`, or taken from the
+//! snapshots written by the `export-state` command of the dispatcher.
+
+extern crate configuration;
+extern crate dispatcher;
+extern crate error;
+extern crate state;
+extern crate transaction;
+extern crate utils;
+extern crate web3;
+
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+
+use configuration::Concern;
+use dispatcher::snapshot::InstanceSnapshot;
+use dispatcher::{
+    Archive, ArchiveEntries, ConfigView, DApp, DAppServices, MockClock,
+    Reaction, Snapshot, SnapshotReader,
+};
+use error::*;
+use state::{Instance, StateReader};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use transaction::TransactionRequest;
+use utils::chain::MockChain;
+use utils::compress;
+use web3::futures::Future;
+
+/// An instance as a DApp saw it, with everything needed to replay it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Fixture {
+    /// Time of the latest block when the instance was read, followed by
+    /// the deadlines of the archive
+    pub timestamp: u64,
+    pub instance: Instance,
+    #[serde(default)]
+    pub archive: ArchiveEntries,
+    #[serde(default)]
+    pub post_action: Option<String>,
+}
+
+impl Fixture {
+    /// Loads a fixture, compressed or not
+    pub fn load(path: &Path) -> Result<Fixture> {
+        Ok(serde_json::from_slice(&compress::read_file(path)?)
+            .chain_err(|| format!("invalid fixture in {}", path.display()))?)
+    }
+
+    /// Saves the fixture compressed, as its archive may hold large proofs
+    pub fn save(&self, path: &Path) -> Result<()> {
+        compress::write_file(path, &serde_json::to_vec_pretty(self)?)
+    }
+
+    /// An archive holding the recorded entries, whose clock is stopped at
+    /// the recorded time
+    pub fn archive(&self) -> Result<Archive> {
+        let mut archive =
+            Archive::with_clock(Arc::new(MockClock::new(self.timestamp)))?;
+        archive.restore(self.archive.clone());
+        Ok(archive)
+    }
+
+    /// Services for the DApp without any node or grpc service behind
+    /// them, with the clock stopped at the recorded time. Only the
+    /// recorded instance can be read.
+    pub fn services(&self) -> DAppServices {
+        let snapshot = Snapshot {
+            taken_at: self.timestamp,
+            main_concern: self.instance.concern,
+            concerns: vec![self.instance.concern],
+            instances: vec![InstanceSnapshot {
+                index: self.instance.index.as_usize(),
+                active: true,
+                instance: self.instance.clone(),
+                pretty_instance: None,
+            }],
+            archive: self.archive.clone(),
+            pending_transactions: vec![],
+        };
+        DAppServices {
+            chain: Arc::new(Mutex::new(MockChain::new())),
+            state: Arc::new(Mutex::new(SnapshotReader::new(&snapshot))),
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            clock: Arc::new(MockClock::new(self.timestamp)),
+            config: Arc::new(ConfigView {
+                main_concern: self.instance.concern,
+                contracts: HashMap::new(),
+                machines: HashMap::new(),
+                machine_backends: HashMap::new(),
+                working_path: PathBuf::from("."),
+                testing: true,
+                chain_id: 0,
+                roles: HashMap::new(),
+                observer: false,
+                high_stake_value: None,
+                concern_networks: HashMap::new(),
+            }),
+            networks: Arc::new(HashMap::new()),
+        }
+    }
+
+    /// Feeds the instance to the DApp, as the dispatcher would, a panic of
+    /// the DApp being returned as an error
+    pub fn react<D: DApp>(&self, params: &D::Params) -> Result<Reaction> {
+        let archive = self.archive()?;
+        let services = self.services();
+        dispatcher::dapp::react_guarded::<D>(
+            &self.instance,
+            &services.context(&archive),
+            &self.post_action,
+            params,
+        )
+    }
+}
+
+/// Records an instance read through the state reader, at the given time
+pub fn record(
+    reader: &dyn StateReader,
+    concern: Concern,
+    index: usize,
+    timestamp: u64,
+) -> Result<Fixture> {
+    let instance = reader
+        .get_instance(concern, index)
+        .wait()
+        .chain_err(|| format!("could not read instance {}", index))?;
+    Ok(Fixture {
+        timestamp: timestamp,
+        instance: instance,
+        archive: ArchiveEntries::default(),
+        post_action: None,
+    })
+}
+
+/// One fixture for each instance of a snapshot, sharing its archive
+pub fn fixtures_of(snapshot: &Snapshot) -> Vec<Fixture> {
+    snapshot
+        .instances
+        .iter()
+        .map(|instance| Fixture {
+            timestamp: snapshot.taken_at,
+            instance: instance.instance.clone(),
+            archive: snapshot.archive.clone(),
+            post_action: None,
+        })
+        .collect()
+}
+
+/// The transaction requested by a reaction, panicking with the reaction
+/// if it is anything else
+pub fn expect_transaction(reaction: &Reaction) -> &TransactionRequest {
+    match reaction {
+        Reaction::Transaction(request) => request,
+        other => panic!("expected a transaction, got {:?}", other),
+    }
+}
+
+/// The transactions requested by a reaction, in the order they are sent,
+/// panicking with the reaction if it requests none
+pub fn expect_transactions(reaction: &Reaction) -> Vec<&TransactionRequest> {
+    match reaction {
+        Reaction::Transaction(request) => vec![request],
+        Reaction::Transactions(requests) if !requests.is_empty() => {
+            requests.iter().collect()
+        }
+        other => panic!("expected transactions, got {:?}", other),
+    }
+}
+
+/// Panics with the reaction unless it is idle, until any time
+pub fn expect_idle(reaction: &Reaction) {
+    match reaction {
+        Reaction::Idle | Reaction::IdleUntil(_) => {}
+        other => panic!("expected to be idle, got {:?}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dispatcher::{parse_state, DAppContext, U256Field};
+    use std::path::PathBuf;
+    use transaction::{CallParams, Strategy};
+
+    // claims victory once the round of the instance is over
+    struct Example();
+
+    impl DApp for Example {
+        type Params = ();
+        type Ctx = (U256Field, U256Field);
+        type Subs = ();
+
+        fn parse(instance: &Instance) -> Result<Self::Ctx> {
+            parse_state(instance)
+        }
+
+        fn react(
+            instance: &Instance,
+            ctx: &Self::Ctx,
+            _subs: &(),
+            _params: &(),
+            context: &DAppContext,
+            _post_action: &Option<String>,
+        ) -> Result<Reaction> {
+            let (time_of_last_move, round_duration) = ctx;
+            let deadline = context.deadline();
+            if !deadline.expired(time_of_last_move.value, round_duration.value)
+            {
+                return Ok(Reaction::IdleUntil(
+                    time_of_last_move.value + round_duration.value,
+                ));
+            }
+            Ok(Reaction::Transaction(TransactionRequest {
+                concern: instance.concern,
+                value: 0.into(),
+                function: String::from("claimVictory"),
+                data: CallParams::new().push(instance.index),
+                gas: None,
+                strategy: Strategy::Simplest,
+                contract_name: None,
+                deadline_block: None,
+                deadline_timestamp: None,
+                stake_value: None,
+            }))
+        }
+
+        fn get_pretty_instance(
+            instance: &Instance,
+            _ctx: &Self::Ctx,
+            _subs: &(),
+            _params: &(),
+            _context: &DAppContext,
+        ) -> Result<Instance> {
+            Ok(instance.clone())
+        }
+    }
+
+    // unwraps a field the instance does not have
+    struct Panicking();
+
+    impl DApp for Panicking {
+        type Params = ();
+        type Ctx = ();
+        type Subs = ();
+
+        fn parse(_instance: &Instance) -> Result<Self::Ctx> {
+            Ok(())
+        }
+
+        fn react(
+            instance: &Instance,
+            _ctx: &(),
+            _subs: &(),
+            _params: &(),
+            _context: &DAppContext,
+            _post_action: &Option<String>,
+        ) -> Result<Reaction> {
+            let state: serde_json::Value =
+                serde_json::from_str(&instance.json_data).unwrap();
+            state.get("missing").unwrap();
+            Ok(Reaction::Idle)
+        }
+
+        fn get_pretty_instance(
+            instance: &Instance,
+            _ctx: &(),
+            _subs: &(),
+            _params: &(),
+            _context: &DAppContext,
+        ) -> Result<Instance> {
+            Ok(instance.clone())
+        }
+    }
+
+    fn fixture(name: &str) -> Fixture {
+        let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "fixtures", name]
+            .iter()
+            .collect();
+        Fixture::load(&path).unwrap()
+    }
+
+    #[test]
+    fn replays_instance_at_recorded_time() {
+        let mut fixture = fixture("idle.json");
+        expect_idle(&fixture.react::<Example>(&()).unwrap());
+
+        fixture.timestamp = 1200;
+        let reaction = fixture.react::<Example>(&()).unwrap();
+        assert_eq!(expect_transaction(&reaction).function, "claimVictory");
+    }
+
+    #[test]
+    fn panics_of_the_dapp_are_errors() {
+        let fixture = fixture("idle.json");
+        let error = fixture.react::<Panicking>(&()).unwrap_err();
+        match error.kind() {
+            ErrorKind::DAppPanicked(concern, details) => {
+                assert_eq!(*concern, format!("{}", fixture.instance.concern));
+                assert!(details.starts_with("instance 0"));
+            }
+            other => panic!("expected a panic, got {:?}", other),
+        }
+    }
+}
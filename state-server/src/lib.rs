@@ -0,0 +1,361 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Server that reads the state of instances from the blockchain on behalf
+//! of several dispatchers (or external tools), together with the client
+//! used by the dispatcher to talk to it. The messages and the service are
+//! defined in `proto/instances.proto`, from which the build generates the
+//! stubs of this crate.
+
+extern crate configuration;
+extern crate error;
+extern crate ethereum_types;
+extern crate grpc;
+extern crate protobuf;
+extern crate state;
+extern crate web3;
+
+#[macro_use]
+extern crate log;
+
+pub mod instances;
+pub mod instances_grpc;
+
+use configuration::checksum::{self, checksummed};
+use configuration::{Concern, TransPort};
+use error::*;
+use ethereum_types::U256;
+use grpc::{Client, ClientStub, RequestOptions, SingleResponse};
+use instances::{Indices, IndicesRequest, InstanceRequest};
+use instances_grpc::{StateServer, StateServerClient, StateServerServer};
+use state::{Instance, ServiceStatus, StateReader};
+use std::sync::{Arc, Mutex};
+use web3::futures::Future;
+
+fn concern_message(concern: &Concern) -> instances::Concern {
+    let mut message = instances::Concern::new();
+    message.set_contract_address(checksummed(&concern.contract_address));
+    message.set_user_address(checksummed(&concern.user_address));
+    message
+}
+
+fn concern_of(message: &instances::Concern) -> Result<Concern> {
+    Ok(Concern {
+        contract_address: checksum::parse(
+            message.get_contract_address(),
+            true,
+        )?,
+        user_address: checksum::parse(message.get_user_address(), true)?,
+    })
+}
+
+// indices travel as u64, but the readers count them in usize
+fn index_of(index: u64) -> Result<usize> {
+    if index > usize::max_value() as u64 {
+        return Err(Error::from(ErrorKind::InvalidStateRequest(format!(
+            "index {} is too large",
+            index
+        ))));
+    }
+    Ok(index as usize)
+}
+
+fn instance_message(instance: &Instance) -> instances::Instance {
+    let mut index = [0u8; 32];
+    instance.index.to_big_endian(&mut index);
+    let mut service_status = instances::ServiceStatus::new();
+    service_status
+        .set_service_name(instance.service_status.service_name.clone());
+    service_status
+        .set_service_method(instance.service_status.service_method.clone());
+    service_status.set_status(instance.service_status.status);
+    service_status.set_description(instance.service_status.description.clone());
+    service_status.set_progress(instance.service_status.progress);
+
+    let mut message = instances::Instance::new();
+    message.set_name(instance.name.clone());
+    message.set_concern(concern_message(&instance.concern));
+    message.set_index(index.to_vec());
+    message.set_service_status(service_status);
+    message.set_json_data(instance.json_data.clone());
+    for sub_instance in &instance.sub_instances {
+        message
+            .mut_sub_instances()
+            .push(instance_message(sub_instance));
+    }
+    message
+}
+
+fn instance_of(message: &instances::Instance) -> Result<Instance> {
+    if message.get_index().len() > 32 {
+        return Err(Error::from(ErrorKind::InvalidStateRequest(format!(
+            "index of instance {} is longer than 32 bytes",
+            message.get_name()
+        ))));
+    }
+    let service_status = message.get_service_status();
+    let sub_instances = message
+        .get_sub_instances()
+        .iter()
+        .map(|sub_instance| instance_of(sub_instance).map(Box::new))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Instance {
+        name: message.get_name().to_string(),
+        concern: concern_of(message.get_concern())?,
+        index: U256::from_big_endian(message.get_index()),
+        service_status: ServiceStatus {
+            service_name: service_status.get_service_name().to_string(),
+            service_method: service_status.get_service_method().to_string(),
+            status: service_status.get_status(),
+            description: service_status.get_description().to_string(),
+            progress: service_status.get_progress(),
+        },
+        json_data: message.get_json_data().to_string(),
+        sub_instances: sub_instances,
+    })
+}
+
+fn to_grpc_error(e: Error) -> grpc::Error {
+    grpc::Error::GrpcMessage(grpc::GrpcMessageError {
+        grpc_status: grpc::GrpcStatus::Internal as i32,
+        grpc_message: format!("{}", e),
+    })
+}
+
+// answers requests with the given state reader
+struct StateHandler<S> {
+    state_reader: Arc<Mutex<S>>,
+}
+
+impl<S: StateReader + 'static> StateServer for StateHandler<S> {
+    fn get_indices(
+        &self,
+        _: RequestOptions,
+        request: IndicesRequest,
+    ) -> SingleResponse<Indices> {
+        trace!("Received indices request: {:?}", request);
+        let concern = match concern_of(request.get_concern()) {
+            Ok(concern) => concern,
+            Err(e) => return SingleResponse::err(to_grpc_error(e)),
+        };
+        let indices = self
+            .state_reader
+            .lock()
+            .unwrap()
+            .get_indices(concern, request.get_active());
+        SingleResponse::no_metadata(
+            indices
+                .map(|indices| {
+                    let mut message = Indices::new();
+                    message.set_indices(
+                        indices.into_iter().map(|index| index as u64).collect(),
+                    );
+                    message
+                })
+                .map_err(to_grpc_error),
+        )
+    }
+
+    fn get_instance(
+        &self,
+        _: RequestOptions,
+        request: InstanceRequest,
+    ) -> SingleResponse<instances::Instance> {
+        trace!("Received instance request: {:?}", request);
+        let concern = match concern_of(request.get_concern()) {
+            Ok(concern) => concern,
+            Err(e) => return SingleResponse::err(to_grpc_error(e)),
+        };
+        let index = match index_of(request.get_index()) {
+            Ok(index) => index,
+            Err(e) => return SingleResponse::err(to_grpc_error(e)),
+        };
+        let reader = self.state_reader.lock().unwrap();
+        let instance = if request.has_block_number() {
+            reader.get_instance_at(concern, index, request.get_block_number())
+        } else {
+            reader.get_instance(concern, index)
+        };
+        SingleResponse::no_metadata(
+            instance
+                .map(|instance| instance_message(&instance))
+                .map_err(to_grpc_error),
+        )
+    }
+}
+
+/// Starts serving the given state reader on the given port
+pub fn serve<S: StateReader + 'static>(
+    state_reader: S,
+    port: u16,
+) -> Result<grpc::Server> {
+    let mut server = grpc::ServerBuilder::new_plain();
+    server.http.set_port(port);
+    server.add_service(StateServerServer::new_service_def(StateHandler {
+        state_reader: Arc::new(Mutex::new(state_reader)),
+    }));
+    let server = server.build()?;
+    info!("State server listening on port {}", port);
+    Ok(server)
+}
+
+/// Reads the state of instances from a remote state server
+pub struct StateClient {
+    client: StateServerClient,
+}
+
+impl StateClient {
+    pub fn new(server: &TransPort) -> Result<StateClient> {
+        let client =
+            Client::new_plain(&server.address, server.port, Default::default())
+                .chain_err(|| {
+                    format!("could not connect to state server at {}", server)
+                })?;
+        Ok(StateClient {
+            client: StateServerClient::with_client(Arc::new(client)),
+        })
+    }
+
+    fn instance(
+        &self,
+        request: InstanceRequest,
+    ) -> Box<dyn Future<Item = Instance, Error = Error> + Send> {
+        Box::new(
+            self.client
+                .get_instance(RequestOptions::new(), request)
+                .drop_metadata()
+                .map_err(|e| {
+                    Error::from(e).chain_err(|| "state server request failed")
+                })
+                .and_then(|message| instance_of(&message)),
+        )
+    }
+}
+
+impl StateReader for StateClient {
+    fn get_indices(
+        &self,
+        concern: Concern,
+        active: bool,
+    ) -> Box<dyn Future<Item = Vec<usize>, Error = Error> + Send> {
+        let mut request = IndicesRequest::new();
+        request.set_concern(concern_message(&concern));
+        request.set_active(active);
+        Box::new(
+            self.client
+                .get_indices(RequestOptions::new(), request)
+                .drop_metadata()
+                .map_err(|e| {
+                    Error::from(e).chain_err(|| "state server request failed")
+                })
+                .and_then(|message| {
+                    message
+                        .get_indices()
+                        .iter()
+                        .map(|index| index_of(*index))
+                        .collect::<Result<Vec<_>>>()
+                }),
+        )
+    }
+
+    fn get_instance(
+        &self,
+        concern: Concern,
+        index: usize,
+    ) -> Box<dyn Future<Item = Instance, Error = Error> + Send> {
+        let mut request = InstanceRequest::new();
+        request.set_concern(concern_message(&concern));
+        request.set_index(index as u64);
+        self.instance(request)
+    }
+
+    fn get_instance_at(
+        &self,
+        concern: Concern,
+        index: usize,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = Instance, Error = Error> + Send> {
+        let mut request = InstanceRequest::new();
+        request.set_concern(concern_message(&concern));
+        request.set_index(index as u64);
+        request.set_block_number(block_number);
+        self.instance(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobuf::Message;
+    use web3::types::Address;
+
+    fn instance(name: &str, index: u64) -> Instance {
+        Instance {
+            name: name.to_string(),
+            concern: Concern {
+                contract_address: Address::repeat_byte(0xaa),
+                user_address: Address::repeat_byte(0xbb),
+            },
+            index: U256::from(index),
+            service_status: ServiceStatus {
+                service_name: "emulator".to_string(),
+                service_method: "run".to_string(),
+                status: 1,
+                description: "running".to_string(),
+                progress: 50,
+            },
+            json_data: "{\"round\":3}".to_string(),
+            sub_instances: vec![],
+        }
+    }
+
+    #[test]
+    fn instances_survive_the_wire() {
+        let mut parent = instance("verification_game", 7);
+        parent.index = U256::max_value();
+        parent
+            .sub_instances
+            .push(Box::new(instance("partition", 2)));
+
+        let bytes = instance_message(&parent).write_to_bytes().unwrap();
+        let message: instances::Instance =
+            protobuf::parse_from_bytes(&bytes).unwrap();
+        let parsed = instance_of(&message).unwrap();
+        assert_eq!(parsed.name, parent.name);
+        assert_eq!(parsed.concern, parent.concern);
+        assert_eq!(parsed.index, U256::max_value());
+        assert_eq!(parsed.service_status.progress, 50);
+        assert_eq!(parsed.json_data, parent.json_data);
+        assert_eq!(parsed.sub_instances.len(), 1);
+        assert_eq!(parsed.sub_instances[0].name, "partition");
+        assert_eq!(parsed.sub_instances[0].index, U256::from(2));
+    }
+
+    #[test]
+    fn indices_longer_than_a_word_are_refused() {
+        let mut message = instance_message(&instance("partition", 2));
+        message.set_index(vec![1; 33]);
+        assert!(instance_of(&message).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+extern crate configuration;
+extern crate env_logger;
+extern crate error;
+extern crate state;
+extern crate state_server;
+extern crate transport;
+extern crate utils;
+extern crate web3;
+
+#[macro_use]
+extern crate log;
+
+use configuration::Configuration;
+use error::*;
+use state::StateManager;
+use transport::{GenericTransport, RequestBudgets};
+use utils::print_error;
+
+fn run() -> Result<()> {
+    info!("Loading configuration file");
+    let config = Configuration::new()
+        .chain_err(|| format!("could not load configuration"))?;
+
+    let port = match &config.state_server {
+        Some(server) => server.port,
+        None => {
+            return Err(Error::from(ErrorKind::ConfigError(String::from(
+                "Need a state_server entry to know which port to serve",
+            ))));
+        }
+    };
+
+    info!("Trying to connect to Eth node at {}", &config.url[..]);
+    let (_eloop, transport) =
+        GenericTransport::new(&config.url[..], config.web3_timeout).chain_err(
+            || format!("could not connect to Eth node at url: {}", &config.url),
+        )?;
+    let transport = transport
+        .with_budgets(RequestBudgets::new(
+            config.read_rate_limit,
+            config.send_rate_limit,
+        ))
+        .with_cache(config.rpc_cache_size);
+    let web3 = web3::Web3::new(transport);
+
+    info!("Creating state manager");
+    let state_manager = StateManager::new(config.clone(), web3)
+        .chain_err(|| format!("could not create state manager"))?;
+
+    let _server = state_server::serve(state_manager, port)?;
+    loop {
+        std::thread::park();
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    if let Err(e) = run() {
+        print_error(&e);
+        std::process::exit(1);
+    }
+}
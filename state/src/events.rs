@@ -0,0 +1,450 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Discovery of the instances of a concern from the events its contract
+//! emits when instantiating, instead of asking the contract about every
+//! index. Used for the concerns configured with an `instance_event`, which
+//! must have an `index` (or `_index`) argument and the addresses of the
+//! players among its arguments.
+//!
+//! Logs of any event of a concern can also be decoded with the abi of its
+//! contract, so that DApps may react to what was emitted rather than only
+//! to the state of an instance.
+
+use configuration::Concern;
+use error::*;
+use ethabi::{RawLog, Token};
+use ethereum_types::Address;
+use std::collections::HashMap;
+use std::sync::Arc;
+use transport::GenericTransport;
+use utils::convert::to_u64;
+use web3::futures::future::{self, Either, Loop};
+use web3::futures::Future;
+use web3::types::{BlockNumber, FilterBuilder};
+
+/// Most blocks asked for in a single `eth_getLogs`, as nodes limit the
+/// size of their answers
+const MAX_BLOCK_RANGE: u64 = 5_000;
+
+/// Scans the blocks not seen yet for the instantiations of each concern
+#[derive(Clone)]
+pub struct EventScanner {
+    web3: Arc<web3::Web3<GenericTransport>>,
+    url: String,
+    start_block: u64,
+}
+
+impl EventScanner {
+    pub fn new(
+        web3: Arc<web3::Web3<GenericTransport>>,
+        url: String,
+        start_block: u64,
+    ) -> Self {
+        EventScanner {
+            web3: web3,
+            url: url,
+            start_block: start_block,
+        }
+    }
+
+    /// Indices of the instances involving the user of the concern that
+    /// were created after the last scanned block (or from the start block),
+    /// up to the latest block, which is returned to resume from later
+    pub fn scan(
+        &self,
+        concern: Concern,
+        event: Arc<ethabi::Event>,
+        last_scanned: Option<u64>,
+    ) -> Box<dyn Future<Item = (Option<u64>, Vec<usize>), Error = Error> + Send>
+    {
+        let web3 = self.web3.clone();
+        let url = self.url.clone();
+        let start_block = self.start_block;
+        Box::new(self.latest_block().and_then(move |latest| {
+            scan_blocks(
+                web3,
+                url,
+                concern,
+                event,
+                start_block,
+                last_scanned,
+                latest,
+            )
+        }))
+    }
+
+    /// Like `scan`, but stopping at the given block
+    pub fn scan_to(
+        &self,
+        concern: Concern,
+        event: Arc<ethabi::Event>,
+        last_scanned: Option<u64>,
+        to: u64,
+    ) -> Box<dyn Future<Item = (Option<u64>, Vec<usize>), Error = Error> + Send>
+    {
+        scan_blocks(
+            self.web3.clone(),
+            self.url.clone(),
+            concern,
+            event,
+            self.start_block,
+            last_scanned,
+            to,
+        )
+    }
+
+    /// First block scanned when nothing was scanned yet
+    pub fn start_block(&self) -> u64 {
+        self.start_block
+    }
+
+    /// Number of the latest block
+    pub fn latest_block(
+        &self,
+    ) -> Box<dyn Future<Item = u64, Error = Error> + Send> {
+        let url = self.url.clone();
+        Box::new(
+            self.web3
+                .eth()
+                .block_number()
+                .map(|latest| latest.as_u64())
+                .map_err(move |_e| {
+                    Error::from(ErrorKind::RpcError(
+                        String::from("eth_blockNumber"),
+                        url,
+                    ))
+                }),
+        )
+    }
+
+    /// Events of a concern logged between the given blocks, decoded with
+    /// the abi of its contract
+    pub fn events(
+        &self,
+        concern: Concern,
+        abi: Arc<ethabi::Contract>,
+        from: u64,
+        to: u64,
+    ) -> Box<dyn Future<Item = Vec<DecodedEvent>, Error = Error> + Send> {
+        let filter = FilterBuilder::default()
+            .address(vec![concern.contract_address])
+            .from_block(BlockNumber::Number(from.into()))
+            .to_block(BlockNumber::Number(to.into()))
+            .build();
+        let url = self.url.clone();
+        Box::new(
+            self.web3
+                .eth()
+                .logs(filter)
+                .map_err(move |_e| {
+                    Error::from(ErrorKind::RpcError(
+                        String::from("eth_getLogs"),
+                        url,
+                    ))
+                })
+                .and_then(move |logs| {
+                    decode_logs(
+                        &abi,
+                        logs.into_iter()
+                            .map(|log| RawLog {
+                                topics: log.topics,
+                                data: log.data.0,
+                            })
+                            .collect(),
+                    )
+                }),
+        )
+    }
+}
+
+// instances involving the user created after the last scanned block (or
+// from the start block) up to the given one, a range at a time
+fn scan_blocks(
+    web3: Arc<web3::Web3<GenericTransport>>,
+    url: String,
+    concern: Concern,
+    event: Arc<ethabi::Event>,
+    start_block: u64,
+    last_scanned: Option<u64>,
+    latest: u64,
+) -> Box<dyn Future<Item = (Option<u64>, Vec<usize>), Error = Error> + Send> {
+    let from = last_scanned.map_or(start_block, |block| block + 1);
+    let start = (from, last_scanned, vec![]);
+    Box::new(future::loop_fn(start, move |(from, last, mut found)| {
+        if from > latest {
+            return Either::B(future::ok(Loop::Break((last, found))));
+        }
+        let to = latest.min(from + MAX_BLOCK_RANGE - 1);
+        Either::A(scan_range(&web3, &url, concern, &event, from, to).map(
+            move |indices| {
+                found.extend(indices);
+                Loop::Continue((to + 1, Some(to), found))
+            },
+        ))
+    }))
+}
+
+// instances involving the user created between the given blocks
+fn scan_range(
+    web3: &web3::Web3<GenericTransport>,
+    url: &str,
+    concern: Concern,
+    event: &Arc<ethabi::Event>,
+    from: u64,
+    to: u64,
+) -> Box<dyn Future<Item = Vec<usize>, Error = Error> + Send> {
+    trace!(
+        "Scanning blocks {} to {} for {} events",
+        from,
+        to,
+        event.name
+    );
+    let filter = FilterBuilder::default()
+        .address(vec![concern.contract_address])
+        .topics(Some(vec![event.signature()]), None, None, None)
+        .from_block(BlockNumber::Number(from.into()))
+        .to_block(BlockNumber::Number(to.into()))
+        .build();
+    let event = event.clone();
+    let url = String::from(url);
+    Box::new(
+        web3.eth()
+            .logs(filter)
+            .map_err(move |_e| {
+                Error::from(ErrorKind::RpcError(
+                    String::from("eth_getLogs"),
+                    url,
+                ))
+            })
+            .and_then(move |logs| {
+                let mut found = vec![];
+                for log in logs {
+                    let raw = RawLog {
+                        topics: log.topics,
+                        data: log.data.0,
+                    };
+                    if let Some(index) =
+                        instance_of(&event, raw, concern.user_address)?
+                    {
+                        found.push(index);
+                    }
+                }
+                Ok(found)
+            }),
+    )
+}
+
+// whether the user's address is among the values of a token
+fn mentions(token: &Token, user: Address) -> bool {
+    match token {
+        Token::Address(address) => *address == user,
+        Token::Array(tokens) | Token::FixedArray(tokens) => {
+            tokens.iter().any(|t| mentions(t, user))
+        }
+        _ => false,
+    }
+}
+
+/// Index of the instance whose creation was logged, if the user takes part
+/// in it
+pub fn instance_of(
+    event: &ethabi::Event,
+    log: RawLog,
+    user: Address,
+) -> Result<Option<usize>> {
+    let log = event.parse_log(log)?;
+    if !log.params.iter().any(|param| mentions(&param.value, user)) {
+        return Ok(None);
+    }
+    let index = log
+        .params
+        .iter()
+        .find(|param| param.name == "index" || param.name == "_index")
+        .and_then(|param| param.value.clone().to_uint())
+        .ok_or(Error::from(format!(
+            "event {} has no index argument",
+            event.name
+        )))?;
+    let index = to_u64(index)
+        .ok()
+        .filter(|index| *index <= usize::max_value() as u64)
+        .ok_or(Error::from(format!(
+            "index {} of event {} is too large",
+            index, event.name
+        )))?;
+    Ok(Some(index as usize))
+}
+
+/// An event logged by a contract, with its arguments by name
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedEvent {
+    pub name: String,
+    pub params: HashMap<String, Token>,
+}
+
+impl DecodedEvent {
+    pub fn param(&self, name: &str) -> Result<&Token> {
+        self.params.get(name).ok_or(Error::from(format!(
+            "event {} has no argument {}",
+            self.name, name
+        )))
+    }
+}
+
+/// Decodes a log with the abi of the contract that emitted it, giving
+/// none if its topic is not one of the (non anonymous) events of the abi
+pub fn decode_log(
+    abi: &ethabi::Contract,
+    log: RawLog,
+) -> Result<Option<DecodedEvent>> {
+    let topic = match log.topics.first() {
+        Some(topic) => *topic,
+        None => return Ok(None),
+    };
+    let event = match abi
+        .events()
+        .find(|event| !event.anonymous && event.signature() == topic)
+    {
+        Some(event) => event,
+        None => return Ok(None),
+    };
+    let parsed = event
+        .parse_log(log)
+        .chain_err(|| format!("could not decode event {}", event.name))?;
+    Ok(Some(DecodedEvent {
+        name: event.name.clone(),
+        params: parsed
+            .params
+            .into_iter()
+            .map(|param| (param.name, param.value))
+            .collect(),
+    }))
+}
+
+/// Decodes the logs of a contract in order, leaving out those of events
+/// unknown to its abi
+pub fn decode_logs(
+    abi: &ethabi::Contract,
+    logs: Vec<RawLog>,
+) -> Result<Vec<DecodedEvent>> {
+    let mut events = vec![];
+    for log in logs {
+        if let Some(event) = decode_log(abi, log)? {
+            events.push(event);
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethabi::{EventParam, ParamType};
+    use ethereum_types::{H256, U256};
+
+    fn instantiated() -> ethabi::Event {
+        ethabi::Event {
+            name: String::from("InstanceCreated"),
+            inputs: vec![
+                EventParam {
+                    name: String::from("_index"),
+                    kind: ParamType::Uint(256),
+                    indexed: true,
+                },
+                EventParam {
+                    name: String::from("_players"),
+                    kind: ParamType::Array(Box::new(ParamType::Address)),
+                    indexed: false,
+                },
+            ],
+            anonymous: false,
+        }
+    }
+
+    fn log(event: &ethabi::Event, index: u64, players: &[Address]) -> RawLog {
+        let mut index_topic = [0u8; 32];
+        U256::from(index).to_big_endian(&mut index_topic);
+        RawLog {
+            topics: vec![event.signature(), H256::from(index_topic)],
+            data: ethabi::encode(&[Token::Array(
+                players.iter().map(|p| Token::Address(*p)).collect(),
+            )]),
+        }
+    }
+
+    #[test]
+    fn finds_instances_involving_the_user() {
+        let event = instantiated();
+        let user = Address::repeat_byte(1);
+        let other = Address::repeat_byte(2);
+
+        let with_user = log(&event, 7, &[other, user]);
+        assert_eq!(instance_of(&event, with_user, user).unwrap(), Some(7));
+        let without_user = log(&event, 8, &[other]);
+        assert_eq!(instance_of(&event, without_user, user).unwrap(), None);
+    }
+
+    #[test]
+    fn refuses_indices_too_large() {
+        let event = instantiated();
+        let user = Address::repeat_byte(1);
+        let mut huge = log(&event, 0, &[user]);
+        huge.topics[1] = H256::repeat_byte(0xff);
+        assert!(instance_of(&event, huge, user).is_err());
+    }
+
+    #[test]
+    fn decodes_logs_of_known_events() {
+        let event = instantiated();
+        let abi = ethabi::Contract::load(
+            r#"[{
+                "type": "event",
+                "name": "InstanceCreated",
+                "anonymous": false,
+                "inputs": [
+                    {"name": "_index", "type": "uint256", "indexed": true},
+                    {"name": "_players", "type": "address[]", "indexed": false}
+                ]
+            }]"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let user = Address::repeat_byte(1);
+        let unknown = RawLog {
+            topics: vec![H256::repeat_byte(9)],
+            data: vec![],
+        };
+
+        let events =
+            decode_logs(&abi, vec![log(&event, 7, &[user]), unknown]).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].name, "InstanceCreated");
+        assert_eq!(
+            events[0].param("_index").unwrap(),
+            &Token::Uint(U256::from(7))
+        );
+        assert!(events[0].param("_winner").is_err());
+    }
+}
@@ -32,28 +32,29 @@ extern crate error;
 extern crate serde_derive;
 #[macro_use]
 extern crate log;
-extern crate db_key;
 extern crate ethabi;
 //extern crate ethcore_transaction;
 extern crate ethereum_types;
-extern crate leveldb;
 extern crate serde_json;
 extern crate transport;
+extern crate utils;
 extern crate web3;
 
+pub mod events;
+
 use configuration::{Concern, Configuration};
 use error::*;
 use ethabi::{Param, Token};
 use ethereum_types::{Address, U256};
-use leveldb::database::Database;
-use leveldb::kv::KV;
-use leveldb::options::{ReadOptions, WriteOptions};
+pub use events::DecodedEvent;
+use events::EventScanner;
 use serde_json::Value;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::Read;
+use std::path::PathBuf;
 use std::sync::Arc;
-use transport::GenericTransport;
+use transport::{Connection, GenericTransport};
+use utils::kv::{KvStore, LazyStore};
+use utils::retry::Retry;
 use web3::contract::Options;
 use web3::futures;
 use web3::futures::future::err;
@@ -62,7 +63,7 @@ use web3::futures::future::Either;
 use web3::futures::stream;
 use web3::futures::Future;
 use web3::futures::Stream;
-use web3::types::{Bytes, CallRequest};
+use web3::types::{BlockId, BlockNumber, Bytes, CallRequest};
 
 use web3::contract::tokens::Tokenize;
 
@@ -85,22 +86,151 @@ pub struct Instance {
     pub sub_instances: Vec<Box<Instance>>,
 }
 
+/// Anything that can read the state of the instances of a concern, either
+/// directly from the blockchain or through a state server
+pub trait StateReader: Send {
+    /// Get relevant indices of a concern, optionally only the active ones
+    fn get_indices(
+        &self,
+        concern: Concern,
+        active: bool,
+    ) -> Box<dyn Future<Item = Vec<usize>, Error = Error> + Send>;
+
+    /// Get an instance, together with all its sub instances
+    fn get_instance(
+        &self,
+        concern: Concern,
+        index: usize,
+    ) -> Box<dyn Future<Item = Instance, Error = Error> + Send>;
+
+    /// Get an instance as it was at the given block, to compare its state
+    /// across blocks
+    fn get_instance_at(
+        &self,
+        concern: Concern,
+        index: usize,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = Instance, Error = Error> + Send>;
+
+    /// Finds all the instances of a concern created so far, including
+    /// those created before the dispatcher first ran, before it starts
+    /// polling. Readers keeping no history of their own simply get the
+    /// indices.
+    fn backfill(
+        &self,
+        concern: Concern,
+    ) -> Box<dyn Future<Item = Vec<usize>, Error = Error> + Send> {
+        self.get_indices(concern, false)
+    }
+
+    /// Reads the instances of a concern with the abi of the given artifact
+    /// from now on, after its contract was upgraded. Readers that do not
+    /// load abis refuse it.
+    fn replace_abi(
+        &mut self,
+        concern: Concern,
+        abi_path: &PathBuf,
+    ) -> Result<()> {
+        Err(Error::from(ErrorKind::InvalidStateRequest(format!(
+            "the abi of {} cannot be replaced, as it is not read here (from \
+             {})",
+            concern,
+            abi_path.display()
+        ))))
+    }
+}
+
 struct ConcernData {
     contract: Arc<web3::contract::Contract<GenericTransport>>,
     abi: Arc<ethabi::Contract>,
     file_name: String,
+    instance_event: Option<Arc<ethabi::Event>>,
+}
+
+// reads the abi of a concern and what is derived from it
+fn load_concern(
+    eth: web3::api::Eth<GenericTransport>,
+    concern: Concern,
+    abi_path: &PathBuf,
+    instance_event: Option<&String>,
+) -> Result<ConcernData> {
+    trace!(
+        "Getting contract {} abi from file {:?}",
+        &concern.contract_address,
+        &abi_path
+    );
+    let abi_error = |details: &str| {
+        ErrorKind::AbiError(abi_path.clone(), String::from(details))
+    };
+    // the abi may still be being written by a deployment
+    let s = Retry::new()
+        .run(|| Ok(std::fs::read_to_string(abi_path)?))
+        .chain_err(|| abi_error("could not read file"))?;
+    let v: Value =
+        serde_json::from_str(&s[..]).chain_err(|| abi_error("invalid json"))?;
+
+    // create a contract object
+    let contract = web3::contract::Contract::from_json(
+        eth,
+        concern.contract_address,
+        serde_json::to_string(&v["abi"]).unwrap().as_bytes(),
+    )
+    .chain_err(|| abi_error("could not decode abi"))?;
+
+    // create a low level abi for contract
+    let abi = ethabi::Contract::load(
+        serde_json::to_string(&v["abi"]).unwrap().as_bytes(),
+    )
+    .chain_err(|| abi_error("could not decode abi"))?;
+
+    // event announcing new instances, if they are found by events
+    let instance_event = match instance_event {
+        Some(name) => Some(Arc::new(
+            abi.event(name)
+                .chain_err(|| abi_error("instance event not found"))?
+                .clone(),
+        )),
+        None => None,
+    };
+
+    Ok(ConcernData {
+        contract: Arc::new(contract),
+        abi: Arc::new(abi),
+        file_name: String::from(
+            abi_path.as_path().file_stem().unwrap().to_str().unwrap(),
+        ),
+        instance_event: instance_event,
+    })
 }
 
+/// Blocks scanned by a backfill between two writes of the cache, so that
+/// an interrupted backfill resumes close to where it stopped
+const BACKFILL_STEP: u64 = 50_000;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct ConcernCache {
     last_maximum_index: usize,
     list_instances: Vec<usize>,
+    // for concerns indexed by events, where to resume scanning
+    #[serde(default)]
+    last_scanned_block: Option<u64>,
 }
 
-pub struct StateManager {
+/// The node the concerns of a network are read from, with the scanner of
+/// their events
+struct NetworkReader {
     web3: Arc<web3::Web3<GenericTransport>>,
+    scanner: EventScanner,
+    // kept to stay in scope, none for the node of the configured url
+    _connection: Option<Connection>,
+}
+
+pub struct StateManager {
     concern_data: HashMap<Concern, ConcernData>,
-    database: Arc<Database<Concern>>,
+    database: Arc<dyn KvStore>,
+    // by network name, none for the one of the configured url
+    networks: HashMap<Option<String>, NetworkReader>,
+    concern_networks: HashMap<Concern, String>,
 }
 
 impl StateManager {
@@ -108,80 +238,206 @@ impl StateManager {
         config: Configuration,
         web3: web3::Web3<GenericTransport>,
     ) -> Result<StateManager> {
-        info!("Opening state manager database");
-        let mut options = leveldb::options::Options::new();
-        // if no database is found we start an empty one (no cache)
-        options.create_if_missing = true;
-        let database: Database<Concern> =
-            Database::open(&config.working_path.join("state_db"), options)
-                .chain_err(|| {
-                    format!(
-                    "no state database (use -i if running for the first time)"
-                )
-                })?;
+        // opened on first use, like the other databases, and started
+        // empty (no cache) if not found
+        let database: Arc<dyn KvStore> = Arc::new(LazyStore::new(
+            config.storage,
+            &config.working_path.join("state_db"),
+        ));
+
+        let web3 = Arc::new(web3);
+        let mut networks = HashMap::new();
+        networks.insert(
+            None,
+            NetworkReader {
+                web3: web3.clone(),
+                scanner: EventScanner::new(
+                    web3,
+                    config.url.clone(),
+                    config.start_block,
+                ),
+                _connection: None,
+            },
+        );
+        for (name, network) in config.networks.iter() {
+            info!("Connecting to network {}", name);
+            let connection = config.connect_to(network)?;
+            let web3 = Arc::new(connection.web3.clone());
+            networks.insert(
+                Some(name.clone()),
+                NetworkReader {
+                    web3: web3.clone(),
+                    scanner: EventScanner::new(
+                        web3,
+                        network.url.clone(),
+                        network.start_block,
+                    ),
+                    _connection: Some(connection),
+                },
+            );
+        }
 
         info!("Preparing assets for {} concerns", config.concerns.len());
         let mut concern_data = HashMap::new();
         for concern in config.clone().concerns {
             let abi_path = &config.abis.get(&concern).unwrap().abi;
-            trace!(
-                "Getting contract {} abi from file {:?}",
-                &concern.contract_address,
-                &abi_path
-            );
-            let mut file = File::open(abi_path)?;
-            let mut s = String::new();
-            file.read_to_string(&mut s)?;
-            let v: Value = serde_json::from_str(&s[..])
-                .chain_err(|| format!("could not read contract json file"))?;
-
-            // create a contract object
-            let contract = web3::contract::Contract::from_json(
-                web3.eth().clone(),
-                concern.contract_address,
-                serde_json::to_string(&v["abi"]).unwrap().as_bytes(),
-            )
-            .chain_err(|| format!("could not decode json abi"))?;
-
-            // create a low level abi for contract
-            let abi = ethabi::Contract::load(
-                serde_json::to_string(&v["abi"]).unwrap().as_bytes(),
+            let network = &networks[&config.network_of(&concern).cloned()];
+            let data = load_concern(
+                network.web3.eth(),
+                concern,
+                abi_path,
+                config.instance_events.get(&concern),
             )?;
-
             // store concern data in hash table
             trace!("Inserting concern {:?}", concern.clone());
-            concern_data.insert(
-                concern.clone(),
-                ConcernData {
-                    contract: Arc::new(contract),
-                    abi: Arc::new(abi),
-                    file_name: String::from(
-                        abi_path
-                            .clone()
-                            .as_path()
-                            .file_stem()
-                            .unwrap()
-                            .to_str()
-                            .unwrap(),
-                    ),
-                },
-            );
+            concern_data.insert(concern.clone(), data);
         }
 
-        Ok(StateManager {
+        let state_manager = StateManager {
             concern_data: concern_data,
-            web3: Arc::new(web3),
-            database: Arc::new(database),
-        })
+            database: database,
+            networks: networks,
+            concern_networks: config.concern_networks.clone(),
+        };
+        if let Some(block) = config.rescan_from {
+            state_manager.rescan_from(block)?;
+        }
+        Ok(state_manager)
+    }
+
+    // the network a concern is read from
+    fn network_of(&self, concern: &Concern) -> &NetworkReader {
+        &self.networks[&self.concern_networks.get(concern).cloned()]
+    }
+
+    /// Makes the concerns indexed by events scan again from the given
+    /// block, keeping the instances already found
+    fn rescan_from(&self, block: u64) -> Result<()> {
+        for (concern, data) in self.concern_data.iter() {
+            if data.instance_event.is_none() {
+                continue;
+            }
+            info!("Scanning events of {} again from block {}", concern, block);
+            let mut concern_cache = self.get_concern_cache(concern)?;
+            concern_cache.last_scanned_block = block.checked_sub(1);
+            put_concern_cache(&self.database, concern, &concern_cache)?;
+        }
+        Ok(())
+    }
+
+    /// Events logged by the contract of a concern between the given
+    /// blocks, decoded with its abi
+    pub fn get_events(
+        &self,
+        concern: Concern,
+        from_block: u64,
+        to_block: u64,
+    ) -> Box<dyn Future<Item = Vec<DecodedEvent>, Error = Error> + Send> {
+        match self.concern_data.get(&concern) {
+            Some(data) => self.network_of(&concern).scanner.events(
+                concern,
+                data.abi.clone(),
+                from_block,
+                to_block,
+            ),
+            None => Box::new(err(Error::from(ErrorKind::InvalidStateRequest(
+                String::from("Concern requested not found"),
+            )))),
+        }
+    }
+
+    /// Scans the instantiation events of a concern from where the last
+    /// scan stopped (or from the start block) up to the latest block,
+    /// writing the instances found after every step, and returns all the
+    /// instances known. Concerns not indexed by events are only asked
+    /// about their instances, as when polling.
+    pub fn backfill(
+        &self,
+        concern: Concern,
+    ) -> Box<dyn Future<Item = Vec<usize>, Error = Error> + Send> {
+        let instance_event = self
+            .concern_data
+            .get(&concern)
+            .and_then(|data| data.instance_event.clone());
+        let event = match instance_event {
+            Some(event) => event,
+            None => return self.get_indices(concern, false),
+        };
+        let concern_cache = match self.get_concern_cache(&concern) {
+            Ok(concern_cache) => concern_cache,
+            Err(e) => {
+                return Box::new(err(Error::from(e)
+                    .chain_err(|| "error while getting concern_cache")));
+            }
+        };
+
+        let scanner = self.network_of(&concern).scanner.clone();
+        let database = Arc::clone(&self.database);
+        let start_block = scanner.start_block();
+        Box::new(scanner.latest_block().and_then(move |latest| {
+            info!(
+                "Backfilling instances of {} from block {} to {}",
+                concern,
+                concern_cache
+                    .last_scanned_block
+                    .map_or(start_block, |block| block + 1),
+                latest
+            );
+            futures::future::loop_fn(concern_cache, move |mut concern_cache| {
+                let from = concern_cache
+                    .last_scanned_block
+                    .map_or(start_block, |block| block + 1);
+                if from > latest {
+                    info!(
+                        "Backfilled {} instances of {}",
+                        concern_cache.list_instances.len(),
+                        concern
+                    );
+                    return Either::B(ok(futures::future::Loop::Break(
+                        concern_cache.list_instances,
+                    )));
+                }
+                let to = latest.min(from + BACKFILL_STEP - 1);
+                let database = Arc::clone(&database);
+                Either::A(
+                    scanner
+                        .scan_to(
+                            concern,
+                            event.clone(),
+                            concern_cache.last_scanned_block,
+                            to,
+                        )
+                        .and_then(move |(last_scanned, found)| {
+                            concern_cache.last_scanned_block = last_scanned;
+                            concern_cache.list_instances.extend(found);
+                            concern_cache.list_instances.sort();
+                            concern_cache.list_instances.dedup();
+                            put_concern_cache(
+                                &database,
+                                &concern,
+                                &concern_cache,
+                            )?;
+                            info!(
+                                "Backfilled {} up to block {} of {}, {} \
+                                 instances found",
+                                concern,
+                                to,
+                                latest,
+                                concern_cache.list_instances.len()
+                            );
+                            Ok(futures::future::Loop::Continue(concern_cache))
+                        }),
+                )
+            })
+        }))
     }
 
     /// Gets the information about a given concern as it was stored in db
     fn get_concern_cache(&self, ref concern: &Concern) -> Result<ConcernCache> {
         let database = Arc::clone(&self.database);
         trace!("Reading cached database for concern {:?}", concern);
-        let read_opts = ReadOptions::new();
         Ok(database
-            .get(read_opts, concern.clone())
+            .get(&concern.to_bytes())
             .chain_err(|| format!("could not read from state database"))?
             .map(|data: Vec<u8>| -> Result<ConcernCache> {
                 let json_string: &str = std::str::from_utf8(&data)?;
@@ -192,6 +448,7 @@ impl StateManager {
             .unwrap_or(ConcernCache {
                 last_maximum_index: 0,
                 list_instances: vec![],
+                last_scanned_block: None,
             }))
     }
 
@@ -210,6 +467,29 @@ impl StateManager {
         };
         trace!("Cached concerns are {:?}", concern_cache);
 
+        // new instances announce themselves through events
+        let instance_event = self
+            .concern_data
+            .get(&concern)
+            .and_then(|data| data.instance_event.clone());
+        if let Some(event) = instance_event {
+            let last_scanned = concern_cache.last_scanned_block;
+            return Box::new(
+                self.network_of(&concern)
+                    .scanner
+                    .scan(concern, event, last_scanned)
+                    .map(move |(last_scanned, found)| {
+                        // stored with the instances, so that a restart resumes
+                        // from there
+                        concern_cache.last_scanned_block = last_scanned;
+                        concern_cache.list_instances.extend(found);
+                        concern_cache.list_instances.sort();
+                        concern_cache.list_instances.dedup();
+                        Arc::new(concern_cache)
+                    }),
+            );
+        }
+
         // clone contract to move it to future clojure
         let contract = Arc::clone(
             &match self.concern_data.get(&concern) {
@@ -282,6 +562,7 @@ impl StateManager {
                             Arc::new(ConcernCache {
                                 last_maximum_index: max_index,
                                 list_instances: concern_cache.list_instances,
+                                last_scanned_block: None,
                             })
                         })
                         .map_err(|e| {
@@ -296,11 +577,13 @@ impl StateManager {
                 Either::B(ok(Arc::new(ConcernCache {
                     last_maximum_index: 0,
                     list_instances: vec![],
+                    last_scanned_block: None,
                 })))
             } else {
                 Either::B(ok(Arc::new(ConcernCache {
                     last_maximum_index: max_index,
                     list_instances: concern_cache.list_instances,
+                    last_scanned_block: None,
                 })))
             }
         }));
@@ -340,12 +623,12 @@ impl StateManager {
             let concern_cache = ConcernCache {
                 last_maximum_index: cache.last_maximum_index,
                 list_instances: cache_list.clone(),
+                last_scanned_block: cache.last_scanned_block,
             };
 
             trace!("Writing relevant instances to state database");
-            let write_opts = WriteOptions::new();
             let value = serde_json::to_string(&concern_cache).unwrap().clone();
-            database.put(write_opts, concern, value.as_bytes()).unwrap();
+            database.put(&concern.to_bytes(), value.as_bytes()).unwrap();
 
             let vector_of_indices = match active {
                 true => {
@@ -388,6 +671,31 @@ impl StateManager {
         &self,
         concern: Concern,
         index: usize,
+    ) -> Box<dyn Future<Item = Instance, Error = Error> + Send> {
+        self.get_instance_in(concern, index, None)
+    }
+
+    /// Get an instance with all its sub instances as they were at the
+    /// given block, which the node must still have the state of
+    pub fn get_instance_at(
+        &self,
+        concern: Concern,
+        index: usize,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = Instance, Error = Error> + Send> {
+        self.get_instance_in(
+            concern,
+            index,
+            Some(BlockId::Number(BlockNumber::Number(block_number.into()))),
+        )
+    }
+
+    // reads the instance at the given block, or at the latest one
+    fn get_instance_in(
+        &self,
+        concern: Concern,
+        index: usize,
+        block: Option<BlockId>,
     ) -> Box<dyn Future<Item = Instance, Error = Error> + Send> {
         // !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
         // this first implementation is completely synchronous
@@ -447,7 +755,7 @@ impl StateManager {
                     value: None.into(),
                     data: Some(Bytes(args)),
                 },
-                None.into(),
+                block.clone(),
             )
             .and_then(|result| {
                 let types = &function.outputs;
@@ -478,7 +786,7 @@ impl StateManager {
                     (U256::from(index), concern.user_address),
                     None,
                     Options::default(),
-                    None,
+                    block.clone(),
                 )
                 .wait()
             {
@@ -498,7 +806,10 @@ impl StateManager {
                 user_address: concern.user_address,
             };
 
-            match self.get_instance(c, instance.1.as_usize()).wait() {
+            match self
+                .get_instance_in(c, instance.1.as_usize(), block.clone())
+                .wait()
+            {
                 Ok(s) => sub_instances.push(Box::new(s)),
                 Err(e) => {
                     return Box::new(futures::future::err(Error::from(e)))
@@ -515,7 +826,8 @@ impl StateManager {
         };
         // join the subinstances together to return the current instance
         let starting_instance = Instance {
-            name: "".to_string(),
+            // the contract name lets dapps route sub-instances
+            name: concern_data.file_name.clone(),
             concern: concern,
             index: U256::from(index),
             service_status: default_status,
@@ -530,6 +842,76 @@ impl StateManager {
     }
 }
 
+impl StateReader for StateManager {
+    fn get_indices(
+        &self,
+        concern: Concern,
+        active: bool,
+    ) -> Box<dyn Future<Item = Vec<usize>, Error = Error> + Send> {
+        StateManager::get_indices(self, concern, active)
+    }
+
+    fn get_instance(
+        &self,
+        concern: Concern,
+        index: usize,
+    ) -> Box<dyn Future<Item = Instance, Error = Error> + Send> {
+        StateManager::get_instance(self, concern, index)
+    }
+
+    fn get_instance_at(
+        &self,
+        concern: Concern,
+        index: usize,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = Instance, Error = Error> + Send> {
+        StateManager::get_instance_at(self, concern, index, block_number)
+    }
+
+    fn backfill(
+        &self,
+        concern: Concern,
+    ) -> Box<dyn Future<Item = Vec<usize>, Error = Error> + Send> {
+        StateManager::backfill(self, concern)
+    }
+
+    fn replace_abi(
+        &mut self,
+        concern: Concern,
+        abi_path: &PathBuf,
+    ) -> Result<()> {
+        let instance_event = match self.concern_data.get(&concern) {
+            Some(data) => data.instance_event.as_ref().map(|e| e.name.clone()),
+            None => {
+                return Err(Error::from(ErrorKind::InvalidStateRequest(
+                    format!("unknown concern {}", concern),
+                )))
+            }
+        };
+        let data = load_concern(
+            self.network_of(&concern).web3.eth(),
+            concern,
+            abi_path,
+            instance_event.as_ref(),
+        )?;
+        info!("Reading {} with the abi of {}", concern, abi_path.display());
+        self.concern_data.insert(concern, data);
+        Ok(())
+    }
+}
+
+// writes the information about a concern to the state database
+fn put_concern_cache(
+    database: &Arc<dyn KvStore>,
+    concern: &Concern,
+    concern_cache: &ConcernCache,
+) -> Result<()> {
+    let value = serde_json::to_string(concern_cache)?;
+    database
+        .put(&concern.to_bytes(), value.as_bytes())
+        .chain_err(|| format!("could not write to state database"))
+}
+
 // !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
 // replace this by proper serialization
 // !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
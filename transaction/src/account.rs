@@ -0,0 +1,235 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! What the transaction manager knows about each sending account, kept
+//! apart so that accounts do not share nonces or gas prices.
+
+use configuration::Concern;
+use ethereum_types::{H256, U256};
+use std::collections::BTreeMap;
+
+/// A transaction sent by an account, that may not be mined yet
+#[derive(Clone, Debug)]
+pub struct SentTransaction {
+    pub gas_price: U256,
+    /// Concern the transaction was sent for
+    pub concern: Concern,
+    /// Function called, none for cancellations
+    pub function: Option<String>,
+    /// Encoded call, with its arguments, empty for cancellations
+    pub data: Vec<u8>,
+    /// Hash given by the node, unknown if it already had the nonce
+    pub hash: Option<H256>,
+}
+
+/// Gas paid by a concern for one of its mined transactions
+#[derive(Clone, Debug)]
+pub struct Spending {
+    pub concern: Concern,
+    pub hash: H256,
+    pub gas_used: U256,
+    pub gas_price: U256,
+}
+
+impl Spending {
+    /// Ether spent, in wei
+    pub fn wei(&self) -> U256 {
+        self.gas_used.saturating_mul(self.gas_price)
+    }
+}
+
+/// Nonces handed out and transactions sent by an account
+#[derive(Debug, Default)]
+pub struct AccountState {
+    next_nonce: Option<U256>,
+    // transactions sent and maybe not mined, by nonce
+    sent: BTreeMap<U256, SentTransaction>,
+    // transactions mined whose spending was not accounted for yet
+    mined: Vec<SentTransaction>,
+}
+
+impl AccountState {
+    pub fn new() -> Self {
+        AccountState::default()
+    }
+
+    /// Hands out the nonce of the next transaction, given the pending
+    /// transaction count of the node. Nonces already handed out are not
+    /// reused, even if the node did not see their transactions yet.
+    pub fn reserve_nonce(&mut self, node_nonce: U256) -> U256 {
+        let nonce = match self.next_nonce {
+            Some(next) if next > node_nonce => next,
+            _ => node_nonce,
+        };
+        self.next_nonce = Some(nonce + 1);
+        nonce
+    }
+
+    /// Moves the transactions below the transaction count of the latest
+    /// block out of the pending ones, as they were mined
+    pub fn mined(&mut self, mined_nonce: U256) {
+        let pending = self.sent.split_off(&mined_nonce);
+        let mined = std::mem::replace(&mut self.sent, pending);
+        self.mined.extend(mined.into_iter().map(|(_, sent)| sent));
+    }
+
+    /// Takes the mined transactions, to account for what they spent
+    pub fn take_mined(&mut self) -> Vec<SentTransaction> {
+        std::mem::replace(&mut self.mined, vec![])
+    }
+
+    /// Records a transaction accepted by the node
+    pub fn sent(&mut self, nonce: U256, transaction: SentTransaction) {
+        self.sent.insert(nonce, transaction);
+    }
+
+    /// Transaction sent with the given nonce by this dispatcher, if it may
+    /// still be pending
+    pub fn sent_with(&self, nonce: U256) -> Option<SentTransaction> {
+        self.sent.get(&nonce).cloned()
+    }
+
+    /// Nonce of the latest pending transaction that made the given call,
+    /// with the same arguments, so that calls of the same function for
+    /// another instance are told apart
+    pub fn pending_nonce_of(
+        &self,
+        concern: &Concern,
+        data: &[u8],
+    ) -> Option<U256> {
+        self.sent
+            .iter()
+            .rev()
+            .find(|(_, sent)| {
+                sent.concern == *concern
+                    && sent.function.is_some()
+                    && sent.data == data
+            })
+            .map(|(nonce, _)| *nonce)
+    }
+
+    /// Functions called by the transactions of the concern that may still
+    /// be pending
+    pub fn pending_functions(&self, concern: &Concern) -> Vec<String> {
+        self.sent
+            .values()
+            .filter(|sent| sent.concern == *concern)
+            .filter_map(|sent| sent.function.clone())
+            .collect()
+    }
+
+    /// Forgets the nonces handed out, after a transaction failed to be
+    /// sent, so that the next one resyncs with the node
+    pub fn reset(&mut self) {
+        self.next_nonce = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethereum_types::Address;
+
+    fn call(function: &str, index: u8) -> SentTransaction {
+        SentTransaction {
+            gas_price: U256::from(10),
+            concern: Concern {
+                contract_address: Address::zero(),
+                user_address: Address::zero(),
+            },
+            function: Some(String::from(function)),
+            data: data(function, index),
+            hash: None,
+        }
+    }
+
+    fn data(function: &str, index: u8) -> Vec<u8> {
+        let mut data = function.as_bytes().to_vec();
+        data.push(index);
+        data
+    }
+
+    #[test]
+    fn nonces_are_not_reused_while_in_flight() {
+        let mut account = AccountState::new();
+        assert_eq!(account.reserve_nonce(U256::from(5)), U256::from(5));
+        account.sent(U256::from(5), call("claimVictory", 0));
+        // the node does not know about nonce 5 yet
+        assert_eq!(account.reserve_nonce(U256::from(5)), U256::from(6));
+        // the node moved past our transactions
+        assert_eq!(account.reserve_nonce(U256::from(9)), U256::from(9));
+
+        account.reset();
+        assert_eq!(account.reserve_nonce(U256::from(7)), U256::from(7));
+    }
+
+    #[test]
+    fn pending_calls_are_found_until_mined() {
+        let mut account = AccountState::new();
+        let concern = call("", 0).concern;
+        account.sent(U256::from(3), call("claimVictory", 0));
+        account.sent(U256::from(4), call("reveal", 0));
+        account.sent(U256::from(5), call("claimVictory", 0));
+        assert_eq!(
+            account.pending_nonce_of(&concern, &data("claimVictory", 0)),
+            Some(U256::from(5))
+        );
+        assert_eq!(
+            account.pending_functions(&concern),
+            vec!["claimVictory", "reveal", "claimVictory"]
+        );
+
+        account.mined(U256::from(5));
+        assert!(account.sent_with(U256::from(4)).is_none());
+        account.mined(U256::from(6));
+        assert_eq!(
+            account.pending_nonce_of(&concern, &data("claimVictory", 0)),
+            None
+        );
+
+        // mined transactions are handed out once for accounting
+        assert_eq!(account.take_mined().len(), 3);
+        assert!(account.take_mined().is_empty());
+    }
+
+    #[test]
+    fn calls_for_other_instances_are_not_replaced() {
+        let mut account = AccountState::new();
+        let concern = call("", 0).concern;
+        account.sent(U256::from(3), call("claimVictory", 0));
+        account.sent(U256::from(4), call("claimVictory", 1));
+        assert_eq!(
+            account.pending_nonce_of(&concern, &data("claimVictory", 0)),
+            Some(U256::from(3))
+        );
+        assert_eq!(
+            account.pending_nonce_of(&concern, &data("claimVictory", 1)),
+            Some(U256::from(4))
+        );
+        assert_eq!(
+            account.pending_nonce_of(&concern, &data("claimVictory", 2)),
+            None
+        );
+    }
+}
@@ -0,0 +1,230 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Append-only log of the transactions sent, for compliance. Each line
+//! is a json entry with a HMAC-SHA256 made with a key of the operator,
+//! covering the entry and the HMAC of the line before it, so that entries
+//! changed, removed or reordered are found by `verify`.
+
+use error::*;
+use ethereum_types::{Address, H256, U256};
+use parity_crypto::hmac;
+use parity_crypto::Keccak256;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// File of the audit log in the working path
+pub const AUDIT_LOG: &str = "audit.log";
+
+/// A transaction accepted by the node, as recorded in the audit log
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    /// Seconds since the epoch when the transaction was sent
+    pub timestamp: u64,
+    pub contract: Address,
+    pub user: Address,
+    /// Function called, none for cancellations
+    pub function: Option<String>,
+    pub nonce: U256,
+    /// Keccak of the call data, arguments included
+    pub params_hash: H256,
+    /// Hash given by the node, unknown if it already had the nonce
+    pub tx_hash: Option<H256>,
+    /// HMAC of the entry and of the previous one, in hex
+    pub mac: String,
+}
+
+impl AuditEntry {
+    // what the HMAC covers, chained to the HMAC of the entry before
+    fn signed_data(&self, previous_mac: &str) -> Vec<u8> {
+        format!(
+            "{}|{:#x}|{:#x}|{}|{}|{:#x}|{}|{}",
+            self.timestamp,
+            self.contract,
+            self.user,
+            self.function.as_ref().map_or("", |f| f.as_str()),
+            self.nonce,
+            self.params_hash,
+            self.tx_hash.map_or(String::new(), |h| format!("{:#x}", h)),
+            previous_mac
+        )
+        .into_bytes()
+    }
+}
+
+/// Reads the key of the audit log from its file
+pub fn read_key(path: &Path) -> Result<Vec<u8>> {
+    let key = std::fs::read_to_string(path).chain_err(|| {
+        format!("could not read audit log key file {}", path.display())
+    })?;
+    Ok(key.trim().as_bytes().to_vec())
+}
+
+/// Log of the transactions sent, appended to as they are accepted
+pub struct AuditLog {
+    path: PathBuf,
+    key: Vec<u8>,
+    // HMAC of the last entry, chained into the next one
+    last_mac: Mutex<String>,
+}
+
+impl AuditLog {
+    /// Opens the log at the given path, continuing the chain of its last
+    /// entry if it already has some
+    pub fn open(path: &Path, key: Vec<u8>) -> Result<AuditLog> {
+        let last_mac = match File::open(path) {
+            Ok(file) => match BufReader::new(file).lines().last() {
+                Some(line) => {
+                    let entry: AuditEntry = serde_json::from_str(&line?)
+                        .chain_err(|| {
+                            format!("invalid audit log {}", path.display())
+                        })?;
+                    entry.mac
+                }
+                None => String::new(),
+            },
+            Err(_) => String::new(),
+        };
+        Ok(AuditLog {
+            path: path.to_path_buf(),
+            key: key,
+            last_mac: Mutex::new(last_mac),
+        })
+    }
+
+    /// Appends a transaction accepted by the node
+    pub fn record(
+        &self,
+        contract: Address,
+        user: Address,
+        function: Option<String>,
+        nonce: U256,
+        data: &[u8],
+        tx_hash: Option<H256>,
+    ) -> Result<()> {
+        let mut last_mac = self.last_mac.lock().unwrap();
+        let params_hash: [u8; 32] = data.keccak256();
+        let mut entry = AuditEntry {
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0),
+            contract: contract,
+            user: user,
+            function: function,
+            nonce: nonce,
+            params_hash: H256::from(params_hash),
+            tx_hash: tx_hash,
+            mac: String::new(),
+        };
+        let signature = hmac::sign(
+            &hmac::SigKey::sha256(&self.key),
+            &entry.signed_data(&last_mac),
+        );
+        entry.mac = hex::encode(&*signature);
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .chain_err(|| {
+                format!("could not open audit log {}", self.path.display())
+            })?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        *last_mac = entry.mac;
+        Ok(())
+    }
+}
+
+/// Checks every entry of the log against the key, returning how many
+/// there are, or failing at the first one that does not verify
+pub fn verify(path: &Path, key: &[u8]) -> Result<usize> {
+    let file = File::open(path)
+        .chain_err(|| format!("could not open audit log {}", path.display()))?;
+    let verify_key = hmac::VerifyKey::sha256(key);
+    let mut previous_mac = String::new();
+    let mut count = 0;
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let invalid = |details: &str| {
+            Error::from(format!(
+                "audit log {} line {}: {}",
+                path.display(),
+                number + 1,
+                details
+            ))
+        };
+        let entry: AuditEntry = serde_json::from_str(&line?)
+            .map_err(|e| invalid(&e.to_string()))?;
+        let mac =
+            hex::decode(&entry.mac).map_err(|_| invalid("invalid mac"))?;
+        if !hmac::verify(&verify_key, &entry.signed_data(&previous_mac), &mac) {
+            return Err(invalid("mac does not match, the log was altered"));
+        }
+        previous_mac = entry.mac;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn altered_entries_do_not_verify() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("audit.log");
+        let key = b"operator key".to_vec();
+
+        let log = AuditLog::open(&path, key.clone()).unwrap();
+        for nonce in 0..2 {
+            log.record(
+                Address::zero(),
+                Address::repeat_byte(1),
+                Some(String::from("claimVictory")),
+                U256::from(nonce),
+                &[nonce as u8],
+                Some(H256::repeat_byte(2)),
+            )
+            .unwrap();
+        }
+        // reopened, the log goes on with the chain
+        let log = AuditLog::open(&path, key.clone()).unwrap();
+        log.record(Address::zero(), Address::zero(), None, 2.into(), &[], None)
+            .unwrap();
+        assert_eq!(verify(&path, &key).unwrap(), 3);
+        assert!(verify(&path, b"another key").is_err());
+
+        // dropping an entry breaks the chain
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        std::fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        let e = verify(&path, &key).unwrap_err().to_string();
+        assert!(e.contains("line 2"));
+    }
+}
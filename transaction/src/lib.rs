@@ -43,62 +43,441 @@ extern crate hex;
 extern crate keccak_hash;
 extern crate parity_crypto;
 extern crate rlp;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 extern crate serde_json;
+#[cfg(test)]
+extern crate tempfile;
 extern crate transport;
+extern crate utils;
 extern crate web3;
 
+pub mod account;
+pub mod audit;
+pub mod queue;
+pub mod relay;
+pub mod sender;
+pub mod strategy;
+pub mod token;
+
 use common_types::transaction::{Action, Transaction};
 use configuration::{Concern, Configuration};
 use error::*;
+use ethabi::token::{LenientTokenizer, Tokenizer};
 use ethabi::Token;
-use ethereum_types::U256;
+use ethereum_types::{Address, H256, U256};
 use serde_json::Value;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::Read;
-use std::sync::Arc;
-use transport::GenericTransport;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use transport::{Connection, GenericTransport};
+use utils::chain::{BlockHeader, ChainReader};
+use utils::retry::Retry;
 use web3::futures::future::err;
-use web3::futures::future::Either;
+use web3::futures::future::{join_all, Either};
 use web3::futures::Future;
 use web3::types;
 use web3::types::Bytes;
 use worker::ConcernKey;
 
-/// In the future there could be several strategies to submit a transaction.
-/// Simplest is based on estimated gas cost.
+pub use account::{AccountState, SentTransaction, Spending};
+pub use audit::{AuditEntry, AuditLog};
+pub use queue::SubmissionQueue;
+pub use relay::{PrivateRelay, Relayed};
+pub use sender::{MockSender, TransactionSender};
+pub use strategy::{deadline_percent, Escalating, Simplest, SubmitStrategy};
+pub use token::Erc20;
+
+/// Strategy a request is submitted with. Simplest is based on estimated
+/// gas cost.
 #[derive(Clone, Debug)]
 pub enum Strategy {
     Simplest,
+    /// Like `Simplest`, but a replacement pays at least the given
+    /// percentage over the gas price of the transaction it replaces
+    Bump(u64),
+    /// A strategy registered in the transaction manager under this name
+    Named(String),
+}
+
+/// A single argument of a contract call, expressed with the native
+/// ethereum types. It gets converted to an ABI token by the transaction
+/// manager.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CallParam {
+    Address(Address),
+    U256(U256),
+    H256(H256),
+    Bytes(Vec<u8>),
+    Bool(bool),
+    AddressArray(Vec<Address>),
+    U256Array(Vec<U256>),
+    H256Array(Vec<H256>),
+}
+
+impl CallParam {
+    /// Converts the argument into the ABI token expected by ethabi
+    pub fn to_token(&self) -> Token {
+        match self {
+            CallParam::Address(a) => Token::Address(*a),
+            CallParam::U256(u) => Token::Uint(*u),
+            CallParam::H256(h) => Token::FixedBytes(h.as_bytes().to_vec()),
+            CallParam::Bytes(b) => Token::Bytes(b.clone()),
+            CallParam::Bool(b) => Token::Bool(*b),
+            CallParam::AddressArray(v) => {
+                Token::Array(v.iter().map(|a| Token::Address(*a)).collect())
+            }
+            CallParam::U256Array(v) => {
+                Token::Array(v.iter().map(|u| Token::Uint(*u)).collect())
+            }
+            CallParam::H256Array(v) => Token::Array(
+                v.iter()
+                    .map(|h| Token::FixedBytes(h.as_bytes().to_vec()))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The solidity type of the argument, as shown in errors
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CallParam::Address(_) => "address",
+            CallParam::U256(_) => "uint256",
+            CallParam::H256(_) => "bytes32",
+            CallParam::Bytes(_) => "bytes",
+            CallParam::Bool(_) => "bool",
+            CallParam::AddressArray(_) => "address[]",
+            CallParam::U256Array(_) => "uint256[]",
+            CallParam::H256Array(_) => "bytes32[]",
+        }
+    }
+
+    /// Converts an ABI token back into an argument, failing for the
+    /// tokens that have no native counterpart
+    pub fn from_token(token: Token) -> Result<CallParam> {
+        let unsupported = |token: &Token| {
+            Error::from(ErrorKind::InvalidTransactionRequest(format!(
+                "unsupported call parameter: {:?}",
+                token
+            )))
+        };
+        match token {
+            Token::Address(a) => Ok(CallParam::Address(a)),
+            Token::Uint(u) | Token::Int(u) => Ok(CallParam::U256(u)),
+            Token::FixedBytes(ref b) if b.len() == 32 => {
+                Ok(CallParam::H256(H256::from_slice(b)))
+            }
+            Token::Bytes(b) => Ok(CallParam::Bytes(b)),
+            Token::Bool(b) => Ok(CallParam::Bool(b)),
+            Token::Array(tokens) | Token::FixedArray(tokens) => {
+                match tokens.first() {
+                    None => Ok(CallParam::U256Array(vec![])),
+                    Some(Token::Address(_)) => tokens
+                        .iter()
+                        .map(|t| t.clone().to_address().ok_or(unsupported(t)))
+                        .collect::<Result<Vec<_>>>()
+                        .map(CallParam::AddressArray),
+                    Some(Token::Uint(_)) => tokens
+                        .iter()
+                        .map(|t| t.clone().to_uint().ok_or(unsupported(t)))
+                        .collect::<Result<Vec<_>>>()
+                        .map(CallParam::U256Array),
+                    Some(Token::FixedBytes(_)) => tokens
+                        .iter()
+                        .map(|t| match t {
+                            Token::FixedBytes(b) if b.len() == 32 => {
+                                Ok(H256::from_slice(b))
+                            }
+                            _ => Err(unsupported(t)),
+                        })
+                        .collect::<Result<Vec<_>>>()
+                        .map(CallParam::H256Array),
+                    Some(t) => Err(unsupported(t)),
+                }
+            }
+            t => Err(unsupported(&t)),
+        }
+    }
+}
+
+impl From<Address> for CallParam {
+    fn from(a: Address) -> Self {
+        CallParam::Address(a)
+    }
+}
+
+impl From<U256> for CallParam {
+    fn from(u: U256) -> Self {
+        CallParam::U256(u)
+    }
+}
+
+impl From<H256> for CallParam {
+    fn from(h: H256) -> Self {
+        CallParam::H256(h)
+    }
+}
+
+impl From<Vec<u8>> for CallParam {
+    fn from(b: Vec<u8>) -> Self {
+        CallParam::Bytes(b)
+    }
+}
+
+impl From<bool> for CallParam {
+    fn from(b: bool) -> Self {
+        CallParam::Bool(b)
+    }
+}
+
+impl From<Vec<Address>> for CallParam {
+    fn from(v: Vec<Address>) -> Self {
+        CallParam::AddressArray(v)
+    }
+}
+
+impl From<Vec<U256>> for CallParam {
+    fn from(v: Vec<U256>) -> Self {
+        CallParam::U256Array(v)
+    }
+}
+
+impl From<Vec<H256>> for CallParam {
+    fn from(v: Vec<H256>) -> Self {
+        CallParam::H256Array(v)
+    }
+}
+
+/// The ordered list of arguments of a contract call. DApps build it with
+/// `CallParams::new().push(a).push(b)`, without touching ethabi tokens.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CallParams {
+    params: Vec<CallParam>,
+}
+
+impl CallParams {
+    pub fn new() -> Self {
+        CallParams { params: vec![] }
+    }
+
+    /// Appends an argument to the call
+    pub fn push<P: Into<CallParam>>(mut self, param: P) -> Self {
+        self.params.push(param.into());
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    /// Converts all arguments into ABI tokens, ready to be encoded
+    pub fn to_tokens(&self) -> Vec<Token> {
+        self.params.iter().map(CallParam::to_token).collect()
+    }
+
+    /// Encodes the call to the named function of the ABI, once the
+    /// arguments are checked against its inputs, so that a wrong name,
+    /// count or type is an error naming the function instead of a failure
+    /// deep inside ethabi
+    pub fn encode_for(
+        &self,
+        abi: &ethabi::Contract,
+        function: &str,
+    ) -> Result<Vec<u8>> {
+        let invalid = |message: String| {
+            Error::from(ErrorKind::InvalidTransactionRequest(message))
+        };
+        let function = abi.function(function).map_err(|_| {
+            invalid(format!("function {} is not in the abi", function))
+        })?;
+        if function.inputs.len() != self.params.len() {
+            return Err(invalid(format!(
+                "function {} expects {} parameters, got {}",
+                function.name,
+                function.inputs.len(),
+                self.params.len()
+            )));
+        }
+        let tokens = self.to_tokens();
+        for (position, (input, token)) in
+            function.inputs.iter().zip(tokens.iter()).enumerate()
+        {
+            if !token.type_check(&input.kind) {
+                return Err(invalid(format!(
+                    "parameter {} ({}) of function {} is {}, got {}",
+                    position,
+                    input.name,
+                    function.name,
+                    input.kind,
+                    self.params[position].kind()
+                )));
+            }
+        }
+        function.encode_input(&tokens).chain_err(|| {
+            format!("could not encode the call to {}", function.name)
+        })
+    }
+}
+
+impl From<Vec<CallParam>> for CallParams {
+    fn from(params: Vec<CallParam>) -> Self {
+        CallParams { params: params }
+    }
 }
 
 /// The transaction manager expects these requests to be submitted to the
-/// blockchain. Note that the data should have already been encoded,
-/// since the trasaction manager does not understand ABI's.
+/// blockchain. The arguments are given in native types and encoded by the
+/// transaction manager against the concern's ABI and the function name.
 #[derive(Clone, Debug)]
 pub struct TransactionRequest {
     pub concern: configuration::Concern,
     pub value: U256,
     pub function: String,
-    pub data: Vec<Token>,
+    pub data: CallParams,
     pub gas: Option<U256>,
     pub strategy: Strategy,
     pub contract_name: Option<String>,
+    /// Block by which the transaction must be mined, its gas price being
+    /// escalated as the block approaches
+    pub deadline_block: Option<u64>,
+    /// Like `deadline_block`, as the timestamp of the block
+    pub deadline_timestamp: Option<u64>,
+    /// Value at stake in the dispute the transaction is for, in wei. Those
+    /// of high value disputes have their gas escalated earlier.
+    pub stake_value: Option<U256>,
+}
+
+impl TransactionRequest {
+    /// Blocks left after the latest one before the closest deadline of
+    /// the request, if it has any
+    pub fn blocks_left(&self, latest: &BlockHeader) -> Option<u64> {
+        let by_block = self.deadline_block.map(|deadline| {
+            deadline.saturating_sub(latest.number.unwrap_or_default())
+        });
+        let by_time = self.deadline_timestamp.map(|deadline| {
+            deadline.saturating_sub(latest.timestamp) / strategy::BLOCK_TIME
+        });
+        match (by_block, by_time) {
+            (Some(block), Some(time)) => Some(block.min(time)),
+            (block, time) => block.or(time),
+        }
+    }
+
+    /// Whether the transaction is for a dispute with at least the given
+    /// value at stake
+    pub fn is_high_stake(&self, high_stake_value: Option<U256>) -> bool {
+        match (self.stake_value, high_stake_value) {
+            (Some(stake), Some(threshold)) => stake >= threshold,
+            _ => false,
+        }
+    }
+
+    fn has_deadline(&self) -> bool {
+        self.deadline_block.is_some() || self.deadline_timestamp.is_some()
+    }
 }
 
 /// Every concern that the Transaction Manager acts uppon should ether be
 /// provided with a key pair to sign transactions, or with an address for
 /// an external signer.
+#[derive(Clone)]
 struct ConcernData {
     key: ConcernKey,
     abi: Arc<ethabi::Contract>,
+    gas_overrides: HashMap<String, u64>,
 }
 
-/// A Transaction Manager server
+/// The node the transactions of the concerns of a network are sent to,
+/// with the accounts sending them. An account used on several networks
+/// keeps apart nonces on each.
+#[derive(Clone)]
+struct NetworkLink {
+    web3: Arc<web3::Web3<GenericTransport>>,
+    url: String,
+    chain_id: u64,
+    accounts: HashMap<Address, Arc<Mutex<AccountState>>>,
+    // kept to stay in scope, none for the node of the configured url
+    _connection: Option<Arc<Connection>>,
+}
+
+/// A Transaction Manager server. Clones share the submission queue and
+/// the nonces of each account.
+#[derive(Clone)]
 pub struct TransactionManager {
     config: Configuration,
     concern_data: HashMap<Concern, ConcernData>,
-    web3: Arc<web3::Web3<GenericTransport>>,
+    queue: SubmissionQueue,
+    // by network name, none for the one of the configured url
+    networks: HashMap<Option<String>, NetworkLink>,
+    strategies: HashMap<String, Arc<dyn SubmitStrategy>>,
+    relays: HashMap<Concern, Arc<PrivateRelay>>,
+    audit: Option<Arc<AuditLog>>,
+}
+
+impl NetworkLink {
+    // what the transactions of the accounts on this network mined since
+    // the last call spent
+    fn mined_spending(&self) -> SendFuture<Vec<Spending>> {
+        let url = self.url.clone();
+        let receipts_url = url.clone();
+        let web3 = self.web3.clone();
+        let mined = self.accounts.iter().map(|(address, account)| {
+            let account = account.clone();
+            self.web3
+                .eth()
+                .transaction_count(*address, Some(types::BlockNumber::Latest))
+                .map(move |mined_nonce| {
+                    let mut account = account.lock().unwrap();
+                    account.mined(mined_nonce);
+                    account.take_mined()
+                })
+        });
+        Box::new(
+            join_all(mined)
+                .map_err(move |_e| {
+                    error::Error::from(ErrorKind::RpcError(
+                        String::from("eth_getTransactionCount"),
+                        url,
+                    ))
+                })
+                .and_then(move |mined| {
+                    let receipts = mined
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|sent| sent.hash.map(|hash| (hash, sent)))
+                        .map(move |(hash, sent)| {
+                            web3.eth().transaction_receipt(hash).map(
+                                move |receipt| {
+                                    receipt.and_then(|r| r.gas_used).map(
+                                        |gas_used| Spending {
+                                            concern: sent.concern,
+                                            hash: hash,
+                                            gas_used: gas_used,
+                                            gas_price: sent.gas_price,
+                                        },
+                                    )
+                                },
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    join_all(receipts)
+                        .map(|spending| {
+                            spending.into_iter().flatten().collect()
+                        })
+                        .map_err(move |_e| {
+                            error::Error::from(ErrorKind::RpcError(
+                                String::from("eth_getTransactionReceipt"),
+                                receipts_url,
+                            ))
+                        })
+                }),
+        )
+    }
 }
 
 // !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
@@ -112,9 +491,33 @@ impl TransactionManager {
         config: Configuration,
         web3: web3::Web3<GenericTransport>,
     ) -> Result<TransactionManager> {
-        let signer_user_address = config.clone().signer_key;
-
         let mut concern_data = HashMap::new();
+        let mut relays = HashMap::new();
+        let mut networks = HashMap::new();
+        networks.insert(
+            None,
+            NetworkLink {
+                web3: Arc::new(web3),
+                url: config.url.clone(),
+                chain_id: config.chain_id,
+                accounts: HashMap::new(),
+                _connection: None,
+            },
+        );
+        for (name, network) in config.networks.iter() {
+            info!("Connecting to network {}", name);
+            let connection = config.connect_to(network)?;
+            networks.insert(
+                Some(name.clone()),
+                NetworkLink {
+                    web3: Arc::new(connection.web3.clone()),
+                    url: network.url.clone(),
+                    chain_id: network.chain_id,
+                    accounts: HashMap::new(),
+                    _connection: Some(Arc::new(connection)),
+                },
+            );
+        }
         // loop through each concern, adding them to the concern's data
         for concern in config.clone().concerns {
             let abi_path = &config.abis.get(&concern).unwrap().abi;
@@ -123,268 +526,889 @@ impl TransactionManager {
                 &concern.contract_address,
                 &abi_path
             );
-            let mut file = File::open(abi_path)?;
-            let mut s = String::new();
-            file.read_to_string(&mut s)?;
-            let v: Value = serde_json::from_str(&s[..])
-                .chain_err(|| format!("could not read contract json file"))?;
-
-            // create a low level abi for contract
-            let abi = ethabi::Contract::load(
-                serde_json::to_string(&v["abi"]).unwrap().as_bytes(),
-            )?;
+            let abi = load_abi(abi_path)?;
 
+            // each account keeps its own nonces, even if shared by concerns
+            let key = config.signer_of(&concern).clone();
+            if let Some(url) = config.relay_of(&concern) {
+                if let ConcernKey::UserAddress(_) = key {
+                    warn!(
+                        "Transactions of {} are signed and sent by an \
+                         external signer, not through its private relay",
+                        concern
+                    );
+                }
+                let relay = PrivateRelay::new(url, config.web3_timeout)
+                    .chain_err(|| format!("invalid relay of {}", concern))?;
+                relays.insert(concern, Arc::new(relay));
+            }
+            networks
+                .get_mut(&config.network_of(&concern).cloned())
+                .unwrap()
+                .accounts
+                .entry(key.address())
+                .or_insert_with(|| Arc::new(Mutex::new(AccountState::new())));
             concern_data.insert(
                 concern,
                 ConcernData {
-                    key: signer_user_address.clone(),
+                    key: key,
                     abi: Arc::new(abi),
+                    gas_overrides: config
+                        .gas_overrides
+                        .get(&concern)
+                        .cloned()
+                        .unwrap_or_default(),
                 },
             );
         }
 
+        let queue = SubmissionQueue::new(
+            config.max_in_flight_transactions,
+            config.max_queued_transactions,
+        );
+
+        // transactions sent are recorded when the operator gave a key
+        let audit = match &config.audit_key_path {
+            Some(key_path) => Some(Arc::new(AuditLog::open(
+                &config.working_path.join(audit::AUDIT_LOG),
+                audit::read_key(key_path)?,
+            )?)),
+            None => None,
+        };
+
         Ok(TransactionManager {
             config: config,
             concern_data: concern_data,
-            web3: Arc::new(web3),
+            queue: queue,
+            networks: networks,
+            strategies: HashMap::new(),
+            relays: relays,
+            audit: audit,
+        })
+    }
+
+    /// Makes a strategy available to requests, under the given name.
+    /// A strategy registered with a name already taken replaces it.
+    pub fn register_strategy(
+        &mut self,
+        name: &str,
+        strategy: Arc<dyn SubmitStrategy>,
+    ) {
+        info!("Registering submission strategy {}", name);
+        self.strategies.insert(String::from(name), strategy);
+    }
+
+    // finds what implements the strategy of a request, sending through the
+    // private relay of the concern if it has one
+    fn strategy(
+        &self,
+        concern: &Concern,
+        strategy: &Strategy,
+    ) -> Result<Arc<dyn SubmitStrategy>> {
+        let strategy: Arc<dyn SubmitStrategy> = match strategy {
+            Strategy::Simplest => Arc::new(Simplest),
+            Strategy::Bump(percent) => Arc::new(Escalating(*percent)),
+            Strategy::Named(name) => self.strategies.get(name).cloned().ok_or(
+                Error::from(ErrorKind::InvalidTransactionRequest(format!(
+                    "submission strategy {} not registered",
+                    name
+                ))),
+            )?,
+        };
+        Ok(match self.relays.get(concern) {
+            Some(relay) => Arc::new(Relayed {
+                relay: relay.clone(),
+                strategy: strategy,
+            }),
+            None => strategy,
         })
     }
 
-    /// Signs and sends a given transaction
+    /// Checks that the transactions of the concern that may still be
+    /// pending call functions the abi has with the same inputs, so that
+    /// they can still be replaced once it is swapped in
+    pub fn check_abi(
+        &self,
+        concern: &Concern,
+        abi: &ethabi::Contract,
+    ) -> Result<()> {
+        let concern_data = self.concern_data.get(concern).ok_or(
+            Error::from(ErrorKind::InvalidTransactionRequest(String::from(
+                "Concern requested not found",
+            ))),
+        )?;
+        let account = match self
+            .link_of(concern)
+            .accounts
+            .get(&concern_data.key.address())
+        {
+            Some(account) => account,
+            None => return Ok(()),
+        };
+        let pending = account.lock().unwrap().pending_functions(concern);
+        for function in pending.iter() {
+            let inputs = |abi: &ethabi::Contract| {
+                abi.function(function).ok().map(|f| {
+                    f.inputs.iter().map(|i| i.kind.clone()).collect::<Vec<_>>()
+                })
+            };
+            if inputs(abi) != inputs(&concern_data.abi) {
+                return Err(Error::from(ErrorKind::InvalidTransactionRequest(
+                    format!(
+                        "pending call to {} could not be replaced with the \
+                         new abi of {}",
+                        function, concern
+                    ),
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Encodes the following transactions of the concern with the given
+    /// abi, once checked against its pending transactions
+    pub fn replace_abi(
+        &mut self,
+        concern: &Concern,
+        abi: ethabi::Contract,
+    ) -> Result<()> {
+        self.check_abi(concern, &abi)?;
+        if let Some(concern_data) = self.concern_data.get_mut(concern) {
+            concern_data.abi = Arc::new(abi);
+        }
+        Ok(())
+    }
+
+    /// Parses the textual arguments of a call to one of the concern's
+    /// functions, according to the types in its ABI
+    pub fn parse_params(
+        &self,
+        concern: &Concern,
+        function: &str,
+        values: &[String],
+    ) -> Result<CallParams> {
+        let concern_data = self.concern_data.get(concern).ok_or(
+            Error::from(ErrorKind::InvalidTransactionRequest(String::from(
+                "Concern requested not found",
+            ))),
+        )?;
+        let function = concern_data.abi.function(function)?;
+        if function.inputs.len() != values.len() {
+            return Err(Error::from(ErrorKind::InvalidTransactionRequest(
+                format!(
+                    "function {} expects {} parameters, got {}",
+                    function.name,
+                    function.inputs.len(),
+                    values.len()
+                ),
+            )));
+        }
+        function
+            .inputs
+            .iter()
+            .zip(values.iter())
+            .map(|(param, value)| {
+                let token = LenientTokenizer::tokenize(&param.kind, value)
+                    .chain_err(|| {
+                        format!("could not parse parameter {}", param.name)
+                    })?;
+                CallParam::from_token(token)
+            })
+            .collect::<Result<Vec<CallParam>>>()
+            .map(CallParams::from)
+    }
+
+    // finds the concern a request is addressed to
+    fn request_concern(&self, request: &TransactionRequest) -> Result<Concern> {
+        match &request.contract_name {
+            None => Ok(request.concern.clone()),
+            Some(s) => self.config.contracts.get(s).cloned().ok_or(
+                Error::from(ErrorKind::InvalidTransactionRequest(
+                    String::from("Contract requested not found"),
+                )),
+            ),
+        }
+    }
+
+    // refuses requests transferring more than allowed to the concern, so
+    // that a bug in the dapp cannot drain the account
+    fn check_value(&self, concern: &Concern, value: U256) -> Result<()> {
+        let cap = self.config.max_value_of(concern);
+        if value > cap {
+            return Err(Error::from(ErrorKind::InvalidTransactionRequest(
+                format!(
+                    "value {} is above the cap of {} wei for {}",
+                    value, cap, concern
+                ),
+            )));
+        }
+        Ok(())
+    }
+
+    // the network the transactions of a concern are sent on
+    fn link_of(&self, concern: &Concern) -> &NetworkLink {
+        &self.networks[&self.config.network_of(concern).cloned()]
+    }
+
+    // gathers what is needed to send transactions on behalf of a concern
+    fn submission(&self, concern: Concern) -> Result<Submission> {
+        let concern_data = self.concern_data.get(&concern).ok_or(
+            Error::from(ErrorKind::InvalidTransactionRequest(String::from(
+                "Concern requested not found",
+            ))),
+        )?;
+        let link = self.link_of(&concern);
+        let address = concern_data.key.address();
+        let account = link.accounts.get(&address).cloned().ok_or(
+            Error::from(ErrorKind::InvalidTransactionRequest(format!(
+                "Account {:#x} not found",
+                address
+            ))),
+        )?;
+        Ok(Submission {
+            web3: Arc::clone(&link.web3),
+            url: link.url.clone(),
+            chain_id: link.chain_id,
+            concern: concern,
+            key: concern_data.key.clone(),
+            abi: concern_data.abi.clone(),
+            gas_overrides: concern_data.gas_overrides.clone(),
+            account: account,
+            audit: self.audit.clone(),
+            high_stake_value: self.config.high_stake_value,
+            max_gas_price: self.config.max_gas_price,
+        })
+    }
+
+    /// Signs and sends a given transaction. A request with a deadline
+    /// replaces the pending transaction making the same call, with the same
+    /// arguments, if any, so that its gas price keeps rising as the
+    /// deadline approaches.
     pub fn send(
         &self,
         request: TransactionRequest,
     ) -> Box<dyn Future<Item = (), Error = error::Error> + Send> {
-        // async_block needs owned values, so let us clone some stuff
-        let web3 = Arc::clone(&self.web3);
-        let request_clone = request.clone();
-        let request_concern = match request_clone.contract_name {
-            None => request_clone.concern.clone(),
-            Some(s) => match self.config.contracts.get(&s) {
-                Some(k) => k.clone(),
-                None => {
-                    return Box::new(err(Error::from(
-                        ErrorKind::InvalidTransactionRequest(String::from(
-                            "Contract requested not found",
-                        )),
-                    )));
+        if request.has_deadline() && self.is_pending(&request) {
+            info!("Escalating pending call to {}", request.function);
+            let strategy = request.strategy.clone();
+            return self.replace(request, strategy);
+        }
+        let (submission, strategy) =
+            match self.request_concern(&request).and_then(|concern| {
+                self.check_value(&concern, request.value)?;
+                let submission = self.submission(concern)?;
+                submission.encode(&request)?;
+                Ok((submission, self.strategy(&concern, &request.strategy)?))
+            }) {
+                Ok(submission) => submission,
+                Err(e) => return Box::new(err(e)),
+            };
+        let address = submission.key.address();
+        let account = submission.account.clone();
+        let high_stake = request.is_high_stake(self.config.high_stake_value);
+
+        // wait for a free slot of the account before touching the node,
+        // the slot is given back once the transaction is sent or failed,
+        // high value disputes going first
+        Box::new(
+            self.queue
+                .acquire(
+                    address,
+                    submission.concern,
+                    self.config.priority_of(&submission.concern),
+                    high_stake,
+                )
+                .and_then(move |permit| {
+                    trace!("Getting nonce");
+                    let account_failed = account.clone();
+                    submission
+                        .node_nonces()
+                        .map(move |(mined, pending)| {
+                            // count pending transactions, as other submissions
+                            // of this account may be in flight
+                            let mut account = account.lock().unwrap();
+                            account.mined(mined);
+                            account.reserve_nonce(pending)
+                        })
+                        .and_then(move |nonce| {
+                            submission.call(request, nonce, strategy)
+                        })
+                        .then(move |res| {
+                            // the nonce may have been handed out without being
+                            // used
+                            if res.is_err() {
+                                account_failed.lock().unwrap().reset();
+                            }
+                            drop(permit);
+                            res
+                        })
+                }),
+        )
+    }
+
+    // whether a transaction making the call of the request, with the same
+    // arguments, was sent and may not be mined yet
+    fn is_pending(&self, request: &TransactionRequest) -> bool {
+        self.request_concern(request)
+            .and_then(|concern| self.submission(concern))
+            .and_then(|submission| {
+                let raw_data = submission.encode(request)?;
+                Ok(submission
+                    .account
+                    .lock()
+                    .unwrap()
+                    .pending_nonce_of(&submission.concern, &raw_data)
+                    .is_some())
+            })
+            .unwrap_or(false)
+    }
+
+    /// Runs the request as an `eth_call` against the latest block, failing
+    /// with the reason given by the node if it would revert
+    pub fn simulate(
+        &self,
+        request: TransactionRequest,
+    ) -> Box<dyn Future<Item = (), Error = error::Error> + Send> {
+        match self
+            .request_concern(&request)
+            .and_then(|concern| self.submission(concern))
+        {
+            Ok(submission) => submission.simulate(request),
+            Err(e) => Box::new(err(e)),
+        }
+    }
+
+    /// Sends the request again in place of the pending transaction that
+    /// made the same call, with the same arguments, with the gas price
+    /// given by the new strategy.
+    /// Replacements skip the submission queue, as they take no new nonce.
+    pub fn replace(
+        &self,
+        request: TransactionRequest,
+        strategy: Strategy,
+    ) -> Box<dyn Future<Item = (), Error = error::Error> + Send> {
+        let (submission, submit_strategy, raw_data) =
+            match self.request_concern(&request).and_then(|concern| {
+                self.check_value(&concern, request.value)?;
+                let submission = self.submission(concern)?;
+                let raw_data = submission.encode(&request)?;
+                let strategy = self.strategy(&concern, &strategy)?;
+                Ok((submission, strategy, raw_data))
+            }) {
+                Ok(submission) => submission,
+                Err(e) => return Box::new(err(e)),
+            };
+        let mut request = request;
+        request.strategy = strategy;
+
+        Box::new(submission.node_nonces().and_then(move |(mined, _)| {
+            let nonce = {
+                let mut account = submission.account.lock().unwrap();
+                account.mined(mined);
+                account.pending_nonce_of(&submission.concern, &raw_data)
+            };
+            match nonce {
+                Some(nonce) => {
+                    info!("Replacing transaction with nonce {}", nonce);
+                    Either::A(submission.call(request, nonce, submit_strategy))
                 }
-            },
-        };
-        let concern_data = match self.concern_data.get(&request_concern) {
-            Some(k) => k,
-            None => {
-                return Box::new(err(Error::from(
-                    ErrorKind::InvalidTransactionRequest(String::from(
-                        "Concern requested not found",
+                None => Either::B(err(Error::from(
+                    ErrorKind::InvalidTransactionRequest(format!(
+                        "no pending call to {} with these arguments to \
+                         replace",
+                        request.function
+                    )),
+                ))),
+            }
+        }))
+    }
+
+    /// What the transactions of each account mined since the last call
+    /// spent, read from their receipts. Transactions whose receipt is not
+    /// found, like those replaced by another one with the same nonce, are
+    /// not accounted for.
+    pub fn mined_spending(&self) -> SendFuture<Vec<Spending>> {
+        let spending = self.networks.values().map(|link| link.mined_spending());
+        Box::new(
+            join_all(spending)
+                .map(|spending| spending.into_iter().flatten().collect()),
+        )
+    }
+
+    /// Unsticks the transaction of the concern's account with the given
+    /// nonce, by sending a zero-value transfer to the account itself with
+    /// the same nonce and a higher gas price
+    pub fn cancel(
+        &self,
+        concern: Concern,
+        nonce: U256,
+    ) -> Box<dyn Future<Item = (), Error = error::Error> + Send> {
+        let (submission, strategy) =
+            match self.submission(concern).and_then(|s| {
+                Ok((s, self.strategy(&concern, &Strategy::Simplest)?))
+            }) {
+                Ok(submission) => submission,
+                Err(e) => return Box::new(err(e)),
+            };
+        let address = submission.key.address();
+
+        Box::new(submission.node_nonces().and_then(move |(mined, _)| {
+            if nonce < mined {
+                return Either::B(err(Error::from(
+                    ErrorKind::InvalidTransactionRequest(format!(
+                        "transaction {} of {:#x} was already mined",
+                        nonce, address
                     )),
                 )));
             }
-        };
+            submission.account.lock().unwrap().mined(mined);
+            info!("Cancelling transaction {} of {:#x}", nonce, address);
+            Either::A(
+                submission.gas_price(nonce, strategy.clone(), 100).and_then(
+                    move |gas_price| {
+                        let tx = types::TransactionRequest {
+                            from: address,
+                            to: Some(address),
+                            gas: Some(U256::from(TRANSFER_GAS)),
+                            gas_price: Some(gas_price),
+                            value: Some(U256::zero()),
+                            data: None,
+                            condition: None,
+                            nonce: Some(nonce),
+                        };
+                        let account = submission.account.clone();
+                        let concern = submission.concern;
+                        let audit = submission.audit.clone();
+                        submission.sign_and_send(tx, &*strategy).map(
+                            move |hash| {
+                                record_audit(
+                                    &audit,
+                                    &concern,
+                                    address,
+                                    None,
+                                    nonce,
+                                    &[],
+                                    hash,
+                                );
+                                account.lock().unwrap().sent(
+                                    nonce,
+                                    SentTransaction {
+                                        gas_price: gas_price,
+                                        concern: concern,
+                                        function: None,
+                                        data: vec![],
+                                        hash: hash,
+                                    },
+                                )
+                            },
+                        )
+                    },
+                ),
+            )
+        }))
+    }
+}
+
+/// Reads the abi of a truffle or hardhat artifact
+pub fn load_abi(abi_path: &Path) -> Result<ethabi::Contract> {
+    let abi_error = |details: &str| {
+        ErrorKind::AbiError(abi_path.to_path_buf(), String::from(details))
+    };
+    // the abi may still be being written by a deployment
+    let s = Retry::new()
+        .run(|| Ok(std::fs::read_to_string(abi_path)?))
+        .chain_err(|| abi_error("could not read file"))?;
+    let v: Value =
+        serde_json::from_str(&s[..]).chain_err(|| abi_error("invalid json"))?;
+
+    // create a low level abi for contract
+    ethabi::Contract::load(serde_json::to_string(&v["abi"]).unwrap().as_bytes())
+        .chain_err(|| abi_error("could not decode abi"))
+}
+
+/// Gas used by a plain transfer of ether
+const TRANSFER_GAS: u64 = 21_000;
+
+// everything needed to build and send transactions on behalf of a concern,
+// owned so that it can be moved into futures
+#[derive(Clone)]
+struct Submission {
+    web3: Arc<web3::Web3<GenericTransport>>,
+    url: String,
+    chain_id: u64,
+    concern: Concern,
+    key: ConcernKey,
+    abi: Arc<ethabi::Contract>,
+    gas_overrides: HashMap<String, u64>,
+    account: Arc<Mutex<AccountState>>,
+    audit: Option<Arc<AuditLog>>,
+    high_stake_value: Option<U256>,
+    max_gas_price: Option<U256>,
+}
+
+type SendFuture<T> = Box<dyn Future<Item = T, Error = error::Error> + Send>;
+
+impl Submission {
+    // transaction counts of the account, in the latest block and including
+    // the pending transactions known to the node
+    fn node_nonces(&self) -> SendFuture<(U256, U256)> {
+        let address = self.key.address();
+        let url = self.url.clone();
+        let web3 = self.web3.clone();
+        Retry::new().run_future(move || {
+            let url = url.clone();
+            let eth = web3.eth();
+            eth.transaction_count(address, Some(types::BlockNumber::Latest))
+                .join(eth.transaction_count(
+                    address,
+                    Some(types::BlockNumber::Pending),
+                ))
+                .map_err(move |_e| {
+                    error::Error::from(ErrorKind::RpcError(
+                        String::from("eth_getTransactionCount"),
+                        url,
+                    ))
+                })
+        })
+    }
+
+    // gas price for the transaction with the given nonce, offering the
+    // given percentage of what the strategy makes of the node's estimate
+    // and outbidding the one already sent with it, if any
+    fn gas_price(
+        &self,
+        nonce: U256,
+        strategy: Arc<dyn SubmitStrategy>,
+        percent: u64,
+    ) -> SendFuture<U256> {
+        trace!("Estimating gas price");
+        let url = self.url.clone();
+        let replaced = self
+            .account
+            .lock()
+            .unwrap()
+            .sent_with(nonce)
+            .map(|sent| sent.gas_price);
+        let max_gas_price = self.max_gas_price;
+        let web3 = self.web3.clone();
+        Box::new(
+            Retry::new()
+                .run_future(move || {
+                    let url = url.clone();
+                    web3.eth().gas_price().map_err(move |_e| {
+                        error::Error::from(ErrorKind::RpcError(
+                            String::from("eth_gasPrice"),
+                            url,
+                        ))
+                    })
+                })
+                .and_then(move |estimated| {
+                    trace!("Gas price estimated as {}", estimated);
+                    offered_gas_price(
+                        &*strategy,
+                        estimated,
+                        replaced,
+                        percent,
+                        max_gas_price,
+                    )
+                }),
+        )
+    }
+
+    // percentage of the gas price to offer for the request, escalated as
+    // its deadline approaches, earlier for high value disputes
+    fn urgency(&self, request: &TransactionRequest) -> SendFuture<u64> {
+        if !request.has_deadline() {
+            return Box::new(web3::futures::future::ok(100));
+        }
         let request = request.clone();
-        let key = concern_data.key.clone();
-        let address = key.address();
-        let abi = concern_data.abi.clone();
-        let chain_id: u64 = (&self).config.chain_id;
+        let high_stake = request.is_high_stake(self.high_stake_value);
+        Box::new(self.web3.latest_block().map(move |latest| {
+            let blocks_left = request.blocks_left(&latest).unwrap_or_default();
+            let percent = if high_stake {
+                deadline_percent(blocks_left / strategy::HIGH_STAKE_FACTOR)
+            } else {
+                deadline_percent(blocks_left)
+            };
+            if percent > 100 {
+                info!(
+                    "Offering {}% of the gas price to {}, {} blocks before \
+                     its deadline",
+                    percent, request.function, blocks_left
+                );
+            }
+            percent
+        }))
+    }
 
-        trace!("Getting nonce");
-        let web3_gas_price = web3.clone();
-        let web3_gas_usage = web3.clone();
-        let request_gas_usage = request.clone();
-        let request_to_address = request.clone();
+    // encodes the call to the contract function of the request
+    fn encode(&self, request: &TransactionRequest) -> Result<Vec<u8>> {
+        request.data.encode_for(&self.abi, &request.function)
+    }
 
+    // runs the call of the request against the latest block, without
+    // sending it
+    fn simulate(self, request: TransactionRequest) -> SendFuture<()> {
+        let raw_data = match self.encode(&request) {
+            Ok(raw_data) => raw_data,
+            Err(e) => return Box::new(err(e)),
+        };
+        let call_request = web3::types::CallRequest {
+            from: Some(self.key.address()),
+            to: self.concern.contract_address,
+            gas: request.gas,
+            gas_price: None,
+            value: Some(request.value),
+            data: Some(Bytes(raw_data)),
+        };
+        let function = request.function.clone();
         Box::new(
-            web3.clone()
+            self.web3
                 .eth()
-                .transaction_count(
-                    address.clone(),
-                    None,
-                )
-                .map_err(|_e| {
-                    error::Error::from(format!("could not retrieve nonce"))
-                })
-                .and_then(move |nonce| {
-                    trace!("Estimating gas price");
-                    web3_gas_price
-                        .eth()
-                        .gas_price()
-                        .map_err(|_e| {
-                            error::Error::from(format!(
-                                "could not retrieve gas price"
-                            ))
-                        })
-                        .map(move |gas_price| (nonce.clone(), gas_price))
-                })
-                .and_then(move |(nonce, gas_price)| {
-                    info!(
-                        "Nonce for {} is {}",
-                        address.clone(),
-                        nonce.clone()
-                    );
-                    trace!("Gas price estimated as {}", gas_price);
-                    let raw_data_result = abi
-                        .function((&request_gas_usage.function[..]).into())
-                        .and_then(|function| {
-                            function.encode_input(&request_gas_usage.data)
-                        })
-                        .chain_err(|| {
-                            error::Error::from(format!(
-                                "could not encode data {:?} to function {}:",
-                                &request_gas_usage.data,
-                                &request_gas_usage.function
-                            ))
-                        });
-                    // Fix this unwrap below
-                    let raw_data = raw_data_result.unwrap();
-                    trace!("Buiding transaction");
-                    let call_request = web3::types::CallRequest {
-                        from: Some(address.clone()),
-                        to: request_gas_usage.concern.contract_address,
-                        gas: None,
-                        gas_price: None,
-                        value: Some(request_gas_usage.value),
-                        data: Some(Bytes(raw_data.clone())),
-                    };
-                    trace!("Estimate total gas usage");
-                    let request_string =
-                        format!("{:?}", request_gas_usage.clone());
-
-                    get_gas(web3_gas_usage, call_request, request_gas_usage)
+                .call(call_request, None)
+                .map(|_| ())
+                .map_err(move |e| {
+                    error::Error::from(ErrorKind::TransactionError(
+                        None,
+                        format!("call to {} would revert: {}", function, e),
+                    ))
+                }),
+        )
+    }
+
+    // calls the contract function of the request with the given nonce
+    fn call(
+        self,
+        request: TransactionRequest,
+        nonce: U256,
+        strategy: Arc<dyn SubmitStrategy>,
+    ) -> SendFuture<()> {
+        let address = self.key.address();
+        info!("Nonce for {} is {}", address, nonce);
+
+        let raw_data = match self.encode(&request) {
+            Ok(raw_data) => raw_data,
+            Err(e) => return Box::new(err(e)),
+        };
+
+        trace!("Buiding transaction");
+        let call_request = web3::types::CallRequest {
+            from: Some(address),
+            to: self.concern.contract_address,
+            gas: None,
+            gas_price: None,
+            value: Some(request.value),
+            data: Some(Bytes(raw_data.clone())),
+        };
+        let gas: SendFuture<U256> = match self
+            .gas_overrides
+            .get(&request.function)
+        {
+            // a limit set by the operator is used as is
+            Some(limit) if request.gas.is_none() => {
+                trace!("Gas limit of {} set to {}", request.function, limit);
+                Box::new(web3::futures::future::ok(U256::from(*limit)))
+            }
+            _ => {
+                trace!("Estimate total gas usage");
+                let request_string = format!("{:?}", request.clone());
+                Box::new(
+                    get_gas(self.web3.clone(), call_request, request.clone())
                         .map_err(move |_e| {
                             error::Error::from(format!(
                                 "could not estimate gas usage for call {:?}",
                                 request_string
                             ))
                         })
-                        .map(move |total_gas| {
-                            (nonce, gas_price, total_gas, raw_data)
-                        })
-                })
-                .and_then(move |(nonce, gas_price, total_gas, raw_data)| {
-                    trace!("Gas usage estimated to be {}", total_gas);
-                    // !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-                    // Implement other sending strategies
-                    // !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-
-
-                    match key {
-                        ConcernKey::KeyPair(key_pair) => {
-                            trace!("Signing transaction");
-                            let signed_tx = Transaction {
-                                action: Action::Call(request_concern.contract_address),
-                                nonce: nonce,
-                                // do something better then double
-                                gas_price: U256::from(2)
-                                    .checked_mul(gas_price)
-                                    .unwrap(),
-                                // do something better then double
-                                gas: U256::from(2).checked_mul(total_gas).unwrap(),
-                                value: request.value,
-                                data: raw_data,
-                            }
-                            .sign(&key_pair.secret(), Some(chain_id));
-
-                            info!("Sending transaction: {:?}", &request);
-                            let raw = Bytes::from(rlp::encode(&signed_tx));
-                            //let hash = await!(web3.eth().send_raw_transaction(raw));
-
-                            Either::A(web3.eth().send_raw_transaction(raw)
-                                .map(|hash| {
-                                    info!("Transaction sent with hash: {:?}", hash);
-                                })
-                                .or_else(|e| {
-                                    // ignore the nonce error, by pass the other errors
-                                    if let web3::error::Error::Rpc(ref rpc_error) = e {
-                                        let nonce_error = String::from(
-                                            "the tx doesn't have the correct nonce",
-                                        );
-                                        if rpc_error.message[..nonce_error.len()]
-                                            == nonce_error
-                                        {
-                                            warn!(
-                                                "Ignoring nonce Error: {}",
-                                                rpc_error.message
-                                            );
-                                            return Box::new(web3::futures::future::ok::<(), _,> (
-                                                ()
-                                            ));
-                                        }
-                                    }
-                                    return Box::new(web3::futures::future::err(e));
-                                })
-                                .map_err(|e| {
-                                    warn!("Failed to send transaction. Error {}", e);
-                                    error::Error::from(e)
-                                }))
-                        }
+                        .map(|total_gas| {
+                            trace!("Gas usage estimated to be {}", total_gas);
+                            // do something better then double
+                            U256::from(2).saturating_mul(total_gas)
+                        }),
+                )
+            }
+        };
 
-                        ConcernKey::UserAddress(address) => {
-                            info!("Getting accounts from signer");
-                            let tx_request =
-                                types::TransactionRequest {
-                                    from: address,
-                                    to: Some(
-                                        request_to_address
-                                        .concern
-                                        .contract_address,
-                                    ),
-                                    // do something better then double
-                                    gas_price: Some(
-                                        U256::from(2)
-                                        .checked_mul(gas_price)
-                                        .unwrap(),
-                                    ),
-                                    // do something better then double
-                                    gas: Some(
-                                        U256::from(2)
-                                        .checked_mul(total_gas)
-                                        .unwrap(),
-                                    ),
-                                    value: Some(request.value),
-                                    data: Some(Bytes(raw_data.clone())),
-                                    condition: None,
-                                    nonce: Some(nonce),
-                                };
-
-                            info!("Sending unsigned transaction to signer: {:?}", &request);
-                            Either::B(
-                                web3.eth().send_transaction(tx_request)
-                                .map(|hash| {
-                                    info!("Transaction sent with hash: {:?}", hash);
-                                })
-                                .or_else(|e| {
-                                    // ignore the nonce error, by pass the other errors
-                                    if let web3::error::Error::Rpc(ref rpc_error) = e {
-                                        let nonce_error = String::from(
-                                            "the tx doesn't have the correct nonce",
-                                        );
-                                        if rpc_error.message[..nonce_error.len()]
-                                            == nonce_error
-                                        {
-                                            warn!(
-                                                "Ignoring nonce Error: {}",
-                                                rpc_error.message
-                                            );
-                                            return Box::new(web3::futures::future::ok::<(),_,> (
-                                                    ()
-                                            ));
-                                        }
-                                    }
-                                    return Box::new(web3::futures::future::err(e));
-                                })
-                                .map_err(|e| {
-                                    warn!("Failed to send transaction. Error {}", e);
-                                    error::Error::from(e)
-                                })
-                            )
+        let priced = self.clone();
+        let price_strategy = strategy.clone();
+        let gas_price = self.urgency(&request).and_then(move |percent| {
+            priced.gas_price(nonce, price_strategy, percent)
+        });
+        Box::new(gas_price.join(gas).and_then(move |(gas_price, gas_limit)| {
+            let tx = types::TransactionRequest {
+                from: address,
+                to: Some(self.concern.contract_address),
+                gas_price: Some(gas_price),
+                gas: Some(gas_limit),
+                value: Some(request.value),
+                data: Some(Bytes(raw_data.clone())),
+                condition: None,
+                nonce: Some(nonce),
+            };
+
+            info!("Sending transaction: {:?}", &request);
+            let account = self.account.clone();
+            let concern = self.concern;
+            let function = Some(request.function.clone());
+            let audit = self.audit.clone();
+            self.sign_and_send(tx, &*strategy).map(move |hash| {
+                record_audit(
+                    &audit,
+                    &concern,
+                    address,
+                    function.clone(),
+                    nonce,
+                    &raw_data,
+                    hash,
+                );
+                account.lock().unwrap().sent(
+                    nonce,
+                    SentTransaction {
+                        gas_price: gas_price,
+                        concern: concern,
+                        function: function,
+                        data: raw_data,
+                        hash: hash,
+                    },
+                )
+            })
+        }))
+    }
+
+    // signs the transaction with the concern's key, or hands it to the
+    // external signer, and sends it to the node, which gives its hash
+    fn sign_and_send(
+        &self,
+        tx: types::TransactionRequest,
+        strategy: &dyn SubmitStrategy,
+    ) -> SendFuture<Option<H256>> {
+        let sending = match &self.key {
+            ConcernKey::KeyPair(key_pair) => {
+                trace!("Signing transaction");
+                let action = match tx.to {
+                    Some(to) => Action::Call(to),
+                    None => Action::Create,
+                };
+                let signed_tx = Transaction {
+                    action: action,
+                    nonce: tx.nonce.unwrap_or_default(),
+                    gas_price: tx.gas_price.unwrap_or_default(),
+                    gas: tx.gas.unwrap_or_default(),
+                    value: tx.value.unwrap_or_default(),
+                    data: tx.data.map(|data| data.0).unwrap_or_default(),
+                }
+                .sign(&key_pair.secret(), Some(self.chain_id));
+
+                let raw = Bytes::from(rlp::encode(&signed_tx));
+                Either::A(
+                    strategy
+                        .send_raw(&self.web3, raw)
+                        .map_err(|e| (e, "node refused raw transaction")),
+                )
+            }
+            ConcernKey::UserAddress(_) => {
+                info!("Sending unsigned transaction to signer");
+                Either::B(
+                    self.web3
+                        .eth()
+                        .send_transaction(tx)
+                        .map_err(|e| (e, "signer refused transaction")),
+                )
+            }
+        };
+
+        Box::new(
+            sending
+                .map(|hash| {
+                    info!("Transaction sent with hash: {:?}", hash);
+                    Some(hash)
+                })
+                .or_else(|(e, reason)| {
+                    // ignore the nonce error, by pass the other errors
+                    if let web3::error::Error::Rpc(ref rpc_error) = e {
+                        if rpc_error.message.starts_with(
+                            "the tx doesn't have the correct nonce",
+                        ) {
+                            warn!(
+                                "Ignoring nonce Error: {}",
+                                rpc_error.message
+                            );
+                            return Ok(None);
                         }
                     }
-
+                    warn!("Failed to send transaction. Error {}", e);
+                    Err(error::Error::with_chain(
+                        e,
+                        ErrorKind::TransactionError(None, String::from(reason)),
+                    ))
                 }),
         )
     }
 }
 
+// gas price offered for a transaction: the urgency raises what the strategy
+// makes of a fresh estimate, not the price already raised of a replaced
+// transaction, so that escalations do not compound, while a replacement
+// still pays the least raise it needs. Never above the cap, if any, which
+// a replacement needing more fails on.
+fn offered_gas_price(
+    strategy: &dyn SubmitStrategy,
+    estimated: U256,
+    replaced: Option<U256>,
+    percent: u64,
+    max_gas_price: Option<U256>,
+) -> Result<U256> {
+    let offered = strategy
+        .gas_price(estimated, None)
+        .saturating_mul(U256::from(percent))
+        / 100;
+    let required = match replaced {
+        Some(_) => strategy.gas_price(estimated, replaced),
+        None => U256::zero(),
+    };
+    match max_gas_price {
+        Some(cap) if required > cap => {
+            Err(Error::from(ErrorKind::TransactionError(
+                None,
+                format!(
+                    "replacement needs a gas price of {}, above the cap of {}",
+                    required, cap
+                ),
+            )))
+        }
+        Some(cap) => Ok(offered.max(required).min(cap)),
+        None => Ok(offered.max(required)),
+    }
+}
+
+// records a transaction accepted by the node in the audit log, if one is
+// kept; as the transaction is sent anyway, a failure is only reported
+fn record_audit(
+    audit: &Option<Arc<AuditLog>>,
+    concern: &Concern,
+    user: Address,
+    function: Option<String>,
+    nonce: U256,
+    data: &[u8],
+    hash: Option<H256>,
+) {
+    if let Some(audit) = audit {
+        if let Err(e) = audit.record(
+            concern.contract_address,
+            user,
+            function,
+            nonce,
+            data,
+            hash,
+        ) {
+            error!(
+                "Could not record transaction {} of {:#x} in the audit log: {}",
+                nonce, user, e
+            );
+        }
+    }
+}
+
 fn get_gas(
     web3: Arc<web3::Web3<GenericTransport>>,
     call_request: web3::types::CallRequest,
@@ -399,3 +1423,68 @@ fn get_gas(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn abi() -> ethabi::Contract {
+        ethabi::Contract::load(
+            &br#"[{
+                "type": "function",
+                "name": "claimVictory",
+                "inputs": [{ "name": "_index", "type": "uint256" }],
+                "outputs": [],
+                "stateMutability": "nonpayable"
+            }]"#[..],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn calls_are_checked_against_the_abi() {
+        let abi = abi();
+        let index = CallParams::new().push(U256::from(3));
+        assert_eq!(index.encode_for(&abi, "claimVictory").unwrap().len(), 36);
+
+        let message = |params: CallParams, function: &str| match params
+            .encode_for(&abi, function)
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::InvalidTransactionRequest(message) => message.clone(),
+            other => panic!("expected an invalid request, got {:?}", other),
+        };
+        assert_eq!(
+            message(index.clone(), "claimDefeat"),
+            "function claimDefeat is not in the abi"
+        );
+        assert_eq!(
+            message(index.clone().push(true), "claimVictory"),
+            "function claimVictory expects 1 parameters, got 2"
+        );
+        assert_eq!(
+            message(CallParams::new().push(Address::zero()), "claimVictory"),
+            "parameter 0 (_index) of function claimVictory is uint256, got \
+             address"
+        );
+    }
+
+    #[test]
+    fn escalations_do_not_compound() {
+        let gwei = U256::from(1_000_000_000u64);
+        let offer = |replaced, percent, cap| {
+            offered_gas_price(&Simplest, gwei, replaced, percent, cap)
+        };
+        assert_eq!(offer(None, 200, None).unwrap(), gwei * 4);
+        // the raise applies to the estimate, not to the replaced price
+        assert_eq!(
+            offer(Some(gwei * 4), 200, None).unwrap(),
+            gwei * 44 / 10 + 1
+        );
+        assert_eq!(offer(Some(gwei * 4), 300, None).unwrap(), gwei * 6);
+
+        assert_eq!(offer(None, 300, Some(gwei * 5)).unwrap(), gwei * 5);
+        assert!(offer(Some(gwei * 4), 100, Some(gwei * 4)).is_err());
+    }
+}
@@ -0,0 +1,307 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Bounded queue of transaction submissions. Each account has a limited
+//! number of transactions in flight, the others wait their turn. Concerns
+//! with a higher priority are served first, then the transactions of high
+//! value disputes, and those of equal priority round-robin so that a busy
+//! concern cannot starve the others. When too many submissions are
+//! waiting, new ones are refused.
+
+use configuration::Concern;
+use error::*;
+use ethereum_types::Address;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use web3::futures::future;
+use web3::futures::sync::oneshot;
+use web3::futures::Future;
+
+// submissions of a concern wait in two lanes, the one of high value
+// disputes going first
+type Lane = (Concern, bool);
+
+#[derive(Default)]
+struct AccountQueue {
+    in_flight: usize,
+    waiting: usize,
+    // waiters of each lane, and the order in which lanes are served
+    waiters: HashMap<Lane, VecDeque<oneshot::Sender<()>>>,
+    turn: VecDeque<Lane>,
+    priorities: HashMap<Lane, u32>,
+}
+
+impl AccountQueue {
+    // hands the slot of a finished submission to the next waiter, returning
+    // false when nobody is waiting for it
+    fn hand_over(&mut self) -> bool {
+        while let Some(lane) = self.next_lane() {
+            let (waiter, more) = match self.waiters.get_mut(&lane) {
+                Some(waiters) => (waiters.pop_front(), !waiters.is_empty()),
+                None => (None, false),
+            };
+            if more {
+                self.turn.push_back(lane);
+            } else {
+                self.waiters.remove(&lane);
+                self.priorities.remove(&lane);
+            }
+            if let Some(waiter) = waiter {
+                self.waiting -= 1;
+                // a waiter whose submission was dropped does not take a slot
+                if waiter.send(()).is_ok() {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    // takes the first lane in turn among those of highest priority, high
+    // value disputes first among lanes of the same priority
+    fn next_lane(&mut self) -> Option<Lane> {
+        let priorities = &self.priorities;
+        let priority_of =
+            |lane: &Lane| (priorities.get(lane).cloned().unwrap_or(0), lane.1);
+        let highest = self.turn.iter().map(&priority_of).max()?;
+        let position = self
+            .turn
+            .iter()
+            .position(|lane| priority_of(lane) == highest)?;
+        self.turn.remove(position)
+    }
+}
+
+struct QueueState {
+    max_in_flight: usize,
+    max_waiting: usize,
+    accounts: HashMap<Address, AccountQueue>,
+}
+
+/// The right to have a transaction in flight for an account, given back to
+/// the queue when dropped
+pub struct Permit {
+    state: Arc<Mutex<QueueState>>,
+    account: Address,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(queue) = state.accounts.get_mut(&self.account) {
+            if !queue.hand_over() {
+                queue.in_flight -= 1;
+            }
+        }
+    }
+}
+
+/// Limits the transactions in flight for each account
+#[derive(Clone)]
+pub struct SubmissionQueue {
+    state: Arc<Mutex<QueueState>>,
+}
+
+impl SubmissionQueue {
+    pub fn new(max_in_flight: usize, max_waiting: usize) -> Self {
+        SubmissionQueue {
+            state: Arc::new(Mutex::new(QueueState {
+                max_in_flight: max_in_flight.max(1),
+                max_waiting: max_waiting,
+                accounts: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Waits for a free slot of the account, failing right away with
+    /// `SubmissionQueueFull` if too many submissions are already waiting.
+    /// Submissions for high value disputes go before the others of the
+    /// same priority.
+    pub fn acquire(
+        &self,
+        account: Address,
+        concern: Concern,
+        priority: u32,
+        high_stake: bool,
+    ) -> Box<dyn Future<Item = Permit, Error = Error> + Send> {
+        let permit_state = self.state.clone();
+        let mut state = self.state.lock().unwrap();
+        let max_in_flight = state.max_in_flight;
+        let max_waiting = state.max_waiting;
+        let queue = state.accounts.entry(account).or_default();
+
+        if queue.in_flight < max_in_flight && queue.waiting == 0 {
+            queue.in_flight += 1;
+            return Box::new(future::ok(Permit {
+                state: permit_state,
+                account: account,
+            }));
+        }
+        if queue.waiting >= max_waiting {
+            return Box::new(future::err(Error::from(
+                ErrorKind::SubmissionQueueFull(format!("{:#x}", account)),
+            )));
+        }
+
+        let (tx, rx) = oneshot::channel();
+        let lane = (concern, high_stake);
+        let waiters = queue.waiters.entry(lane).or_default();
+        if waiters.is_empty() {
+            queue.turn.push_back(lane);
+            queue.priorities.insert(lane, priority);
+        }
+        waiters.push_back(tx);
+        queue.waiting += 1;
+        trace!(
+            "Submission for {} waiting, {} ahead",
+            concern,
+            queue.waiting - 1
+        );
+
+        Box::new(
+            rx.map(move |_| Permit {
+                state: permit_state,
+                account: account,
+            })
+            .map_err(|_| Error::from("submission queue dropped")),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn concern(n: u64) -> Concern {
+        Concern {
+            contract_address: Address::from_low_u64_be(n),
+            user_address: Address::zero(),
+        }
+    }
+
+    fn waiting(queue: &SubmissionQueue, account: Address) -> Vec<usize> {
+        let state = queue.state.lock().unwrap();
+        let account = &state.accounts[&account];
+        (1..3)
+            .map(|n| {
+                [false, true]
+                    .iter()
+                    .map(|high_stake| {
+                        account
+                            .waiters
+                            .get(&(concern(n), *high_stake))
+                            .map_or(0, |w| w.len())
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn limits_in_flight_and_serves_concerns_in_turn() {
+        let queue = SubmissionQueue::new(1, 3);
+        let account = Address::zero();
+
+        let first =
+            queue.acquire(account, concern(1), 0, false).wait().unwrap();
+        let a1 = queue.acquire(account, concern(1), 0, false);
+        let _a2 = queue.acquire(account, concern(1), 0, false);
+        let b1 = queue.acquire(account, concern(2), 0, false);
+        assert_eq!(waiting(&queue, account), vec![2, 1]);
+        assert!(queue.acquire(account, concern(1), 0, false).wait().is_err());
+
+        // the first concern waited first, then the second gets its turn
+        // even though the first one still has submissions waiting
+        drop(first);
+        assert_eq!(waiting(&queue, account), vec![1, 1]);
+        drop(a1.wait().unwrap());
+        assert_eq!(waiting(&queue, account), vec![1, 0]);
+        drop(b1.wait().unwrap());
+        assert_eq!(waiting(&queue, account), vec![0, 0]);
+    }
+
+    #[test]
+    fn higher_priority_concerns_are_served_first() {
+        let queue = SubmissionQueue::new(1, 3);
+        let account = Address::zero();
+
+        let first =
+            queue.acquire(account, concern(1), 0, false).wait().unwrap();
+        let _a1 = queue.acquire(account, concern(1), 0, false);
+        let b1 = queue.acquire(account, concern(2), 5, false);
+        let _b2 = queue.acquire(account, concern(2), 5, false);
+        assert_eq!(waiting(&queue, account), vec![1, 2]);
+
+        drop(first);
+        assert_eq!(waiting(&queue, account), vec![1, 1]);
+        drop(b1.wait().unwrap());
+        assert_eq!(waiting(&queue, account), vec![1, 0]);
+    }
+
+    #[test]
+    fn slots_of_other_accounts_are_independent() {
+        let queue = SubmissionQueue::new(1, 0);
+        let a = queue
+            .acquire(Address::zero(), concern(1), 0, false)
+            .wait()
+            .unwrap();
+        assert!(queue
+            .acquire(Address::zero(), concern(1), 0, false)
+            .wait()
+            .is_err());
+        let b = queue
+            .acquire(Address::repeat_byte(1), concern(1), 0, false)
+            .wait();
+        assert!(b.is_ok());
+        drop(a);
+        assert!(queue
+            .acquire(Address::zero(), concern(1), 0, false)
+            .wait()
+            .is_ok());
+    }
+
+    #[test]
+    fn high_value_disputes_go_first_within_a_priority() {
+        let queue = SubmissionQueue::new(1, 4);
+        let account = Address::zero();
+
+        let first =
+            queue.acquire(account, concern(1), 0, false).wait().unwrap();
+        let a1 = queue.acquire(account, concern(1), 0, false);
+        let b1 = queue.acquire(account, concern(2), 0, true);
+        let c1 = queue.acquire(account, concern(2), 5, false);
+        assert_eq!(waiting(&queue, account), vec![1, 2]);
+
+        // the priority of the concern goes before the value at stake
+        drop(first);
+        let c1 = c1.wait().unwrap();
+        assert_eq!(waiting(&queue, account), vec![1, 1]);
+        drop(c1);
+        let b1 = b1.wait().unwrap();
+        assert_eq!(waiting(&queue, account), vec![1, 0]);
+        drop(b1);
+        drop(a1.wait().unwrap());
+        assert_eq!(waiting(&queue, account), vec![0, 0]);
+    }
+}
@@ -0,0 +1,100 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Submission through a private relay, like Flashbots Protect, which takes
+//! signed transactions over json-rpc and keeps them out of the public
+//! mempool until they are mined. Timeout claims of disputes sent there
+//! cannot be frontrun by the other party.
+
+use super::SubmitStrategy;
+use error::*;
+use ethereum_types::{H256, U256};
+use std::sync::Arc;
+use std::time::Duration;
+use transport::GenericTransport;
+use web3::futures::Future;
+use web3::transports::EventLoopHandle;
+use web3::types::Bytes;
+
+/// Connection to a private relay
+pub struct PrivateRelay {
+    web3: web3::Web3<GenericTransport>,
+    _eloop: EventLoopHandle, // kept to stay in scope
+}
+
+impl PrivateRelay {
+    pub fn new(url: &str, timeout: Duration) -> Result<PrivateRelay> {
+        let (eloop, transport) = GenericTransport::new(url, timeout)
+            .chain_err(|| format!("could not connect to private relay"))?;
+        Ok(PrivateRelay {
+            web3: web3::Web3::new(transport),
+            _eloop: eloop,
+        })
+    }
+}
+
+/// Sends transactions through a relay, offering the gas price given by
+/// another strategy
+pub struct Relayed {
+    pub relay: Arc<PrivateRelay>,
+    pub strategy: Arc<dyn SubmitStrategy>,
+}
+
+impl SubmitStrategy for Relayed {
+    fn gas_price(&self, estimated: U256, replaced: Option<U256>) -> U256 {
+        self.strategy.gas_price(estimated, replaced)
+    }
+
+    fn send_raw(
+        &self,
+        _web3: &web3::Web3<GenericTransport>,
+        raw: Bytes,
+    ) -> Box<dyn Future<Item = H256, Error = web3::Error> + Send> {
+        trace!("Sending transaction through private relay");
+        Box::new(self.relay.web3.eth().send_raw_transaction(raw))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strategy::Escalating;
+
+    #[test]
+    fn relayed_transactions_keep_their_gas_price() {
+        let timeout = Duration::from_secs(1);
+        assert!(PrivateRelay::new("ftp://relay", timeout).is_err());
+
+        let relay = PrivateRelay::new("http://127.0.0.1:1", timeout).unwrap();
+        let relayed = Relayed {
+            relay: Arc::new(relay),
+            strategy: Arc::new(Escalating(50)),
+        };
+        let estimated = U256::from(100);
+        assert_eq!(
+            relayed.gas_price(estimated, Some(estimated * 2)),
+            Escalating(50).gas_price(estimated, Some(estimated * 2))
+        );
+    }
+}
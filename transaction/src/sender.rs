@@ -0,0 +1,138 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Where reactions of the dispatcher send their transactions, behind a
+//! trait so that the dispatcher and DApps can be tested without a node.
+
+use super::{TransactionManager, TransactionRequest};
+use error::*;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use web3::futures::future;
+use web3::futures::Future;
+
+/// Sends the transactions requested by DApps
+pub trait TransactionSender: Send {
+    fn send(
+        &self,
+        request: TransactionRequest,
+    ) -> Box<dyn Future<Item = (), Error = Error> + Send>;
+
+    /// Checks that the request would not revert, without sending it
+    fn simulate(
+        &self,
+        _request: TransactionRequest,
+    ) -> Box<dyn Future<Item = (), Error = Error> + Send> {
+        Box::new(future::ok(()))
+    }
+}
+
+impl TransactionSender for TransactionManager {
+    fn send(
+        &self,
+        request: TransactionRequest,
+    ) -> Box<dyn Future<Item = (), Error = Error> + Send> {
+        TransactionManager::send(self, request)
+    }
+
+    fn simulate(
+        &self,
+        request: TransactionRequest,
+    ) -> Box<dyn Future<Item = (), Error = Error> + Send> {
+        TransactionManager::simulate(self, request)
+    }
+}
+
+/// Records the requests it is given, answering with the scripted results
+/// in order and succeeding once they run out
+#[derive(Default)]
+pub struct MockSender {
+    sent: Mutex<Vec<TransactionRequest>>,
+    results: Mutex<VecDeque<Result<()>>>,
+}
+
+impl MockSender {
+    pub fn new() -> Self {
+        MockSender::default()
+    }
+
+    /// Scripts the result of the next request
+    pub fn answer(self, result: Result<()>) -> Self {
+        self.results.lock().unwrap().push_back(result);
+        self
+    }
+
+    /// The requests received so far
+    pub fn sent(&self) -> Vec<TransactionRequest> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+impl TransactionSender for MockSender {
+    fn send(
+        &self,
+        request: TransactionRequest,
+    ) -> Box<dyn Future<Item = (), Error = Error> + Send> {
+        self.sent.lock().unwrap().push(request);
+        let result = self.results.lock().unwrap().pop_front();
+        Box::new(future::result(result.unwrap_or(Ok(()))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use configuration::Concern;
+    use ethereum_types::Address;
+    use {CallParams, Strategy};
+
+    fn request(function: &str) -> TransactionRequest {
+        TransactionRequest {
+            concern: Concern {
+                contract_address: Address::zero(),
+                user_address: Address::zero(),
+            },
+            value: 0.into(),
+            function: String::from(function),
+            data: CallParams::new(),
+            gas: None,
+            strategy: Strategy::Simplest,
+            contract_name: None,
+            deadline_block: None,
+            deadline_timestamp: None,
+            stake_value: None,
+        }
+    }
+
+    #[test]
+    fn mock_sender_records_requests_and_scripted_results() {
+        let sender = MockSender::new().answer(Err(Error::from("reverted")));
+        assert!(sender.send(request("claimVictory")).wait().is_err());
+        assert!(sender.send(request("reveal")).wait().is_ok());
+
+        let functions: Vec<String> =
+            sender.sent().into_iter().map(|r| r.function).collect();
+        assert_eq!(functions, vec!["claimVictory", "reveal"]);
+    }
+}
@@ -0,0 +1,182 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Strategies deciding how a transaction is submitted: the gas price it
+//! offers and where the signed transaction is sent. Besides the built-in
+//! ones, downstream crates may register their own in the transaction
+//! manager and select them by name in their requests.
+
+use ethereum_types::{H256, U256};
+use transport::GenericTransport;
+use web3::futures::Future;
+use web3::types::Bytes;
+
+/// Least raise of gas price that nodes accept to replace a transaction
+pub const MIN_BUMP_PERCENT: u64 = 10;
+/// Blocks before its deadline from which a transaction is escalated
+pub const DEADLINE_WINDOW: u64 = 20;
+/// Raise of gas price for each block of the window already gone
+const DEADLINE_STEP_PERCENT: u64 = 25;
+/// How many times earlier the gas of a transaction of a high value dispute
+/// is escalated
+pub const HIGH_STAKE_FACTOR: u64 = 2;
+/// Seconds expected between blocks, to count the blocks left before a
+/// deadline given as a timestamp
+pub const BLOCK_TIME: u64 = 15;
+
+/// How a transaction gets submitted
+pub trait SubmitStrategy: Send + Sync {
+    /// Gas price to offer, given the node's estimate and the gas price of
+    /// the pending transaction being replaced, if any
+    fn gas_price(&self, estimated: U256, replaced: Option<U256>) -> U256;
+
+    /// Hands the signed transaction over to be mined, giving its hash.
+    /// Transactions go to the mempool of the node by default.
+    fn send_raw(
+        &self,
+        web3: &web3::Web3<GenericTransport>,
+        raw: Bytes,
+    ) -> Box<dyn Future<Item = H256, Error = web3::Error> + Send> {
+        Box::new(web3.eth().send_raw_transaction(raw))
+    }
+}
+
+// raises the gas price of the replaced transaction by the given percentage
+fn bumped(replaced: U256, percent: u64) -> U256 {
+    replaced.saturating_mul(U256::from(100u64.saturating_add(percent)))
+        / U256::from(100)
+        + U256::one()
+}
+
+/// Offers twice the estimated gas price, outbidding a replaced transaction
+/// by the least raise nodes accept
+#[derive(Clone, Debug, Default)]
+pub struct Simplest;
+
+impl SubmitStrategy for Simplest {
+    fn gas_price(&self, estimated: U256, replaced: Option<U256>) -> U256 {
+        Escalating(MIN_BUMP_PERCENT).gas_price(estimated, replaced)
+    }
+}
+
+/// Like `Simplest`, but a replacement pays at least the given percentage
+/// over the gas price of the transaction it replaces
+#[derive(Clone, Debug)]
+pub struct Escalating(pub u64);
+
+impl SubmitStrategy for Escalating {
+    fn gas_price(&self, estimated: U256, replaced: Option<U256>) -> U256 {
+        // do something better then double
+        let price = U256::from(2).saturating_mul(estimated);
+        match replaced {
+            Some(replaced) => {
+                price.max(bumped(replaced, self.0.max(MIN_BUMP_PERCENT)))
+            }
+            None => price,
+        }
+    }
+}
+
+/// Percentage of the gas price given by the strategy offered for a
+/// transaction with the given blocks left before its deadline: nothing more
+/// outside the window, then a quarter more for each block, up to six times
+/// the price when the deadline is reached
+pub fn deadline_percent(blocks_left: u64) -> u64 {
+    100 + DEADLINE_STEP_PERCENT * DEADLINE_WINDOW.saturating_sub(blocks_left)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use configuration::Concern;
+    use ethereum_types::Address;
+    use utils::chain::BlockHeader;
+    use {CallParams, Strategy, TransactionRequest};
+
+    #[test]
+    fn replacements_outbid_the_replaced_transaction() {
+        let gwei = U256::from(1_000_000_000u64);
+        assert_eq!(Simplest.gas_price(gwei, None), gwei * 2);
+        // doubling the estimate is already enough of a raise
+        assert_eq!(Simplest.gas_price(gwei, Some(gwei)), gwei * 2);
+        assert_eq!(
+            Simplest.gas_price(gwei, Some(gwei * 2)),
+            gwei * 22 / 10 + 1
+        );
+        assert_eq!(
+            Escalating(50).gas_price(gwei, Some(gwei * 2)),
+            gwei * 3 + 1
+        );
+        // raises below what nodes accept are not offered
+        assert_eq!(
+            Escalating(1).gas_price(gwei, Some(gwei * 2)),
+            Simplest.gas_price(gwei, Some(gwei * 2))
+        );
+    }
+
+    #[test]
+    fn gas_escalates_as_the_deadline_approaches() {
+        let mut request = TransactionRequest {
+            concern: Concern {
+                contract_address: Address::zero(),
+                user_address: Address::zero(),
+            },
+            value: 0.into(),
+            function: String::from("claimVictoryByTime"),
+            data: CallParams::new(),
+            gas: None,
+            strategy: Strategy::Simplest,
+            contract_name: None,
+            deadline_block: None,
+            deadline_timestamp: None,
+            stake_value: None,
+        };
+        let latest = BlockHeader {
+            number: Some(100),
+            timestamp: 1500,
+        };
+        assert_eq!(request.blocks_left(&latest), None);
+
+        request.deadline_block = Some(130);
+        assert_eq!(request.blocks_left(&latest), Some(30));
+        // the closest of both deadlines counts
+        request.deadline_timestamp = Some(1500 + 10 * BLOCK_TIME);
+        assert_eq!(request.blocks_left(&latest), Some(10));
+        request.deadline_block = Some(90);
+        assert_eq!(request.blocks_left(&latest), Some(0));
+
+        assert_eq!(deadline_percent(30), 100);
+        assert_eq!(deadline_percent(DEADLINE_WINDOW), 100);
+        assert_eq!(deadline_percent(DEADLINE_WINDOW - 4), 200);
+        assert_eq!(deadline_percent(0), 600);
+
+        let high_stake = Some(U256::from(1000));
+        assert!(!request.is_high_stake(high_stake));
+        request.stake_value = Some(U256::from(999));
+        assert!(!request.is_high_stake(high_stake));
+        assert!(!request.is_high_stake(None));
+        request.stake_value = Some(U256::from(1000));
+        assert!(request.is_high_stake(high_stake));
+    }
+}
@@ -0,0 +1,242 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Helpers for DApps that must bond ERC-20 tokens in a contract before
+//! taking part in its games. The token is one of the named contracts of
+//! the configuration, and its ABI must have the usual ERC-20 functions.
+//! The bonding contract is expected to take the tokens with
+//! `deposit(uint256)`.
+
+use super::{CallParams, Strategy, TransactionManager, TransactionRequest};
+use configuration::Concern;
+use error::*;
+use ethabi::Token;
+use ethereum_types::{Address, U256};
+use std::sync::Arc;
+use transport::GenericTransport;
+use web3::futures::future::{err, ok, Either};
+use web3::futures::Future;
+use web3::types::{Bytes, CallRequest};
+
+/// An ERC-20 token, checked through the node and moved with transactions
+/// sent by the transaction manager
+#[derive(Clone)]
+pub struct Erc20 {
+    concern: Concern,
+    abi: Arc<ethabi::Contract>,
+    web3: Arc<web3::Web3<GenericTransport>>,
+    url: String,
+}
+
+impl Erc20 {
+    pub fn concern(&self) -> Concern {
+        self.concern
+    }
+
+    /// Tokens held by the owner
+    pub fn balance_of(
+        &self,
+        owner: Address,
+    ) -> Box<dyn Future<Item = U256, Error = Error> + Send> {
+        self.call_uint("balanceOf", &[Token::Address(owner)])
+    }
+
+    /// Tokens the spender may still take from the owner
+    pub fn allowance(
+        &self,
+        owner: Address,
+        spender: Address,
+    ) -> Box<dyn Future<Item = U256, Error = Error> + Send> {
+        self.call_uint(
+            "allowance",
+            &[Token::Address(owner), Token::Address(spender)],
+        )
+    }
+
+    /// Request allowing the spender to take the amount from the account
+    /// signing for the token
+    pub fn approve(
+        &self,
+        spender: Address,
+        amount: U256,
+    ) -> TransactionRequest {
+        TransactionRequest {
+            concern: self.concern,
+            value: U256::zero(),
+            function: String::from("approve"),
+            data: CallParams::new().push(spender).push(amount),
+            gas: None,
+            strategy: Strategy::Simplest,
+            contract_name: None,
+            deadline_block: None,
+            deadline_timestamp: None,
+            stake_value: None,
+        }
+    }
+
+    // calls a view function of the token returning a single integer
+    fn call_uint(
+        &self,
+        function: &str,
+        params: &[Token],
+    ) -> Box<dyn Future<Item = U256, Error = Error> + Send> {
+        let data = match self
+            .abi
+            .function(function)
+            .and_then(|f| f.encode_input(params))
+        {
+            Ok(data) => data,
+            Err(e) => return Box::new(err(Error::from(e))),
+        };
+        let abi = self.abi.clone();
+        let function = String::from(function);
+        let url = self.url.clone();
+        Box::new(
+            self.web3
+                .eth()
+                .call(
+                    CallRequest {
+                        from: None,
+                        to: self.concern.contract_address,
+                        gas: None,
+                        gas_price: None,
+                        value: None,
+                        data: Some(Bytes(data)),
+                    },
+                    None,
+                )
+                .map_err(move |_e| {
+                    Error::from(ErrorKind::RpcError(
+                        String::from("eth_call"),
+                        url,
+                    ))
+                })
+                .and_then(move |result| {
+                    let tokens =
+                        abi.function(&function)?.decode_output(&result.0)?;
+                    tokens.into_iter().next().and_then(Token::to_uint).ok_or(
+                        Error::from(format!(
+                            "{} did not return an integer",
+                            function
+                        )),
+                    )
+                }),
+        )
+    }
+}
+
+/// Request depositing the amount of tokens in the concern's contract,
+/// which must have been approved to take them
+pub fn deposit(concern: Concern, amount: U256) -> TransactionRequest {
+    TransactionRequest {
+        concern: concern,
+        value: U256::zero(),
+        function: String::from("deposit"),
+        data: CallParams::new().push(amount),
+        gas: None,
+        strategy: Strategy::Simplest,
+        contract_name: None,
+        deadline_block: None,
+        deadline_timestamp: None,
+        stake_value: None,
+    }
+}
+
+impl TransactionManager {
+    /// The ERC-20 token configured as the named contract
+    pub fn erc20(&self, contract_name: &str) -> Result<Erc20> {
+        let concern = self.config.contracts.get(contract_name).ok_or(
+            Error::from(ErrorKind::ConfigError(format!(
+                "unknown token contract: {}",
+                contract_name
+            ))),
+        )?;
+        let concern_data = self.concern_data.get(concern).ok_or(
+            Error::from(ErrorKind::ConfigError(format!(
+                "no abi for token contract: {}",
+                contract_name
+            ))),
+        )?;
+        // read from the node of the token's own network
+        let link = self.link_of(concern);
+        Ok(Erc20 {
+            concern: *concern,
+            abi: concern_data.abi.clone(),
+            web3: link.web3.clone(),
+            url: link.url.clone(),
+        })
+    }
+
+    /// Bonds the amount of tokens in the concern's contract, approving it
+    /// first if its allowance falls short. Nothing is sent if the account
+    /// does not hold enough tokens.
+    pub fn bond(
+        &self,
+        token: &Erc20,
+        concern: Concern,
+        amount: U256,
+    ) -> Box<dyn Future<Item = (), Error = Error> + Send> {
+        let owner = self.config.signer_of(&concern).address();
+        // the approval and the deposit must come from the same account
+        if self.config.signer_of(&token.concern).address() != owner {
+            return Box::new(err(Error::from(
+                ErrorKind::InvalidTransactionRequest(format!(
+                    "token {} and concern {} are signed by different accounts",
+                    token.concern, concern
+                )),
+            )));
+        }
+        let spender = concern.contract_address;
+        let manager = self.clone();
+        let approve = token.approve(spender, amount);
+
+        Box::new(
+            token
+                .balance_of(owner)
+                .join(token.allowance(owner, spender))
+                .and_then(move |(balance, allowance)| {
+                    if balance < amount {
+                        return Either::B(err(Error::from(
+                            ErrorKind::InvalidTransactionRequest(format!(
+                                "balance of {:#x} is {}, cannot bond {}",
+                                owner, balance, amount
+                            )),
+                        )));
+                    }
+                    // approving first takes the lower nonce, so the
+                    // deposit is mined after it
+                    let approved = if allowance < amount {
+                        info!("Approving {} tokens for {}", amount, concern);
+                        Either::A(manager.send(approve))
+                    } else {
+                        Either::B(ok(()))
+                    };
+                    Either::A(approved.and_then(move |_| {
+                        info!("Depositing {} tokens in {}", amount, concern);
+                        manager.send(deposit(concern, amount))
+                    }))
+                }),
+        )
+    }
+}
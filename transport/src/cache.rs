@@ -0,0 +1,265 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Cache of the responses of the node that never change once known: the
+//! code deployed at an address, mined transactions and blocks, both by
+//! hash. Parsing and verification read them over and over, and they are
+//! served from memory after the first time.
+//!
+//! Blocks passing through the transport are watched for reorgs: once a
+//! block number is seen with another hash, the transactions and blocks
+//! cached from the replaced blocks are dropped.
+
+use jsonrpc_core::{Params, Value};
+use serde_json;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Blocks below the latest one whose hashes are remembered to notice
+/// reorgs, far deeper than any reorg
+const REORG_DEPTH: u64 = 256;
+
+// a response, with the block it belongs to when addressed by hash
+#[derive(Debug)]
+struct Cached {
+    value: Value,
+    block_hash: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    responses: HashMap<String, Cached>,
+    // keys in the order they were cached, the oldest evicted first
+    order: VecDeque<String>,
+    // hash of the block seen at each number
+    canonical: BTreeMap<u64, String>,
+}
+
+/// Immutable responses of the node, by method and parameters
+#[derive(Debug)]
+pub struct ResponseCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+    hits: AtomicUsize,
+}
+
+impl ResponseCache {
+    /// A cache of at most `capacity` responses
+    pub fn new(capacity: usize) -> Self {
+        ResponseCache {
+            capacity: capacity,
+            entries: Mutex::new(Entries::default()),
+            hits: AtomicUsize::new(0),
+        }
+    }
+
+    /// Key of the response to a request, if it may be cached
+    pub fn key(&self, method: &str, params: &Params) -> Option<String> {
+        match method {
+            "eth_getCode"
+            | "eth_getTransactionByHash"
+            | "eth_getBlockByHash" => Some(format!(
+                "{}{}",
+                method,
+                serde_json::to_string(params).ok()?
+            )),
+            _ => None,
+        }
+    }
+
+    /// The cached response for the key, if any
+    pub fn get(&self, key: &str) -> Option<Value> {
+        let entries = self.entries.lock().unwrap();
+        let value = entries.responses.get(key).map(|c| c.value.clone());
+        if value.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        value
+    }
+
+    /// Records the response to a request, cached under its key when it
+    /// is final: code that is deployed, transactions that are mined and
+    /// blocks that exist
+    pub fn store(&self, method: &str, key: Option<String>, value: &Value) {
+        let mut entries = self.entries.lock().unwrap();
+        if let "eth_getBlockByNumber" | "eth_getBlockByHash" = method {
+            observe_block(&mut entries, value);
+        }
+        let key = match key {
+            Some(key) => key,
+            None => return,
+        };
+        let block_hash = match method {
+            "eth_getCode" => match value.as_str() {
+                Some(code) if code != "0x" => None,
+                _ => return,
+            },
+            "eth_getTransactionByHash" => match value["blockHash"].as_str() {
+                Some(hash) => Some(String::from(hash)),
+                None => return,
+            },
+            "eth_getBlockByHash" => match value["hash"].as_str() {
+                Some(hash) => Some(String::from(hash)),
+                None => return,
+            },
+            _ => return,
+        };
+        if entries.responses.contains_key(&key) {
+            return;
+        }
+        entries.order.push_back(key.clone());
+        entries.responses.insert(
+            key,
+            Cached {
+                value: value.clone(),
+                block_hash: block_hash,
+            },
+        );
+        while entries.responses.len() > self.capacity {
+            match entries.order.pop_front() {
+                Some(oldest) => {
+                    entries.responses.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Requests answered from the cache so far
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+// remembers the hash of a block, dropping what was cached from the blocks
+// it replaces, at its height and above
+fn observe_block(entries: &mut Entries, block: &Value) {
+    let number = match block["number"]
+        .as_str()
+        .and_then(|n| u64::from_str_radix(n.trim_start_matches("0x"), 16).ok())
+    {
+        Some(number) => number,
+        None => return,
+    };
+    let hash = match block["hash"].as_str() {
+        Some(hash) => String::from(hash),
+        None => return,
+    };
+    if entries
+        .canonical
+        .get(&number)
+        .map_or(false, |seen| *seen != hash)
+    {
+        let replaced: HashSet<String> = entries
+            .canonical
+            .split_off(&number)
+            .into_iter()
+            .map(|(_, hash)| hash)
+            .collect();
+        warn!(
+            "Reorg at block {}, forgetting what was read from {} blocks",
+            number,
+            replaced.len()
+        );
+        entries.responses.retain(|_, cached| {
+            cached
+                .block_hash
+                .as_ref()
+                .map_or(true, |hash| !replaced.contains(hash))
+        });
+    }
+    entries.canonical.insert(number, hash);
+    if number > REORG_DEPTH {
+        entries.canonical =
+            entries.canonical.split_off(&(number - REORG_DEPTH));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(value: &str) -> Params {
+        Params::Array(vec![Value::String(String::from(value))])
+    }
+
+    fn block(number: u64, hash: &str) -> Value {
+        json!({ "number": format!("0x{:x}", number), "hash": hash })
+    }
+
+    #[test]
+    fn only_final_responses_are_cached() {
+        let cache = ResponseCache::new(10);
+        let code = cache.key("eth_getCode", &params("0x01"));
+        cache.store("eth_getCode", code.clone(), &json!("0x"));
+        assert_eq!(cache.get(code.as_ref().unwrap()), None);
+        cache.store("eth_getCode", code.clone(), &json!("0x6080"));
+        assert_eq!(cache.get(code.as_ref().unwrap()), Some(json!("0x6080")));
+
+        let pending = cache.key("eth_getTransactionByHash", &params("0xaa"));
+        cache.store(
+            "eth_getTransactionByHash",
+            pending.clone(),
+            &json!({ "hash": "0xaa", "blockHash": null }),
+        );
+        assert_eq!(cache.get(pending.as_ref().unwrap()), None);
+        assert_eq!(cache.key("eth_blockNumber", &Params::None), None);
+        assert_eq!(cache.hits(), 1);
+    }
+
+    #[test]
+    fn reorgs_drop_what_was_read_from_replaced_blocks() {
+        let cache = ResponseCache::new(10);
+        cache.store("eth_getBlockByNumber", None, &block(10, "0xb10"));
+        cache.store("eth_getBlockByNumber", None, &block(11, "0xb11"));
+        let mined = cache.key("eth_getTransactionByHash", &params("0xaa"));
+        cache.store(
+            "eth_getTransactionByHash",
+            mined.clone(),
+            &json!({ "hash": "0xaa", "blockHash": "0xb11" }),
+        );
+        let older = cache.key("eth_getTransactionByHash", &params("0xbb"));
+        cache.store(
+            "eth_getTransactionByHash",
+            older.clone(),
+            &json!({ "hash": "0xbb", "blockHash": "0xb10" }),
+        );
+
+        cache.store("eth_getBlockByNumber", None, &block(11, "0xc11"));
+        assert_eq!(cache.get(mined.as_ref().unwrap()), None);
+        assert!(cache.get(older.as_ref().unwrap()).is_some());
+    }
+
+    #[test]
+    fn oldest_responses_are_evicted() {
+        let cache = ResponseCache::new(1);
+        let first = cache.key("eth_getCode", &params("0x01"));
+        let second = cache.key("eth_getCode", &params("0x02"));
+        cache.store("eth_getCode", first.clone(), &json!("0x60"));
+        cache.store("eth_getCode", second.clone(), &json!("0x61"));
+        assert_eq!(cache.get(first.as_ref().unwrap()), None);
+        assert_eq!(cache.get(second.as_ref().unwrap()), Some(json!("0x61")));
+    }
+}
@@ -32,35 +32,53 @@ extern crate error_chain;
 #[macro_use]
 extern crate log;
 extern crate jsonrpc_core;
+#[macro_use]
 extern crate serde_json;
 extern crate tokio_timer;
 extern crate url;
 extern crate web3;
 
+pub mod cache;
+pub mod rate;
+
+pub use cache::ResponseCache;
 use error::*;
 use jsonrpc_core::Value;
+pub use rate::{RateLimit, RequestBudgets, RequestKind, Throttled};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio_timer::Timer;
 use web3::futures::future;
 use web3::futures::Future;
 
+type SendFuture =
+    Box<dyn Future<Item = Value, Error = web3::error::Error> + Send + 'static>;
+
 /// Generic transport
 #[derive(Debug, Clone)]
 pub struct GenericTransport {
     http: Option<web3::transports::http::Http>,
     ws: Option<web3::transports::ws::WebSocket>,
-    timeout: u64,
+    timeout: Duration,
+    // requests sent, shared by the clones of the transport
+    calls: Arc<AtomicUsize>,
+    budgets: Arc<RequestBudgets>,
+    cache: Option<Arc<ResponseCache>>,
 }
 
 impl GenericTransport {
     pub fn new(
         connstr: &str,
-        timeout: u64,
+        timeout: Duration,
     ) -> Result<(web3::transports::EventLoopHandle, GenericTransport)> {
         let mut generic_transport = GenericTransport {
             http: None,
             ws: None,
             timeout: timeout,
+            calls: Arc::new(AtomicUsize::new(0)),
+            budgets: Arc::new(RequestBudgets::default()),
+            cache: None,
         };
 
         match url::Url::parse(connstr)?.scheme() {
@@ -81,58 +99,84 @@ impl GenericTransport {
                 info!("GenericTransport created successfully with underlying WebSocket");
                 return Ok((transport.0, generic_transport));
             }
-            _ => bail!(ErrorKind::InvalidConfig(
+            _ => bail!(ErrorKind::ConfigError(
                 "Need to provide a valid http(s)/ws url (config file, command line or env)"
                     .to_string(),
             )),
         }
     }
-}
 
-impl web3::Transport for GenericTransport {
-    type Out = Box<
-        dyn Future<Item = Value, Error = web3::error::Error> + Send + 'static,
-    >;
-    fn send(
+    /// Number of requests sent to the node so far, through this transport
+    /// or any of its clones
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::Relaxed)
+    }
+
+    /// Makes requests beyond the given budgets wait for their turn
+    pub fn with_budgets(mut self, budgets: RequestBudgets) -> Self {
+        self.budgets = Arc::new(budgets);
+        self
+    }
+
+    /// Number of requests that waited for their turn so far
+    pub fn throttled(&self) -> Throttled {
+        self.budgets.throttled()
+    }
+
+    /// Keeps up to `capacity` responses that never change, like mined
+    /// transactions, to answer the same requests without the node
+    pub fn with_cache(mut self, capacity: usize) -> Self {
+        self.cache = match capacity {
+            0 => None,
+            capacity => Some(Arc::new(ResponseCache::new(capacity))),
+        };
+        self
+    }
+
+    /// Number of requests answered from the cache so far
+    pub fn cache_hits(&self) -> usize {
+        self.cache.as_ref().map_or(0, |cache| cache.hits())
+    }
+
+    // sends the request right away
+    fn send_now(
         &self,
         id: web3::RequestId,
         request: jsonrpc_core::Call,
-    ) -> Self::Out {
+    ) -> SendFuture {
+        self.calls.fetch_add(1, Ordering::Relaxed);
         if let Some(s) = &self.http {
             return Box::new(s.send(id, request));
         }
         if let Some(s) = &self.ws {
-            let duration = Duration::from_secs(self.timeout);
             let timer = Timer::default();
-            let timeout = timer.sleep(duration);
-
-            let timeout_send =
-                s.send(id, request)
-                    .select2(timeout)
-                    .then(|res| -> Self::Out {
-                        match res {
-                            Ok(future::Either::A((v, _))) => Box::new(
-                                future::ok::<Value, web3::error::Error>(v),
-                            ),
-                            Ok(future::Either::B((_, _))) => Box::new(
-                                future::err(web3::error::Error::Transport(
-                                    "timeout sending request.".to_string(),
-                                )),
+            let timeout = timer.sleep(self.timeout);
+
+            let timeout_send = s.send(id, request).select2(timeout).then(
+                |res| -> SendFuture {
+                    match res {
+                        Ok(future::Either::A((v, _))) => {
+                            Box::new(future::ok::<Value, web3::error::Error>(v))
+                        }
+                        Ok(future::Either::B((_, _))) => Box::new(future::err(
+                            web3::error::Error::Transport(
+                                "timeout sending request.".to_string(),
                             ),
-                            Err(future::Either::A((e, _))) => {
-                                Box::new(future::err(e))
-                            }
-                            Err(future::Either::B((e, _))) => {
-                                error!("{}", e);
-                                Box::new(future::err(
-                                    web3::error::Error::Transport(
-                                        "timer error sending request."
-                                            .to_string(),
-                                    ),
-                                ))
-                            }
+                        )),
+                        Err(future::Either::A((e, _))) => {
+                            Box::new(future::err(e))
                         }
-                    });
+                        Err(future::Either::B((e, _))) => {
+                            error!("{}", e);
+                            Box::new(future::err(
+                                web3::error::Error::Transport(
+                                    "timer error sending request.".to_string(),
+                                ),
+                            ))
+                        }
+                    }
+                },
+            );
             return Box::new(timeout_send);
         }
 
@@ -142,6 +186,88 @@ impl web3::Transport for GenericTransport {
             ),
         ));
     }
+}
+
+/// A transport to a node together with the event loop it runs on, which
+/// must outlive it
+pub struct Connection {
+    pub web3: web3::Web3<GenericTransport>,
+    _eloop: web3::transports::EventLoopHandle, // kept to stay in scope
+}
+
+impl Connection {
+    /// Connects to the node at `url`, with budgets and a cache of its own,
+    /// as the quotas of one node say nothing of another
+    pub fn open(
+        url: &str,
+        timeout: Duration,
+        budgets: RequestBudgets,
+        cache_size: usize,
+    ) -> Result<Connection> {
+        let (eloop, transport) = GenericTransport::new(url, timeout)
+            .chain_err(|| {
+                format!("could not connect to Eth node at url: {}", url)
+            })?;
+        let transport = transport.with_budgets(budgets).with_cache(cache_size);
+        Ok(Connection {
+            web3: web3::Web3::new(transport),
+            _eloop: eloop,
+        })
+    }
+}
+
+impl web3::Transport for GenericTransport {
+    type Out = SendFuture;
+    fn send(
+        &self,
+        id: web3::RequestId,
+        request: jsonrpc_core::Call,
+    ) -> Self::Out {
+        let method = match &request {
+            jsonrpc_core::Call::MethodCall(call) => call.method.clone(),
+            _ => String::new(),
+        };
+        // immutable data already read is not asked for again
+        let cache_key = match (&self.cache, &request) {
+            (Some(cache), jsonrpc_core::Call::MethodCall(call)) => {
+                cache.key(&call.method, &call.params)
+            }
+            _ => None,
+        };
+        if let (Some(cache), Some(key)) = (&self.cache, &cache_key) {
+            if let Some(value) = cache.get(key) {
+                return Box::new(future::ok(value));
+            }
+        }
+
+        let kind = RequestKind::of(&method);
+        let wait = self.budgets.wait(kind);
+        let sent: SendFuture = if wait == Duration::from_secs(0) {
+            self.send_now(id, request)
+        } else {
+            trace!("Throttling {:?} request for {:?}", kind, wait);
+            let transport = self.clone();
+            Box::new(
+                Timer::default()
+                    .sleep(wait)
+                    .map_err(|e| {
+                        error!("{}", e);
+                        web3::error::Error::Transport(
+                            "timer error throttling request.".to_string(),
+                        )
+                    })
+                    .and_then(move |_| transport.send_now(id, request)),
+            )
+        };
+        match self.cache.clone() {
+            Some(cache) => Box::new(sent.map(move |value| {
+                cache.store(&method, cache_key, &value);
+                value
+            })),
+            None => sent,
+        }
+    }
+
     fn prepare(
         &self,
         method: &str,
@@ -0,0 +1,191 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Budgets of the requests sent to the node. Hosted node providers enforce
+//! quotas, so requests beyond the configured rate wait for their turn here
+//! instead of being refused by the provider. Sends of transactions have a
+//! budget of their own, so that polling never holds them back.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Requests allowed per second, and how many may go at once after a
+/// quiet period
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+/// Kind of a request, each kind having its own budget
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RequestKind {
+    Read,
+    Send,
+}
+
+impl RequestKind {
+    /// Sends of transactions, and reads for any other method
+    pub fn of(method: &str) -> Self {
+        match method {
+            "eth_sendRawTransaction" | "eth_sendTransaction" => {
+                RequestKind::Send
+            }
+            _ => RequestKind::Read,
+        }
+    }
+}
+
+/// Requests that waited for their turn, by kind
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Throttled {
+    pub reads: usize,
+    pub sends: usize,
+}
+
+// tokens of a budget, refilled with time; taking more than there are
+// reserves the next ones, so that requests go in the order they came
+#[derive(Debug)]
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        Bucket {
+            limit: limit,
+            tokens: f64::from(limit.burst),
+            refilled: now,
+        }
+    }
+
+    // takes a token, returning how long to wait for it
+    fn take(&mut self, now: Instant) -> Duration {
+        if now > self.refilled {
+            let elapsed = (now - self.refilled).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.limit.per_second)
+                .min(f64::from(self.limit.burst));
+            self.refilled = now;
+        }
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.limit.per_second)
+        }
+    }
+}
+
+/// Budgets of the reads and of the sends, unlimited unless given
+#[derive(Debug, Default)]
+pub struct RequestBudgets {
+    reads: Option<Mutex<Bucket>>,
+    sends: Option<Mutex<Bucket>>,
+    throttled_reads: AtomicUsize,
+    throttled_sends: AtomicUsize,
+}
+
+impl RequestBudgets {
+    pub fn new(reads: Option<RateLimit>, sends: Option<RateLimit>) -> Self {
+        let now = Instant::now();
+        RequestBudgets {
+            reads: reads.map(|limit| Mutex::new(Bucket::new(limit, now))),
+            sends: sends.map(|limit| Mutex::new(Bucket::new(limit, now))),
+            throttled_reads: AtomicUsize::new(0),
+            throttled_sends: AtomicUsize::new(0),
+        }
+    }
+
+    /// How long a request of the given kind waits for its turn, counting
+    /// it as throttled if it does
+    pub fn wait(&self, kind: RequestKind) -> Duration {
+        let (bucket, throttled) = match kind {
+            RequestKind::Read => (&self.reads, &self.throttled_reads),
+            RequestKind::Send => (&self.sends, &self.throttled_sends),
+        };
+        let wait = match bucket {
+            Some(bucket) => bucket.lock().unwrap().take(Instant::now()),
+            None => Duration::from_secs(0),
+        };
+        if wait > Duration::from_secs(0) {
+            throttled.fetch_add(1, Ordering::Relaxed);
+        }
+        wait
+    }
+
+    pub fn throttled(&self) -> Throttled {
+        Throttled {
+            reads: self.throttled_reads.load(Ordering::Relaxed),
+            sends: self.throttled_sends.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_beyond_the_burst_wait_for_their_turn() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(
+            RateLimit {
+                per_second: 10.0,
+                burst: 2,
+            },
+            start,
+        );
+        assert_eq!(bucket.take(start), Duration::from_secs(0));
+        assert_eq!(bucket.take(start), Duration::from_secs(0));
+        assert_eq!(bucket.take(start), Duration::from_millis(100));
+        assert_eq!(bucket.take(start), Duration::from_millis(200));
+
+        // refilled with time, but never beyond the burst
+        let later = start + Duration::from_secs(10);
+        assert_eq!(bucket.take(later), Duration::from_secs(0));
+        assert_eq!(bucket.take(later), Duration::from_secs(0));
+        assert!(bucket.take(later) > Duration::from_secs(0));
+    }
+
+    #[test]
+    fn sends_have_a_budget_of_their_own() {
+        let budgets = RequestBudgets::new(
+            Some(RateLimit {
+                per_second: 1.0,
+                burst: 1,
+            }),
+            None,
+        );
+        assert_eq!(budgets.wait(RequestKind::Read), Duration::from_secs(0));
+        assert!(budgets.wait(RequestKind::Read) > Duration::from_secs(0));
+        assert_eq!(
+            budgets.wait(RequestKind::of("eth_sendRawTransaction")),
+            Duration::from_secs(0)
+        );
+        assert_eq!(budgets.throttled(), Throttled { reads: 1, sends: 0 });
+    }
+}
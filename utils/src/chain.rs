@@ -0,0 +1,156 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! What the dispatcher reads from the chain besides the state of the
+//! instances, behind a trait so that it can be tested without a node.
+
+use super::convert;
+use super::EthExt;
+use error::*;
+use ethereum_types::Address;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use web3::futures::future;
+use web3::futures::Future;
+use web3::types::Bytes;
+use web3::Transport;
+
+/// What the dispatcher needs to know about a block
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlockHeader {
+    /// None for a pending block
+    pub number: Option<u64>,
+    pub timestamp: u64,
+}
+
+/// Reads blocks and deployed code from the chain
+pub trait ChainReader: Send {
+    /// The latest block mined
+    fn latest_block(
+        &self,
+    ) -> Box<dyn Future<Item = BlockHeader, Error = Error> + Send>;
+
+    /// The code deployed at an address, empty if there is no contract
+    fn code(
+        &self,
+        address: Address,
+    ) -> Box<dyn Future<Item = Bytes, Error = Error> + Send>;
+}
+
+impl<T: Transport + Send + 'static> ChainReader for web3::Web3<T>
+where
+    T::Out: Send,
+{
+    fn latest_block(
+        &self,
+    ) -> Box<dyn Future<Item = BlockHeader, Error = Error> + Send> {
+        Box::new(self.eth().get_latest_block().and_then(|block| {
+            Ok(BlockHeader {
+                number: block.number.map(|n| n.as_u64()),
+                timestamp: convert::to_u64(block.timestamp)?,
+            })
+        }))
+    }
+
+    fn code(
+        &self,
+        address: Address,
+    ) -> Box<dyn Future<Item = Bytes, Error = Error> + Send> {
+        Box::new(
+            self.eth()
+                .code(address, None)
+                .map_err(|e| Error::from(ErrorKind::Web3(e))),
+        )
+    }
+}
+
+/// A chain kept in memory, mining the scripted blocks one per call to
+/// `latest_block` and staying at the last one
+#[derive(Default)]
+pub struct MockChain {
+    blocks: Mutex<VecDeque<BlockHeader>>,
+    code: HashMap<Address, Bytes>,
+}
+
+impl MockChain {
+    pub fn new() -> Self {
+        MockChain::default()
+    }
+
+    /// Scripts the next block, with the given number and timestamp
+    pub fn mine(self, number: u64, timestamp: u64) -> Self {
+        self.blocks.lock().unwrap().push_back(BlockHeader {
+            number: Some(number),
+            timestamp: timestamp,
+        });
+        self
+    }
+
+    /// Deploys code at an address
+    pub fn deploy(mut self, address: Address, code: Vec<u8>) -> Self {
+        self.code.insert(address, Bytes(code));
+        self
+    }
+}
+
+impl ChainReader for MockChain {
+    fn latest_block(
+        &self,
+    ) -> Box<dyn Future<Item = BlockHeader, Error = Error> + Send> {
+        let mut blocks = self.blocks.lock().unwrap();
+        let block = if blocks.len() > 1 {
+            blocks.pop_front()
+        } else {
+            blocks.front().cloned()
+        };
+        Box::new(future::result(block.ok_or(Error::from(
+            ErrorKind::ChainError(String::from("no block was mined")),
+        ))))
+    }
+
+    fn code(
+        &self,
+        address: Address,
+    ) -> Box<dyn Future<Item = Bytes, Error = Error> + Send> {
+        Box::new(future::ok(
+            self.code.get(&address).cloned().unwrap_or_default(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_chain_mines_scripted_blocks() {
+        let chain = MockChain::new().mine(1, 100).mine(2, 115);
+        assert!(MockChain::new().latest_block().wait().is_err());
+
+        let timestamps: Vec<u64> = (0..3)
+            .map(|_| chain.latest_block().wait().unwrap().timestamp)
+            .collect();
+        assert_eq!(timestamps, vec![100, 115, 115]);
+    }
+}
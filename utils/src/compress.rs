@@ -0,0 +1,101 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Transparent compression of the files holding archive entries, like
+//! machine hashes and Merkle proofs, as written in snapshots and replay
+//! fixtures. Compressed contents start with a header naming the format
+//! and its version; contents without it are read as they are, so files
+//! written before compression remain readable.
+
+use error::*;
+use std::fs;
+use std::path::Path;
+
+const MAGIC: &[u8] = b"CTSI";
+
+/// Versions of the compressed format, written after the magic bytes
+const SNAPPY_V1: u8 = 1;
+
+/// Compresses the contents, prefixed by the header
+pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let compressed = snap::raw::Encoder::new()
+        .compress_vec(data)
+        .chain_err(|| "could not compress")?;
+    let mut out = Vec::with_capacity(MAGIC.len() + 1 + compressed.len());
+    out.extend_from_slice(MAGIC);
+    out.push(SNAPPY_V1);
+    out.extend(compressed);
+    Ok(out)
+}
+
+/// Decompresses contents written by `compress`, returning those without
+/// a header unchanged
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    if !data.starts_with(MAGIC) {
+        return Ok(data.to_vec());
+    }
+    match data.get(MAGIC.len()) {
+        Some(&SNAPPY_V1) => Ok(snap::raw::Decoder::new()
+            .decompress_vec(&data[MAGIC.len() + 1..])
+            .chain_err(|| "could not decompress")?),
+        Some(version) => Err(Error::from(format!(
+            "unknown compression format version {}",
+            version
+        ))),
+        None => Err(Error::from("truncated compression header")),
+    }
+}
+
+/// Writes the contents compressed to a file
+pub fn write_file(path: &Path, data: &[u8]) -> Result<()> {
+    fs::write(path, compress(data)?)
+        .chain_err(|| format!("could not write {}", path.display()))
+}
+
+/// Reads a file, decompressing it if it was compressed
+pub fn read_file(path: &Path) -> Result<Vec<u8>> {
+    let data = fs::read(path)
+        .chain_err(|| format!("could not read {}", path.display()))?;
+    decompress(&data).chain_err(|| format!("invalid {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressed_and_plain_contents_are_read() {
+        let proof = vec![7u8; 4096];
+        let compressed = compress(&proof).unwrap();
+        assert!(compressed.len() < proof.len());
+        assert_eq!(decompress(&compressed).unwrap(), proof);
+
+        let plain = b"{\"taken_at\": 0}".to_vec();
+        assert_eq!(decompress(&plain).unwrap(), plain);
+
+        let mut unknown = compressed.clone();
+        unknown[MAGIC.len()] = 9;
+        assert!(decompress(&unknown).is_err());
+    }
+}
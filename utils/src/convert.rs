@@ -0,0 +1,148 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Conversions between the ethereum types the contracts use and the
+//! native ones, for dapps parsing the state of their instances. Times are
+//! kept by contracts as `uint256` seconds, hashes are read as hex strings
+//! with or without their `0x`.
+
+use error::*;
+use ethereum_types::{H256, U256};
+use std::convert::TryFrom;
+use std::ops::Add;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A time in seconds since the epoch, as contracts and blocks keep it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(pub u64);
+
+impl Timestamp {
+    /// The time of the system clock
+    pub fn now() -> Timestamp {
+        Timestamp(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or(0),
+        )
+    }
+}
+
+impl From<u64> for Timestamp {
+    fn from(seconds: u64) -> Self {
+        Timestamp(seconds)
+    }
+}
+
+impl From<Timestamp> for U256 {
+    fn from(timestamp: Timestamp) -> Self {
+        U256::from(timestamp.0)
+    }
+}
+
+impl TryFrom<U256> for Timestamp {
+    type Error = Error;
+
+    fn try_from(value: U256) -> Result<Self> {
+        to_u64(value).map(Timestamp)
+    }
+}
+
+impl Add<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    fn add(self, duration: Duration) -> Timestamp {
+        Timestamp(self.0.saturating_add(duration.as_secs()))
+    }
+}
+
+/// The value as a u64, failing instead of panicking when it does not fit
+pub fn to_u64(value: U256) -> Result<u64> {
+    if value > U256::from(u64::max_value()) {
+        return Err(Error::from(format!("{} does not fit in 64 bits", value)));
+    }
+    Ok(value.low_u64())
+}
+
+/// A duration given in seconds, like the round duration of a dispute
+pub fn to_duration(seconds: U256) -> Result<Duration> {
+    to_u64(seconds)
+        .map(Duration::from_secs)
+        .chain_err(|| format!("invalid duration of {} seconds", seconds))
+}
+
+/// A hash written in hex, with or without `0x`
+pub fn parse_h256(value: &str) -> Result<H256> {
+    let digits = value.trim();
+    let digits = digits.trim_start_matches("0x");
+    if digits.len() != 64 {
+        return Err(Error::from(format!(
+            "{} is not a hash of 32 bytes",
+            value
+        )));
+    }
+    digits
+        .parse()
+        .chain_err(|| format!("{} is not a hex hash", value))
+}
+
+/// A number written as a hex quantity, like those of the json rpc, or in
+/// decimal
+pub fn parse_u256(value: &str) -> Result<U256> {
+    let value = value.trim();
+    let parsed = if value.starts_with("0x") {
+        U256::from_str_radix(&value[2..], 16).ok()
+    } else {
+        U256::from_dec_str(value).ok()
+    };
+    parsed.ok_or(Error::from(format!("{} is not a number", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_out_of_range_are_errors() {
+        let seconds = U256::from(90);
+        assert_eq!(to_duration(seconds).unwrap(), Duration::from_secs(90));
+        assert_eq!(Timestamp::try_from(seconds).unwrap(), Timestamp(90));
+        assert_eq!(U256::from(Timestamp(90)), seconds);
+        assert!(to_u64(U256::max_value()).is_err());
+        assert!(to_duration(U256::from(u64::max_value()) + 1).is_err());
+    }
+
+    #[test]
+    fn hex_is_read_with_or_without_prefix() {
+        let hash = H256::repeat_byte(0xab);
+        let digits = "ab".repeat(32);
+        assert_eq!(parse_h256(&digits).unwrap(), hash);
+        assert_eq!(parse_h256(&format!(" 0x{}\n", digits)).unwrap(), hash);
+        assert!(parse_h256("0xabab").is_err());
+
+        assert_eq!(parse_u256("0x1b4").unwrap(), U256::from(436));
+        assert_eq!(parse_u256("436").unwrap(), U256::from(436));
+        assert!(parse_u256("0xzz").is_err());
+    }
+}
@@ -0,0 +1,390 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Key-value stores backing the databases kept in the working path, so
+//! that the dispatcher is not tied to leveldb. Stores are picked by the
+//! `storage` setting of the configuration. With sqlite, every database is
+//! a table of a single `dispatcher.sqlite` file, next to where the other
+//! stores keep their directories, that operators can inspect with the
+//! usual tools.
+
+use configuration::Storage;
+use db_key::Key;
+use error::*;
+use leveldb::database::Database;
+use leveldb::iterator::Iterable;
+use leveldb::kv::KV;
+use leveldb::options::{Options, ReadOptions, WriteOptions};
+use rocksdb::{Direction, IteratorMode, DB};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Byte keys and values, shared by threads
+pub trait KvStore: Send + Sync {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()>;
+
+    fn delete(&self, key: &[u8]) -> Result<()>;
+
+    /// Entries whose key starts with the prefix, in key order
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
+
+    /// Writes to disk what the store still buffers in memory. Leveldb and
+    /// sqlite write through on every change, leaving nothing to flush.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Opens the store at the given path, creating it if missing
+pub fn open(storage: Storage, path: &Path) -> Result<Arc<dyn KvStore>> {
+    Ok(match storage {
+        Storage::LevelDb => Arc::new(LevelDbStore::open(path)?),
+        Storage::RocksDb => Arc::new(RocksDbStore::open(path)?),
+        Storage::Sqlite => Arc::new(SqliteStore::open(path)?),
+    })
+}
+
+/// A store only opened on first use. Databases are locked by the process
+/// opening them, and commands run next to a dispatcher sharing the working
+/// path may not need them, so the stores the dispatcher keeps open are
+/// behind one.
+pub struct LazyStore {
+    storage: Storage,
+    path: PathBuf,
+    store: Mutex<Option<Arc<dyn KvStore>>>,
+}
+
+impl LazyStore {
+    pub fn new(storage: Storage, path: &Path) -> Self {
+        LazyStore {
+            storage: storage,
+            path: path.to_path_buf(),
+            store: Mutex::new(None),
+        }
+    }
+
+    fn store(&self) -> Result<Arc<dyn KvStore>> {
+        let mut store = self.store.lock().unwrap();
+        if let Some(store) = &*store {
+            return Ok(store.clone());
+        }
+        let opened = open(self.storage, &self.path).chain_err(|| {
+            format!("could not open database {}", self.path.display())
+        })?;
+        *store = Some(opened.clone());
+        Ok(opened)
+    }
+}
+
+impl KvStore for LazyStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.store()?.get(key)
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.store()?.put(key, value)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        self.store()?.delete(key)
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.store()?.scan_prefix(prefix)
+    }
+
+    // a store never opened has nothing to flush
+    fn flush(&self) -> Result<()> {
+        match &*self.store.lock().unwrap() {
+            Some(store) => store.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+// raw bytes as a leveldb key
+struct BytesKey(Vec<u8>);
+
+impl Key for BytesKey {
+    fn from_u8(key: &[u8]) -> BytesKey {
+        BytesKey(key.to_vec())
+    }
+
+    fn as_slice<T, F: Fn(&[u8]) -> T>(&self, f: F) -> T {
+        f(&self.0)
+    }
+}
+
+pub struct LevelDbStore {
+    database: Database<BytesKey>,
+}
+
+impl LevelDbStore {
+    pub fn open(path: &Path) -> Result<LevelDbStore> {
+        let mut options = Options::new();
+        options.create_if_missing = true;
+        Ok(LevelDbStore {
+            database: Database::open(path, options).chain_err(|| {
+                format!("could not open leveldb at {}", path.display())
+            })?,
+        })
+    }
+}
+
+impl KvStore for LevelDbStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.database
+            .get(ReadOptions::new(), BytesKey(key.to_vec()))
+            .chain_err(|| "could not read from leveldb")
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.database
+            .put(WriteOptions::new(), BytesKey(key.to_vec()), value)
+            .chain_err(|| "could not write to leveldb")
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        self.database
+            .delete(WriteOptions::new(), BytesKey(key.to_vec()))
+            .chain_err(|| "could not delete from leveldb")
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self
+            .database
+            .iter(ReadOptions::new())
+            .skip_while(|(key, _)| key.0.as_slice() < prefix)
+            .take_while(|(key, _)| key.0.starts_with(prefix))
+            .map(|(key, value)| (key.0, value))
+            .collect())
+    }
+}
+
+pub struct RocksDbStore {
+    database: DB,
+}
+
+impl RocksDbStore {
+    pub fn open(path: &Path) -> Result<RocksDbStore> {
+        Ok(RocksDbStore {
+            database: DB::open_default(path).chain_err(|| {
+                format!("could not open rocksdb at {}", path.display())
+            })?,
+        })
+    }
+}
+
+impl KvStore for RocksDbStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .database
+            .get(key)
+            .chain_err(|| "could not read from rocksdb")?
+            .map(|value| value.to_vec()))
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.database
+            .put(key, value)
+            .chain_err(|| "could not write to rocksdb")
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        self.database
+            .delete(key)
+            .chain_err(|| "could not delete from rocksdb")
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self
+            .database
+            .iterator(IteratorMode::From(prefix, Direction::Forward))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.to_vec(), value.to_vec()))
+            .collect())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.database
+            .flush()
+            .chain_err(|| "could not flush rocksdb")
+    }
+}
+
+/// Name of the file holding the sqlite tables
+pub const SQLITE_FILE: &str = "dispatcher.sqlite";
+
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+    table: String,
+}
+
+impl SqliteStore {
+    /// Opens the table named after the last component of the path, in the
+    /// sqlite file of its directory
+    pub fn open(path: &Path) -> Result<SqliteStore> {
+        let table = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .filter(|name| {
+                !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            })
+            .map(String::from)
+            .ok_or(Error::from(format!(
+                "no table name in {}",
+                path.display()
+            )))?;
+        let file = path.parent().unwrap_or(Path::new(".")).join(SQLITE_FILE);
+        let connection = Connection::open(&file).chain_err(|| {
+            format!("could not open sqlite at {}", file.display())
+        })?;
+        // other stores of the process share the file
+        connection
+            .busy_timeout(std::time::Duration::from_secs(5))
+            .chain_err(|| "could not configure sqlite")?;
+        connection
+            .execute(
+                &format!(
+                    "CREATE TABLE IF NOT EXISTS \"{}\" \
+                     (key BLOB PRIMARY KEY, value BLOB NOT NULL)",
+                    table
+                ),
+                params![],
+            )
+            .chain_err(|| format!("could not create sqlite table {}", table))?;
+        Ok(SqliteStore {
+            connection: Mutex::new(connection),
+            table: table,
+        })
+    }
+}
+
+impl KvStore for SqliteStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.connection
+            .lock()
+            .unwrap()
+            .query_row(
+                &format!("SELECT value FROM \"{}\" WHERE key = ?1", self.table),
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()
+            .chain_err(|| "could not read from sqlite")
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute(
+                &format!(
+                    "INSERT OR REPLACE INTO \"{}\" (key, value) \
+                     VALUES (?1, ?2)",
+                    self.table
+                ),
+                params![key, value],
+            )
+            .map(|_| ())
+            .chain_err(|| "could not write to sqlite")
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute(
+                &format!("DELETE FROM \"{}\" WHERE key = ?1", self.table),
+                params![key],
+            )
+            .map(|_| ())
+            .chain_err(|| "could not delete from sqlite")
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let connection = self.connection.lock().unwrap();
+        // blobs are compared byte by byte, like the keys of the other stores
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT key, value FROM \"{}\" WHERE key >= ?1 ORDER BY key",
+                self.table
+            ))
+            .chain_err(|| "could not read from sqlite")?;
+        let rows = statement
+            .query_map(params![prefix], |row| Ok((row.get(0)?, row.get(1)?)))
+            .chain_err(|| "could not read from sqlite")?;
+        let mut entries = vec![];
+        for row in rows {
+            let (key, value): (Vec<u8>, Vec<u8>) =
+                row.chain_err(|| "could not read from sqlite")?;
+            if !key.starts_with(prefix) {
+                break;
+            }
+            entries.push((key, value));
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn scan_by_prefix(storage: Storage, path: &Path) {
+        let store = LazyStore::new(storage, path);
+        store.put(b"a1", b"x").unwrap();
+        store.put(b"b1", b"y").unwrap();
+        store.put(b"b2", b"z").unwrap();
+        store.put(b"c1", b"w").unwrap();
+        store.delete(b"b2").unwrap();
+
+        assert_eq!(store.get(b"a1").unwrap(), Some(b"x".to_vec()));
+        assert_eq!(store.get(b"b2").unwrap(), None);
+        assert_eq!(
+            store.scan_prefix(b"b").unwrap(),
+            vec![(b"b1".to_vec(), b"y".to_vec())]
+        );
+        assert_eq!(store.scan_prefix(b"").unwrap().len(), 3);
+    }
+
+    #[test]
+    fn stores_scan_by_prefix() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path();
+        scan_by_prefix(Storage::LevelDb, &dir.join("leveldb"));
+        scan_by_prefix(Storage::RocksDb, &dir.join("rocksdb"));
+        scan_by_prefix(Storage::Sqlite, &dir.join("sqlite_db"));
+        // tables of the same file are apart
+        scan_by_prefix(Storage::Sqlite, &dir.join("other_db"));
+        assert!(dir.join(SQLITE_FILE).is_file());
+    }
+}
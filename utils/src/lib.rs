@@ -25,23 +25,49 @@
 #[macro_use]
 extern crate log;
 extern crate configuration;
+extern crate db_key;
 extern crate env_logger;
 extern crate error;
+extern crate ethereum_types;
+extern crate leveldb;
+extern crate rand;
+extern crate rocksdb;
+extern crate rusqlite;
+extern crate snap;
+#[cfg(test)]
+extern crate tempfile;
 extern crate time;
+extern crate tiny_keccak;
+extern crate tokio;
 extern crate web3;
 
+pub mod chain;
+pub mod compress;
+pub mod convert;
+pub mod kv;
+pub mod merkle;
+pub mod retry;
+
 pub use error::*;
 use std::time::{SystemTime, UNIX_EPOCH};
 use time::Duration;
 use web3::futures::Future;
-use web3::types::{BlockId, BlockNumber};
+use web3::types::{Block, BlockId, BlockNumber, H256};
 use web3::Transport;
 
 pub trait EthExt<T: Transport> {
     fn get_delay(self) -> Box<dyn Future<Item = i64, Error = Error>>;
+    fn get_timestamp(self)
+        -> Box<dyn Future<Item = u64, Error = Error> + Send>;
+    fn get_latest_block(
+        self,
+    ) -> Box<dyn Future<Item = Block<H256>, Error = Error> + Send>;
 }
 
-impl<T: Transport + 'static> EthExt<T> for web3::api::Eth<T> {
+impl<T: Transport + 'static> EthExt<T> for web3::api::Eth<T>
+where
+    T::Out: Send,
+{
     fn get_delay(self) -> Box<dyn Future<Item = i64, Error = Error>> {
         Box::new(
             self.block(BlockId::Number(BlockNumber::Latest))
@@ -56,7 +82,7 @@ impl<T: Transport + 'static> EthExt<T> for web3::api::Eth<T> {
                     ))
                 })
                 .and_then(|block| {
-                    let block_time: i64 = block.timestamp.as_u64() as i64;
+                    let block_time = convert::to_u64(block.timestamp)? as i64;
                     SystemTime::now()
                         .duration_since(UNIX_EPOCH)
                         .map_err(|_e| {
@@ -71,6 +97,33 @@ impl<T: Transport + 'static> EthExt<T> for web3::api::Eth<T> {
                 }),
         )
     }
+
+    fn get_timestamp(
+        self,
+    ) -> Box<dyn Future<Item = u64, Error = Error> + Send> {
+        Box::new(
+            self.get_latest_block()
+                .and_then(|block| convert::to_u64(block.timestamp)),
+        )
+    }
+
+    fn get_latest_block(
+        self,
+    ) -> Box<dyn Future<Item = Block<H256>, Error = Error> + Send> {
+        Box::new(
+            self.block(BlockId::Number(BlockNumber::Latest))
+                .map_err(|_| {
+                    Error::from(ErrorKind::ChainError(
+                        "Latest block not found".to_string(),
+                    ))
+                })
+                .and_then(|block| {
+                    block.ok_or(Error::from(ErrorKind::ChainError(
+                        "Latest block not found".to_string(),
+                    )))
+                }),
+        )
+    }
 }
 
 pub trait EthWeb3<T: Transport> {
@@ -78,28 +131,40 @@ pub trait EthWeb3<T: Transport> {
         &self,
         &configuration::Configuration,
     ) -> Box<dyn Future<Item = (), Error = Error>>;
+    fn test_node(&self, url: &str)
+        -> Box<dyn Future<Item = (), Error = Error>>;
     fn node_in_sync(
         &self,
         &configuration::Configuration,
     ) -> Box<dyn Future<Item = (), Error = Error>>;
 }
 
-impl<T: Transport + 'static> EthWeb3<T> for web3::Web3<T> {
+impl<T: Transport + 'static> EthWeb3<T> for web3::Web3<T>
+where
+    T::Out: Send,
+{
     fn test_connection(
         &self,
         config: &configuration::Configuration,
+    ) -> Box<dyn Future<Item = (), Error = Error>> {
+        self.test_node(&config.url)
+    }
+
+    fn test_node(
+        &self,
+        url: &str,
     ) -> Box<dyn Future<Item = (), Error = Error>> {
         info!("Testing Ethereum node's responsiveness");
-        let url = config.url.clone();
+        let url = url.to_string();
         let web3_clone = self.web3().clone();
         Box::new(
             web3_clone
                 .client_version()
                 .map_err(move |_e| {
-                    Error::from(ErrorKind::ChainError(format!(
-                        "no Ethereum node responding at url: {}",
-                        url
-                    )))
+                    Error::from(ErrorKind::RpcError(
+                        String::from("web3_clientVersion"),
+                        url,
+                    ))
                 })
                 .map(|_| ()),
         )
@@ -0,0 +1,159 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Verification of the Merkle proofs returned by the machine manager.
+//!
+//! DApps read proofs through `Archive::get_proof` of the dispatcher, which
+//! checks them before any transaction is built from them. When the check
+//! fails, it returns `ErrorKind::ResponseInvalidError` for the archive key,
+//! which makes the dispatcher drop the response and request it again,
+//! instead of burning gas on a transaction that will revert.
+
+use error::*;
+use ethereum_types::H256;
+use tiny_keccak::keccak256;
+
+/// A proof that `target_hash` is the node of size `2^log2_size` found at
+/// `address`, in the tree of size `2^log2_root_size` with `root_hash`.
+/// The sibling hashes are ordered bottom-up, starting at the level of the
+/// target node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MerkleProof {
+    pub address: u64,
+    pub log2_size: usize,
+    pub log2_root_size: usize,
+    pub target_hash: H256,
+    pub sibling_hashes: Vec<H256>,
+    pub root_hash: H256,
+}
+
+/// Hash of the concatenation of two nodes
+pub fn hash_pair(left: &H256, right: &H256) -> H256 {
+    let data = [left.as_bytes(), right.as_bytes()].concat();
+    H256::from(keccak256(&data))
+}
+
+impl MerkleProof {
+    /// Folds the target hash with its siblings up to the root
+    pub fn compute_root(&self) -> Result<H256> {
+        if self.log2_size > self.log2_root_size || self.log2_root_size > 64 {
+            return Err(Error::from(format!(
+                "invalid proof sizes, log2_size: {}, log2_root_size: {}",
+                self.log2_size, self.log2_root_size
+            )));
+        }
+        let levels = self.log2_root_size - self.log2_size;
+        if self.sibling_hashes.len() != levels {
+            return Err(Error::from(format!(
+                "proof has {} sibling hashes, expected {}",
+                self.sibling_hashes.len(),
+                levels
+            )));
+        }
+        if self.log2_size < 64
+            && self.address & ((1 << self.log2_size) - 1) != 0
+        {
+            return Err(Error::from(format!(
+                "address {:#x} is not aligned to 2^{}",
+                self.address, self.log2_size
+            )));
+        }
+
+        let mut hash = self.target_hash;
+        for (level, sibling) in self.sibling_hashes.iter().enumerate() {
+            let bit = (self.address >> (self.log2_size + level)) & 1;
+            hash = if bit == 0 {
+                hash_pair(&hash, sibling)
+            } else {
+                hash_pair(sibling, &hash)
+            };
+        }
+        Ok(hash)
+    }
+
+    /// Checks the proof against its own root hash
+    pub fn verify(&self) -> Result<()> {
+        self.verify_against(&self.root_hash)
+    }
+
+    /// Checks the proof against the root hash the DApp expects, for
+    /// instance the one already committed on-chain
+    pub fn verify_against(&self, expected_root: &H256) -> Result<()> {
+        let root = self.compute_root()?;
+        if root != *expected_root {
+            return Err(Error::from(format!(
+                "proof for address {:#x} leads to root {:?}, expected {:?}",
+                self.address, root, expected_root
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proof_for(address: u64, target: H256) -> MerkleProof {
+        let sibling_hashes: Vec<H256> =
+            (0..5).map(|i| H256::from_low_u64_be(i + 1)).collect();
+        let mut proof = MerkleProof {
+            address: address,
+            log2_size: 3,
+            log2_root_size: 8,
+            target_hash: target,
+            sibling_hashes: sibling_hashes,
+            root_hash: H256::zero(),
+        };
+        proof.root_hash = proof.compute_root().unwrap();
+        proof
+    }
+
+    #[test]
+    fn proof_verifies_only_against_its_root() {
+        let proof = proof_for(0x28, H256::from_low_u64_be(42));
+        assert!(proof.verify().is_ok());
+
+        let mut tampered = proof.clone();
+        tampered.sibling_hashes[2] = H256::zero();
+        assert!(tampered.verify().is_err());
+
+        let moved = MerkleProof {
+            address: 0x30,
+            ..proof.clone()
+        };
+        assert!(moved.verify().is_err());
+    }
+
+    #[test]
+    fn malformed_proofs_are_rejected() {
+        let mut proof = proof_for(0x28, H256::from_low_u64_be(42));
+        proof.address = 0x29;
+        assert!(proof.compute_root().is_err());
+
+        let mut proof = proof_for(0x28, H256::from_low_u64_be(42));
+        proof.sibling_hashes.pop();
+        assert!(proof.compute_root().is_err());
+    }
+}
@@ -0,0 +1,291 @@
+// Dispatcher provides the infrastructure to support the development of DApps,
+// mediating the communication between on-chain and off-chain components.
+
+// Copyright (C) 2019 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Retrying of operations that may fail for transient reasons, like a node
+//! dropping a connection or a file still being written, with exponential
+//! backoff between attempts.
+
+use error::*;
+use rand::Rng;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+use web3;
+use web3::futures::future::{self, Loop};
+use web3::futures::Future;
+
+/// Whether an error is worth retrying: failures to reach the node, a
+/// service or the filesystem. Application errors returned by grpc services
+/// should be taken out of the `Err` path before retrying.
+pub fn is_transient(e: &Error) -> bool {
+    match e.kind() {
+        ErrorKind::RpcError(..) => true,
+        ErrorKind::Io(_) => true,
+        ErrorKind::Hyper(_) => true,
+        ErrorKind::Grpc(_) => true,
+        ErrorKind::Web3(web3::Error::Transport(_)) => true,
+        ErrorKind::Web3(web3::Error::Io(_)) => true,
+        _ => false,
+    }
+}
+
+/// Policy to retry an operation: how many attempts, how long to wait
+/// between them and which errors deserve another attempt
+#[derive(Clone)]
+pub struct Retry {
+    max_attempts: u32,
+    initial_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+    retry_on: Arc<dyn Fn(&Error) -> bool + Send + Sync>,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            max_attempts: 5,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            jitter: true,
+            retry_on: Arc::new(is_transient),
+        }
+    }
+}
+
+impl Retry {
+    /// Five attempts, starting at half a second, retrying transient errors
+    pub fn new() -> Self {
+        Retry::default()
+    }
+
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    pub fn initial_delay(mut self, initial_delay: Duration) -> Self {
+        self.initial_delay = initial_delay;
+        self
+    }
+
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Randomizes each delay between half and all of its value, so that
+    /// several dispatchers do not hit a recovering node at the same time
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Replaces the predicate that decides which errors are retried
+    pub fn retry_on<P>(mut self, predicate: P) -> Self
+    where
+        P: Fn(&Error) -> bool + Send + Sync + 'static,
+    {
+        self.retry_on = Arc::new(predicate);
+        self
+    }
+
+    /// Delay before the given retry (starting at 1), without jitter
+    pub fn backoff(&self, retry: u32) -> Duration {
+        1u32.checked_shl(retry.saturating_sub(1))
+            .and_then(|factor| self.initial_delay.checked_mul(factor))
+            .filter(|delay| *delay <= self.max_delay)
+            .unwrap_or(self.max_delay)
+    }
+
+    fn delay(&self, retry: u32) -> Duration {
+        let delay = self.backoff(retry);
+        if !self.jitter || delay.as_millis() == 0 {
+            return delay;
+        }
+        let millis = delay.as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(millis / 2, millis))
+    }
+
+    /// Runs the operation until it succeeds, fails with an error that is
+    /// not retried or runs out of attempts, blocking between attempts
+    pub fn run<T, F>(&self, mut operation: F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation() {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    if attempt >= self.max_attempts || !(self.retry_on)(&e) {
+                        return Err(e);
+                    }
+                    let delay = self.delay(attempt);
+                    warn!(
+                        "Attempt {} of {} failed, retrying in {:?}: {}",
+                        attempt, self.max_attempts, delay, e
+                    );
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Like `run`, for an operation giving a future, which waits between
+    /// attempts on a timer instead of blocking the thread. Needs to be
+    /// polled inside a tokio runtime once an attempt fails.
+    pub fn run_future<T, F, R>(
+        &self,
+        operation: F,
+    ) -> Box<dyn Future<Item = T, Error = Error> + Send>
+    where
+        T: Send + 'static,
+        F: FnMut() -> R + Send + 'static,
+        R: Future<Item = T, Error = Error> + Send + 'static,
+    {
+        let retry = self.clone();
+        Box::new(future::loop_fn(
+            (operation, 1),
+            move |(mut operation, attempt): (F, u32)| {
+                let retry = retry.clone();
+                operation().then(
+                    move |res| -> Box<
+                        dyn Future<Item = Loop<T, (F, u32)>, Error = Error>
+                            + Send,
+                    > {
+                        let e = match res {
+                            Ok(value) => {
+                                return Box::new(future::ok(Loop::Break(value)))
+                            }
+                            Err(e) => e,
+                        };
+                        if attempt >= retry.max_attempts
+                            || !(retry.retry_on)(&e)
+                        {
+                            return Box::new(future::err(e));
+                        }
+                        let delay = retry.delay(attempt);
+                        warn!(
+                            "Attempt {} of {} failed, retrying in {:?}: {}",
+                            attempt, retry.max_attempts, delay, e
+                        );
+                        Box::new(
+                            Delay::new(Instant::now() + delay)
+                                .map_err(|e| {
+                                    Error::from(format!(
+                                        "retry timer failed: {}",
+                                        e
+                                    ))
+                                })
+                                .map(move |_| {
+                                    Loop::Continue((operation, attempt + 1))
+                                }),
+                        )
+                    },
+                )
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_wait() -> Retry {
+        Retry::new()
+            .initial_delay(Duration::from_millis(0))
+            .jitter(false)
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_delay() {
+        let retry = Retry::new()
+            .initial_delay(Duration::from_millis(100))
+            .max_delay(Duration::from_millis(1000));
+        assert_eq!(retry.backoff(1), Duration::from_millis(100));
+        assert_eq!(retry.backoff(2), Duration::from_millis(200));
+        assert_eq!(retry.backoff(4), Duration::from_millis(800));
+        assert_eq!(retry.backoff(5), Duration::from_millis(1000));
+        assert_eq!(retry.backoff(64), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn retries_transient_errors_only() {
+        let mut calls = 0;
+        let result = no_wait().run(|| {
+            calls += 1;
+            if calls < 3 {
+                Err(Error::from(ErrorKind::RpcError(
+                    String::from("eth_blockNumber"),
+                    String::from("http://localhost:8545"),
+                )))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let mut calls = 0;
+        let result: Result<()> = no_wait().run(|| {
+            calls += 1;
+            Err(Error::from("not transient"))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+
+        let mut calls = 0;
+        let result: Result<()> = no_wait().max_attempts(4).run(|| {
+            calls += 1;
+            Err(Error::from(ErrorKind::RpcError(
+                String::from("eth_blockNumber"),
+                String::from("http://localhost:8545"),
+            )))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 4);
+    }
+
+    #[test]
+    fn futures_are_retried_on_a_timer() {
+        let calls = Arc::new(std::sync::Mutex::new(0));
+        let counted = calls.clone();
+        let retried = no_wait().run_future(move || {
+            let mut calls = counted.lock().unwrap();
+            *calls += 1;
+            if *calls < 3 {
+                future::err(Error::from(ErrorKind::RpcError(
+                    String::from("eth_blockNumber"),
+                    String::from("http://localhost:8545"),
+                )))
+            } else {
+                future::ok(*calls)
+            }
+        });
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        assert_eq!(runtime.block_on(retried).unwrap(), 3);
+        assert_eq!(*calls.lock().unwrap(), 3);
+    }
+}